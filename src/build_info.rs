@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short commit hash, or "unknown" when built outside a git checkout.
+pub const GIT_SHA: &str = env!("GIT_SHA");
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+pub const RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+
+/// Served by `/_build` and logged at startup.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_timestamp: &'static str,
+    pub rustc_version: &'static str,
+}
+
+pub fn current() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_sha: GIT_SHA,
+        build_timestamp: BUILD_TIMESTAMP,
+        rustc_version: RUSTC_VERSION,
+    }
+}
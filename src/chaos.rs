@@ -0,0 +1,145 @@
+use crate::handlers::HandlerOutcome;
+use crate::observability::metrics::Metrics;
+use crate::protocol::ExecStatus;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Error code on every result the chaos layer made up.
+pub const CHAOS_ERROR_CODE: &str = "CHAOS_INJECTED";
+
+/// The `CHAOS_*` knobs; only present when `CHAOS_ENABLED=true`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChaosSettings {
+    /// Share of assignments that fail with `CHAOS_INJECTED` instead of running.
+    pub fail_rate: f64,
+    /// Share of assignments reported as timed out instead of running.
+    pub timeout_rate: f64,
+    /// Each assignment is delayed by up to this long before it runs.
+    pub extra_latency_ms: u64,
+    /// Share of results that are never published.
+    pub drop_result_rate: f64,
+    /// Makes a run's injected faults repeatable.
+    pub seed: Option<u64>,
+}
+
+/// Fault injection for exercising the controller's retry and DLQ handling.
+///
+/// Components hold an `Option<Arc<Chaos>>` that is `None` unless `CHAOS_ENABLED=true`, so a
+/// worker without it configured takes none of these paths.
+pub struct Chaos {
+    settings: ChaosSettings,
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    pub fn new(settings: ChaosSettings) -> Self {
+        let rng = match settings.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { settings, rng: Mutex::new(rng) }
+    }
+
+    pub fn from_settings(settings: Option<&ChaosSettings>) -> Option<Arc<Chaos>> {
+        settings.map(|settings| Arc::new(Chaos::new(settings.clone())))
+    }
+
+    fn roll(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.lock().unwrap_or_else(|e| e.into_inner()).gen::<f64>() < rate
+    }
+
+    fn latency(&self) -> Duration {
+        match self.settings.extra_latency_ms {
+            0 => Duration::ZERO,
+            max => Duration::from_millis(self.rng.lock().unwrap_or_else(|e| e.into_inner()).gen_range(0..=max)),
+        }
+    }
+
+    /// Delays the assignment, then possibly returns the outcome to report instead of running it.
+    pub async fn before_dispatch(&self, metrics: &Metrics) -> Option<HandlerOutcome> {
+        let delay = self.latency();
+        if !delay.is_zero() {
+            metrics.chaos_injected_total.with_label_values(&["latency"]).inc();
+            tokio::time::sleep(delay).await;
+        }
+        if self.roll(self.settings.fail_rate) {
+            metrics.chaos_injected_total.with_label_values(&["fail"]).inc();
+            return Some(HandlerOutcome::error(CHAOS_ERROR_CODE, "Failure injected by CHAOS_FAIL_RATE"));
+        }
+        if self.roll(self.settings.timeout_rate) {
+            metrics.chaos_injected_total.with_label_values(&["timeout"]).inc();
+            return Some(HandlerOutcome::failure(ExecStatus::Timeout, CHAOS_ERROR_CODE, "Timeout injected by CHAOS_TIMEOUT_RATE"));
+        }
+        None
+    }
+
+    /// Whether to skip publishing a result, as if it had been lost on the way.
+    pub fn drop_result(&self, metrics: &Metrics) -> bool {
+        let dropped = self.roll(self.settings.drop_result_rate);
+        if dropped {
+            metrics.chaos_injected_total.with_label_values(&["drop_result"]).inc();
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(seed: u64) -> ChaosSettings {
+        ChaosSettings { fail_rate: 0.3, timeout_rate: 0.2, extra_latency_ms: 0, drop_result_rate: 0.5, seed: Some(seed) }
+    }
+
+    async fn outcomes(chaos: &Chaos, metrics: &Metrics, n: usize) -> Vec<Option<String>> {
+        let mut seen = Vec::new();
+        for _ in 0..n {
+            seen.push(chaos.before_dispatch(metrics).await.map(|o| format!("{:?}", o.status)));
+        }
+        seen
+    }
+
+    #[tokio::test]
+    async fn test_seeded_runs_inject_the_same_faults() {
+        let metrics = Metrics::new();
+        let first = outcomes(&Chaos::new(settings(7)), &metrics, 200).await;
+        let second = outcomes(&Chaos::new(settings(7)), &metrics, 200).await;
+        assert_eq!(first, second);
+        assert_ne!(first, outcomes(&Chaos::new(settings(8)), &metrics, 200).await);
+
+        let failed = first.iter().filter(|o| o.as_deref() == Some("Error")).count();
+        let timed_out = first.iter().filter(|o| o.as_deref() == Some("Timeout")).count();
+        // 30% fail, then 20% of the rest time out
+        assert!((40..80).contains(&failed), "{}", failed);
+        assert!((15..50).contains(&timed_out), "{}", timed_out);
+        let counted = metrics.chaos_injected_total.with_label_values(&["fail"]).get();
+        assert!(counted >= failed as u64 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_injected_outcomes_are_labeled() {
+        let metrics = Metrics::new();
+        let always = ChaosSettings { fail_rate: 1.0, ..settings(1) };
+        let outcome = Chaos::new(always).before_dispatch(&metrics).await.unwrap();
+        assert_eq!(outcome.error.unwrap().code, CHAOS_ERROR_CODE);
+
+        let never = ChaosSettings { fail_rate: 0.0, timeout_rate: 0.0, drop_result_rate: 0.0, ..settings(1) };
+        let chaos = Chaos::new(never);
+        assert!(chaos.before_dispatch(&metrics).await.is_none());
+        assert!(!chaos.drop_result(&metrics));
+        assert_eq!(metrics.chaos_injected_total.with_label_values(&["fail"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_latency_is_bounded() {
+        let metrics = Metrics::new();
+        let chaos = Chaos::new(ChaosSettings { fail_rate: 0.0, timeout_rate: 0.0, extra_latency_ms: 20, drop_result_rate: 0.0, seed: Some(3) });
+        let delays: Vec<Duration> = (0..100).map(|_| chaos.latency()).collect();
+        assert!(delays.iter().all(|d| *d <= Duration::from_millis(20)));
+        assert!(delays.iter().any(|d| *d >= Duration::from_millis(10)));
+        assert!(chaos.before_dispatch(&metrics).await.is_none());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::config::Config;
+use crate::dlq::{self, DlqEntry};
+use crate::executor;
+use crate::loadgen::{self, LoadgenOptions};
+use crate::protocol::DeadLetter;
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use std::future::Future;
+use std::io::Write;
+use std::time::Duration;
+
+/// Runtime failure in `run`, or an unexpected I/O error in any subcommand.
+pub const EXIT_FAILURE: u8 = 1;
+// 2 is taken by clap for usage errors
+pub const EXIT_CONFIG_INVALID: u8 = 3;
+pub const EXIT_NATS_UNAVAILABLE: u8 = 4;
+pub const EXIT_DLQ_UNREADABLE: u8 = 5;
+/// Some entries are still unpublished after `replay-dlq`.
+pub const EXIT_REPLAY_INCOMPLETE: u8 = 6;
+/// `loadgen` sent assignments whose results never arrived.
+pub const EXIT_RESULTS_MISSING: u8 = 7;
+/// `replay` produced a result that differs from the recorded one.
+pub const EXIT_REPLAY_DIVERGED: u8 = 8;
+
+/// Configuration always comes from the environment (and `WORKER_CONFIG_FILE`); flags only
+/// select what to do with it.
+#[derive(Debug, Parser)]
+#[command(name = "worker", version = crate::build_info::VERSION, about = "Beamline CAF worker")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Connect to NATS and process assignments (the default)
+    Run,
+    /// Validate the configuration, print it redacted, and exit
+    CheckConfig,
+    /// Publish dead letters from a DLQ file to NATS without running the worker
+    ReplayDlq(ReplayArgs),
+    /// Publish generated assignments to a running worker and report throughput and latency
+    Loadgen(LoadgenArgs),
+    /// Run a recorded assignment through a local executor and compare the result with the recorded one
+    Replay(RecordingArgs),
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct RecordingArgs {
+    /// Recording written under RECORD_DIR
+    #[arg(long)]
+    pub file: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ReplayArgs {
+    /// DLQ file; rotated siblings are read too. Defaults to DLQ_PATH
+    #[arg(long)]
+    pub file: Option<String>,
+    /// Subject to publish to. Defaults to CAF_DLQ_SUBJECT
+    #[arg(long)]
+    pub subject: Option<String>,
+    /// Maximum entries published per second. Defaults to DLQ_RECOVERY_RATE_PER_SECOND
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=10_000))]
+    pub rate: Option<u32>,
+    /// Republish entries that already reached NATS, not just the pending ones
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct LoadgenArgs {
+    /// Assignments published per second
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=100_000))]
+    pub rate: u32,
+    /// Assignments to send. Defaults to 1000 unless --duration is given
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub count: Option<u64>,
+    /// Maximum assignments waiting for their result at once
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=100_000))]
+    pub concurrency: u32,
+    /// Job type to send; echo, sleep and jmespath have built-in payloads
+    #[arg(long, default_value = "echo")]
+    pub job_type: String,
+    /// JSON payload template; `{{seq}}` and `{{assignment_id}}` in its strings are filled per assignment
+    #[arg(long)]
+    pub payload_file: Option<String>,
+    /// Stop publishing after this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub duration: Option<u64>,
+}
+
+impl LoadgenArgs {
+    pub fn options(&self) -> Result<LoadgenOptions, String> {
+        let payload = match &self.payload_file {
+            Some(path) => {
+                let raw = std::fs::read_to_string(path).map_err(|e| format!("cannot read payload file {}: {}", path, e))?;
+                serde_json::from_str(&raw).map_err(|e| format!("payload file {} is not JSON: {}", path, e))?
+            }
+            None => loadgen::default_payload(&self.job_type)?,
+        };
+        Ok(LoadgenOptions {
+            rate: self.rate,
+            count: self.count,
+            duration: self.duration.map(Duration::from_secs),
+            concurrency: self.concurrency as usize,
+            job_type: self.job_type.clone(),
+            payload,
+            result_wait: loadgen::DEFAULT_RESULT_WAIT,
+        })
+    }
+}
+
+impl Cli {
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Run)
+    }
+}
+
+/// Writes the redacted config to `out`, or every validation error to `err`, and returns the exit code.
+///
+/// Job type lists are checked against the built-in types, the only ones the binary has.
+pub fn check_config(result: Result<Config, Vec<String>>, out: &mut impl Write, err: &mut impl Write) -> u8 {
+    let result = result.and_then(|config| match config.unknown_job_types(executor::JOB_TYPES) {
+        unknown if unknown.is_empty() => Ok(config),
+        unknown => Err(unknown),
+    });
+    match result {
+        Ok(config) => {
+            for warning in &config.load_warnings {
+                let _ = writeln!(err, "warning: {}", warning);
+            }
+            let rendered = serde_json::to_string_pretty(&config.redacted_json()).unwrap_or_default();
+            let _ = writeln!(out, "{}", rendered);
+            0
+        }
+        Err(errors) => {
+            let _ = writeln!(err, "invalid configuration ({} errors):", errors.len());
+            for e in &errors {
+                let _ = writeln!(err, "  - {}", e);
+            }
+            EXIT_CONFIG_INVALID
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplaySummary {
+    pub file: String,
+    pub total: usize,
+    pub published: usize,
+    /// Left unpublished because a publish failed; they stay pending for the next run.
+    pub remaining: usize,
+}
+
+impl ReplaySummary {
+    pub fn exit_code(&self) -> u8 {
+        if self.remaining == 0 { 0 } else { EXIT_REPLAY_INCOMPLETE }
+    }
+}
+
+/// Entries to replay, oldest first: only unpublished ones unless `all` is set.
+pub fn replay_candidates(file: &str, all: bool) -> Result<Vec<DlqEntry>, std::io::Error> {
+    if !all {
+        return dlq::pending_entries(file);
+    }
+    let mut entries = dlq::read_entries(file, usize::MAX, None)?;
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Publishes `entries` at most `rate` per second, marking each one published in `file`.
+///
+/// Like DLQ recovery it stops at the first failed publish; the rest are reported as remaining.
+pub async fn replay_entries<F, Fut>(file: &str, entries: Vec<DlqEntry>, rate: u32, mut publish: F) -> ReplaySummary
+where
+    F: FnMut(DeadLetter) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let pace = Duration::from_secs(1) / rate.max(1);
+    let total = entries.len();
+    let mut published = 0;
+    for entry in entries {
+        if published > 0 {
+            tokio::time::sleep(pace).await;
+        }
+        if !publish(entry.record).await {
+            break;
+        }
+        // A missing marker only means the entry may be republished next time
+        let _ = dlq::mark_published(file, &entry.id);
+        published += 1;
+    }
+    ReplaySummary { file: file.to_string(), total, published, remaining: total - published }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DeadLetterReason;
+    use serde_json::json;
+    use serial_test::serial;
+
+    #[test]
+    fn test_parse_subcommands() {
+        assert_eq!(Cli::try_parse_from(["worker"]).unwrap().command(), Command::Run);
+        assert_eq!(Cli::try_parse_from(["worker", "check-config"]).unwrap().command(), Command::CheckConfig);
+
+        let cli = Cli::try_parse_from(["worker", "replay-dlq", "--file", "/tmp/dlq.jsonl", "--subject", "caf.deadletter.v1", "--rate", "5"]).unwrap();
+        let Command::ReplayDlq(args) = cli.command() else { panic!("expected replay-dlq") };
+        assert_eq!(args.file.as_deref(), Some("/tmp/dlq.jsonl"));
+        assert_eq!(args.subject.as_deref(), Some("caf.deadletter.v1"));
+        assert_eq!(args.rate, Some(5));
+        assert!(!args.all);
+
+        assert!(Cli::try_parse_from(["worker", "replay-dlq", "--rate", "0"]).is_err());
+
+        let cli = Cli::try_parse_from(["worker", "loadgen", "--rate", "500", "--count", "20", "--job-type", "sleep"]).unwrap();
+        let Command::Loadgen(args) = cli.command() else { panic!("expected loadgen") };
+        let options = args.options().unwrap();
+        assert_eq!((options.rate, options.count, options.concurrency, options.duration), (500, Some(20), 64, None));
+        assert_eq!(options.payload, json!({"ms": 10}));
+        let cli = Cli::try_parse_from(["worker", "loadgen", "--job-type", "sql", "--duration", "5"]).unwrap();
+        let Command::Loadgen(args) = cli.command() else { panic!("expected loadgen") };
+        assert!(args.options().unwrap_err().contains("--payload-file"));
+        assert!(Cli::try_parse_from(["worker", "loadgen", "--concurrency", "0"]).is_err());
+
+        let cli = Cli::try_parse_from(["worker", "replay", "--file", "recordings/2026-10-14/a1.json"]).unwrap();
+        assert_eq!(cli.command(), Command::Replay(RecordingArgs { file: "recordings/2026-10-14/a1.json".to_string() }));
+        assert!(Cli::try_parse_from(["worker", "replay"]).is_err());
+        assert!(Cli::try_parse_from(["worker", "bogus"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_check_config_reports_every_error() {
+        std::env::set_var("WORKER_MAX_CONCURRENCY", "0");
+        std::env::set_var("CAF_ASSIGN_SUBJECT", "bad subject");
+        let result = Config::from_env_all_errors();
+        std::env::remove_var("WORKER_MAX_CONCURRENCY");
+        std::env::remove_var("CAF_ASSIGN_SUBJECT");
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        assert_eq!(check_config(result, &mut out, &mut err), EXIT_CONFIG_INVALID);
+        let err = String::from_utf8(err).unwrap();
+        assert!(err.contains("CAF_ASSIGN_SUBJECT invalid format"));
+        assert!(err.contains("WORKER_MAX_CONCURRENCY must be between 1 and 256"));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_check_config_rejects_unknown_job_types() {
+        std::env::set_var("WORKER_JOB_TYPE_ALLOWLIST", "echo,sqll");
+        let result = Config::from_env_all_errors();
+        std::env::remove_var("WORKER_JOB_TYPE_ALLOWLIST");
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        assert_eq!(check_config(result, &mut out, &mut err), EXIT_CONFIG_INVALID);
+        assert!(String::from_utf8(err).unwrap().contains("WORKER_JOB_TYPE_ALLOWLIST names unknown job type 'sqll'"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_check_config_prints_redacted_json() {
+        std::env::set_var("ADMIN_TOKEN", "s3cret");
+        let result = Config::from_env_all_errors();
+        std::env::remove_var("ADMIN_TOKEN");
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        assert_eq!(check_config(result, &mut out, &mut err), 0);
+        let printed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(printed["admin_token"], "***");
+        assert!(!String::from_utf8(out).unwrap().contains("s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_marks_published_and_stops_on_failure() {
+        let dir = std::env::temp_dir().join(format!("replay-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("dlq.jsonl").to_string_lossy().to_string();
+        let policy = crate::rotation::RotationPolicy { max_bytes: u64::MAX, max_rotations: 1, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+        for i in 0..3 {
+            dlq::write_deadletter_to_file(&DeadLetter::new(DeadLetterReason::ParseError, json!({"i": i})), &file, &policy).unwrap();
+        }
+
+        let entries = replay_candidates(&file, false).unwrap();
+        let mut calls = 0;
+        let summary = replay_entries(&file, entries, 10_000, |_| {
+            calls += 1;
+            let ok = calls <= 2;
+            async move { ok }
+        }).await;
+        assert_eq!((summary.total, summary.published, summary.remaining), (3, 2, 1));
+        assert_eq!(summary.exit_code(), EXIT_REPLAY_INCOMPLETE);
+
+        // Published entries are no longer candidates unless --all is given
+        let pending = replay_candidates(&file, false).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].record.payload_ref["i"], 2);
+        assert_eq!(replay_candidates(&file, true).unwrap().len(), 3);
+
+        let summary = replay_entries(&file, pending, 10_000, |_| async { true }).await;
+        assert_eq!(summary.exit_code(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
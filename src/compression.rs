@@ -0,0 +1,158 @@
+use async_nats::HeaderMap;
+use base64::{Engine as _, engine::general_purpose};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+pub const GZIP: &str = "gzip";
+/// Encoding tag used by the JSON wrapper when headers can't be carried.
+pub const GZIP_BASE64: &str = "gzip+base64";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Fallback form for transports without headers: `{"encoding":"gzip+base64","data":"..."}`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncodedPayload {
+    pub encoding: String,
+    pub data: String,
+}
+
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec cannot fail
+    let _ = encoder.write_all(bytes);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Inflates `bytes`, refusing to produce more than `max_inflated` bytes (zip-bomb guard).
+pub fn gunzip_bounded(bytes: &[u8], max_inflated: u64) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    GzDecoder::new(bytes)
+        .take(max_inflated.saturating_add(1))
+        .read_to_end(&mut out)
+        .map_err(|e| format!("gzip decode failed: {}", e))?;
+    if out.len() as u64 > max_inflated {
+        return Err(format!("inflated payload exceeds {} bytes", max_inflated));
+    }
+    Ok(out)
+}
+
+/// Gzips `payload` when it is larger than `threshold`, returning the header to publish with.
+///
+/// Borrows rather than consumes, so callers keep the plain bytes (e.g. to embed in a dead letter).
+pub fn encode_for_publish(payload: &[u8], threshold: usize) -> (Option<HeaderMap>, Cow<'_, [u8]>) {
+    if payload.len() <= threshold {
+        return (None, Cow::Borrowed(payload));
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_ENCODING_HEADER, GZIP);
+    (Some(headers), Cow::Owned(gzip(payload)))
+}
+
+/// Wraps gzipped bytes in the JSON fallback envelope.
+#[cfg(test)]
+pub fn wrap_base64(payload: &[u8]) -> Vec<u8> {
+    let wrapped = EncodedPayload {
+        encoding: GZIP_BASE64.to_string(),
+        data: general_purpose::STANDARD.encode(gzip(payload)),
+    };
+    serde_json::to_vec(&wrapped).unwrap_or_default()
+}
+
+/// Returns the plain payload of an incoming message.
+///
+/// Recognizes the `Content-Encoding: gzip` header, raw gzip bytes, and the
+/// `gzip+base64` JSON wrapper; anything else is passed through untouched.
+pub fn decode_incoming<'a>(headers: Option<&HeaderMap>, payload: &'a [u8], max_inflated: u64) -> Result<Cow<'a, [u8]>, String> {
+    let header_gzip = headers
+        .and_then(|h| h.get(CONTENT_ENCODING_HEADER))
+        .map(|v| v.as_str().eq_ignore_ascii_case(GZIP))
+        .unwrap_or(false);
+    if header_gzip || payload.starts_with(&GZIP_MAGIC) {
+        return gunzip_bounded(payload, max_inflated).map(Cow::Owned);
+    }
+    if payload.first() == Some(&b'{') {
+        if let Ok(wrapped) = serde_json::from_slice::<EncodedPayload>(payload) {
+            if wrapped.encoding == GZIP_BASE64 {
+                let raw = general_purpose::STANDARD
+                    .decode(wrapped.data.as_bytes())
+                    .map_err(|e| format!("base64 decode failed: {}", e))?;
+                return gunzip_bounded(&raw, max_inflated).map(Cow::Owned);
+            }
+        }
+    }
+    Ok(Cow::Borrowed(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn large_envelope() -> Vec<u8> {
+        let rows: Vec<_> = (0..8000)
+            .map(|i| json!({"id": i, "name": format!("customer-{}", i), "status": "active", "balance": i * 10}))
+            .collect();
+        serde_json::to_vec(&json!({"version": "v1", "kind": "exec_result", "data": {"output": rows}})).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_large_payload_fits_under_1mb() {
+        let payload = large_envelope();
+        assert!(payload.len() > 500_000);
+
+        let (headers, compressed) = encode_for_publish(&payload, 64 * 1024);
+        assert!(headers.is_some());
+        assert!(compressed.len() < 1024 * 1024);
+        assert!(compressed.len() < payload.len());
+
+        let decoded = decode_incoming(headers.as_ref(), &compressed, 16 * 1024 * 1024).unwrap();
+        assert_eq!(decoded.as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_small_payload_passes_through() {
+        let payload = br#"{"version":"v1"}"#.to_vec();
+        let (headers, out) = encode_for_publish(&payload, 1024);
+        assert!(headers.is_none());
+        assert!(matches!(out, Cow::Borrowed(_)));
+        assert_eq!(out.as_ref(), payload.as_slice());
+        let decoded = decode_incoming(None, &out, 1024).unwrap();
+        assert!(matches!(decoded, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_detects_gzip_without_header() {
+        let payload = large_envelope();
+        let compressed = gzip(&payload);
+        let decoded = decode_incoming(None, &compressed, 16 * 1024 * 1024).unwrap();
+        assert_eq!(decoded.as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_base64_wrapper() {
+        let payload = large_envelope();
+        let wrapped = wrap_base64(&payload);
+        let decoded = decode_incoming(None, &wrapped, 16 * 1024 * 1024).unwrap();
+        assert_eq!(decoded.as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_zip_bomb_rejected() {
+        let bomb = gzip(&vec![0u8; 10 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024);
+        let err = decode_incoming(None, &bomb, 1024 * 1024).unwrap_err();
+        assert!(err.contains("exceeds"));
+    }
+
+    #[test]
+    fn test_corrupt_gzip_is_error() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING_HEADER, GZIP);
+        assert!(decode_incoming(Some(&headers), b"not gzip", 1024).is_err());
+    }
+}
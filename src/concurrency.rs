@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+/// Job concurrency limit that can be changed while jobs are running.
+///
+/// A semaphore can gain permits but never lose them, so raising the limit adds permits and
+/// lowering it only moves a soft `limit` that is checked on every acquire: running jobs finish
+/// normally and new ones wait until the active count is back under the limit.
+pub struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    /// Permits the semaphore holds in total; only grows.
+    capacity: AtomicUsize,
+    limit: AtomicUsize,
+    active: AtomicUsize,
+    changed: Notify,
+}
+
+/// Held for the duration of one job.
+pub struct LimitPermit {
+    limit: Arc<ConcurrencyLimit>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        self.limit.active.fetch_sub(1, Ordering::SeqCst);
+        self.limit.changed.notify_waiters();
+    }
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            capacity: AtomicUsize::new(limit),
+            limit: AtomicUsize::new(limit),
+            active: AtomicUsize::new(0),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Permits ever handed out at once; at least `limit()`.
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::SeqCst)
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let capacity = self.capacity.load(Ordering::SeqCst);
+        if limit > capacity {
+            self.semaphore.add_permits(limit - capacity);
+            self.capacity.store(limit, Ordering::SeqCst);
+        }
+        self.limit.store(limit, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    fn admit(self: &Arc<Self>, permit: OwnedSemaphorePermit) -> Option<LimitPermit> {
+        if self.active.fetch_add(1, Ordering::SeqCst) < self.limit() {
+            return Some(LimitPermit { limit: self.clone(), _permit: permit });
+        }
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        None
+    }
+
+    pub fn try_acquire(self: &Arc<Self>) -> Option<LimitPermit> {
+        let permit = self.semaphore.clone().try_acquire_owned().ok()?;
+        self.admit(permit)
+    }
+
+    pub async fn acquire(self: &Arc<Self>) -> LimitPermit {
+        loop {
+            // Registered before checking, so a release between the check and the wait isn't missed
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.in_use() < self.limit() {
+                let permit = self.semaphore.clone().acquire_owned().await.expect("semaphore is never closed");
+                if let Some(permit) = self.admit(permit) {
+                    return permit;
+                }
+                continue;
+            }
+            changed.await;
+        }
+    }
+
+    /// Resolves once no job holds a permit.
+    #[cfg(test)]
+    pub async fn wait_idle(&self) {
+        let capacity = self.capacity.load(Ordering::SeqCst) as u32;
+        let _ = self.semaphore.acquire_many(capacity).await;
+    }
+}
+
+/// Idle per-key locks kept around before the least recently used are dropped.
+pub const KEY_LOCKS_MAX_IDLE: usize = 1024;
+
+/// One async mutex per `concurrency_key`, so assignments sharing a key run one at a time.
+///
+/// A lock no one holds or waits on is idle; once more than `max_idle` keys are known, idle
+/// ones are dropped least recently used first. Locks in use are never dropped, so the map
+/// can exceed the bound while that many keys are busy.
+pub struct KeyedLocks {
+    max_idle: usize,
+    locks: Mutex<HashMap<String, KeyLock>>,
+    uses: AtomicU64,
+}
+
+struct KeyLock {
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// Value of `KeyedLocks::uses` when last locked, for least-recently-used eviction.
+    last_used: u64,
+}
+
+impl KeyedLocks {
+    pub fn new(max_idle: usize) -> Self {
+        Self { max_idle, locks: Mutex::new(HashMap::new()), uses: AtomicU64::new(0) }
+    }
+
+    /// Waits until no other holder of `key` is left.
+    pub async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            let used = self.uses.fetch_add(1, Ordering::Relaxed);
+            let lock = match locks.get_mut(key) {
+                Some(entry) => {
+                    entry.last_used = used;
+                    entry.lock.clone()
+                }
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    locks.insert(key.to_string(), KeyLock { lock: lock.clone(), last_used: used });
+                    lock
+                }
+            };
+            if locks.len() > self.max_idle {
+                let mut idle: Vec<_> = locks.iter()
+                    .filter(|(_, entry)| Arc::strong_count(&entry.lock) == 1)
+                    .map(|(key, entry)| (entry.last_used, key.clone()))
+                    .collect();
+                idle.sort();
+                let excess = locks.len() - self.max_idle;
+                for (_, key) in idle.into_iter().take(excess) {
+                    locks.remove(&key);
+                }
+            }
+            lock
+        };
+        lock.lock_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_raising_the_limit_adds_permits() {
+        let limit = Arc::new(ConcurrencyLimit::new(1));
+        let first = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        limit.set_limit(2);
+        let second = limit.try_acquire().unwrap();
+        assert_eq!(limit.in_use(), 2);
+        drop((first, second));
+        assert_eq!(limit.in_use(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lowering_the_limit_holds_back_new_jobs() {
+        let limit = Arc::new(ConcurrencyLimit::new(3));
+        let a = limit.try_acquire().unwrap();
+        let b = limit.try_acquire().unwrap();
+        limit.set_limit(1);
+        // Free semaphore permits remain, but two jobs already exceed the new limit
+        assert!(limit.try_acquire().is_none());
+
+        let waiter = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.acquire().await }
+        });
+        drop(a);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished(), "one job still running at limit 1");
+        drop(b);
+        let c = tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+        assert_eq!(limit.in_use(), 1);
+        drop(c);
+
+        tokio::time::timeout(Duration::from_secs(1), limit.wait_idle()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keyed_locks_serialize_one_key_and_drop_idle_ones() {
+        let locks = Arc::new(KeyedLocks::new(2));
+        let held = locks.lock("db-1").await;
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { drop(locks.lock("db-1").await) }
+        });
+        let other = tokio::time::timeout(Duration::from_secs(1), locks.lock("db-2")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished(), "db-1 is still held");
+        drop(held);
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+
+        // db-1 is idle and least recently used, db-2 is held and must stay
+        drop(locks.lock("db-3").await);
+        drop(locks.lock("db-4").await);
+        let mut keys: Vec<_> = locks.locks.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["db-2", "db-4"]);
+        drop(other);
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Rates applied to a single execution; every field defaults to zero.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostRate {
+    pub base: f64,
+    pub per_second: f64,
+    pub per_output_byte: f64,
+}
+
+/// Per-job-type cost rates, with `default` covering types that aren't listed.
+///
+/// Accepted as JSON or TOML with the same shape:
+/// `{"default": {"base": 0.0001}, "http": {"base": 0.001, "per_second": 0.01}}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CostModel {
+    #[serde(default)]
+    pub default: CostRate,
+    #[serde(flatten)]
+    pub job_types: HashMap<String, CostRate>,
+}
+
+impl CostModel {
+    /// Parses `COST_MODEL`: inline JSON, or a path to a `.toml` / `.json` file.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        if raw.starts_with('{') {
+            return serde_json::from_str(raw).map_err(|e| format!("COST_MODEL is not valid JSON: {}", e));
+        }
+        let contents = std::fs::read_to_string(raw)
+            .map_err(|e| format!("COST_MODEL file {} could not be read: {}", raw, e))?;
+        if raw.ends_with(".toml") {
+            toml::from_str(&contents).map_err(|e| format!("COST_MODEL file {} is not valid TOML: {}", raw, e))
+        } else {
+            serde_json::from_str(&contents).map_err(|e| format!("COST_MODEL file {} is not valid JSON: {}", raw, e))
+        }
+    }
+
+    pub fn rate_for(&self, job_type: &str) -> &CostRate {
+        self.job_types.get(job_type).unwrap_or(&self.default)
+    }
+
+    /// `base + duration_s * per_second + output_bytes * per_output_byte`.
+    pub fn estimate(&self, job_type: &str, duration: Duration, output: Option<&Value>) -> f64 {
+        let rate = self.rate_for(job_type);
+        let mut cost = rate.base + duration.as_secs_f64() * rate.per_second;
+        // Only serialize the output when it actually contributes to the cost
+        if rate.per_output_byte != 0.0 {
+            let bytes = output.and_then(|v| serde_json::to_vec(v).ok()).map(|b| b.len()).unwrap_or(0);
+            cost += bytes as f64 * rate.per_output_byte;
+        }
+        cost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn model() -> CostModel {
+        CostModel::parse(r#"{"default": {"base": 0.5}, "http": {"base": 1.0, "per_second": 2.0, "per_output_byte": 0.01}}"#).unwrap()
+    }
+
+    #[test]
+    fn test_estimate_arithmetic() {
+        let output = json!("abcdefgh"); // 10 bytes serialized, quotes included
+        let cost = model().estimate("http", Duration::from_millis(1500), Some(&output));
+        assert!((cost - (1.0 + 1.5 * 2.0 + 10.0 * 0.01)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unlisted_type_uses_default() {
+        let cost = model().estimate("echo", Duration::from_secs(10), Some(&json!({"a": 1})));
+        assert_eq!(cost, 0.5);
+    }
+
+    #[test]
+    fn test_empty_model_is_free() {
+        let cost = CostModel::default().estimate("sql", Duration::from_secs(3), None);
+        assert_eq!(cost, 0.0);
+    }
+
+    #[test]
+    fn test_parse_toml_file() {
+        let path = std::env::temp_dir().join(format!("cost-model-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "[default]\nbase = 0.1\n\n[sql]\nper_second = 0.25\n").unwrap();
+        let parsed = CostModel::parse(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(parsed.default.base, 0.1);
+        assert_eq!(parsed.rate_for("sql").per_second, 0.25);
+        assert_eq!(parsed.rate_for("sql").base, 0.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(CostModel::parse("{not json").is_err());
+        assert!(CostModel::parse("/nonexistent/cost.toml").is_err());
+    }
+}
@@ -1,84 +1,808 @@
-use std::fs::{OpenOptions, rename, metadata, remove_file, read_dir, create_dir_all};
-use std::io::Write;
-use std::path::Path;
+use crate::observability::metrics::Metrics;
+use crate::observability::Logger;
 use crate::protocol::DeadLetter;
-use chrono::Utc;
+use crate::rotation::{append_line, enforce_shared_limits, is_rotation_of, rotated_files, usage, RotationPolicy, GZ_SUFFIX};
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::collections::BTreeSet;
+use std::fs::{metadata, read_dir, read_to_string, rename, File, OpenOptions};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+pub fn write_deadletter_to_file(dlq: &DeadLetter, path: &str, policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    let line = serde_json::to_string(dlq).unwrap_or_else(|_| "{}".to_string());
+    append_line(path, &line, policy)
+}
+
+/// Dead letters buffered between the processing loop and the file writer before new ones are dropped.
+pub const DLQ_WRITER_CAPACITY: usize = 1024;
+
+/// A full channel is reported at most this often, however many records are dropped.
+const DROP_WARN_INTERVAL_MS: u64 = 10_000;
+
+enum WriterMsg {
+    /// The file the line goes to, and the line.
+    Entry(String, String),
+    Published(String),
+}
+
+/// Appends dead letters to the rotating DLQ file from a dedicated writer thread.
+///
+/// Senders never touch the disk: a record is queued on a bounded channel, or dropped and
+/// counted in `dlq_dropped_total` when the writer has fallen behind.
+pub struct DlqWriter {
+    path: String,
+    /// Dead letters with a tenant go to that tenant's `tenant_path`. Each file family rotates on
+    /// its own, but the size and age limits cover all of them together.
+    partition_by_tenant: bool,
+    tx: Mutex<Option<SyncSender<WriterMsg>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    metrics: Arc<Metrics>,
+    logger: Logger,
+    last_drop_warn_ms: AtomicU64,
+}
 
-fn rotate_if_needed(path: &str, max_bytes: u64, max_rotations: u32, total_max_bytes: u64, max_age_days: Option<u32>) -> Result<(), std::io::Error> {
-    if let Ok(meta) = metadata(path) {
-        if meta.len() >= max_bytes {
-            let ts = Utc::now().format("%Y%m%d-%H%M%S").to_string();
-            let rotated = format!("{}.{}", path, ts);
-            rename(path, &rotated)?;
+impl DlqWriter {
+    pub fn new(path: String, policy: RotationPolicy, metrics: Arc<Metrics>, logger: Logger) -> Self {
+        Self::with_capacity(path, policy, DLQ_WRITER_CAPACITY, metrics, logger)
+    }
 
-            enforce_limits(path, max_rotations, total_max_bytes, max_age_days)?;
+    /// `dlq_file_bytes` and `dlq_rotations` are refreshed by the writer after every entry.
+    pub fn with_capacity(path: String, policy: RotationPolicy, capacity: usize, metrics: Arc<Metrics>, logger: Logger) -> Self {
+        let (tx, rx) = sync_channel::<WriterMsg>(capacity);
+        let writer_logger = logger.clone();
+        let writer_metrics = metrics.clone();
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            let path = writer_path;
+            let update_usage = || {
+                if let Ok((bytes, rotations)) = family_usage(&path) {
+                    writer_metrics.dlq_file_bytes.set(bytes as i64);
+                    writer_metrics.dlq_rotations.set(rotations as i64);
+                }
+            };
+            update_usage();
+            for msg in rx {
+                let is_entry = matches!(msg, WriterMsg::Entry(..));
+                let written = match msg {
+                    WriterMsg::Entry(file, line) => {
+                        // Same test `rotate_if_needed` makes, so this runs once per rotation of any family
+                        let rotates = metadata(&file).is_ok_and(|m| m.len() >= policy.max_bytes);
+                        append_line(&file, &line, &policy).and_then(|()| if rotates { enforce_family_limits(&path, &policy) } else { Ok(()) })
+                    }
+                    WriterMsg::Published(id) => mark_published(&path, &id),
+                };
+                if let Err(e) = written {
+                    writer_logger.error("DLQ file write failed", Some(&json!({"path": path, "error": e.to_string()})));
+                }
+                if is_entry {
+                    update_usage();
+                }
+            }
+        });
+        Self {
+            path,
+            partition_by_tenant: false,
+            tx: Mutex::new(Some(tx)),
+            writer: Mutex::new(Some(writer)),
+            metrics,
+            logger,
+            last_drop_warn_ms: AtomicU64::new(0),
         }
     }
-    Ok(())
+
+    pub fn with_partition_by_tenant(mut self, enabled: bool) -> Self {
+        self.partition_by_tenant = enabled;
+        self
+    }
+
+    /// Queues `dlq` for the file, returning its entry id, or `None` when it was dropped instead.
+    pub fn send(&self, dlq: &DeadLetter) -> Option<String> {
+        let line = serde_json::to_string(dlq).unwrap_or_else(|_| "{}".to_string());
+        let id = entry_id(&line);
+        let file = match dlq.tenant_id.as_deref() {
+            Some(tenant_id) if self.partition_by_tenant => tenant_path(&self.path, tenant_id),
+            _ => self.path.clone(),
+        };
+        let tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(tx) = tx.as_ref() else {
+            self.record_drop("DLQ writer already flushed");
+            return None;
+        };
+        match tx.try_send(WriterMsg::Entry(file, line)) {
+            Ok(()) => Some(id),
+            Err(TrySendError::Full(_)) => {
+                self.record_drop("DLQ writer queue full");
+                None
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.record_drop("DLQ writer stopped");
+                None
+            }
+        }
+    }
+
+    /// Records that entry `id` reached the DLQ subject so recovery skips it.
+    ///
+    /// A lost marker only means the entry is published again on the next recovery pass.
+    pub fn mark_published(&self, id: &str) {
+        let tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tx) = tx.as_ref() {
+            let _ = tx.try_send(WriterMsg::Published(id.to_string()));
+        }
+    }
+
+    fn record_drop(&self, msg: &str) {
+        self.metrics.dlq_dropped_total.inc();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let last = self.last_drop_warn_ms.load(Ordering::Relaxed);
+        let due = now.saturating_sub(last) >= DROP_WARN_INTERVAL_MS;
+        if due && self.last_drop_warn_ms.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.logger.warn(msg, Some(&json!({"dropped_total": self.metrics.dlq_dropped_total.get()})));
+        }
+    }
+
+    /// Closes the channel and waits until every queued record is on disk.
+    ///
+    /// Later sends are dropped, so call this once processing has drained.
+    pub fn flush(&self) {
+        self.tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(writer) = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Drop for DlqWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// A dead letter read back from disk, identified by a hash of its line.
+#[derive(Debug, Clone)]
+pub struct DlqEntry {
+    pub id: String,
+    pub replayed: bool,
+    /// Reached the DLQ subject, either when written or by startup recovery.
+    pub published: bool,
+    pub record: DeadLetter,
 }
 
-fn enforce_limits(path: &str, max_rotations: u32, total_max_bytes: u64, max_age_days: Option<u32>) -> Result<(), std::io::Error> {
+impl DlqEntry {
+    /// Listing view; the payload itself is summarized by size, never echoed.
+    pub fn summary(&self) -> Value {
+        json!({
+            "id": self.id,
+            "reason": self.record.reason,
+            "ts": self.record.ts,
+            "subject": self.record.subject,
+            "payload_ref": self.record.payload_ref,
+            "payload_bytes": self.record.original_payload().map(|p| p.len()),
+            "truncated": self.record.truncated,
+            "error_detail": self.record.error_detail,
+            "worker_id": self.record.worker_id,
+            "attempts": self.record.attempts,
+            "replayable": self.record.is_replayable() && !self.replayed,
+            "replayed": self.replayed,
+            "published": self.published,
+        })
+    }
+}
+
+/// Stable across rotations, since it only depends on the line's content.
+pub fn entry_id(line: &str) -> String {
+    let digest = Sha256::digest(line.as_bytes());
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `tenant_id` as one file name segment: `[A-Za-z0-9_-]` only, with a hash of the original
+/// appended whenever characters had to be replaced, so two tenants never share a file.
+pub fn tenant_file_id(tenant_id: &str) -> String {
+    let safe: String = tenant_id.chars().take(64).map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    if !safe.is_empty() && safe == tenant_id {
+        return safe;
+    }
+    format!("{}-{}", safe, &entry_id(tenant_id)[..8])
+}
+
+/// The DLQ file for `tenant_id`: `worker-dlq.jsonl` becomes `worker-dlq.<tenant>.jsonl`, a
+/// path without an extension gets `.<tenant>` appended.
+pub fn tenant_path(path: &str, tenant_id: &str) -> String {
+    let tenant = tenant_file_id(tenant_id);
     let base = Path::new(path);
-    let dir = base.parent().unwrap_or(Path::new("."));
-    let base_name = base.file_name().unwrap().to_string_lossy().to_string();
-
-    let mut rotated_files: Vec<(String, u64)> = Vec::new();
-    for e in read_dir(dir)?.flatten() {
-        if let Ok(md) = e.metadata() {
-            if let Some(name) = e.file_name().to_str() {
-                if name.starts_with(&format!("{}.", base_name)) {
-                    rotated_files.push((dir.join(name).to_string_lossy().to_string(), md.len()));
+    let name = base.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let file = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}.{}.{}", stem, tenant, ext),
+        _ => format!("{}.{}", name, tenant),
+    };
+    base.with_file_name(file).to_string_lossy().to_string()
+}
+
+/// `path` followed by every per-tenant file family next to it that has a live file or rotation.
+///
+/// Replay tombstones and published markers stay with `path` for all of them; they hold only ids.
+pub fn family_paths(path: &str) -> Result<Vec<String>, std::io::Error> {
+    let base = Path::new(path);
+    let dir = base.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = base.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name.as_str(),
+    };
+    let mut families = BTreeSet::new();
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![path.to_string()]),
+        Err(e) => return Err(e),
+    };
+    for file in entries.flatten().filter_map(|e| e.file_name().to_str().map(str::to_string)) {
+        let Some(tenant) = file.strip_prefix(stem).and_then(|rest| rest.strip_prefix('.')).and_then(|rest| rest.split('.').next()) else {
+            continue;
+        };
+        let family = tenant_path(path, tenant);
+        let in_family = Path::new(&family).file_name().is_some_and(|n| n.to_string_lossy() == file) || is_rotation_of(&family, &file);
+        if tenant_file_id(tenant) == tenant && in_family && !is_rotation_of(path, &family) {
+            families.insert(family);
+        }
+    }
+    let mut paths = vec![path.to_string()];
+    paths.extend(families);
+    Ok(paths)
+}
+
+/// Holds every family of `path` to one `DLQ_TOTAL_MAX_BYTES` and `DLQ_MAX_AGE_DAYS`, then drops
+/// the markers of whatever that removed.
+fn enforce_family_limits(path: &str, policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    enforce_shared_limits(&family_paths(path)?, policy)?;
+    compact_markers(path)
+}
+
+/// `usage` summed over every file family of `path`.
+fn family_usage(path: &str) -> Result<(u64, usize), std::io::Error> {
+    family_paths(path)?.iter().try_fold((0, 0), |(bytes, rotations), family| {
+        let (b, r) = usage(family)?;
+        Ok((bytes + b, rotations + r))
+    })
+}
+
+/// Replay tombstones live beside the DLQ under a name rotation cleanup doesn't match.
+pub fn tombstone_path(path: &str) -> String {
+    format!("{}-replayed", path)
+}
+
+/// Ids of entries already published to the DLQ subject, one JSON line each, plus a `settled`
+/// line for every rotation whose entries were all published.
+pub fn published_marker_path(path: &str) -> String {
+    format!("{}-published", path)
+}
+
+/// Up to `limit` entries, newest first across the live file and its rotations, and across
+/// per-tenant files by `ts`.
+pub fn read_entries(path: &str, limit: usize, reason: Option<&str>) -> Result<Vec<DlqEntry>, std::io::Error> {
+    let replayed = marked_ids(&tombstone_path(path));
+    let published = published_markers(path);
+    let families = family_paths(path)?;
+    let mut entries = Vec::new();
+    for family in &families {
+        entries.extend(read_family(family, limit, reason, &replayed, &published)?);
+    }
+    if families.len() > 1 {
+        entries.sort_by(|a, b| b.record.ts.cmp(&a.record.ts));
+        entries.truncate(limit);
+    }
+    Ok(entries)
+}
+
+fn read_family(path: &str, limit: usize, reason: Option<&str>, replayed: &HashSet<String>, published: &PublishedMarkers) -> Result<Vec<DlqEntry>, std::io::Error> {
+    let mut entries = Vec::new();
+    for file in files_newest_first(path)? {
+        let settled = published.settled(path, &file);
+        let Ok(contents) = read_dlq_file(&file) else {
+            continue;
+        };
+        for line in contents.lines().rev().filter(|l| !l.trim().is_empty()) {
+            // Lines this version can't parse are skipped rather than failing the listing
+            let Ok(record) = serde_json::from_str::<DeadLetter>(line) else {
+                continue;
+            };
+            if reason.is_some_and(|r| record.reason.as_str() != r) {
+                continue;
+            }
+            let id = entry_id(line);
+            entries.push(DlqEntry { replayed: replayed.contains(&id), published: settled || published.ids.contains_key(&id), id, record });
+            if entries.len() >= limit {
+                return Ok(entries);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+pub fn find_entries(path: &str, ids: &[String]) -> Result<Vec<DlqEntry>, std::io::Error> {
+    let wanted: HashSet<&str> = ids.iter().map(String::as_str).collect();
+    Ok(read_entries(path, usize::MAX, None)?
+        .into_iter()
+        .filter(|e| wanted.contains(e.id.as_str()))
+        .collect())
+}
+
+pub fn mark_replayed(path: &str, id: &str) -> Result<(), std::io::Error> {
+    let line = json!({"id": id, "replayed_at": chrono::Utc::now().to_rfc3339()}).to_string();
+    append_marker(&tombstone_path(path), &line)
+}
+
+pub fn mark_published(path: &str, id: &str) -> Result<(), std::io::Error> {
+    let line = json!({"id": id, "published_at": chrono::Utc::now().to_rfc3339()}).to_string();
+    append_marker(&published_marker_path(path), &line)
+}
+
+/// Marks every entry of `rotation`, a rotated DLQ file, as published at once.
+fn mark_settled(path: &str, rotation: &str) -> Result<(), std::io::Error> {
+    let line = json!({"settled": settled_name(rotation), "settled_at": chrono::Utc::now().to_rfc3339()}).to_string();
+    append_marker(&published_marker_path(path), &line)
+}
+
+/// Held while a marker file is appended to or rewritten, so compaction never loses a line
+/// appended by the health server or the CLI in the meantime.
+static MARKER_LOCK: Mutex<()> = Mutex::new(());
+
+fn append_marker(marker: &str, line: &str) -> Result<(), std::io::Error> {
+    let _guard = MARKER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut f = OpenOptions::new().create(true).append(true).open(marker)?;
+    f.write_all(format!("{}\n", line).as_bytes())
+}
+
+/// Rewrites both marker files to hold only what the files still on disk need: ids of entries
+/// that are still there, and one `settled` line per remaining rotation that was fully published.
+///
+/// The writer calls this after every rotation, so the markers shrink with the DLQ rather than
+/// keeping the id of every entry rotation cleanup has long since removed.
+pub fn compact_markers(path: &str) -> Result<(), std::io::Error> {
+    let _guard = MARKER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let published = published_markers(path);
+    let replayed_marker = tombstone_path(path);
+    let replayed = marked_lines(&replayed_marker);
+    let (mut keep_published, mut keep_replayed) = (Vec::new(), Vec::new());
+    for family in family_paths(path)? {
+        for file in files_newest_first(&family)? {
+            let rotation = file != family;
+            let settled = published.settled(&family, &file);
+            if settled {
+                keep_published.push(published.settled[&settled_name(&file)].clone());
+                if replayed.is_empty() {
+                    continue;
                 }
             }
+            let Ok(contents) = read_dlq_file(&file) else {
+                continue;
+            };
+            let ids: Vec<String> = contents.lines().filter(|l| !l.trim().is_empty()).map(entry_id).collect();
+            keep_replayed.extend(ids.iter().filter_map(|id| replayed.get(id).cloned()));
+            if settled {
+                continue;
+            }
+            if rotation && !ids.is_empty() && ids.iter().all(|id| published.ids.contains_key(id)) {
+                keep_published.push(json!({"settled": settled_name(&file), "settled_at": chrono::Utc::now().to_rfc3339()}).to_string());
+            } else {
+                keep_published.extend(ids.iter().filter_map(|id| published.ids.get(id).cloned()));
+            }
         }
     }
-    // Sort by filename ascending (timestamp ensures chronological order)
-    rotated_files.sort_by(|a, b| a.0.cmp(&b.0));
-    if let Some(days) = max_age_days {
-        let now = Utc::now();
-        rotated_files.retain(|(name, _)| {
-            if let Ok(md) = metadata(name) {
-                if let Ok(modified) = md.modified() {
-                    let file_dt: chrono::DateTime<chrono::Utc> = modified.into();
-                    let age = now.signed_duration_since(file_dt).num_days();
-                    if age > days as i64 {
-                        let _ = remove_file(name);
-                        return false;
-                    }
+    rewrite_marker(&published_marker_path(path), &keep_published)?;
+    rewrite_marker(&replayed_marker, &keep_replayed)
+}
+
+fn rewrite_marker(marker: &str, lines: &[String]) -> Result<(), std::io::Error> {
+    if lines.is_empty() && !Path::new(marker).exists() {
+        return Ok(());
+    }
+    let tmp = format!("{}.tmp", marker);
+    let contents: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+    std::fs::write(&tmp, contents)?;
+    rename(&tmp, marker)
+}
+
+/// Entries never published to the DLQ subject, oldest first.
+///
+/// Rotations already settled are not read at all; a rotation found with nothing left to
+/// publish is settled here, so each pass only reads what may still be pending.
+pub fn pending_entries(path: &str) -> Result<Vec<DlqEntry>, std::io::Error> {
+    let replayed = marked_ids(&tombstone_path(path));
+    let published = published_markers(path);
+    let families = family_paths(path)?;
+    let mut pending = Vec::new();
+    for family in &families {
+        for file in files_newest_first(family)?.into_iter().rev() {
+            let rotation = &file != family;
+            if published.settled(family, &file) {
+                continue;
+            }
+            let Ok(contents) = read_dlq_file(&file) else {
+                continue;
+            };
+            let before = pending.len();
+            for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+                let Ok(record) = serde_json::from_str::<DeadLetter>(line) else {
+                    continue;
+                };
+                let id = entry_id(line);
+                if !published.ids.contains_key(&id) {
+                    pending.push(DlqEntry { replayed: replayed.contains(&id), published: false, id, record });
                 }
             }
-            true
-        });
+            // Rotated files never change again, so one with nothing pending never needs reading
+            if rotation && pending.len() == before {
+                // Failing to settle only means this rotation is read again next pass
+                let _ = mark_settled(path, &file);
+            }
+        }
+    }
+    if families.len() > 1 {
+        pending.sort_by(|a, b| a.record.ts.cmp(&b.record.ts));
     }
-    while rotated_files.len() > max_rotations as usize {
-        if let Some((oldest, _)) = rotated_files.first().cloned() {
-            let _ = remove_file(&oldest);
-            rotated_files.remove(0);
-        } else {
+    Ok(pending)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryOutcome {
+    pub published: usize,
+    pub pending: usize,
+}
+
+/// Publishes up to `max` pending entries through `publish`, pausing `pace` between them.
+///
+/// Stops at the first failed publish, since NATS is most likely gone again; what is left
+/// stays pending for the next pass.
+pub async fn recover_pending<F, Fut>(path: &str, writer: &DlqWriter, max: usize, pace: Duration, mut publish: F) -> Result<RecoveryOutcome, std::io::Error>
+where
+    F: FnMut(DeadLetter) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let scan_path = path.to_string();
+    let pending = tokio::task::spawn_blocking(move || pending_entries(&scan_path))
+        .await
+        .map_err(std::io::Error::other)??;
+    let total = pending.len();
+    let mut published = 0;
+    for entry in pending.into_iter().take(max) {
+        if published > 0 && !pace.is_zero() {
+            tokio::time::sleep(pace).await;
+        }
+        if !publish(entry.record).await {
             break;
         }
+        writer.mark_published(&entry.id);
+        published += 1;
     }
-    let mut total: u64 = rotated_files.iter().map(|(_, sz)| *sz).sum();
-    while total > total_max_bytes {
-        if let Some((oldest, sz)) = rotated_files.first().cloned() {
-            let _ = remove_file(&oldest);
-            rotated_files.remove(0);
-            total = total.saturating_sub(sz);
-        } else {
-            break;
+    Ok(RecoveryOutcome { published, pending: total - published })
+}
+
+fn marked_ids(marker: &str) -> HashSet<String> {
+    marked_lines(marker).into_keys().collect()
+}
+
+/// Each marked id with the line that marked it.
+fn marked_lines(marker: &str) -> HashMap<String, String> {
+    read_to_string(marker)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| Some((serde_json::from_str::<Value>(l).ok()?["id"].as_str()?.to_string(), l.to_string())))
+        .collect()
+}
+
+/// The published marker, split into single ids and settled rotations, each with its line.
+struct PublishedMarkers {
+    ids: HashMap<String, String>,
+    settled: HashMap<String, String>,
+}
+
+impl PublishedMarkers {
+    /// Whether `file`, of family `family`, is a rotation already fully published.
+    fn settled(&self, family: &str, file: &str) -> bool {
+        file != family && self.settled.contains_key(&settled_name(file))
+    }
+}
+
+fn published_markers(path: &str) -> PublishedMarkers {
+    let contents = read_to_string(published_marker_path(path)).unwrap_or_default();
+    let mut markers = PublishedMarkers { ids: HashMap::new(), settled: HashMap::new() };
+    for line in contents.lines() {
+        let Ok(v) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if let Some(id) = v["id"].as_str() {
+            markers.ids.insert(id.to_string(), line.to_string());
+        } else if let Some(name) = v["settled"].as_str() {
+            markers.settled.insert(name.to_string(), line.to_string());
         }
     }
-    Ok(())
+    markers
+}
+
+/// A rotation's file name without `.gz`, so it names the same rotation before and after compression.
+fn settled_name(rotation: &str) -> String {
+    Path::new(rotation).file_name().map(|n| n.to_string_lossy().trim_end_matches(GZ_SUFFIX).to_string()).unwrap_or_default()
+}
+
+fn files_newest_first(path: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut files = vec![path.to_string()];
+    files.extend(rotated_files(path)?.into_iter().rev());
+    Ok(files)
 }
 
-pub fn write_deadletter_to_file(dlq: &DeadLetter, path: &str, max_bytes: u64, max_rotations: u32, total_max_bytes: u64, max_age_days: Option<u32>) -> Result<(), std::io::Error> {
-    if let Some(parent) = Path::new(path).parent() {
-        let _ = create_dir_all(parent);
+fn read_dlq_file(file: &str) -> Result<String, std::io::Error> {
+    if !file.ends_with(GZ_SUFFIX) {
+        return read_to_string(file);
+    }
+    let mut contents = String::new();
+    GzDecoder::new(File::open(file)?).read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DeadLetterReason;
+
+    fn unbounded() -> RotationPolicy {
+        RotationPolicy { max_bytes: u64::MAX, max_rotations: 5, total_max_bytes: u64::MAX, max_age_days: None, compress: false }
+    }
+
+    fn temp_path() -> (std::path::PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("dlq-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dlq.jsonl").to_string_lossy().to_string();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_read_entries_newest_first_with_filter() {
+        let (dir, path) = temp_path();
+        let policy = unbounded();
+        for (i, reason) in [DeadLetterReason::ParseError, DeadLetterReason::PublishError, DeadLetterReason::ParseError].into_iter().enumerate() {
+            let dlq = DeadLetter::new(reason, json!({"i": i}));
+            write_deadletter_to_file(&dlq, &path, &policy).unwrap();
+        }
+        // An older rotation is read after the live file
+        std::fs::write(format!("{}.20200101-000000", path), serde_json::to_string(&DeadLetter::new(DeadLetterReason::ParseError, json!({"i": "old"}))).unwrap() + "\n").unwrap();
+
+        let all = read_entries(&path, 10, None).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].record.payload_ref["i"], 2);
+        assert_eq!(all[3].record.payload_ref["i"], "old");
+
+        let parse = read_entries(&path, 2, Some("PARSE_ERROR")).unwrap();
+        assert_eq!(parse.len(), 2);
+        assert!(parse.iter().all(|e| e.record.reason == DeadLetterReason::ParseError));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_replay_marks_and_legacy_entries() {
+        let (dir, path) = temp_path();
+        let legacy = r#"{"reason":"DECODE_ERROR","payload_ref":{"len":3},"ts":"2025-01-01T00:00:00Z"}"#;
+        std::fs::write(&path, format!("{}\n", legacy)).unwrap();
+        let replayable = DeadLetter::new(DeadLetterReason::PublishError, json!({})).with_original("caf.exec.result.v1", b"{\"kind\":\"exec_result\"}", 1024);
+        write_deadletter_to_file(&replayable, &path, &unbounded()).unwrap();
+
+        let entries = read_entries(&path, 10, None).unwrap();
+        assert!(entries[0].record.is_replayable());
+        assert_eq!(entries[0].record.original_payload().unwrap(), b"{\"kind\":\"exec_result\"}");
+        assert!(!entries[1].record.is_replayable());
+        assert_eq!(entries[1].summary()["replayable"], false);
+
+        mark_replayed(&path, &entries[0].id).unwrap();
+        let found = find_entries(&path, std::slice::from_ref(&entries[0].id)).unwrap();
+        assert!(found[0].replayed);
+        assert_eq!(found[0].summary()["replayable"], false);
+        // The tombstone file isn't mistaken for a rotation
+        assert_eq!(read_entries(&path, 10, None).unwrap().len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn test_writer(path: &str, policy: RotationPolicy, capacity: usize) -> (DlqWriter, Arc<Metrics>) {
+        let metrics = Arc::new(Metrics::new());
+        let writer = DlqWriter::with_capacity(path.to_string(), policy, capacity, metrics.clone(), Logger::new("test".to_string()));
+        (writer, metrics)
+    }
+
+    #[test]
+    fn test_writer_keeps_order_and_flushes() {
+        let (dir, path) = temp_path();
+        let policy = unbounded();
+        let (writer, metrics) = test_writer(&path, policy, 256);
+        for i in 0..100 {
+            assert!(writer.send(&DeadLetter::new(DeadLetterReason::ParseError, json!({"i": i}))).is_some());
+        }
+        writer.flush();
+        assert_eq!(metrics.dlq_dropped_total.get(), 0);
+
+        let lines: Vec<DeadLetter> = read_to_string(&path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let order: Vec<_> = lines.iter().map(|d| d.payload_ref["i"].as_u64().unwrap()).collect();
+        assert_eq!(order, (0..100).collect::<Vec<_>>());
+
+        // Anything sent after the flush is counted rather than lost silently
+        assert!(writer.send(&DeadLetter::new(DeadLetterReason::ParseError, json!({}))).is_none());
+        assert_eq!(metrics.dlq_dropped_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_writer_rotates_compresses_and_reports_usage() {
+        let (dir, path) = temp_path();
+        let policy = RotationPolicy { max_bytes: 512, max_rotations: 100, total_max_bytes: u64::MAX, max_age_days: None, compress: true };
+        let (writer, metrics) = test_writer(&path, policy, 256);
+        for i in 0..50 {
+            writer.send(&DeadLetter::new(DeadLetterReason::ParseError, json!({"i": i, "pad": "x".repeat(40)})));
+        }
+        drop(writer);
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().flatten().collect();
+        let rotations: Vec<_> = files.iter().filter(|e| e.file_name().to_string_lossy().starts_with("dlq.jsonl.")).collect();
+        assert!(rotations.len() > 1);
+        assert!(rotations.iter().all(|e| e.file_name().to_string_lossy().ends_with(GZ_SUFFIX)));
+        assert_eq!(metrics.dlq_rotations.get() as usize, rotations.len());
+        let on_disk: u64 = files.iter().map(|e| e.metadata().unwrap().len()).sum();
+        assert_eq!(metrics.dlq_file_bytes.get() as u64, on_disk);
+
+        // Compressed rotations are still read back, newest first
+        let entries = read_entries(&path, 100, None).unwrap();
+        assert_eq!(entries.len(), 50);
+        assert_eq!(entries[0].record.payload_ref["i"], 49);
+        assert_eq!(entries[49].record.payload_ref["i"], 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_writer_drops_when_full() {
+        let (dir, path) = temp_path();
+        let policy = RotationPolicy { max_bytes: u64::MAX, max_rotations: 1, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+        // A zero-capacity channel only accepts a record while the writer is waiting on it
+        let (writer, metrics) = test_writer(&path, policy, 0);
+        let accepted = (0..1000).filter(|_| writer.send(&DeadLetter::new(DeadLetterReason::ParseError, json!({}))).is_some()).count();
+        writer.flush();
+        assert!(metrics.dlq_dropped_total.get() > 0);
+        assert_eq!(accepted as u64 + metrics.dlq_dropped_total.get(), 1000);
+        let written = read_to_string(&path).map(|c| c.lines().count()).unwrap_or(0);
+        assert_eq!(written, accepted);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_publishes_entries_written_offline() {
+        let (dir, path) = temp_path();
+        // First run: NATS is down, so entries only reach the file
+        let (offline, _) = test_writer(&path, unbounded(), 64);
+        for i in 0..3 {
+            offline.send(&DeadLetter::new(DeadLetterReason::ParseError, json!({"i": i})));
+        }
+        let online = DeadLetter::new(DeadLetterReason::ParseError, json!({"i": "online"}));
+        let id = offline.send(&online).unwrap();
+        offline.mark_published(&id);
+        drop(offline);
+        assert_eq!(pending_entries(&path).unwrap().len(), 3);
+
+        // Next run: recovery publishes oldest first and stops at the first failure
+        let (writer, _) = test_writer(&path, unbounded(), 64);
+        let mut sent = Vec::new();
+        let outcome = recover_pending(&path, &writer, 10, Duration::ZERO, |dlq| {
+            let ok = sent.len() < 2;
+            if ok {
+                sent.push(dlq.payload_ref["i"].clone());
+            }
+            async move { ok }
+        }).await.unwrap();
+        assert_eq!(outcome, RecoveryOutcome { published: 2, pending: 1 });
+        assert_eq!(sent, vec![json!(0), json!(1)]);
+        writer.flush();
+
+        let (writer, _) = test_writer(&path, unbounded(), 64);
+        let outcome = recover_pending(&path, &writer, 10, Duration::ZERO, |_| async { true }).await.unwrap();
+        assert_eq!(outcome, RecoveryOutcome { published: 1, pending: 0 });
+        writer.flush();
+        assert!(pending_entries(&path).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_tenant_dead_letters_get_their_own_rotating_files() {
+        let (dir, path) = temp_path();
+        let policy = RotationPolicy { max_bytes: 300, max_rotations: 2, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+        let (writer, metrics) = test_writer(&path, policy, 256);
+        let writer = writer.with_partition_by_tenant(true);
+        let letter = |tenant: Option<&str>, i: usize| DeadLetter::new(DeadLetterReason::PublishError, json!({"i": i, "pad": "x".repeat(100)})).with_tenant(tenant);
+        for i in 0..20 {
+            writer.send(&letter(Some("acme"), i)).unwrap();
+        }
+        writer.send(&letter(Some("globex/eu"), 0)).unwrap();
+        writer.send(&letter(None, 0)).unwrap();
+        writer.flush();
+
+        let acme = tenant_path(&path, "acme");
+        let globex = tenant_path(&path, "globex/eu");
+        assert_eq!(acme, dir.join("dlq.acme.jsonl").to_string_lossy());
+        assert!(globex.ends_with(&format!("dlq.globex_eu-{}.jsonl", &entry_id("globex/eu")[..8])), "{}", globex);
+        assert_eq!(family_paths(&path).unwrap(), vec![path.clone(), acme.clone(), globex.clone()]);
+        for (file, tenant) in [(&acme, Some("acme")), (&globex, Some("globex/eu")), (&path, None)] {
+            let lines = read_to_string(file).unwrap();
+            assert!(lines.lines().all(|l| serde_json::from_str::<DeadLetter>(l).unwrap().tenant_id.as_deref() == tenant), "{}", file);
+        }
+        // Only acme's family rotated, and its rotations are capped on their own
+        assert_eq!(rotated_files(&acme).unwrap().len(), 2);
+        assert!(rotated_files(&globex).unwrap().is_empty() && rotated_files(&path).unwrap().is_empty());
+        let (bytes, rotations) = family_usage(&path).unwrap();
+        assert_eq!((metrics.dlq_file_bytes.get() as u64, metrics.dlq_rotations.get()), (bytes, rotations as i64));
+
+        let entries = read_entries(&path, 100, None).unwrap();
+        assert!(entries.iter().any(|e| e.record.tenant_id.is_none()) && entries.iter().any(|e| e.record.tenant_id.as_deref() == Some("globex/eu")));
+        assert!(entries.windows(2).all(|w| w[0].record.ts >= w[1].record.ts));
+        assert_eq!(read_entries(&path, 3, None).unwrap().len(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_markers_follow_rotations_and_settled_rotations_are_skipped() {
+        let (dir, path) = temp_path();
+        let policy = RotationPolicy { max_bytes: 300, max_rotations: 2, total_max_bytes: u64::MAX, max_age_days: None, compress: true };
+        let (writer, _) = test_writer(&path, policy, 256);
+        let first = writer.send(&DeadLetter::new(DeadLetterReason::PublishError, json!({"i": "first"}))).unwrap();
+        writer.flush();
+        mark_replayed(&path, &first).unwrap();
+
+        let (writer, _) = test_writer(&path, policy, 256);
+        for i in 0..30 {
+            let id = writer.send(&DeadLetter::new(DeadLetterReason::PublishError, json!({"i": i, "pad": "x".repeat(100)}))).unwrap();
+            writer.mark_published(&id);
+        }
+        writer.flush();
+
+        // Only ids still on disk are kept, so the markers stay as small as the DLQ itself
+        let on_disk: HashSet<String> = read_entries(&path, usize::MAX, None).unwrap().into_iter().map(|e| e.id).collect();
+        assert!(on_disk.len() < 30 && !on_disk.contains(&first));
+        let published = published_markers(&path);
+        assert!(published.ids.keys().all(|id| on_disk.contains(id)), "{:?}", published.ids.keys());
+        assert!(!published.settled.is_empty());
+        assert!(marked_ids(&tombstone_path(&path)).is_empty());
+
+        assert!(pending_entries(&path).unwrap().is_empty());
+        let rotations = rotated_files(&path).unwrap();
+        assert_eq!(rotations.len(), 2);
+        let published = published_markers(&path);
+        assert!(rotations.iter().all(|r| published.settled(&path, r)));
+        // A settled rotation is not even opened again: an unreadable one goes unnoticed
+        std::fs::write(&rotations[0], "not gzip").unwrap();
+        assert!(pending_entries(&path).unwrap().is_empty());
+        assert!(read_entries(&path, usize::MAX, None).unwrap().iter().all(|e| e.published));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_total_limit_covers_every_tenant_family() {
+        let (dir, path) = temp_path();
+        let policy = RotationPolicy { max_bytes: 300, max_rotations: 100, total_max_bytes: 2000, max_age_days: None, compress: false };
+        let (writer, _) = test_writer(&path, policy, 256);
+        let writer = writer.with_partition_by_tenant(true);
+        for i in 0..60 {
+            let tenant = ["acme", "globex", "initech"][i % 3];
+            writer.send(&DeadLetter::new(DeadLetterReason::PublishError, json!({"i": i, "pad": "x".repeat(100)})).with_tenant(Some(tenant))).unwrap();
+        }
+        writer.flush();
+
+        let (bytes, rotations) = family_usage(&path).unwrap();
+        assert!(rotations > 0);
+        // Live files keep growing until they next rotate, which is when the total is checked
+        assert!(bytes <= 2000 + 3 * 300, "{} bytes on disk", bytes);
+        assert!(family_paths(&path).unwrap().iter().skip(1).all(|f| Path::new(f).exists()));
+        let _ = std::fs::remove_dir_all(&dir);
     }
-    rotate_if_needed(path, max_bytes, max_rotations, total_max_bytes, max_age_days)?;
-    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
-    let line = serde_json::to_string(dlq).unwrap_or_else(|_| "{}".to_string());
-    f.write_all(line.as_bytes())?;
-    f.write_all(b"\n")?;
-    Ok(())
 }
@@ -1,31 +1,206 @@
-#[derive(Debug, Clone)]
-pub enum WorkerError {
-    Transient(String),
-    Permanent(String),
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorClass {
+    Transient,
+    Permanent,
+}
+
+/// A failure classified by whether retrying the same operation can succeed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerError {
+    class: ErrorClass,
+    message: String,
+    code: Option<String>,
 }
 
 impl WorkerError {
     pub fn transient<S: Into<String>>(msg: S) -> Self {
-        Self::Transient(msg.into())
+        Self { class: ErrorClass::Transient, message: msg.into(), code: None }
     }
     pub fn permanent<S: Into<String>>(msg: S) -> Self {
-        Self::Permanent(msg.into())
+        Self { class: ErrorClass::Permanent, message: msg.into(), code: None }
+    }
+    /// Machine-readable cause, e.g. a SQLSTATE or `NATS_CLIENT_CLOSED`.
+    pub fn with_code<S: Into<String>>(mut self, code: S) -> Self {
+        self.code = Some(code.into());
+        self
     }
     pub fn is_transient(&self) -> bool {
-        matches!(self, WorkerError::Transient(_))
+        self.class == ErrorClass::Transient
     }
     pub fn message(&self) -> &str {
-        match self {
-            WorkerError::Transient(s) | WorkerError::Permanent(s) => s.as_str(),
+        &self.message
+    }
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({})", self.message, code),
+            None => f.write_str(&self.message),
         }
     }
 }
 
-pub fn classify_publish_error<E: std::fmt::Display>(e: &E) -> WorkerError {
-    let s = e.to_string();
-    if s.contains("connection") || s.contains("timeout") || s.contains("broken pipe") {
-        WorkerError::transient(s)
-    } else {
-        WorkerError::permanent(s)
+impl StdError for WorkerError {}
+
+/// Looks for an `io::Error` anywhere in the source chain and classifies it by kind.
+pub fn classify_io_chain(err: &(dyn StdError + 'static)) -> Option<WorkerError> {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            return Some(classify_io(io));
+        }
+        current = e.source();
+    }
+    None
+}
+
+pub fn classify_io(err: &std::io::Error) -> WorkerError {
+    let transient = matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::UnexpectedEof
+    );
+    let classified = if transient { WorkerError::transient(err.to_string()) } else { WorkerError::permanent(err.to_string()) };
+    classified.with_code(format!("IO_{:?}", err.kind()).to_uppercase())
+}
+
+/// async-nats only fails a publish once the client's command channel is closed, i.e. the
+/// client has shut down; while disconnected it buffers instead. Retrying on the same
+/// client cannot succeed, so anything without a transient io cause is permanent.
+pub fn classify_nats_publish(err: &async_nats::PublishError) -> WorkerError {
+    if let Some(classified) = err.source().and_then(classify_io_chain) {
+        return classified;
+    }
+    WorkerError::permanent(err.to_string()).with_code("NATS_CLIENT_CLOSED")
+}
+
+/// SQLSTATE classes worth retrying: connection exceptions, insufficient resources,
+/// operator intervention (restarts), plus serialization failures and deadlocks.
+fn sqlstate_is_transient(code: &str) -> bool {
+    matches!(code.get(..2), Some("08" | "53" | "57")) || matches!(code, "40001" | "40P01")
+}
+
+pub fn classify_sqlx(err: &sqlx::Error) -> WorkerError {
+    match err {
+        sqlx::Error::Io(io) => classify_io(io),
+        sqlx::Error::PoolTimedOut => WorkerError::transient(err.to_string()).with_code("DB_POOL_TIMEOUT"),
+        sqlx::Error::WorkerCrashed => WorkerError::transient(err.to_string()).with_code("DB_WORKER_CRASHED"),
+        sqlx::Error::Tls(_) => WorkerError::transient(err.to_string()).with_code("DB_TLS"),
+        sqlx::Error::PoolClosed => WorkerError::permanent(err.to_string()).with_code("DB_POOL_CLOSED"),
+        sqlx::Error::Database(db) => {
+            let code = db.code().map(|c| c.to_string());
+            let transient = code.as_deref().is_some_and(sqlstate_is_transient);
+            let classified = if transient { WorkerError::transient(db.message()) } else { WorkerError::permanent(db.message()) };
+            match code {
+                Some(code) => classified.with_code(code),
+                None => classified,
+            }
+        }
+        _ => WorkerError::permanent(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::error::{DatabaseError, ErrorKind as DbErrorKind};
+    use std::borrow::Cow;
+
+    #[derive(Debug)]
+    struct FakeDbError(&'static str);
+
+    impl fmt::Display for FakeDbError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "database error {}", self.0)
+        }
+    }
+
+    impl StdError for FakeDbError {}
+
+    impl DatabaseError for FakeDbError {
+        fn message(&self) -> &str {
+            "database error"
+        }
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+        fn kind(&self) -> DbErrorKind {
+            DbErrorKind::Other
+        }
+    }
+
+    fn db(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(FakeDbError(code)))
+    }
+
+    #[test]
+    fn test_classify_sqlx() {
+        assert!(classify_sqlx(&sqlx::Error::PoolTimedOut).is_transient());
+        assert!(classify_sqlx(&sqlx::Error::Io(std::io::Error::from(ErrorKind::ConnectionReset))).is_transient());
+        assert!(!classify_sqlx(&sqlx::Error::PoolClosed).is_transient());
+        assert!(!classify_sqlx(&sqlx::Error::RowNotFound).is_transient());
+
+        for code in ["08006", "40001", "40P01", "53300", "57P01"] {
+            let classified = classify_sqlx(&db(code));
+            assert!(classified.is_transient(), "{} should be transient", code);
+            assert_eq!(classified.code(), Some(code));
+        }
+        // Syntax errors and bad credentials never fix themselves
+        assert!(!classify_sqlx(&db("42601")).is_transient());
+        assert!(!classify_sqlx(&db("28P01")).is_transient());
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(std::io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "send failed")
+        }
+    }
+
+    impl StdError for Wrapped {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_classify_io_chain() {
+        let transient = classify_io_chain(&Wrapped(std::io::Error::from(ErrorKind::BrokenPipe))).unwrap();
+        assert!(transient.is_transient());
+        assert_eq!(transient.code(), Some("IO_BROKENPIPE"));
+        assert!(!classify_io_chain(&Wrapped(std::io::Error::from(ErrorKind::PermissionDenied))).unwrap().is_transient());
+        assert!(classify_io_chain(&WorkerError::permanent("no io here")).is_none());
+    }
+
+    #[test]
+    fn test_worker_error_is_std_error() {
+        let err: Box<dyn StdError> = Box::new(WorkerError::transient("pool timed out").with_code("DB_POOL_TIMEOUT"));
+        assert_eq!(err.to_string(), "pool timed out (DB_POOL_TIMEOUT)");
     }
 }
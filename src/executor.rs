@@ -1,72 +1,386 @@
-use crate::protocol::{ExecAssignment, ExecResult, ExecStatus};
-use crate::handlers;
-use sqlx::{Pool, Postgres};
+use crate::chaos::Chaos;
+use crate::config::{job_type_allowed, Config};
+use crate::cost::CostModel;
+use crate::retry::{Backoff, RetryPolicy};
+use crate::observability::{Logger, metrics::Metrics, truncate};
+use crate::params::{self, Params};
+use crate::redaction::ResultRedaction;
+use crate::protocol::{ExecAssignment, ExecResult, ExecStatus, Job};
+use crate::handlers::{self, ExecContext, HandlerOutcome, JobHandler};
+use arc_swap::ArcSwap;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Clone)]
+/// Job types dispatched by `Executor::execute`.
+pub const JOB_TYPES: &[&str] = &[
+    "echo",
+    "sleep",
+    "http",
+    "jmespath",
+    "javascript",
+    "sql",
+    "graphql",
+    "fs_blob_get",
+    "fs_blob_put",
+    "human_approval",
+    "cache",
+    "json_diff",
+    "datetime",
+    "generate",
+];
+
+/// Maps a job type onto a bounded label set so arbitrary types can't blow up metric cardinality.
+pub fn job_type_label(job_type: &str) -> &str {
+    if JOB_TYPES.contains(&job_type) {
+        job_type
+    } else {
+        "other"
+    }
+}
+
+/// Where an assignment's timeout came from, for logging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutSource {
+    Payload,
+    JobType,
+    Default,
+}
+
+impl TimeoutSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeoutSource::Payload => "payload",
+            TimeoutSource::JobType => "job_type",
+            TimeoutSource::Default => "default",
+        }
+    }
+}
+
+struct JobTimeouts {
+    default: Duration,
+    by_type: HashMap<String, Duration>,
+}
+
+#[derive(Clone)]
 pub struct Executor {
     worker_id: String,
     http_client: reqwest::Client,
-    db_pool_cache: Arc<Mutex<HashMap<String, Pool<Postgres>>>>,
+    db_pool_cache: Arc<handlers::sql::PoolCache>,
+    /// Backs the `cache` job type; shared by clones, never across workers.
+    kv_cache: Arc<handlers::cache::KvCache>,
+    sql_export_limits: handlers::sql::ExportLimits,
+    fs_cas_max_bytes: Option<u64>,
+    graphql_limits: handlers::http::GraphqlLimits,
+    js_contexts: Arc<handlers::script::JsContextPool>,
+    script_limits: handlers::script::ScriptLimits,
     fs_base_dir: String,
+    cost_model: Arc<CostModel>,
+    logger: Logger,
+    metrics: Arc<Metrics>,
+    /// Shared by clones so a config reload reaches every task's executor.
+    timeouts: Arc<ArcSwap<JobTimeouts>>,
+    http_retry: RetryPolicy,
+    sleep_limits: handlers::common::SleepLimits,
+    result_max_output_bytes: Option<usize>,
+    /// Job types registered by an embedding application, consulted before the built-in ones.
+    custom: Arc<HashMap<String, Arc<dyn JobHandler>>>,
+    /// `WORKER_JOB_TYPE_ALLOWLIST` / `WORKER_JOB_TYPE_DENYLIST`; at most one is non-empty.
+    job_type_allowlist: Arc<Vec<String>>,
+    job_type_denylist: Arc<Vec<String>>,
+    chaos: Option<Arc<Chaos>>,
+    /// `${params.*}` / `${secret.*}` substitution applied to payloads before dispatch.
+    params: Arc<Params>,
+    result_redaction: Arc<ResultRedaction>,
 }
 
 impl Executor {
     pub fn new(worker_id: String, fs_base_dir: String) -> Self {
         Self {
+            logger: Logger::new(worker_id.clone()),
+            metrics: Arc::new(Metrics::new()),
+            timeouts: Arc::new(ArcSwap::from_pointee(JobTimeouts { default: Duration::from_millis(60_000), by_type: HashMap::new() })),
             worker_id,
             http_client: reqwest::Client::new(),
-            db_pool_cache: Arc::new(Mutex::new(HashMap::new())),
+            db_pool_cache: Arc::new(handlers::sql::PoolCache::default()),
+            kv_cache: Arc::new(handlers::cache::KvCache::default()),
+            sql_export_limits: handlers::sql::ExportLimits::default(),
+            fs_cas_max_bytes: None,
+            graphql_limits: handlers::http::GraphqlLimits::default(),
+            js_contexts: Arc::new(handlers::script::JsContextPool::new(0)),
+            script_limits: handlers::script::ScriptLimits::default(),
             fs_base_dir,
+            cost_model: Arc::new(CostModel::default()),
+            http_retry: RetryPolicy::new(Backoff::new(Duration::from_millis(200), Duration::from_secs(5)), Some(3)),
+            sleep_limits: handlers::common::SleepLimits::default(),
+            result_max_output_bytes: None,
+            custom: Arc::new(HashMap::new()),
+            job_type_allowlist: Arc::new(Vec::new()),
+            job_type_denylist: Arc::new(Vec::new()),
+            chaos: None,
+            params: Arc::new(Params::default()),
+            result_redaction: Arc::new(ResultRedaction::default()),
         }
     }
+    pub fn with_cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = Arc::new(cost_model);
+        self
+    }
+    /// An executor set up the way `Config` describes, with the built-in handlers only.
+    pub fn from_config(config: &Config) -> Self {
+        Executor::new(config.worker_id.clone(), config.fs_base_dir.clone())
+            .with_cost_model(config.cost_model.clone())
+            .with_default_timeout(Duration::from_millis(config.default_job_timeout_ms))
+            .with_job_timeouts(&config.job_timeouts)
+            .with_http_retry(config.http_retry())
+            .with_js_context_pool(config.js_context_pool_size)
+            .with_script_limits(config.script_limits())
+            .with_sleep_limits(config.sleep_limits())
+            .with_result_max_output_bytes(config.result_max_output_bytes)
+            .with_job_type_lists(&config.job_type_allowlist, &config.job_type_denylist)
+            .with_chaos(Chaos::from_settings(config.chaos.as_ref()))
+            .with_params(Params::new(config.worker_params.clone(), config.params_strict))
+            .with_cache_limits(config.cache_max_entries, config.cache_max_value_bytes)
+            .with_sql_export_limits(config.sql_export_max_rows, config.sql_export_max_bytes)
+            .with_fs_cas_max_bytes(config.fs_cas_max_bytes)
+            .with_graphql_limits(config.graphql_max_query_bytes, config.graphql_max_variables_bytes)
+            .with_result_redaction(ResultRedaction::new(
+                config.result_redact_headers.clone(),
+                config.result_mask_pii,
+                config.result_redaction_optional,
+            ))
+    }
+
+    pub fn with_observability(mut self, logger: Logger, metrics: Arc<Metrics>) -> Self {
+        self.logger = logger;
+        self.metrics = metrics;
+        self
+    }
+    pub fn with_http_retry(mut self, policy: RetryPolicy) -> Self {
+        self.http_retry = policy;
+        self
+    }
+    pub fn with_sleep_limits(mut self, limits: handlers::common::SleepLimits) -> Self {
+        self.sleep_limits = limits;
+        self
+    }
+    /// Outputs encoding to more than `max` bytes are replaced with a `RESULT_TOO_LARGE` error.
+    pub fn with_result_max_output_bytes(mut self, max: Option<usize>) -> Self {
+        self.result_max_output_bytes = max;
+        self
+    }
+    /// Bounds of the `cache` job type's store, from `CACHE_MAX_ENTRIES` and `CACHE_MAX_VALUE_BYTES`.
+    pub fn with_cache_limits(mut self, max_entries: usize, max_value_bytes: usize) -> Self {
+        self.kv_cache = Arc::new(handlers::cache::KvCache::new(max_entries, max_value_bytes));
+        self
+    }
+    /// Bounds of sql exports to files, from `SQL_EXPORT_MAX_ROWS` and `SQL_EXPORT_MAX_BYTES`.
+    pub fn with_sql_export_limits(mut self, max_rows: u64, max_bytes: u64) -> Self {
+        self.sql_export_limits = handlers::sql::ExportLimits { max_rows, max_bytes };
+        self
+    }
+    /// Bounds of graphql documents and variables, from `GRAPHQL_MAX_QUERY_BYTES` and `GRAPHQL_MAX_VARIABLES_BYTES`.
+    pub fn with_graphql_limits(mut self, max_query_bytes: u64, max_variables_bytes: u64) -> Self {
+        self.graphql_limits = handlers::http::GraphqlLimits { max_query_bytes, max_variables_bytes };
+        self
+    }
+    /// Bytes of content-addressed blobs kept before the least recently used go, from `FS_CAS_MAX_BYTES`.
+    pub fn with_fs_cas_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.fs_cas_max_bytes = max_bytes;
+        self
+    }
+    pub fn with_js_context_pool(mut self, size: usize) -> Self {
+        self.js_contexts = Arc::new(handlers::script::JsContextPool::new(size));
+        self
+    }
+    /// Input, output, loop and recursion bounds of javascript and jmespath jobs.
+    pub fn with_script_limits(mut self, limits: handlers::script::ScriptLimits) -> Self {
+        self.script_limits = limits;
+        self
+    }
+    pub fn with_handler(mut self, job_type: impl Into<String>, handler: Arc<dyn JobHandler>) -> Self {
+        Arc::make_mut(&mut self.custom).insert(job_type.into(), handler);
+        self
+    }
+    pub fn with_job_type_lists(mut self, allowlist: &[String], denylist: &[String]) -> Self {
+        self.job_type_allowlist = Arc::new(allowlist.to_vec());
+        self.job_type_denylist = Arc::new(denylist.to_vec());
+        self
+    }
+    /// Faults injected before dispatch; `None` runs every job as asked.
+    pub fn with_chaos(mut self, chaos: Option<Arc<Chaos>>) -> Self {
+        self.chaos = chaos;
+        self
+    }
+    pub fn with_params(mut self, params: Params) -> Self {
+        self.params = Arc::new(params);
+        self
+    }
+    /// Header and PII scrubbing applied to outputs before they are returned.
+    pub fn with_result_redaction(mut self, redaction: ResultRedaction) -> Self {
+        self.result_redaction = Arc::new(redaction);
+        self
+    }
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        let by_type = self.timeouts.load().by_type.clone();
+        self.timeouts = Arc::new(ArcSwap::from_pointee(JobTimeouts { default: timeout, by_type }));
+        self
+    }
+    pub fn with_job_timeouts(mut self, timeouts_ms: &HashMap<String, u64>) -> Self {
+        let default = self.timeouts.load().default;
+        self.timeouts = Arc::new(ArcSwap::from_pointee(JobTimeouts { default, by_type: HashMap::new() }));
+        self.set_timeouts(default, timeouts_ms);
+        self
+    }
+    /// Replaces the timeouts for every clone of this executor; running jobs keep theirs.
+    pub fn set_timeouts(&self, default: Duration, timeouts_ms: &HashMap<String, u64>) {
+        let by_type = timeouts_ms.iter().map(|(t, ms)| (t.clone(), Duration::from_millis(*ms))).collect();
+        self.timeouts.store(Arc::new(JobTimeouts { default, by_type }));
+    }
     pub fn id(&self) -> &str {
         &self.worker_id
     }
+    pub fn fs_base_dir(&self) -> &str {
+        &self.fs_base_dir
+    }
+    /// Every job type this executor can dispatch, whether or not the lists let it run.
+    pub fn known_job_types(&self) -> Vec<String> {
+        let mut custom: Vec<String> = self.custom.keys().filter(|t| !JOB_TYPES.contains(&t.as_str())).cloned().collect();
+        custom.sort();
+        JOB_TYPES.iter().map(|t| t.to_string()).chain(custom).collect()
+    }
+
+    /// The job types it will actually run, as advertised in heartbeats.
+    pub fn capabilities(&self) -> Vec<String> {
+        self.known_job_types().into_iter().filter(|t| self.allows(t)).collect()
+    }
+
+    fn allows(&self, job_type: &str) -> bool {
+        job_type_allowed(&self.job_type_allowlist, &self.job_type_denylist, job_type)
+    }
+
+    /// Handlers that know their real cost take precedence over the model's estimate.
+    fn cost_for(&self, job_type: &str, duration: std::time::Duration, outcome: &HandlerOutcome) -> f64 {
+        outcome.cost.unwrap_or_else(|| self.cost_model.estimate(job_type, duration, outcome.output.as_ref()))
+    }
+
+    pub fn timeout_for(&self, job: &Job) -> Duration {
+        self.resolve_timeout(job).0
+    }
+
+    /// `payload.timeout_ms` when present, then the job type's default, then the global default.
+    pub fn resolve_timeout(&self, job: &Job) -> (Duration, TimeoutSource) {
+        if let Some(ms) = job.payload.get("timeout_ms").and_then(|v| v.as_u64()) {
+            return (Duration::from_millis(ms), TimeoutSource::Payload);
+        }
+        let timeouts = self.timeouts.load();
+        match timeouts.by_type.get(&job.r#type) {
+            Some(timeout) => (*timeout, TimeoutSource::JobType),
+            None => (timeouts.default, TimeoutSource::Default),
+        }
+    }
 
     pub async fn execute(&self, assignment: ExecAssignment) -> ExecResult {
+        self.execute_with_cancel(assignment, CancellationToken::new()).await
+    }
+
+    pub async fn execute_with_cancel(&self, mut assignment: ExecAssignment, cancel: CancellationToken) -> ExecResult {
         let start = std::time::Instant::now();
+        let started_at = chrono::Utc::now().to_rfc3339();
+        let deadline = tokio::time::Instant::now() + self.timeout_for(&assignment.job);
+        let substituted = self.params.substitute(&mut assignment.job.payload);
+        let secrets = substituted.clone().unwrap_or_default();
+        let ctx = ExecContext::for_assignment(
+            &assignment,
+            self.logger.with_secrets(&secrets),
+            self.metrics.clone(),
+            cancel,
+            deadline,
+        );
         
         // Execute the job logic
-        let (status, job_output, output, error_code, error_message) = match assignment.job.r#type.as_str() {
-            "echo" => handlers::common::handle_echo(&assignment.job).await,
-            "sleep" => handlers::common::handle_sleep(&assignment.job).await,
-            "http" => handlers::http::handle_http(&self.http_client, &assignment.job).await,
-            "jmespath" => handlers::script::handle_jmespath(&assignment.job).await,
-            "javascript" => handlers::script::handle_javascript(&assignment.job).await,
-            "sql" => handlers::sql::handle_sql(&self.db_pool_cache, &assignment.job).await,
-            "graphql" => handlers::http::handle_graphql(&self.http_client, &assignment.job).await,
-            "fs_blob_get" => handlers::fs::handle_fs_blob_get(&self.fs_base_dir, &assignment.job).await,
-            "fs_blob_put" => handlers::fs::handle_fs_blob_put(&self.fs_base_dir, &assignment.job).await,
-            "human_approval" => handlers::human::handle_human_approval(&assignment.job).await,
-            _ => (
-                ExecStatus::Error,
-                assignment.job.r#type.clone(),
-                None,
-                Some("UNKNOWN_JOB_TYPE".to_string()),
-                Some(format!("Unknown job type: {}", assignment.job.r#type)),
-            ),
+        let disabled = !self.allows(&assignment.job.r#type);
+        let injected = match &self.chaos {
+            Some(chaos) if !disabled && substituted.is_ok() => chaos.before_dispatch(&self.metrics).await,
+            _ => None,
+        };
+        let outcome = if disabled {
+            self.metrics.job_type_denied_total.with_label_values(&[job_type_label(&assignment.job.r#type)]).inc();
+            self.logger.warn("Job type disabled on this worker, not executed", Some(&serde_json::json!({
+                "audit": "job_type_denied",
+                "assignment_id": assignment.assignment_id,
+                "job_type": assignment.job.r#type,
+                "tenant_id": assignment.tenant_id,
+                "trace_id": assignment.trace_id
+            })));
+            HandlerOutcome::error("JOB_TYPE_DISABLED", format!("Job type {} is disabled on this worker", assignment.job.r#type))
+        } else if let Err(message) = substituted {
+            HandlerOutcome::error(params::UNKNOWN_PARAM, message)
+        } else if let Some(outcome) = injected {
+            outcome
+        } else if let Some(handler) = self.custom.get(&assignment.job.r#type) {
+            handler.handle(&ctx, &assignment.job).await
+        } else {
+            match assignment.job.r#type.as_str() {
+                "echo" => handlers::common::handle_echo(&ctx, &self.sleep_limits, self.result_max_output_bytes, &assignment.job).await,
+                "sleep" => handlers::common::handle_sleep(&ctx, &self.sleep_limits, &assignment.job).await,
+                "http" => handlers::http::handle_http(&ctx, &self.http_client, &self.http_retry, &assignment.job).await,
+                "jmespath" => handlers::script::handle_jmespath(&ctx, self.script_limits, &assignment.job).await,
+                "json_diff" => handlers::diff::handle_json_diff(&ctx, &assignment.job).await,
+                "datetime" => handlers::datetime::handle_datetime(&ctx, &assignment.job).await,
+                "generate" => handlers::generate::handle_generate(&ctx, &assignment.job).await,
+                "javascript" => handlers::script::handle_javascript(&ctx, &self.js_contexts, self.script_limits, &assignment.job).await,
+                "sql" => handlers::sql::handle_sql(&ctx, &self.db_pool_cache, &self.fs_base_dir, self.sql_export_limits, &assignment.job).await,
+                "graphql" => handlers::http::handle_graphql(&ctx, &self.http_client, &self.http_retry, &self.fs_base_dir, self.graphql_limits, &assignment.job).await,
+                "fs_blob_get" => handlers::fs::handle_fs_blob_get(&ctx, &self.fs_base_dir, &assignment.job).await,
+                "fs_blob_put" => handlers::fs::handle_fs_blob_put(&ctx, &self.fs_base_dir, self.fs_cas_max_bytes, &assignment.job).await,
+                "human_approval" => handlers::human::handle_human_approval(&ctx, &assignment.job).await,
+                "cache" => handlers::cache::handle_cache(&ctx, &self.kv_cache, &assignment.job).await,
+                _ => {
+                    let message = format!("Unknown job type: {}", assignment.job.r#type);
+                    return ExecResult::for_failure(&assignment, &self.worker_id, ExecStatus::Error, "UNKNOWN_JOB_TYPE", &message, start.elapsed());
+                }
+            }
         };
 
+        let mut outcome = match (self.result_max_output_bytes, &outcome.output) {
+            (Some(max), Some(output)) if !truncate::fits(output, max) => {
+                HandlerOutcome::error("RESULT_TOO_LARGE", format!("Output exceeds RESULT_MAX_OUTPUT_BYTES ({})", max))
+            }
+            _ => outcome,
+        };
+        if !secrets.is_empty() {
+            if let Some(output) = outcome.output.as_mut() {
+                params::redact(output, &secrets);
+            }
+            if let Some(err) = outcome.error.as_mut() {
+                params::redact_str(&mut err.message, &secrets);
+            }
+        }
+        if let Some(output) = outcome.output.as_mut() {
+            if !self.result_redaction.skips(&assignment.job.payload) {
+                self.result_redaction.apply(output);
+            }
+        }
         let duration = start.elapsed();
+        let cost = self.cost_for(&assignment.job.r#type, duration, &outcome);
+        let (error_code, error_message, retryable) = match outcome.error {
+            Some(err) => (Some(err.code), Some(err.message), err.retryable),
+            None => (None, None, false),
+        };
         
         ExecResult {
-            version: "1.0".to_string(),
-            assignment_id: assignment.assignment_id,
-            request_id: assignment.request_id,
-            status,
-            provider_id: self.worker_id.clone(),
-            job_type: job_output,
-            output,
+            output: outcome.output,
             latency_ms: duration.as_millis() as u64,
-            cost: 0.0,
-            trace_id: assignment.trace_id,
-            tenant_id: Some(assignment.tenant_id),
-            run_id: assignment.run_id,
+            cost,
             error_code,
             error_message,
+            retryable,
+            artifacts: outcome.artifacts,
+            started_at: Some(started_at),
+            finished_at: Some(chrono::Utc::now().to_rfc3339()),
+            ..ExecResult::from_assignment(&assignment, &self.worker_id, outcome.status)
         }
     }
 }
@@ -74,7 +388,6 @@ impl Executor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::Job;
     use serde_json::json;
 
     #[tokio::test]
@@ -93,6 +406,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -117,6 +433,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -154,6 +473,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -190,6 +512,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -218,6 +543,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -250,6 +578,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -294,6 +625,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let result = executor.execute(assignment).await;
@@ -333,6 +667,9 @@ mod tests {
              run_id: None,
              flow_id: None,
              step_id: None,
+             published_at: None,
+             concurrency_key: None,
+             idempotency_key: None,
          };
 
          let result = executor.execute(assignment).await;
@@ -346,4 +683,483 @@ mod tests {
              _ => panic!("Human Approval job failed: {:?}", result),
          }
     }
+
+    #[tokio::test]
+    async fn test_cost_model_applied() {
+        let model = CostModel::parse(r#"{"echo": {"base": 0.25}}"#).unwrap();
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_cost_model(model);
+        let assignment = ExecAssignment {
+            version: "1.0".to_string(),
+            assignment_id: "a1".to_string(),
+            request_id: "r1".to_string(),
+            tenant_id: "t1".to_string(),
+            job: Job {
+                r#type: "echo".to_string(),
+                payload: json!({}),
+            },
+            trace_id: None,
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
+        };
+
+        let result = executor.execute(assignment).await;
+        assert_eq!(result.cost, 0.25);
+    }
+
+    #[test]
+    fn test_handler_cost_overrides_model() {
+        let model = CostModel::parse(r#"{"default": {"base": 0.25}}"#).unwrap();
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_cost_model(model);
+        let duration = std::time::Duration::from_secs(1);
+
+        assert_eq!(executor.cost_for("sql", duration, &HandlerOutcome::success(json!({}))), 0.25);
+        assert_eq!(executor.cost_for("sql", duration, &HandlerOutcome::success(json!({})).with_cost(3.0)), 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_sleep_job_observes_cancellation() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string());
+        let assignment = ExecAssignment {
+            version: "1.0".to_string(),
+            assignment_id: "a1".to_string(),
+            request_id: "r1".to_string(),
+            tenant_id: "t1".to_string(),
+            job: Job {
+                r#type: "sleep".to_string(),
+                payload: json!({"ms": 10_000}),
+            },
+            trace_id: None,
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
+        };
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            canceller.cancel();
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(2), executor.execute_with_cancel(assignment, cancel))
+            .await
+            .expect("sleep should stop once cancelled");
+        assert_eq!(result.error_code, Some("CANCELLED".to_string()));
+    }
+
+    #[test]
+    fn test_timeout_for_prefers_payload() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_default_timeout(Duration::from_secs(7));
+        let job = Job { r#type: "sleep".to_string(), payload: json!({"timeout_ms": 250}) };
+        assert_eq!(executor.timeout_for(&job), Duration::from_millis(250));
+        let job = Job { r#type: "sleep".to_string(), payload: json!({}) };
+        assert_eq!(executor.timeout_for(&job), Duration::from_secs(7));
+    }
+
+    #[test]
+    fn test_timeout_precedence() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_default_timeout(Duration::from_secs(60))
+            .with_job_timeouts(&HashMap::from([("sql".to_string(), 600_000), ("javascript".to_string(), 5_000)]));
+        let job = |t: &str, payload| Job { r#type: t.to_string(), payload };
+        assert_eq!(executor.resolve_timeout(&job("sql", json!({}))), (Duration::from_secs(600), TimeoutSource::JobType));
+        assert_eq!(executor.resolve_timeout(&job("javascript", json!({"timeout_ms": 250}))), (Duration::from_millis(250), TimeoutSource::Payload));
+        assert_eq!(executor.resolve_timeout(&job("echo", json!({}))), (Duration::from_secs(60), TimeoutSource::Default));
+
+        // A reload through any clone reaches all of them
+        executor.clone().set_timeouts(Duration::from_secs(5), &HashMap::from([("echo".to_string(), 1_000)]));
+        assert_eq!(executor.resolve_timeout(&job("echo", json!({}))), (Duration::from_secs(1), TimeoutSource::JobType));
+        assert_eq!(executor.resolve_timeout(&job("sql", json!({}))), (Duration::from_secs(5), TimeoutSource::Default));
+    }
+
+    #[test]
+    fn test_job_type_label_bounds_cardinality() {
+        assert_eq!(job_type_label("http"), "http");
+        assert_eq!(job_type_label("quantum_compute"), "other");
+    }
+
+    struct Upper;
+
+    impl JobHandler for Upper {
+        fn handle<'a>(&'a self, _ctx: &'a ExecContext, job: &'a Job) -> futures::future::BoxFuture<'a, HandlerOutcome> {
+            Box::pin(async move { HandlerOutcome::success(json!(job.payload["text"].as_str().unwrap_or("").to_uppercase())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_handler_runs_and_is_advertised() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_handler("upper", Arc::new(Upper))
+            .with_handler("echo", Arc::new(Upper));
+        let caps = executor.capabilities();
+        assert_eq!(caps.last().map(String::as_str), Some("upper"));
+        assert_eq!(caps.iter().filter(|t| *t == "echo").count(), 1);
+
+        for job_type in ["upper", "echo"] {
+            let assignment: ExecAssignment = serde_json::from_value(json!({
+                "version": "1.0",
+                "assignment_id": "a1",
+                "request_id": "r1",
+                "tenant_id": "t1",
+                "job": {"type": job_type, "payload": {"text": "hi"}}
+            })).unwrap();
+            let result = executor.execute(assignment).await;
+            assert!(matches!(result.status, ExecStatus::Success));
+            assert_eq!(result.output, Some(json!("HI")), "{} goes to the registered handler", job_type);
+        }
+    }
+
+    struct Flaky;
+
+    impl JobHandler for Flaky {
+        fn handle<'a>(&'a self, _ctx: &'a ExecContext, job: &'a Job) -> futures::future::BoxFuture<'a, HandlerOutcome> {
+            Box::pin(async move {
+                match job.payload["fail"].as_bool() {
+                    Some(true) => HandlerOutcome::error("UPSTREAM_DOWN", "try later").retryable(),
+                    _ => HandlerOutcome::success(json!({})).with_artifact(json!({"blob": "sha256:abc"})),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retryability_and_artifacts_reach_the_result() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_handler("flaky", Arc::new(Flaky));
+        let run = |fail: bool| executor.execute(serde_json::from_value(json!({
+            "version": "1.0",
+            "assignment_id": "a1",
+            "request_id": "r1",
+            "tenant_id": "t1",
+            "job": {"type": "flaky", "payload": {"fail": fail}}
+        })).unwrap());
+
+        let failed = run(true).await;
+        assert_eq!((failed.error_code.as_deref(), failed.retryable), (Some("UPSTREAM_DOWN"), true));
+        let wire = serde_json::to_value(&failed).unwrap();
+        assert_eq!(wire["retryable"], true);
+        assert!(wire.get("artifacts").is_none());
+
+        let succeeded = run(false).await;
+        assert!(!succeeded.retryable);
+        assert_eq!(succeeded.artifacts, vec![json!({"blob": "sha256:abc"})]);
+        let wire = serde_json::to_value(&succeeded).unwrap();
+        assert!(wire.get("retryable").is_none());
+        assert_eq!(wire["artifacts"], json!([{"blob": "sha256:abc"}]));
+
+        // Built-in failures that retrying won't fix leave it out
+        let no_url: ExecAssignment = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a2", "request_id": "r2", "tenant_id": "t1",
+            "job": {"type": "http", "payload": {}}
+        })).unwrap();
+        let rejected = serde_json::to_value(executor.execute(no_url).await).unwrap();
+        assert_eq!((rejected["status"].as_str(), rejected.get("retryable")), (Some("error"), None));
+    }
+
+    #[tokio::test]
+    async fn test_http_body_is_truncated_to_max_body_bytes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let app = axum::Router::new().route("/", axum::routing::get(|| async { "y".repeat(50_000) }));
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string());
+        let url = format!("http://127.0.0.1:{}", port);
+        for (max, truncated) in [(Some(1024), true), (None, false)] {
+            let mut payload = json!({"url": url});
+            if let Some(max) = max {
+                payload["max_body_bytes"] = json!(max);
+            }
+            let assignment: ExecAssignment = serde_json::from_value(json!({
+                "version": "1.0",
+                "assignment_id": "a1",
+                "request_id": "r1",
+                "tenant_id": "t1",
+                "job": {"type": "http", "payload": payload}
+            })).unwrap();
+            let output = executor.execute(assignment).await.output.unwrap();
+            let body = output["body"].as_str().unwrap();
+            if truncated {
+                assert_eq!(output["truncated"], true);
+                assert!(body.len() <= 1024 && body.ends_with(" bytes>"));
+            } else {
+                assert!(output.get("truncated").is_none());
+                assert_eq!(body.len(), 50_000);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outputs_over_the_limit_fail() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_result_max_output_bytes(Some(1024));
+        let assignment = |payload: serde_json::Value| -> ExecAssignment {
+            serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": "echo", "payload": payload}
+            })).unwrap()
+        };
+        let result = executor.execute(assignment(json!({"text": "x".repeat(2000)}))).await;
+        assert_eq!(result.error_code.as_deref(), Some("RESULT_TOO_LARGE"));
+        assert!(result.output.is_none());
+        // The generated blob fits, but the blob plus the echoed fields does not
+        let result = executor.execute(assignment(json!({"output_bytes": 1000, "pad": "x".repeat(100)}))).await;
+        assert_eq!(result.error_code.as_deref(), Some("RESULT_TOO_LARGE"));
+        let result = executor.execute(assignment(json!({"output_bytes": 500, "echo_fields": []}))).await;
+        assert!(matches!(result.status, ExecStatus::Success));
+    }
+
+    #[tokio::test]
+    async fn test_script_limits_bound_javascript_and_jmespath_jobs() {
+        let limits = handlers::script::ScriptLimits { max_input_bytes: 64, max_output_bytes: 4096, ..Default::default() };
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_script_limits(limits);
+        let run = |job_type: &str, payload: serde_json::Value| {
+            let mut assignment = typed(job_type);
+            assignment.job.payload = payload;
+            executor.execute(assignment)
+        };
+        let result = run("javascript", json!({"code": "'x'.repeat(1 << 24)"})).await;
+        assert!(matches!(result.status, ExecStatus::Error));
+        assert_eq!(result.error_code.as_deref(), Some("OUTPUT_TOO_LARGE"));
+        let result = run("javascript", json!({"code": "s.length", "args": {"s": "x".repeat(100)}})).await;
+        assert_eq!(result.error_code.as_deref(), Some("INPUT_TOO_LARGE"));
+        let result = run("jmespath", json!({"expression": "a", "data": {"a": "x".repeat(100)}})).await;
+        assert_eq!(result.error_code.as_deref(), Some("INPUT_TOO_LARGE"));
+        let result = run("javascript", json!({"code": "'x'.repeat(100)", "limits": {"max_output_bytes": 50}})).await;
+        assert_eq!(result.error_code.as_deref(), Some("OUTPUT_TOO_LARGE"));
+        assert_eq!(run("javascript", json!({"code": "'x'.repeat(100)"})).await.output, Some(json!("x".repeat(100))));
+    }
+
+    fn typed(job_type: &str) -> ExecAssignment {
+        serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1", "trace_id": "tr1",
+            "job": {"type": job_type, "payload": {"ms": 1}}
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_denylisted_job_types_are_not_executed() {
+        let metrics = Arc::new(Metrics::new());
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_observability(Logger::new("worker-test".to_string()), metrics.clone())
+            .with_job_type_lists(&[], &["javascript".to_string(), "sql".to_string()]);
+        let result = executor.execute(typed("sql")).await;
+        assert!(matches!(result.status, ExecStatus::Error));
+        assert_eq!(result.error_code.as_deref(), Some("JOB_TYPE_DISABLED"));
+        assert_eq!(metrics.job_type_denied_total.with_label_values(&["sql"]).get(), 1);
+        assert!(matches!(executor.execute(typed("sleep")).await.status, ExecStatus::Success));
+
+        let capabilities = executor.capabilities();
+        assert!(capabilities.contains(&"echo".to_string()));
+        assert!(!capabilities.iter().any(|t| t == "javascript" || t == "sql"));
+        assert_eq!(executor.known_job_types().len(), JOB_TYPES.len());
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_limits_execution_and_capabilities() {
+        let metrics = Arc::new(Metrics::new());
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_observability(Logger::new("worker-test".to_string()), metrics.clone())
+            .with_job_type_lists(&["echo".to_string(), "sleep".to_string()], &[]);
+        assert_eq!(executor.capabilities(), vec!["echo", "sleep"]);
+        assert!(matches!(executor.execute(typed("sleep")).await.status, ExecStatus::Success));
+        let result = executor.execute(typed("http")).await;
+        assert_eq!(result.error_code.as_deref(), Some("JOB_TYPE_DISABLED"));
+        assert_eq!(metrics.job_type_denied_total.with_label_values(&["http"]).get(), 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_params_are_substituted_before_dispatch() {
+        std::env::set_var("EXECUTOR_TEST_TOKEN", "tok-987");
+        let values = HashMap::from([("region".to_string(), "eu-west-1".to_string())]);
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string()).with_params(Params::new(values, true));
+        let assignment = |payload: serde_json::Value| -> ExecAssignment {
+            serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": "echo", "payload": payload}
+            })).unwrap()
+        };
+        let result = executor.execute(assignment(json!({"region": "${params.region}", "auth": "Bearer ${secret.EXECUTOR_TEST_TOKEN}"}))).await;
+        std::env::remove_var("EXECUTOR_TEST_TOKEN");
+        assert!(matches!(result.status, ExecStatus::Success));
+        let output = serde_json::to_string(&result.output).unwrap();
+        assert!(output.contains("eu-west-1"), "{}", output);
+        assert!(output.contains("Bearer ***") && !output.contains("tok-987"), "{}", output);
+
+        let result = executor.execute(assignment(json!({"region": "${params.zone}"}))).await;
+        assert_eq!(result.error_code.as_deref(), Some("UNKNOWN_PARAM"));
+        assert_eq!(result.error_message.as_deref(), Some("Unknown parameter ${params.zone}"));
+    }
+
+    #[tokio::test]
+    async fn test_http_repeated_headers_methods_and_final_url() {
+        use axum::http::{header, HeaderMap};
+        use axum::response::{AppendHeaders, Redirect};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let echo = |method: axum::http::Method, headers: HeaderMap, body: String| async move {
+                let tags: Vec<_> = headers.get_all("x-tag").iter().map(|v| v.to_str().unwrap().to_string()).collect();
+                let cookies = AppendHeaders([(header::SET_COOKIE, "a=1"), (header::SET_COOKIE, "b=2")]);
+                (cookies, axum::Json(json!({"method": method.as_str(), "tags": tags, "body": body})))
+            };
+            let app = axum::Router::new()
+                .route("/echo", axum::routing::any(echo))
+                .route("/old", axum::routing::any(|| async { Redirect::temporary("/echo") }));
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        // Headers come back as received only with scrubbing turned off
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_result_redaction(ResultRedaction::new(Vec::new(), false, false));
+        let run = |payload: serde_json::Value| {
+            let assignment: ExecAssignment = serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": "http", "payload": payload}
+            })).unwrap();
+            executor.execute(assignment)
+        };
+        let result = run(json!({
+            "method": "get",
+            "url": format!("http://127.0.0.1:{}/old", port),
+            "headers": {"x-tag": ["one", "two"], "accept": "application/json"},
+            "body": "search terms"
+        })).await;
+        let output = result.output.unwrap();
+        assert_eq!(output["body"], json!({"method": "GET", "tags": ["one", "two"], "body": "search terms"}));
+        assert_eq!(output["headers"]["set-cookie"], json!(["a=1", "b=2"]));
+        assert!(output["headers"]["content-type"].is_string());
+        assert_eq!(output["final_url"], format!("http://127.0.0.1:{}/echo", port));
+
+        let output = run(json!({"method": "PURGE", "url": format!("http://127.0.0.1:{}/echo", port)})).await.output.unwrap();
+        assert_eq!(output["body"]["method"], "PURGE");
+
+        for headers in [json!({"x-tag": 5}), json!({"x-tag": ["ok", 1]}), json!({"bad name": "x"}), json!(["x-tag"])] {
+            let result = run(json!({"url": format!("http://127.0.0.1:{}/echo", port), "headers": headers})).await;
+            assert_eq!(result.error_code.as_deref(), Some("INVALID_HEADER"), "{}", headers);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outputs_are_scrubbed_before_returning() {
+        let assignment = |payload: serde_json::Value| -> ExecAssignment {
+            serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": "echo", "payload": payload}
+            })).unwrap()
+        };
+        let payload = json!({"headers": {"Set-Cookie": "session=abc", "accept": "*/*"}, "contact": "a@b.com", "redact": false});
+        let output = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .execute(assignment(payload.clone())).await.output.unwrap();
+        assert_eq!(output["headers"], json!({"Set-Cookie": "***", "accept": "*/*"}));
+        assert_eq!(output["contact"], "a@b.com");
+
+        let masking = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_result_redaction(ResultRedaction::new(vec!["set-cookie".to_string()], true, false));
+        let output = masking.execute(assignment(payload.clone())).await.output.unwrap();
+        assert_eq!(output["contact"], "***@***.***");
+
+        // `"redact": false` only counts where the worker allows it
+        let optional = Executor::new("worker-test".to_string(), "/tmp".to_string())
+            .with_result_redaction(ResultRedaction::new(vec!["set-cookie".to_string()], true, true));
+        let output = optional.execute(assignment(payload)).await.output.unwrap();
+        assert_eq!(output["headers"]["Set-Cookie"], "session=abc");
+        assert_eq!(output["contact"], "a@b.com");
+    }
+
+    #[tokio::test]
+    async fn test_http_pagination_by_cursor_link_header_and_page_cap() {
+        use axum::extract::Query;
+        use std::collections::HashMap;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            // Three pages of two items, then the cursor runs out
+            let cursor = axum::routing::get(|Query(q): Query<HashMap<String, String>>| async move {
+                let page: u64 = q.get("after").map(|c| c.trim_start_matches('p').parse().unwrap()).unwrap_or(0);
+                let next = if page < 2 { json!(format!("p{}", page + 1)) } else { serde_json::Value::Null };
+                axum::Json(json!({"data": [page * 2, page * 2 + 1], "meta": {"next": next}}))
+            });
+            let linked = axum::routing::get(|Query(q): Query<HashMap<String, String>>| async move {
+                let page: u64 = q.get("page").map(|p| p.parse().unwrap()).unwrap_or(1);
+                let link = if page < 3 { format!("</linked?page={}>; rel=\"next\"", page + 1) } else { String::new() };
+                ([(reqwest::header::LINK.as_str(), link)], axum::Json(json!([format!("item-{}", page)])))
+            });
+            // Never ends
+            let endless = axum::routing::get(|Query(q): Query<HashMap<String, String>>| async move {
+                let offset: u64 = q.get("offset").map(|o| o.parse().unwrap()).unwrap_or(0);
+                axum::Json(json!({"rows": [offset, offset + 1, offset + 2]}))
+            });
+            let app = axum::Router::new().route("/cursor", cursor).route("/linked", linked).route("/endless", endless);
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string());
+        let run = |path: &str, paginate: serde_json::Value| {
+            let assignment: ExecAssignment = serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": "http", "payload": {"url": format!("http://127.0.0.1:{}{}", port, path), "paginate": paginate}}
+            })).unwrap();
+            executor.execute(assignment)
+        };
+
+        let cursor = run("/cursor", json!({"mode": "cursor", "cursor_path": "meta.next", "cursor_param": "after", "items_path": "data"})).await;
+        assert_eq!(cursor.output, Some(json!({"items": [0, 1, 2, 3, 4, 5], "pages_fetched": 3, "truncated": false})));
+
+        let linked = run("/linked", json!({"mode": "link_header"})).await;
+        assert_eq!(linked.output, Some(json!({"items": ["item-1", "item-2", "item-3"], "pages_fetched": 3, "truncated": false})));
+
+        let capped = run("/endless", json!({"mode": "offset", "items_path": "rows", "max_pages": 4})).await;
+        assert_eq!(capped.output, Some(json!({"items": (0..12).collect::<Vec<u64>>(), "pages_fetched": 4, "truncated": true})));
+        let by_items = run("/endless", json!({"mode": "offset", "items_path": "rows", "max_items": 5})).await;
+        assert_eq!(by_items.output, Some(json!({"items": [0, 1, 2, 3, 4], "pages_fetched": 2, "truncated": true})));
+
+        let invalid = run("/cursor", json!({"mode": "cursor"})).await;
+        assert_eq!(invalid.error_code.as_deref(), Some("INVALID_PAGINATE"));
+    }
+
+    #[tokio::test]
+    async fn test_failure_results_match_executed_ones_field_by_field() {
+        let executor = Executor::new("worker-test".to_string(), "/tmp".to_string());
+        let assignment = |job_type: &str, payload: serde_json::Value| -> ExecAssignment {
+            serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+                "job": {"type": job_type, "payload": payload},
+                "trace_id": "0af7651916cd43dd8448eb211c80319c", "run_id": "run-1", "flow_id": "flow-1", "step_id": "step-1"
+            })).unwrap()
+        };
+        // Everything but what the outcome and clock decide
+        let fields = |result: &ExecResult| {
+            let mut value = serde_json::to_value(result).unwrap();
+            let map = value.as_object_mut().unwrap();
+            for timing in ["latency_ms", "started_at", "finished_at"] {
+                assert!(map.remove(timing).is_some(), "{} is missing", timing);
+            }
+            value
+        };
+
+        let forced = assignment("echo", json!({"force_status": "error", "error_code": "BOOM"}));
+        let executed = executor.execute(forced.clone()).await;
+        let message = executed.error_message.clone().unwrap();
+        let mut built = ExecResult::for_failure(&forced, executor.id(), ExecStatus::Error, "BOOM", &message, Duration::from_millis(3));
+        built.output = executed.output.clone();
+        assert_eq!(fields(&executed), fields(&built));
+        assert_eq!(built.latency_ms, 3);
+        let started = chrono::DateTime::parse_from_rfc3339(built.started_at.as_deref().unwrap()).unwrap();
+        let finished = chrono::DateTime::parse_from_rfc3339(built.finished_at.as_deref().unwrap()).unwrap();
+        assert_eq!((finished - started).num_milliseconds(), 3);
+
+        let unknown = assignment("quantum_compute", json!({}));
+        let built = ExecResult::for_failure(&unknown, executor.id(), ExecStatus::Error, "UNKNOWN_JOB_TYPE", "Unknown job type: quantum_compute", Duration::ZERO);
+        assert_eq!(fields(&executor.execute(unknown).await), fields(&built));
+    }
 }
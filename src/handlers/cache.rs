@@ -0,0 +1,177 @@
+use crate::observability::metrics::Metrics;
+use crate::protocol::Job;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use super::{ExecContext, HandlerOutcome};
+
+/// Longest key a `cache` job may use.
+const MAX_KEY_LEN: usize = 256;
+
+/// `(tenant_id, key)`: tenants never see each other's entries.
+type Key = (String, String);
+
+struct Entry {
+    value: Value,
+    stored: Instant,
+    ttl: Duration,
+}
+
+/// The store behind the `cache` job type: at most `max_entries` values of at most
+/// `max_value_bytes` encoded, least recently used evicted first, each gone after its own TTL.
+///
+/// Lives in one worker's memory only, so flows behind several workers each warm their own copy
+/// and nothing survives a restart; it saves repeated fetches, it is not shared state.
+pub struct KvCache {
+    max_entries: usize,
+    max_value_bytes: usize,
+    inner: Mutex<(HashMap<Key, Entry>, VecDeque<Key>)>,
+}
+
+impl Default for KvCache {
+    fn default() -> Self {
+        Self::new(10_000, 64 * 1024)
+    }
+}
+
+impl KvCache {
+    pub fn new(max_entries: usize, max_value_bytes: usize) -> Self {
+        Self { max_entries, max_value_bytes, inner: Mutex::new((HashMap::new(), VecDeque::new())) }
+    }
+
+    /// The value and its age in seconds, marking it recently used.
+    pub fn get(&self, tenant_id: &str, key: &str, metrics: &Metrics) -> Option<(Value, f64)> {
+        let key = (tenant_id.to_string(), key.to_string());
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        let entry = entries.get(&key)?;
+        let age = entry.stored.elapsed();
+        let expired = age > entry.ttl;
+        order.retain(|k| *k != key);
+        if expired {
+            entries.remove(&key);
+            metrics.cache_entries.set(entries.len() as i64);
+            return None;
+        }
+        let value = entry.value.clone();
+        order.push_back(key);
+        Some((value, age.as_secs_f64()))
+    }
+
+    /// Stores `value`, returning how many entries made room for it; the error is for values
+    /// over the size cap.
+    pub fn put(&self, tenant_id: &str, key: &str, value: Value, ttl: Duration, metrics: &Metrics) -> Result<usize, String> {
+        let size = serde_json::to_vec(&value).map(|v| v.len()).unwrap_or(usize::MAX);
+        if size > self.max_value_bytes {
+            return Err(format!("Value of {} bytes exceeds CACHE_MAX_VALUE_BYTES ({})", size, self.max_value_bytes));
+        }
+        if self.max_entries == 0 {
+            return Ok(0);
+        }
+        let key = (tenant_id.to_string(), key.to_string());
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        if entries.insert(key.clone(), Entry { value, stored: Instant::now(), ttl }).is_some() {
+            order.retain(|k| *k != key);
+        }
+        order.push_back(key);
+        let mut evicted = 0;
+        while order.len() > self.max_entries {
+            if let Some(old) = order.pop_front() {
+                entries.remove(&old);
+                evicted += 1;
+            }
+        }
+        metrics.cache_entries.set(entries.len() as i64);
+        Ok(evicted)
+    }
+
+    pub fn delete(&self, tenant_id: &str, key: &str, metrics: &Metrics) -> bool {
+        let key = (tenant_id.to_string(), key.to_string());
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        let removed = entries.remove(&key).is_some();
+        if removed {
+            order.retain(|k| *k != key);
+            metrics.cache_entries.set(entries.len() as i64);
+        }
+        removed
+    }
+}
+
+/// `{"op": "get" | "put" | "delete", "key": ...}`; `put` also takes `value` and `ttl_s`
+/// (seconds, fractions allowed). Entries belong to the assignment's tenant.
+pub async fn handle_cache(ctx: &ExecContext, cache: &KvCache, job: &Job) -> HandlerOutcome {
+    let payload = &job.payload;
+    let key = match payload.get("key").and_then(|v| v.as_str()) {
+        Some(key) if !key.is_empty() && key.chars().count() <= MAX_KEY_LEN => key,
+        Some(_) => return HandlerOutcome::error("INVALID_KEY", format!("'key' must be 1 to {} characters", MAX_KEY_LEN)),
+        None => return HandlerOutcome::error("MISSING_KEY", "Missing 'key' in payload"),
+    };
+    match payload.get("op").and_then(|v| v.as_str()) {
+        Some("get") => match cache.get(&ctx.tenant_id, key, &ctx.metrics) {
+            Some((value, age_s)) => HandlerOutcome::success(json!({"found": true, "value": value, "age_s": age_s})),
+            None => HandlerOutcome::success(json!({"found": false, "value": null, "age_s": null})),
+        },
+        Some("put") => {
+            let Some(value) = payload.get("value") else {
+                return HandlerOutcome::error("MISSING_VALUE", "Missing 'value' in payload");
+            };
+            let ttl = match payload.get("ttl_s").and_then(|v| v.as_f64()) {
+                Some(ttl_s) if ttl_s > 0.0 && ttl_s.is_finite() => Duration::from_secs_f64(ttl_s),
+                _ => return HandlerOutcome::error("INVALID_TTL", "'ttl_s' must be a positive number of seconds"),
+            };
+            match cache.put(&ctx.tenant_id, key, value.clone(), ttl, &ctx.metrics) {
+                Ok(evicted) => HandlerOutcome::success(json!({"stored": true, "evicted": evicted})),
+                Err(message) => HandlerOutcome::error("VALUE_TOO_LARGE", message),
+            }
+        }
+        Some("delete") => HandlerOutcome::success(json!({"deleted": cache.delete(&ctx.tenant_id, key, &ctx.metrics)})),
+        _ => HandlerOutcome::error("INVALID_OP", "'op' must be one of get, put, delete"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_expire_after_their_ttl() {
+        let metrics = Metrics::new();
+        let cache = KvCache::new(8, 1024);
+        cache.put("t1", "rates", json!({"EUR": 1.1}), Duration::from_millis(20), &metrics).unwrap();
+        cache.put("t1", "flags", json!(true), Duration::from_secs(60), &metrics).unwrap();
+        assert_eq!(cache.get("t1", "rates", &metrics).unwrap().0, json!({"EUR": 1.1}));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(cache.get("t1", "rates", &metrics).is_none());
+        assert!(cache.get("t1", "flags", &metrics).is_some());
+        assert_eq!(metrics.cache_entries.get(), 1);
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let metrics = Metrics::new();
+        let cache = KvCache::new(8, 1024);
+        cache.put("t1", "k", json!("one"), Duration::from_secs(60), &metrics).unwrap();
+        assert!(cache.get("t2", "k", &metrics).is_none());
+        assert!(!cache.delete("t2", "k", &metrics));
+        assert_eq!(cache.get("t1", "k", &metrics).unwrap().0, "one");
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted_at_the_cap() {
+        let metrics = Metrics::new();
+        let cache = KvCache::new(2, 16);
+        let ttl = Duration::from_secs(60);
+        cache.put("t1", "a", json!(1), ttl, &metrics).unwrap();
+        cache.put("t1", "b", json!(2), ttl, &metrics).unwrap();
+        cache.get("t1", "a", &metrics);
+        assert_eq!(cache.put("t1", "c", json!(3), ttl, &metrics).unwrap(), 1);
+        assert!(cache.get("t1", "b", &metrics).is_none(), "a was used more recently");
+        assert!(cache.get("t1", "a", &metrics).is_some());
+        assert_eq!(metrics.cache_entries.get(), 2);
+
+        assert!(cache.put("t1", "big", json!("x".repeat(32)), ttl, &metrics).unwrap_err().contains("CACHE_MAX_VALUE_BYTES"));
+    }
+}
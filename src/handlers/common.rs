@@ -1,14 +1,232 @@
 use crate::protocol::{ExecStatus, Job};
+use serde_json::{json, Map, Value};
 use std::time::Duration;
-use tokio::time::sleep;
-use super::HandlerResult;
+use tokio::time::{sleep, Instant};
+use super::{ExecContext, HandlerOutcome};
 
-pub async fn handle_echo(job: &Job) -> HandlerResult {
-    (ExecStatus::Success, job.r#type.clone(), Some(job.payload.clone()), None, None)
+/// Longest slice slept between cancellation and progress checks.
+const SLEEP_SLICE: Duration = Duration::from_millis(500);
+
+/// Bounds for the sleep job type, from `SLEEP_MAX_MS` and `SLEEP_PROGRESS_INTERVAL_MS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SleepLimits {
+    pub max: Duration,
+    /// Log progress this often during a sleep; `None` stays quiet.
+    pub progress_interval: Option<Duration>,
+}
+
+impl Default for SleepLimits {
+    fn default() -> Self {
+        Self { max: Duration::from_millis(300_000), progress_interval: None }
+    }
+}
+
+/// Largest blob `output_bytes` may ask for when `RESULT_MAX_OUTPUT_BYTES` is unset.
+const ECHO_MAX_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+
+fn invalid_echo(message: &str) -> HandlerOutcome {
+    HandlerOutcome::error("INVALID_ECHO_PAYLOAD", message)
 }
 
-pub async fn handle_sleep(job: &Job) -> HandlerResult {
+/// Echoes the payload back. Optional fields shape the response for pipeline tests: `delay_ms`
+/// waits first (bounded like a sleep job), `force_status` (`error`, `timeout` or `cancelled`, with
+/// `error_code`) fails instead, `echo_fields` keeps only the listed keys, and `output_bytes` adds a
+/// deterministic `blob` of that size.
+pub async fn handle_echo(ctx: &ExecContext, limits: &SleepLimits, max_output_bytes: Option<usize>, job: &Job) -> HandlerOutcome {
+    let payload = &job.payload;
+    if let Some(delay) = payload.get("delay_ms") {
+        let Some(ms) = delay.as_u64() else { return invalid_echo("delay_ms must be a number") };
+        if Duration::from_millis(ms) > limits.max {
+            return HandlerOutcome::error("SLEEP_TOO_LONG", format!("Delay of {}ms exceeds the {}ms maximum", ms, limits.max.as_millis()));
+        }
+        tokio::select! {
+            _ = sleep(Duration::from_millis(ms)) => {}
+            _ = ctx.cancel.cancelled() => return HandlerOutcome::failure(ExecStatus::Cancelled, "CANCELLED", "Echo cancelled"),
+        }
+    }
+    if let Some(forced) = payload.get("force_status") {
+        let status = match forced.as_str() {
+            Some("error") => ExecStatus::Error,
+            Some("timeout") => ExecStatus::Timeout,
+            Some("cancelled") => ExecStatus::Cancelled,
+            _ => return invalid_echo("force_status must be one of error, timeout, cancelled"),
+        };
+        let code = payload.get("error_code").and_then(|v| v.as_str()).unwrap_or("ECHO_FORCED");
+        return HandlerOutcome::failure(status, code, "Status forced by the echo payload");
+    }
+    let mut output = match payload.get("echo_fields") {
+        None => payload.clone(),
+        Some(Value::Array(fields)) => {
+            let mut kept = Map::new();
+            for field in fields {
+                let Some(name) = field.as_str() else { return invalid_echo("echo_fields must be a list of strings") };
+                if let Some(value) = payload.get(name) {
+                    kept.insert(name.to_string(), value.clone());
+                }
+            }
+            Value::Object(kept)
+        }
+        Some(_) => return invalid_echo("echo_fields must be a list of strings"),
+    };
+    if let Some(size) = payload.get("output_bytes") {
+        let Some(size) = size.as_u64() else { return invalid_echo("output_bytes must be a number") };
+        let max = max_output_bytes.unwrap_or(ECHO_MAX_OUTPUT_BYTES);
+        if size > max as u64 {
+            return HandlerOutcome::error("RESULT_TOO_LARGE", format!("output_bytes {} exceeds the {} byte limit", size, max));
+        }
+        let blob: String = (0..size).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        match &mut output {
+            Value::Object(map) => {
+                map.insert("blob".to_string(), Value::String(blob));
+            }
+            _ => output = json!({"blob": blob}),
+        }
+    }
+    HandlerOutcome::success(output)
+}
+
+/// Sleeps `payload.ms` (default 100) in slices, returning `Cancelled` as soon as the context is.
+pub async fn handle_sleep(ctx: &ExecContext, limits: &SleepLimits, job: &Job) -> HandlerOutcome {
     let ms = job.payload.get("ms").and_then(|v| v.as_u64()).unwrap_or(100);
-    sleep(Duration::from_millis(ms)).await;
-    (ExecStatus::Success, job.r#type.clone(), None, None, None)
+    let requested = Duration::from_millis(ms);
+    if requested > limits.max {
+        return HandlerOutcome::error("SLEEP_TOO_LONG", format!("Sleep of {}ms exceeds the {}ms maximum", ms, limits.max.as_millis()));
+    }
+    let started = Instant::now();
+    let mut next_progress = limits.progress_interval.map(|every| started + every);
+    loop {
+        let slept = started.elapsed();
+        if slept >= requested {
+            return HandlerOutcome::success(json!({"slept_ms": slept.as_millis() as u64}));
+        }
+        if let (Some(at), Some(every)) = (next_progress, limits.progress_interval) {
+            if Instant::now() >= at {
+                ctx.info("Sleep progress", Some(json!({
+                    "slept_ms": slept.as_millis() as u64,
+                    "remaining_ms": (requested - slept).as_millis() as u64
+                })));
+                next_progress = Some(at + every);
+            }
+        }
+        let slice = (requested - slept).min(SLEEP_SLICE);
+        tokio::select! {
+            _ = sleep(slice) => {}
+            _ = ctx.cancel.cancelled() => {
+                let mut outcome = HandlerOutcome::failure(ExecStatus::Cancelled, "CANCELLED", "Sleep cancelled");
+                outcome.output = Some(json!({"slept_ms": started.elapsed().as_millis() as u64}));
+                return outcome;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::{metrics::Metrics, Logger};
+    use crate::protocol::ExecAssignment;
+    use std::sync::Arc;
+    use tokio_util::sync::CancellationToken;
+
+    fn context(cancel: CancellationToken) -> ExecContext {
+        let assignment: ExecAssignment = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+            "job": {"type": "sleep", "payload": {}}
+        })).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        ExecContext::for_assignment(&assignment, Logger::new("w1".to_string()), Arc::new(Metrics::new()), cancel, deadline)
+    }
+
+    fn sleep_job(ms: u64) -> Job {
+        Job { r#type: "sleep".to_string(), payload: json!({"ms": ms}) }
+    }
+
+    #[tokio::test]
+    async fn test_sleep_over_the_cap_is_rejected() {
+        let limits = SleepLimits { max: Duration::from_millis(1000), ..SleepLimits::default() };
+        let started = Instant::now();
+        let outcome = handle_sleep(&context(CancellationToken::new()), &limits, &sleep_job(1001)).await;
+        assert_eq!(outcome.error.unwrap().code, "SLEEP_TOO_LONG");
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    // Paused time advances only through the sleeps themselves, so these are exact rather than timed
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_mid_sleep_returns_promptly() {
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(700)).await;
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let outcome = handle_sleep(&context(cancel), &SleepLimits::default(), &sleep_job(10_000)).await;
+        assert_eq!(started.elapsed(), Duration::from_millis(700));
+        assert!(matches!(outcome.status, ExecStatus::Cancelled));
+        assert_eq!(outcome.error.unwrap().code, "CANCELLED");
+        assert_eq!(outcome.output.unwrap()["slept_ms"], 700);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sleep_reports_the_time_slept() {
+        let limits = SleepLimits { progress_interval: Some(Duration::from_millis(500)), ..SleepLimits::default() };
+        let started = Instant::now();
+        let outcome = handle_sleep(&context(CancellationToken::new()), &limits, &sleep_job(1200)).await;
+        assert_eq!(started.elapsed(), Duration::from_millis(1200));
+        assert!(matches!(outcome.status, ExecStatus::Success));
+        assert_eq!(outcome.output.unwrap()["slept_ms"], 1200);
+    }
+
+    fn echo_job(payload: Value) -> Job {
+        Job { r#type: "echo".to_string(), payload }
+    }
+
+    async fn echo(payload: Value) -> HandlerOutcome {
+        handle_echo(&context(CancellationToken::new()), &SleepLimits::default(), Some(4096), &echo_job(payload)).await
+    }
+
+    #[tokio::test]
+    async fn test_echo_without_extras_is_verbatim() {
+        let payload = json!({"hello": "world", "n": [1, 2]});
+        assert_eq!(echo(payload.clone()).await.output, Some(payload));
+    }
+
+    #[tokio::test]
+    async fn test_echo_forced_status_and_projection() {
+        let outcome = echo(json!({"force_status": "timeout", "error_code": "E2E_TIMEOUT"})).await;
+        assert!(matches!(outcome.status, ExecStatus::Timeout));
+        assert_eq!(outcome.error.unwrap().code, "E2E_TIMEOUT");
+        assert_eq!(echo(json!({"force_status": "error"})).await.error.unwrap().code, "ECHO_FORCED");
+        assert_eq!(echo(json!({"force_status": "ok"})).await.error.unwrap().code, "INVALID_ECHO_PAYLOAD");
+
+        let outcome = echo(json!({"a": 1, "b": {"c": 2}, "d": 3, "echo_fields": ["a", "b", "missing"]})).await;
+        assert_eq!(outcome.output, Some(json!({"a": 1, "b": {"c": 2}})));
+        assert_eq!(echo(json!({"echo_fields": "a"})).await.error.unwrap().code, "INVALID_ECHO_PAYLOAD");
+    }
+
+    #[tokio::test]
+    async fn test_echo_generated_output_is_deterministic_and_bounded() {
+        let first = echo(json!({"output_bytes": 100, "echo_fields": []})).await.output.unwrap();
+        let blob = first["blob"].as_str().unwrap();
+        assert_eq!(blob.len(), 100);
+        assert!(blob.starts_with("abcdefghijklmnopqrstuvwxyzabc"));
+        assert_eq!(echo(json!({"output_bytes": 100, "echo_fields": []})).await.output.unwrap(), first);
+        assert_eq!(echo(json!({"output_bytes": 4097})).await.error.unwrap().code, "RESULT_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn test_echo_delay_is_bounded_and_cancellable() {
+        let started = Instant::now();
+        let outcome = echo(json!({"delay_ms": 100, "x": 1})).await;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(outcome.output.unwrap()["x"] == 1);
+        let limits = SleepLimits { max: Duration::from_millis(50), ..SleepLimits::default() };
+        let outcome = handle_echo(&context(CancellationToken::new()), &limits, None, &echo_job(json!({"delay_ms": 51}))).await;
+        assert_eq!(outcome.error.unwrap().code, "SLEEP_TOO_LONG");
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let outcome = handle_echo(&context(cancel), &SleepLimits::default(), None, &echo_job(json!({"delay_ms": 10_000}))).await;
+        assert!(matches!(outcome.status, ExecStatus::Cancelled));
+    }
 }
@@ -0,0 +1,409 @@
+use crate::protocol::Job;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Days, LocalResult, Months, NaiveDate, NaiveDateTime, NaiveTime, Offset, SecondsFormat, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use super::{ExecContext, HandlerOutcome};
+
+/// Business days a single `add`/`subtract` may move, so a bad payload can't spin for long.
+const MAX_BUSINESS_DAYS: i64 = 10_000;
+
+/// Local date-times `format: "auto"` tries after RFC 3339 and RFC 2822, read in `tz`.
+const AUTO_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"];
+
+const WEEK: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+type DatetimeError = (&'static str, String);
+
+/// What to do with a local time a DST transition skipped or repeats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DstPolicy {
+    Earliest,
+    Latest,
+    Error,
+}
+
+struct Settings {
+    tz: Tz,
+    dst: DstPolicy,
+}
+
+impl Settings {
+    fn parse(payload: &Value) -> Result<Self, DatetimeError> {
+        let tz = match payload.get("tz") {
+            None => Tz::UTC,
+            Some(v) => v.as_str().and_then(|name| name.parse().ok())
+                .ok_or(("INVALID_TIMEZONE", "'tz' must be an IANA timezone name such as Europe/Berlin".to_string()))?,
+        };
+        let dst = match payload.get("dst_policy").map(|v| v.as_str()) {
+            None | Some(Some("earliest")) => DstPolicy::Earliest,
+            Some(Some("latest")) => DstPolicy::Latest,
+            Some(Some("error")) => DstPolicy::Error,
+            Some(_) => return Err(("INVALID_DST_POLICY", "'dst_policy' must be earliest, latest or error".to_string())),
+        };
+        Ok(Self { tz, dst })
+    }
+
+    /// The instant `local` names in `tz`. A repeated time picks its first or second occurrence;
+    /// a skipped one is read with the offset from before the transition, so it lands as far past
+    /// the transition as it was past the gap's start (02:30 becomes 03:30 in a one-hour gap).
+    fn resolve(&self, local: NaiveDateTime) -> Result<DateTime<Tz>, DatetimeError> {
+        match (self.tz.from_local_datetime(&local), self.dst) {
+            (LocalResult::Single(at), _) => Ok(at),
+            (LocalResult::Ambiguous(earliest, _), DstPolicy::Earliest) => Ok(earliest),
+            (LocalResult::Ambiguous(_, latest), DstPolicy::Latest) => Ok(latest),
+            (LocalResult::Ambiguous(..), DstPolicy::Error) => {
+                Err(("AMBIGUOUS_LOCAL_TIME", format!("{} happens twice in {}", local, self.tz.name())))
+            }
+            (LocalResult::None, DstPolicy::Error) => {
+                Err(("NONEXISTENT_LOCAL_TIME", format!("{} does not exist in {}", local, self.tz.name())))
+            }
+            (LocalResult::None, _) => {
+                let before = self.tz.offset_from_utc_datetime(&(local - TimeDelta::days(1))).fix();
+                let utc = local - TimeDelta::seconds(before.local_minus_utc() as i64);
+                Ok(Utc.from_utc_datetime(&utc).with_timezone(&self.tz))
+            }
+        }
+    }
+
+    /// A payload timestamp: epoch milliseconds, or a string read with `format` (`auto` when absent).
+    fn instant(&self, payload: &Value, field: &str, format: Option<&str>) -> Result<DateTime<Tz>, DatetimeError> {
+        match payload.get(field) {
+            None => Err(("MISSING_INPUT", format!("'{}' is required", field))),
+            Some(Value::String(text)) => self.parse_text(text, format.unwrap_or("auto")),
+            Some(v) => v.as_i64().and_then(DateTime::from_timestamp_millis).map(|at| at.with_timezone(&self.tz))
+                .ok_or(("PARSE_FAILED", format!("'{}' must be a timestamp string or epoch milliseconds", field))),
+        }
+    }
+
+    fn parse_text(&self, text: &str, format: &str) -> Result<DateTime<Tz>, DatetimeError> {
+        let failed = || ("PARSE_FAILED", format!("'{}' does not match format '{}'", text, format));
+        let with_offset = match format {
+            "auto" => DateTime::parse_from_rfc3339(text).or_else(|_| DateTime::parse_from_rfc2822(text)).ok(),
+            "rfc3339" => DateTime::parse_from_rfc3339(text).ok(),
+            "rfc2822" => DateTime::parse_from_rfc2822(text).ok(),
+            _ => DateTime::parse_from_str(text, format).ok(),
+        };
+        if let Some(at) = with_offset {
+            return Ok(at.with_timezone(&self.tz));
+        }
+        let local = match format {
+            "auto" => AUTO_FORMATS.iter().find_map(|f| NaiveDateTime::parse_from_str(text, f).ok())
+                .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().map(|d| d.and_time(NaiveTime::MIN))),
+            "rfc3339" | "rfc2822" => None,
+            _ => NaiveDateTime::parse_from_str(text, format).ok()
+                .or_else(|| NaiveDate::parse_from_str(text, format).ok().map(|d| d.and_time(NaiveTime::MIN))),
+        };
+        self.resolve(local.ok_or_else(failed)?)
+    }
+}
+
+/// Weekdays and dates `business_days` steps over.
+struct Calendar {
+    weekend: Vec<Weekday>,
+    holidays: HashSet<NaiveDate>,
+}
+
+impl Calendar {
+    fn parse(payload: &Value) -> Result<Self, DatetimeError> {
+        let invalid = |message: &str| ("INVALID_CALENDAR", message.to_string());
+        let weekend = match payload.get("weekend") {
+            None => vec![Weekday::Sat, Weekday::Sun],
+            Some(Value::Array(days)) => days.iter()
+                .map(|d| d.as_str().and_then(|d| d.parse().ok()))
+                .collect::<Option<Vec<Weekday>>>()
+                .ok_or_else(|| invalid("'weekend' must list weekday names such as sat and sun"))?,
+            Some(_) => return Err(invalid("'weekend' must list weekday names such as sat and sun")),
+        };
+        if WEEK.iter().all(|d| weekend.contains(d)) {
+            return Err(invalid("'weekend' leaves no business days"));
+        }
+        let holidays = match payload.get("holidays") {
+            None => HashSet::new(),
+            Some(Value::Array(dates)) => dates.iter()
+                .map(|d| d.as_str().and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()))
+                .collect::<Option<HashSet<NaiveDate>>>()
+                .ok_or_else(|| invalid("'holidays' must list YYYY-MM-DD dates"))?,
+            Some(_) => return Err(invalid("'holidays' must list YYYY-MM-DD dates")),
+        };
+        Ok(Self { weekend, holidays })
+    }
+
+    fn is_business_day(&self, date: NaiveDate) -> bool {
+        !self.weekend.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    /// Moves `days` business days (backwards when negative) keeping the time of day; from a
+    /// weekend or holiday the first step lands on the next business day.
+    fn add_business_days(&self, local: NaiveDateTime, days: i64) -> Option<NaiveDateTime> {
+        let mut date = local.date();
+        for _ in 0..days.unsigned_abs() {
+            loop {
+                date = if days < 0 { date.pred_opt()? } else { date.succ_opt()? };
+                if self.is_business_day(date) {
+                    break;
+                }
+            }
+        }
+        Some(date.and_time(local.time()))
+    }
+}
+
+/// A `duration` object; `subtract` negates every component.
+#[derive(Debug, Default)]
+struct Shift {
+    years: i64,
+    months: i64,
+    weeks: i64,
+    days: i64,
+    business_days: i64,
+    hours: i64,
+    minutes: i64,
+    seconds: i64,
+    milliseconds: i64,
+}
+
+impl Shift {
+    fn parse(payload: &Value, negate: bool) -> Result<Self, DatetimeError> {
+        let invalid = |message: String| ("INVALID_DURATION", message);
+        let Some(components) = payload.get("duration").and_then(|d| d.as_object()) else {
+            return Err(invalid("'duration' must be an object such as {\"days\": 3}".to_string()));
+        };
+        let mut shift = Shift::default();
+        for (name, value) in components {
+            let value = value.as_i64().ok_or_else(|| invalid(format!("duration '{}' must be an integer", name)))?;
+            let value = if negate { value.checked_neg().ok_or_else(|| invalid(format!("duration '{}' is out of range", name)))? } else { value };
+            let slot = match name.as_str() {
+                "years" => &mut shift.years,
+                "months" => &mut shift.months,
+                "weeks" => &mut shift.weeks,
+                "days" => &mut shift.days,
+                "business_days" => &mut shift.business_days,
+                "hours" => &mut shift.hours,
+                "minutes" => &mut shift.minutes,
+                "seconds" => &mut shift.seconds,
+                "milliseconds" => &mut shift.milliseconds,
+                _ => return Err(invalid(format!("unknown duration component '{}'", name))),
+            };
+            *slot = value;
+        }
+        if shift.business_days.abs() > MAX_BUSINESS_DAYS {
+            return Err(invalid(format!("'business_days' must be within ±{}", MAX_BUSINESS_DAYS)));
+        }
+        Ok(shift)
+    }
+
+    /// Calendar components move the local date in `years`, `months`, `weeks`, `days`,
+    /// `business_days` order (a month past the 31st clamps to the month's end) and the result is
+    /// resolved under `dst_policy`; the time components are then added as elapsed time, so
+    /// `days: 1` keeps the wall clock across a transition while `hours: 24` does not.
+    fn apply(&self, at: DateTime<Tz>, settings: &Settings, calendar: &Calendar) -> Result<DateTime<Tz>, DatetimeError> {
+        let out_of_range = || ("OUT_OF_RANGE", "the result is outside the supported date range".to_string());
+        let months = self.years.checked_mul(12).and_then(|m| m.checked_add(self.months)).ok_or_else(out_of_range)?;
+        let days = self.weeks.checked_mul(7).and_then(|d| d.checked_add(self.days)).ok_or_else(out_of_range)?;
+        let mut local = at.naive_local();
+        local = add_months(local, months).ok_or_else(out_of_range)?;
+        local = add_days(local, days).ok_or_else(out_of_range)?;
+        local = calendar.add_business_days(local, self.business_days).ok_or_else(out_of_range)?;
+        // Untouched dates keep the instant, so the second 02:30 of a fall-back night stays itself
+        let at = if local == at.naive_local() { at } else { settings.resolve(local)? };
+        let elapsed = [(self.hours, 3_600_000), (self.minutes, 60_000), (self.seconds, 1_000), (self.milliseconds, 1)]
+            .iter()
+            .try_fold(0i64, |total, (n, unit)| n.checked_mul(*unit).and_then(|ms| total.checked_add(ms)))
+            .and_then(TimeDelta::try_milliseconds)
+            .ok_or_else(out_of_range)?;
+        at.checked_add_signed(elapsed).ok_or_else(out_of_range)
+    }
+}
+
+fn add_months(local: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let n = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    if months < 0 { local.checked_sub_months(n) } else { local.checked_add_months(n) }
+}
+
+fn add_days(local: NaiveDateTime, days: i64) -> Option<NaiveDateTime> {
+    let n = Days::new(days.unsigned_abs());
+    if days < 0 { local.checked_sub_days(n) } else { local.checked_add_days(n) }
+}
+
+/// An output format: `rfc3339` (the default), `rfc2822`, or a strftime string.
+fn output_format<'a>(payload: &'a Value, field: &str) -> Result<&'a str, DatetimeError> {
+    let format = match payload.get(field) {
+        None => return Ok("rfc3339"),
+        Some(v) => v.as_str().ok_or(("INVALID_FORMAT", format!("'{}' must be a string", field)))?,
+    };
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(("INVALID_FORMAT", format!("'{}' is not a valid strftime format", format)));
+    }
+    Ok(format)
+}
+
+fn render(at: &DateTime<Tz>, format: &str) -> Value {
+    let formatted = match format {
+        "rfc3339" => at.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        "rfc2822" => at.to_rfc2822(),
+        _ => at.format(format).to_string(),
+    };
+    json!({
+        "formatted": formatted,
+        "epoch_ms": at.timestamp_millis(),
+        "tz": at.timezone().name(),
+        "offset": at.format("%:z").to_string(),
+    })
+}
+
+/// Runs one operation with `now` standing in for the current time.
+fn datetime(payload: &Value, now: DateTime<Utc>) -> Result<Value, DatetimeError> {
+    let settings = Settings::parse(payload)?;
+    match payload.get("op").and_then(|v| v.as_str()) {
+        Some("now") => Ok(render(&now.with_timezone(&settings.tz), output_format(payload, "format")?)),
+        Some("parse") => {
+            let input_format = payload.get("format").map(|v| v.as_str().ok_or(("INVALID_FORMAT", "'format' must be a string".to_string()))).transpose()?;
+            let at = settings.instant(payload, "input", input_format)?;
+            Ok(render(&at, output_format(payload, "output_format")?))
+        }
+        Some("format") => Ok(render(&settings.instant(payload, "input", None)?, output_format(payload, "format")?)),
+        Some(op @ ("add" | "subtract")) => {
+            let format = output_format(payload, "format")?;
+            let shift = Shift::parse(payload, op == "subtract")?;
+            let calendar = Calendar::parse(payload)?;
+            let at = match payload.get("input") {
+                None => now.with_timezone(&settings.tz),
+                Some(_) => settings.instant(payload, "input", None)?,
+            };
+            Ok(render(&shift.apply(at, &settings, &calendar)?, format))
+        }
+        Some("diff") => {
+            let format = output_format(payload, "format")?;
+            let (from, to) = (settings.instant(payload, "from", None)?, settings.instant(payload, "to", None)?);
+            let total = to.timestamp_millis() - from.timestamp_millis();
+            let ms = total.unsigned_abs();
+            Ok(json!({
+                "total_ms": total,
+                "negative": total < 0,
+                "days": ms / 86_400_000,
+                "hours": ms / 3_600_000 % 24,
+                "minutes": ms / 60_000 % 60,
+                "seconds": ms / 1_000 % 60,
+                "milliseconds": ms % 1_000,
+                "from": render(&from, format),
+                "to": render(&to, format),
+            }))
+        }
+        _ => Err(("INVALID_OPERATION", "'op' must be one of now, parse, add, subtract, diff, format".to_string())),
+    }
+}
+
+/// Timezone-aware date arithmetic for flows that used to hand-roll it in JavaScript; every
+/// instant comes back as `formatted` plus `epoch_ms`.
+pub async fn handle_datetime(_ctx: &ExecContext, job: &Job) -> HandlerOutcome {
+    match datetime(&job.payload, Utc::now()) {
+        Ok(output) => HandlerOutcome::success(output),
+        Err((code, message)) => HandlerOutcome::error(code, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-01-15T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn run(payload: Value) -> Value {
+        datetime(&payload, now()).unwrap()
+    }
+
+    fn failure(payload: Value) -> &'static str {
+        datetime(&payload, now()).unwrap_err().0
+    }
+
+    #[test]
+    fn test_timezones_and_formats() {
+        let berlin = run(json!({"op": "now", "tz": "Europe/Berlin"}));
+        assert_eq!(berlin, json!({"formatted": "2026-01-15T13:00:00+01:00", "epoch_ms": 1768478400000_i64, "tz": "Europe/Berlin", "offset": "+01:00"}));
+        assert_eq!(run(json!({"op": "now"}))["formatted"], "2026-01-15T12:00:00Z");
+
+        let summer = json!({"op": "format", "input": "2026-07-01T12:00:00Z", "tz": "America/New_York", "format": "%Y-%m-%d %H:%M %Z"});
+        assert_eq!(run(summer)["formatted"], "2026-07-01 08:00 EDT");
+        let epoch = run(json!({"op": "format", "input": 1768478400000_i64, "tz": "Asia/Kolkata", "format": "rfc2822"}));
+        assert_eq!(epoch["formatted"], "Thu, 15 Jan 2026 17:30:00 +0530");
+
+        assert_eq!(failure(json!({"op": "now", "tz": "Mars/Olympus"})), "INVALID_TIMEZONE");
+        assert_eq!(failure(json!({"op": "now", "format": "%Y-%"})), "INVALID_FORMAT");
+        assert_eq!(failure(json!({"op": "tomorrow"})), "INVALID_OPERATION");
+    }
+
+    #[test]
+    fn test_dst_gaps_and_overlaps() {
+        // Berlin springs forward at 02:00 on 2026-03-29 and falls back at 03:00 on 2026-10-25
+        let gap = json!({"op": "parse", "input": "2026-03-29T02:30:00", "tz": "Europe/Berlin"});
+        assert_eq!(run(gap.clone())["formatted"], "2026-03-29T03:30:00+02:00");
+        let mut strict = gap;
+        strict["dst_policy"] = json!("error");
+        assert_eq!(failure(strict), "NONEXISTENT_LOCAL_TIME");
+
+        let overlap = |policy: &str| json!({"op": "parse", "input": "2026-10-25 02:30:00", "tz": "Europe/Berlin", "dst_policy": policy});
+        assert_eq!(run(overlap("earliest"))["formatted"], "2026-10-25T02:30:00+02:00");
+        assert_eq!(run(overlap("latest"))["formatted"], "2026-10-25T02:30:00+01:00");
+        assert_eq!(failure(overlap("error")), "AMBIGUOUS_LOCAL_TIME");
+        assert_eq!(failure(overlap("never")), "INVALID_DST_POLICY");
+
+        let add = |duration: Value| run(json!({"op": "add", "input": "2026-03-28T12:00:00+01:00", "tz": "Europe/Berlin", "duration": duration}));
+        assert_eq!(add(json!({"days": 1}))["formatted"], "2026-03-29T12:00:00+02:00");
+        assert_eq!(add(json!({"hours": 24}))["formatted"], "2026-03-29T13:00:00+02:00");
+        let second = json!({"op": "add", "input": "2026-10-25T02:30:00+01:00", "tz": "Europe/Berlin", "duration": {"minutes": 10}});
+        assert_eq!(run(second)["formatted"], "2026-10-25T02:40:00+01:00", "no calendar move keeps the later occurrence");
+    }
+
+    #[test]
+    fn test_business_days_and_calendar_math() {
+        let christmas = json!({"op": "add", "input": "2026-12-23T09:00:00", "tz": "Europe/Berlin", "duration": {"business_days": 3}, "holidays": ["2026-12-24", "2026-12-25"]});
+        assert_eq!(run(christmas)["formatted"], "2026-12-30T09:00:00+01:00");
+        let back = json!({"op": "subtract", "input": "2026-12-28T09:00:00", "tz": "Europe/Berlin", "duration": {"business_days": 1}, "holidays": ["2026-12-24", "2026-12-25"]});
+        assert_eq!(run(back)["formatted"], "2026-12-23T09:00:00+01:00");
+        let gulf = json!({"op": "add", "input": "2026-12-31", "duration": {"business_days": 1}, "weekend": ["fri", "sat"]});
+        assert_eq!(run(gulf)["formatted"], "2027-01-03T00:00:00Z");
+        let from_now = run(json!({"op": "add", "tz": "Europe/Berlin", "duration": {"business_days": 3}}));
+        assert_eq!(from_now["formatted"], "2026-01-20T13:00:00+01:00", "Thursday plus three skips the weekend");
+
+        assert_eq!(run(json!({"op": "add", "input": "2026-01-31", "duration": {"months": 1}}))["formatted"], "2026-02-28T00:00:00Z");
+        assert_eq!(run(json!({"op": "subtract", "input": "2026-01-15T12:00:00Z", "duration": {"years": 1, "weeks": 2, "milliseconds": 5}}))["epoch_ms"], 1735732799995_i64);
+
+        let all_week = json!({"op": "add", "input": "2026-01-01", "duration": {"business_days": 1}, "weekend": ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]});
+        assert_eq!(failure(all_week), "INVALID_CALENDAR");
+        assert_eq!(failure(json!({"op": "add", "duration": {"fortnights": 1}})), "INVALID_DURATION");
+        assert_eq!(failure(json!({"op": "add", "duration": {"business_days": 20000}})), "INVALID_DURATION");
+        assert_eq!(failure(json!({"op": "add", "duration": {"years": i64::MAX}})), "OUT_OF_RANGE");
+    }
+
+    #[test]
+    fn test_diff_components() {
+        let diff = run(json!({"op": "diff", "from": "2026-03-28T12:00:00+01:00", "to": "2026-03-29T12:00:01.250+02:00", "tz": "Europe/Berlin"}));
+        assert_eq!((diff["total_ms"].as_i64(), diff["days"].as_u64(), diff["hours"].as_u64()), (Some(82_801_250), Some(0), Some(23)));
+        assert_eq!((diff["seconds"].as_u64(), diff["milliseconds"].as_u64(), diff["negative"].as_bool()), (Some(1), Some(250), Some(false)));
+        assert_eq!(diff["to"]["formatted"], "2026-03-29T12:00:01.250+02:00");
+
+        let backwards = run(json!({"op": "diff", "from": 1768478400000_i64 + 90_061_000, "to": 1768478400000_i64}));
+        assert_eq!((backwards["negative"].as_bool(), backwards["days"].as_u64(), backwards["minutes"].as_u64()), (Some(true), Some(1), Some(1)));
+        assert_eq!(failure(json!({"op": "diff", "from": "2026-01-01"})), "MISSING_INPUT");
+    }
+
+    #[test]
+    fn test_parse_formats_and_failures() {
+        let tokyo = run(json!({"op": "parse", "input": "15/01/2026 21:00", "format": "%d/%m/%Y %H:%M", "tz": "Asia/Tokyo"}));
+        assert_eq!(tokyo["epoch_ms"], 1768478400000_i64);
+        let day = run(json!({"op": "parse", "input": "2026-01-15", "format": "%Y-%m-%d", "output_format": "%A"}));
+        assert_eq!(day["formatted"], "Thursday");
+        let offset = run(json!({"op": "parse", "input": "Thu, 15 Jan 2026 12:00:00 +0000", "tz": "Europe/Berlin"}));
+        assert_eq!(offset["formatted"], "2026-01-15T13:00:00+01:00");
+
+        assert_eq!(failure(json!({"op": "parse", "input": "next tuesday"})), "PARSE_FAILED");
+        assert_eq!(failure(json!({"op": "parse", "input": "2026-01-15", "format": "%d/%m/%Y"})), "PARSE_FAILED");
+        assert_eq!(failure(json!({"op": "parse", "input": "2026-01-15", "format": "rfc3339"})), "PARSE_FAILED");
+        assert_eq!(failure(json!({"op": "parse", "input": true})), "PARSE_FAILED");
+        assert_eq!(failure(json!({"op": "parse"})), "MISSING_INPUT");
+    }
+}
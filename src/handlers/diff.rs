@@ -0,0 +1,322 @@
+use crate::protocol::Job;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use super::{ExecContext, HandlerOutcome};
+
+const DEFAULT_MAX_DIFFERENCES: u64 = 100;
+const MAX_DIFFERENCES_LIMIT: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// The path exists on one side only.
+    Missing,
+    /// Both sides hold different JSON types.
+    Type,
+    Value,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Missing => "missing",
+            Kind::Type => "type",
+            Kind::Value => "value",
+        }
+    }
+}
+
+/// How `left` and `right` are compared, from the job payload.
+struct DiffOptions {
+    /// Dot-separated patterns split into segments; `*` matches one segment, `**` any number.
+    ignore: Vec<Vec<String>>,
+    numeric_tolerance: f64,
+    /// `array_mode: "set"`: arrays match when every element has an equal partner, in any order.
+    set_arrays: bool,
+    max_differences: usize,
+}
+
+#[derive(Default)]
+struct Differences {
+    reported: Vec<Value>,
+    count: usize,
+}
+
+impl DiffOptions {
+    fn parse(payload: &Value) -> Result<Self, (&'static str, String)> {
+        let ignore = match payload.get("ignore_paths") {
+            None => Vec::new(),
+            Some(Value::Array(paths)) if paths.iter().all(Value::is_string) => paths.iter()
+                .filter_map(Value::as_str)
+                .map(|p| p.split('.').map(str::to_string).collect())
+                .collect(),
+            Some(_) => return Err(("INVALID_IGNORE_PATHS", "'ignore_paths' must be an array of strings".to_string())),
+        };
+        let numeric_tolerance = match payload.get("numeric_tolerance") {
+            None => 0.0,
+            Some(v) => match v.as_f64() {
+                Some(t) if t >= 0.0 && t.is_finite() => t,
+                _ => return Err(("INVALID_TOLERANCE", "'numeric_tolerance' must be a non-negative number".to_string())),
+            },
+        };
+        let set_arrays = match payload.get("array_mode").and_then(|v| v.as_str()) {
+            None if payload.get("array_mode").is_none() => false,
+            Some("ordered") => false,
+            Some("set") => true,
+            _ => return Err(("INVALID_ARRAY_MODE", "'array_mode' must be ordered or set".to_string())),
+        };
+        let max_differences = match payload.get("max_differences") {
+            None => DEFAULT_MAX_DIFFERENCES,
+            Some(v) => match v.as_u64() {
+                Some(n) if (1..=MAX_DIFFERENCES_LIMIT).contains(&n) => n,
+                _ => return Err(("INVALID_MAX_DIFFERENCES", format!("'max_differences' must be between 1 and {}", MAX_DIFFERENCES_LIMIT))),
+            },
+        };
+        Ok(Self { ignore, numeric_tolerance, set_arrays, max_differences: max_differences as usize })
+    }
+
+    fn report(&self, left: &Value, right: &Value) -> Value {
+        let mut differences = Differences::default();
+        self.compare(&mut Vec::new(), left, right, &mut differences);
+        json!({
+            "equal": differences.count == 0,
+            "diff_count": differences.count,
+            "truncated": differences.count > differences.reported.len(),
+            "differences": differences.reported,
+        })
+    }
+
+    fn ignored(&self, path: &[String]) -> bool {
+        self.ignore.iter().any(|pattern| glob_match(pattern, path))
+    }
+
+    fn compare(&self, path: &mut Vec<String>, left: &Value, right: &Value, out: &mut Differences) {
+        if self.ignored(path) {
+            return;
+        }
+        match (left, right) {
+            (Value::Object(l), Value::Object(r)) => {
+                let keys: BTreeSet<&String> = l.keys().chain(r.keys()).collect();
+                for key in keys {
+                    path.push(key.clone());
+                    match (l.get(key), r.get(key)) {
+                        (Some(lv), Some(rv)) => self.compare(path, lv, rv, out),
+                        (lv, rv) if !self.ignored(path) => self.record(path, lv, rv, Kind::Missing, out),
+                        _ => {}
+                    }
+                    path.pop();
+                }
+            }
+            (Value::Array(l), Value::Array(r)) if self.set_arrays => self.compare_sets(path, l, r, out),
+            (Value::Array(l), Value::Array(r)) => {
+                for index in 0..l.len().max(r.len()) {
+                    path.push(index.to_string());
+                    match (l.get(index), r.get(index)) {
+                        (Some(lv), Some(rv)) => self.compare(path, lv, rv, out),
+                        (lv, rv) if !self.ignored(path) => self.record(path, lv, rv, Kind::Missing, out),
+                        _ => {}
+                    }
+                    path.pop();
+                }
+            }
+            (Value::Number(l), Value::Number(r)) => {
+                // Two integers without a tolerance compare exactly, so ids past f64 precision still differ
+                let integers = !l.is_f64() && !r.is_f64();
+                let within = l == r
+                    || (!(integers && self.numeric_tolerance == 0.0)
+                        && matches!((l.as_f64(), r.as_f64()), (Some(a), Some(b)) if (a - b).abs() <= self.numeric_tolerance));
+                if !within {
+                    self.record(path, Some(left), Some(right), Kind::Value, out);
+                }
+            }
+            _ if std::mem::discriminant(left) != std::mem::discriminant(right) => self.record(path, Some(left), Some(right), Kind::Type, out),
+            _ if left != right => self.record(path, Some(left), Some(right), Kind::Value, out),
+            _ => {}
+        }
+    }
+
+    /// Pairs each left element with an unused equal right one; the leftovers on either side
+    /// are reported as missing at their own index.
+    fn compare_sets(&self, path: &mut Vec<String>, left: &[Value], right: &[Value], out: &mut Differences) {
+        let mut used = vec![false; right.len()];
+        for (index, lv) in left.iter().enumerate() {
+            path.push(index.to_string());
+            let partner = (0..right.len()).find(|&j| !used[j] && self.equal(path, lv, &right[j]));
+            match partner {
+                Some(j) => used[j] = true,
+                None if !self.ignored(path) => self.record(path, Some(lv), None, Kind::Missing, out),
+                None => {}
+            }
+            path.pop();
+        }
+        for (index, rv) in right.iter().enumerate().filter(|(j, _)| !used[*j]) {
+            path.push(index.to_string());
+            if !self.ignored(path) {
+                self.record(path, None, Some(rv), Kind::Missing, out);
+            }
+            path.pop();
+        }
+    }
+
+    fn equal(&self, path: &mut Vec<String>, left: &Value, right: &Value) -> bool {
+        let mut scratch = Differences::default();
+        self.compare(path, left, right, &mut scratch);
+        scratch.count == 0
+    }
+
+    /// Counts every difference, keeping the first `max_differences`; the absent side of a
+    /// missing one is left out.
+    fn record(&self, path: &[String], left: Option<&Value>, right: Option<&Value>, kind: Kind, out: &mut Differences) {
+        out.count += 1;
+        if out.reported.len() >= self.max_differences {
+            return;
+        }
+        let mut difference = json!({"path": display_path(path), "kind": kind.as_str()});
+        if let Some(left) = left {
+            difference["left"] = left.clone();
+        }
+        if let Some(right) = right {
+            difference["right"] = right.clone();
+        }
+        out.reported.push(difference);
+    }
+}
+
+fn glob_match(pattern: &[String], path: &[String]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((first, rest)), _) if first == "**" => (0..=path.len()).any(|skip| glob_match(rest, &path[skip..])),
+        (Some((first, rest)), Some((segment, path_rest))) => (first == "*" || first == segment) && glob_match(rest, path_rest),
+        _ => false,
+    }
+}
+
+/// Segments joined by dots, array indices included (`items.2.price`); `$` for the root.
+fn display_path(path: &[String]) -> String {
+    if path.is_empty() {
+        "$".to_string()
+    } else {
+        path.join(".")
+    }
+}
+
+/// `{left, right}` plus `ignore_paths`, `numeric_tolerance`, `array_mode` (`ordered` or `set`)
+/// and `max_differences`, e.g. to compare shadow results with production ones.
+pub async fn handle_json_diff(_ctx: &ExecContext, job: &Job) -> HandlerOutcome {
+    let payload = &job.payload;
+    let (Some(left), Some(right)) = (payload.get("left"), payload.get("right")) else {
+        return HandlerOutcome::error("MISSING_OPERAND", "Both 'left' and 'right' are required");
+    };
+    let options = match DiffOptions::parse(payload) {
+        Ok(options) => options,
+        Err((code, message)) => return HandlerOutcome::error(code, message),
+    };
+    HandlerOutcome::success(options.report(left, right))
+}
+
+/// The `json_diff` output for two documents, compared in order with `ignore_paths` skipped.
+pub fn diff_values(left: &Value, right: &Value, ignore_paths: &[&str]) -> Value {
+    let options = DiffOptions {
+        ignore: ignore_paths.iter().map(|p| p.split('.').map(str::to_string).collect()).collect(),
+        numeric_tolerance: 0.0,
+        set_arrays: false,
+        max_differences: DEFAULT_MAX_DIFFERENCES as usize,
+    };
+    options.report(left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(payload: Value) -> Value {
+        let options = DiffOptions::parse(&payload).ok().unwrap();
+        let mut differences = Differences::default();
+        options.compare(&mut Vec::new(), &payload["left"], &payload["right"], &mut differences);
+        json!({"count": differences.count, "reported": differences.reported})
+    }
+
+    #[test]
+    fn test_nested_objects_report_each_kind() {
+        let out = diff(json!({
+            "left": {"user": {"id": 1, "name": "Ann", "tags": {"vip": true}}, "total": 10},
+            "right": {"user": {"id": "1", "name": "Anne", "email": "a@x"}, "total": 10}
+        }));
+        assert_eq!(out["reported"], json!([
+            {"path": "user.email", "kind": "missing", "right": "a@x"},
+            {"path": "user.id", "kind": "type", "left": 1, "right": "1"},
+            {"path": "user.name", "kind": "value", "left": "Ann", "right": "Anne"},
+            {"path": "user.tags", "kind": "missing", "left": {"vip": true}},
+        ]));
+        assert_eq!(diff(json!({"left": 1, "right": [1]}))["reported"], json!([{"path": "$", "kind": "type", "left": 1, "right": [1]}]));
+        assert_eq!(diff(json!({"left": null, "right": {}}))["reported"][0]["kind"], "type");
+    }
+
+    #[test]
+    fn test_arrays_ordered_and_as_sets() {
+        let ordered = diff(json!({"left": {"xs": [1, 2, 3]}, "right": {"xs": [1, 3]}}));
+        assert_eq!(ordered["reported"], json!([
+            {"path": "xs.1", "kind": "value", "left": 2, "right": 3},
+            {"path": "xs.2", "kind": "missing", "left": 3},
+        ]));
+        let set = json!({"left": [{"id": 1}, {"id": 2}, 3], "right": [3, {"id": 2}, {"id": 1}], "array_mode": "set"});
+        assert_eq!(diff(set)["count"], 0);
+        let set = diff(json!({"left": [1, 1, 2], "right": [1, 2, 2], "array_mode": "set"}));
+        assert_eq!(set["reported"], json!([
+            {"path": "1", "kind": "missing", "left": 1},
+            {"path": "2", "kind": "missing", "right": 2},
+        ]), "each element pairs with one partner only");
+    }
+
+    #[test]
+    fn test_numeric_tolerance() {
+        assert_eq!(diff(json!({"left": {"p": 1.0005}, "right": {"p": 1.0}, "numeric_tolerance": 0.001}))["count"], 0);
+        assert_eq!(diff(json!({"left": {"p": 1.01}, "right": {"p": 1.0}, "numeric_tolerance": 0.001}))["count"], 1);
+        assert_eq!(diff(json!({"left": 1, "right": 1.0}))["count"], 0);
+        assert_eq!(diff(json!({"left": 9007199254740993_u64, "right": 9007199254740992_u64}))["count"], 1, "compared exactly without a tolerance");
+        assert!(DiffOptions::parse(&json!({"numeric_tolerance": -1})).is_err());
+    }
+
+    #[test]
+    fn test_ignore_patterns() {
+        let base = json!({
+            "left": {"a": {"b": 1, "c": 1}, "timestamps": {"created": "t1", "updated": "t1"}, "items": [{"at": 1, "v": 1}, {"at": 2, "v": 2}], "deep": {"x": {"y": {"etag": 1}}}},
+            "right": {"a": {"b": 2, "c": 1}, "timestamps": {"created": "t2"}, "items": [{"at": 9, "v": 1}, {"at": 8, "v": 3}], "deep": {"x": {"y": {"etag": 2}}}}
+        });
+        assert_eq!(diff(base.clone())["count"], 7);
+        let mut ignoring = base.clone();
+        ignoring["ignore_paths"] = json!(["a.b", "timestamps.*", "items.*.at", "**.etag"]);
+        assert_eq!(diff(ignoring)["reported"], json!([{"path": "items.1.v", "kind": "value", "left": 2, "right": 3}]));
+        let mut set = base;
+        set["ignore_paths"] = json!(["**.at", "**.b", "timestamps", "deep"]);
+        set["array_mode"] = json!("set");
+        assert_eq!(diff(set)["count"], 2, "{{v: 2}} and {{v: 3}} have no partner");
+        assert!(glob_match(&["**".to_string()], &[]));
+        assert!(!glob_match(&["a".to_string(), "*".to_string()], &["a".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_differences_are_capped() {
+        use crate::observability::{metrics::Metrics, Logger};
+        use crate::protocol::ExecAssignment;
+        use std::sync::Arc;
+        use tokio::time::{Duration, Instant};
+        use tokio_util::sync::CancellationToken;
+
+        let assignment: ExecAssignment = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+            "job": {"type": "json_diff", "payload": {}}
+        })).unwrap();
+        let ctx = ExecContext::for_assignment(&assignment, Logger::new("w1".to_string()), Arc::new(Metrics::new()), CancellationToken::new(), Instant::now() + Duration::from_secs(60));
+        let left: Vec<u64> = (0..50).collect();
+        let right: Vec<u64> = (100..150).collect();
+        let job = Job { r#type: "json_diff".to_string(), payload: json!({"left": left, "right": right, "max_differences": 10}) };
+        let output = handle_json_diff(&ctx, &job).await.output.unwrap();
+        assert_eq!((output["equal"].as_bool(), output["diff_count"].as_u64(), output["truncated"].as_bool()), (Some(false), Some(50), Some(true)));
+        assert_eq!(output["differences"].as_array().unwrap().len(), 10);
+
+        let job = Job { r#type: "json_diff".to_string(), payload: json!({"left": {"a": 1}, "right": {"a": 1}}) };
+        assert_eq!(handle_json_diff(&ctx, &job).await.output.unwrap(), json!({"equal": true, "diff_count": 0, "truncated": false, "differences": []}));
+        let job = Job { r#type: "json_diff".to_string(), payload: json!({"left": 1}) };
+        assert_eq!(handle_json_diff(&ctx, &job).await.error.unwrap().code, "MISSING_OPERAND");
+    }
+}
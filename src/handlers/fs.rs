@@ -1,124 +1,478 @@
-use crate::protocol::{ExecStatus, Job};
-use serde_json::json;
+use crate::protocol::Job;
+use serde_json::{json, Value};
 use base64::{Engine as _, engine::general_purpose};
-use super::HandlerResult;
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use super::{ExecContext, HandlerOutcome};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// Raw bytes read per base64 chunk; a multiple of 3 so only the last chunk is padded.
+const ENCODE_CHUNK: usize = 3 * 64 * 1024;
+/// Base64 characters decoded per chunk; a multiple of 4 so chunks decode independently.
+const DECODE_CHUNK: usize = 4 * 64 * 1024;
+/// Content-addressed blobs live at `<base_dir>/cas/<first two hex digits>/<sha256>`.
+const CAS_DIR: &str = "cas";
+
+/// Reads the blob at `path`, or with `hash` instead the content-addressed one.
+pub async fn handle_fs_blob_get(ctx: &ExecContext, base_dir: &str, job: &Job) -> HandlerOutcome {
+    if let Some(hash) = job.payload.get("hash") {
+        let Some(hash) = hash.as_str().filter(|h| is_sha256_hex(h)) else {
+            return HandlerOutcome::error("INVALID_HASH", "'hash' must be a sha256 in lowercase hex");
+        };
+        let full_path = cas_path(base_dir, hash);
+        let outcome = read_blob(ctx, &full_path, json!({"hash": hash})).await;
+        // Eviction goes by access time, which noatime mounts would never move
+        touch(&full_path);
+        return outcome;
+    }
 
-pub async fn handle_fs_blob_get(base_dir: &str, job: &Job) -> HandlerResult {
     let path_str = match job.payload.get("path").and_then(|v| v.as_str()) {
         Some(p) => p,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_PATH".to_string()),
-            Some("Missing 'path' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_PATH", "Missing 'path' or 'hash' in payload"),
     };
 
-    if path_str.contains("..") || Path::new(path_str).is_absolute() {
-         return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("INVALID_PATH".to_string()),
-            Some("Path traversal or absolute path not allowed".to_string())
-         );
-    }
-    let full_path = Path::new(base_dir).join(path_str);
-
-    match tokio::fs::read(&full_path).await {
-        Ok(content) => {
-            let encoded = general_purpose::STANDARD.encode(&content);
-            let output = json!({
-                "path": path_str,
-                "bytes": encoded,
-                "size": content.len()
-            });
-            (ExecStatus::Success, job.r#type.clone(), Some(output), None, None)
+    let Some(full_path) = sandboxed_path(base_dir, path_str) else {
+         return HandlerOutcome::error("INVALID_PATH", "Path traversal or absolute path not allowed");
+    };
+
+    read_blob(ctx, &full_path, json!({"path": path_str})).await
+}
+
+/// Adds `size` and `bytes` to `output`.
+async fn read_blob(ctx: &ExecContext, full_path: &Path, mut output: Value) -> HandlerOutcome {
+    match read_base64(full_path).await {
+        Ok((encoded, size)) => {
+            output["size"] = json!(size);
+            // Moved in rather than through `json!`, which would copy the string
+            output["bytes"] = Value::String(encoded);
+            HandlerOutcome::success(output)
         },
-        Err(e) => (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("FILE_READ_ERROR".to_string()),
-            Some(e.to_string())
-        )
+        Err(e) => {
+            let mut fields = output.clone();
+            fields["error"] = json!(e.to_string());
+            ctx.error("Blob read failed", Some(fields));
+            HandlerOutcome::error("FILE_READ_ERROR", e.to_string())
+        }
     }
 }
 
-pub async fn handle_fs_blob_put(base_dir: &str, job: &Job) -> HandlerResult {
-    let path_str = match job.payload.get("path").and_then(|v| v.as_str()) {
-        Some(p) => p,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_PATH".to_string()),
-            Some("Missing 'path' in payload".to_string())
-        ),
+/// Writes the blob to `path`, or with `mode: "cas"` under its sha256, evicting the least
+/// recently used content-addressed blobs beyond `cas_max_bytes`.
+pub async fn handle_fs_blob_put(ctx: &ExecContext, base_dir: &str, cas_max_bytes: Option<u64>, job: &Job) -> HandlerOutcome {
+    let cas = match job.payload.get("mode").and_then(|v| v.as_str()) {
+        None | Some("path") => false,
+        Some("cas") => true,
+        Some(_) => return HandlerOutcome::error("INVALID_MODE", "'mode' must be path or cas"),
     };
 
-    if path_str.contains("..") || Path::new(path_str).is_absolute() {
-         return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("INVALID_PATH".to_string()),
-            Some("Path traversal or absolute path not allowed".to_string())
-         );
-    }
-    let full_path = Path::new(base_dir).join(path_str);
-
-    let content_bytes = if let Some(bytes_b64) = job.payload.get("bytes").and_then(|v| v.as_str()) {
-         match general_purpose::STANDARD.decode(bytes_b64) {
-             Ok(b) => b,
-             Err(e) => return (
-                ExecStatus::Error,
-                job.r#type.clone(),
-                None,
-                Some("BASE64_DECODE_ERROR".to_string()),
-                Some(e.to_string())
-             )
-         }
+    let content = if let Some(bytes_b64) = job.payload.get("bytes").and_then(|v| v.as_str()) {
+         Content::Base64(bytes_b64)
     } else if let Some(content_str) = job.payload.get("content").and_then(|v| v.as_str()) {
-         content_str.as_bytes().to_vec()
+         Content::Text(content_str)
     } else {
-         return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_CONTENT".to_string()),
-            Some("Missing 'bytes' (base64) or 'content' (string) in payload".to_string())
-         )
+         return HandlerOutcome::error("MISSING_CONTENT", "Missing 'bytes' (base64) or 'content' (string) in payload")
+    };
+
+    if cas {
+        return put_cas(ctx, base_dir, cas_max_bytes, content).await;
+    }
+
+    let path_str = match job.payload.get("path").and_then(|v| v.as_str()) {
+        Some(p) => p,
+        None => return HandlerOutcome::error("MISSING_PATH", "Missing 'path' in payload"),
+    };
+
+    let Some(full_path) = sandboxed_path(base_dir, path_str) else {
+         return HandlerOutcome::error("INVALID_PATH", "Path traversal or absolute path not allowed");
     };
 
     if let Some(parent) = full_path.parent() {
          if let Err(e) = tokio::fs::create_dir_all(parent).await {
-             return (
-                ExecStatus::Error,
-                job.r#type.clone(),
-                None,
-                Some("DIR_CREATE_ERROR".to_string()),
-                Some(e.to_string())
-             );
+             return HandlerOutcome::error("DIR_CREATE_ERROR", e.to_string());
          }
     }
 
-    match tokio::fs::write(&full_path, &content_bytes).await {
-        Ok(_) => {
+    match write_atomically(&full_path, content).await {
+        Ok(size) => {
             let output = json!({
                 "path": path_str,
-                "size": content_bytes.len()
+                "size": size
             });
-            (ExecStatus::Success, job.r#type.clone(), Some(output), None, None)
+            HandlerOutcome::success(output)
         },
-        Err(e) => (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("FILE_WRITE_ERROR".to_string()),
-            Some(e.to_string())
-        )
+        Err(WriteError::Decode(e)) => HandlerOutcome::error("BASE64_DECODE_ERROR", e),
+        Err(WriteError::Io(e)) => {
+            ctx.error("Blob write failed", Some(json!({"path": path_str, "error": e.to_string()})));
+            HandlerOutcome::error("FILE_WRITE_ERROR", e.to_string())
+        }
+    }
+}
+
+async fn put_cas(ctx: &ExecContext, base_dir: &str, max_bytes: Option<u64>, content: Content<'_>) -> HandlerOutcome {
+    let cas_dir = Path::new(base_dir).join(CAS_DIR);
+    if let Err(e) = tokio::fs::create_dir_all(&cas_dir).await {
+        return HandlerOutcome::error("DIR_CREATE_ERROR", e.to_string());
+    }
+    // The name is only known once the content is hashed, so it is written beside the shards first
+    let tmp_path = temp_path_for(&cas_dir.join("blob"));
+    let mut hasher = Sha256::new();
+    let stored = match write_chunks(&tmp_path, content, Some(&mut hasher)).await {
+        Ok(size) => {
+            let hash: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+            let stored = link_cas(&tmp_path, &cas_path(base_dir, &hash)).await;
+            stored.map(|already_existed| (hash, size, already_existed)).map_err(WriteError::Io)
+        }
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+
+    match stored {
+        Ok((hash, size, already_existed)) => {
+            let full_path = cas_path(base_dir, &hash);
+            if already_existed {
+                touch(&full_path);
+            } else if let Some(max_bytes) = max_bytes {
+                let keep = full_path.clone();
+                let evicted = tokio::task::spawn_blocking(move || evict_cas(&cas_dir, max_bytes, &keep)).await
+                    .map_err(|e| e.to_string())
+                    .and_then(|evicted| evicted.map_err(|e| e.to_string()));
+                match evicted {
+                    Ok(evicted) => ctx.metrics.fs_cas_evicted_total.inc_by(evicted as u64),
+                    Err(e) => ctx.error("CAS eviction failed", Some(json!({"error": e}))),
+                }
+            }
+            HandlerOutcome::success(json!({"hash": hash, "size": size, "already_existed": already_existed}))
+        }
+        Err(WriteError::Decode(e)) => HandlerOutcome::error("BASE64_DECODE_ERROR", e),
+        Err(WriteError::Io(e)) => {
+            ctx.error("Blob write failed", Some(json!({"mode": "cas", "error": e.to_string()})));
+            HandlerOutcome::error("FILE_WRITE_ERROR", e.to_string())
+        }
+    }
+}
+
+/// Links the finished temp file in as `path`. Linking never replaces a file, so when a
+/// concurrent put of the same content got there first the existing blob is kept and this
+/// returns `true`.
+async fn link_cas(tmp_path: &Path, path: &Path) -> std::io::Result<bool> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    match tokio::fs::hard_link(tmp_path, path).await {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+fn cas_path(base_dir: &str, hash: &str) -> PathBuf {
+    Path::new(base_dir).join(CAS_DIR).join(&hash[..2]).join(hash)
+}
+
+fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Marks the blob as just used; best effort, a missing file is simply not there to evict.
+fn touch(path: &Path) {
+    if let Ok(file) = std::fs::File::open(path) {
+        let _ = file.set_times(std::fs::FileTimes::new().set_accessed(SystemTime::now()));
+    }
+}
+
+/// Removes the least recently accessed blobs under `root` until the rest fit in `max_bytes`,
+/// never `keep`. Returns how many were removed.
+fn evict_cas(root: &Path, max_bytes: u64, keep: &Path) -> std::io::Result<usize> {
+    let mut blobs = Vec::new();
+    let mut total = 0u64;
+    for shard in std::fs::read_dir(root)? {
+        let shard = shard?;
+        // Temp files of puts in progress sit beside the shard directories
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(shard.path())?.flatten() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let used = meta.accessed().or_else(|_| meta.modified()).unwrap_or(UNIX_EPOCH);
+            total += meta.len();
+            blobs.push((used, meta.len(), entry.path()));
+        }
+    }
+    blobs.sort_by_key(|(used, ..)| *used);
+    let mut evicted = 0;
+    for (_, size, path) in blobs {
+        if total <= max_bytes {
+            break;
+        }
+        if path != keep && std::fs::remove_file(&path).is_ok() {
+            total -= size;
+            evicted += 1;
+        }
+    }
+    Ok(evicted)
+}
+
+/// `path_str` under `base_dir`; `None` for absolute paths and anything with `..`.
+pub(super) fn sandboxed_path(base_dir: &str, path_str: &str) -> Option<PathBuf> {
+    if path_str.contains("..") || Path::new(path_str).is_absolute() {
+        return None;
+    }
+    Some(Path::new(base_dir).join(path_str))
+}
+
+/// A hidden, unique sibling of `path` to write into before renaming over it.
+pub(super) fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp-{}", file_name, uuid::Uuid::new_v4()))
+}
+
+enum Content<'a> {
+    Base64(&'a str),
+    Text(&'a str),
+}
+
+enum WriteError {
+    Decode(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(e: std::io::Error) -> Self {
+        WriteError::Io(e)
+    }
+}
+
+/// Reads `path` as base64 a chunk at a time, so the raw file is never resident next to its
+/// encoding. Returns the encoding and the file size.
+async fn read_base64(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let expected = file.metadata().await?.len() as usize;
+    let mut encoded = String::with_capacity(expected.div_ceil(3) * 4);
+    let mut chunk = vec![0u8; ENCODE_CHUNK.min(expected.max(3))];
+    let mut size = 0u64;
+    loop {
+        let filled = fill(&mut file, &mut chunk).await?;
+        if filled == 0 {
+            break;
+        }
+        general_purpose::STANDARD.encode_string(&chunk[..filled], &mut encoded);
+        size += filled as u64;
+        if filled < chunk.len() {
+            break;
+        }
+    }
+    Ok((encoded, size))
+}
+
+/// Reads until `buf` is full or the reader is exhausted.
+async fn fill<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes `content` to a temp file beside `path`, decoding base64 a chunk at a time, then
+/// renames it into place so readers never see a partial blob. Returns the bytes written.
+async fn write_atomically(path: &Path, content: Content<'_>) -> Result<u64, WriteError> {
+    let tmp_path = temp_path_for(path);
+    let written = write_chunks(&tmp_path, content, None).await;
+    match written {
+        Ok(size) => match tokio::fs::rename(&tmp_path, path).await {
+            Ok(()) => Ok(size),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                Err(e.into())
+            }
+        },
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            Err(e)
+        }
+    }
+}
+
+/// Also feeds the decoded bytes to `hasher` when given one.
+async fn write_chunks(tmp_path: &Path, content: Content<'_>, mut hasher: Option<&mut Sha256>) -> Result<u64, WriteError> {
+    let mut file = tokio::fs::File::create(tmp_path).await?;
+    let mut size = 0u64;
+    match content {
+        Content::Text(text) => {
+            file.write_all(text.as_bytes()).await?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(text.as_bytes());
+            }
+            size = text.len() as u64;
+        }
+        Content::Base64(encoded) => {
+            let mut decoded = Vec::with_capacity(DECODE_CHUNK / 4 * 3);
+            for (index, chunk) in encoded.as_bytes().chunks(DECODE_CHUNK).enumerate() {
+                decoded.clear();
+                general_purpose::STANDARD.decode_vec(chunk, &mut decoded)
+                    .map_err(|e| WriteError::Decode(format!("{} (in chunk starting at byte {})", e, index * DECODE_CHUNK)))?;
+                file.write_all(&decoded).await?;
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&decoded);
+                }
+                size += decoded.len() as u64;
+            }
+        }
+    }
+    file.flush().await?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::{metrics::Metrics, Logger};
+    use crate::protocol::ExecAssignment;
+    use std::sync::Arc;
+    use tokio::time::{Duration, Instant};
+    use tokio_util::sync::CancellationToken;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fs-blob-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn context() -> ExecContext {
+        let assignment: ExecAssignment = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+            "job": {"type": "fs_blob_put", "payload": {}}
+        })).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        ExecContext::for_assignment(&assignment, Logger::new("w1".to_string()), Arc::new(Metrics::new()), CancellationToken::new(), deadline)
+    }
+
+    fn cas_put(content: &str) -> Job {
+        Job { r#type: "fs_blob_put".to_string(), payload: json!({"mode": "cas", "content": content}) }
+    }
+
+    fn cas_blobs(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join(CAS_DIR)).unwrap().flatten()
+            .filter(|shard| shard.path().is_dir())
+            .map(|shard| std::fs::read_dir(shard.path()).unwrap().count())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_multi_megabyte_blob_round_trips_in_chunks() {
+        let dir = temp_dir();
+        // Not a multiple of either chunk size, so the padded tail is exercised too
+        let original: Vec<u8> = (0..5 * 1024 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+        let encoded = general_purpose::STANDARD.encode(&original);
+
+        let path = dir.join("nested").join("blob.bin");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let written = write_atomically(&path, Content::Base64(&encoded)).await.ok().unwrap();
+        assert_eq!(written, original.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        let (read_back, size) = read_base64(&path).await.unwrap();
+        assert_eq!(size, original.len() as u64);
+        assert_eq!(read_back, encoded);
+        assert_eq!(read_back.capacity(), encoded.len(), "output buffer is sized up front");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_bad_base64_leaves_no_partial_file() {
+        let dir = temp_dir();
+        let path = dir.join("blob.bin");
+        std::fs::write(&path, b"previous").unwrap();
+        // Valid first chunk, garbage in the second
+        let encoded = format!("{}!!!!", general_purpose::STANDARD.encode(vec![0u8; DECODE_CHUNK / 4 * 3]));
+        let Err(WriteError::Decode(e)) = write_atomically(&path, Content::Base64(&encoded)).await else {
+            panic!("expected a decode error");
+        };
+        assert!(e.contains(&format!("starting at byte {}", DECODE_CHUNK)), "{}", e);
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "temp file removed");
+
+        assert!(matches!(read_base64(&dir.join("missing")).await, Err(e) if e.kind() == std::io::ErrorKind::NotFound));
+        std::fs::write(&path, b"").unwrap();
+        let (empty, size) = read_base64(&path).await.unwrap();
+        assert_eq!((empty.as_str(), size), ("", 0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_cas_puts_store_identical_content_once() {
+        let dir = temp_dir();
+        let base = dir.to_str().unwrap();
+        let ctx = context();
+        let first = handle_fs_blob_put(&ctx, base, None, &cas_put("hello")).await.output.unwrap();
+        let hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(first, json!({"hash": hash, "size": 5, "already_existed": false}));
+        let bytes = Job { r#type: "fs_blob_put".to_string(), payload: json!({"mode": "cas", "bytes": general_purpose::STANDARD.encode("hello")}) };
+        assert_eq!(handle_fs_blob_put(&ctx, base, None, &bytes).await.output.unwrap()["already_existed"], true);
+        assert_eq!(std::fs::read(dir.join("cas/2c").join(hash)).unwrap(), b"hello");
+        assert_eq!(cas_blobs(&dir), 1);
+
+        let get = Job { r#type: "fs_blob_get".to_string(), payload: json!({"hash": hash}) };
+        let output = handle_fs_blob_get(&ctx, base, &get).await.output.unwrap();
+        assert_eq!(output, json!({"hash": hash, "size": 5, "bytes": general_purpose::STANDARD.encode("hello")}));
+        let bad = Job { r#type: "fs_blob_get".to_string(), payload: json!({"hash": "../../etc/passwd"}) };
+        assert_eq!(handle_fs_blob_get(&ctx, base, &bad).await.error.unwrap().code, "INVALID_HASH");
+        let missing = Job { r#type: "fs_blob_get".to_string(), payload: json!({"hash": "0".repeat(64)}) };
+        assert_eq!(handle_fs_blob_get(&ctx, base, &missing).await.error.unwrap().code, "FILE_READ_ERROR");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_cas_puts_of_the_same_content() {
+        let dir = temp_dir();
+        let base = dir.to_string_lossy().to_string();
+        let content = "x".repeat(DECODE_CHUNK * 2);
+        let puts = (0..16).map(|_| {
+            let (base, content) = (base.clone(), content.clone());
+            tokio::spawn(async move { handle_fs_blob_put(&context(), &base, None, &cas_put(&content)).await.output.unwrap() })
+        });
+        let outputs: Vec<Value> = futures::future::join_all(puts).await.into_iter().map(|o| o.unwrap()).collect();
+
+        assert_eq!(outputs.iter().filter(|o| o["already_existed"] == false).count(), 1, "exactly one put stored it");
+        let hash = outputs[0]["hash"].as_str().unwrap();
+        assert!(outputs.iter().all(|o| o["hash"] == hash));
+        assert_eq!(std::fs::read_to_string(cas_path(&base, hash)).unwrap(), content);
+        assert_eq!(cas_blobs(&dir), 1);
+        assert_eq!(std::fs::read_dir(dir.join(CAS_DIR)).unwrap().count(), 1, "no temp files left behind");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_read_cas_blobs_are_evicted() {
+        let dir = temp_dir();
+        let base = dir.to_str().unwrap();
+        let ctx = context();
+        let mut hashes = Vec::new();
+        for content in ["aaaa", "bbbb", "cccc"] {
+            let output = handle_fs_blob_put(&ctx, base, Some(12), &cas_put(content)).await.output.unwrap();
+            hashes.push(output["hash"].as_str().unwrap().to_string());
+            // File times come from a coarse clock
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(cas_blobs(&dir), 3, "12 bytes fit");
+        let get = Job { r#type: "fs_blob_get".to_string(), payload: json!({"hash": hashes[0]}) };
+        assert!(handle_fs_blob_get(&ctx, base, &get).await.error.is_none());
+
+        handle_fs_blob_put(&ctx, base, Some(12), &cas_put("dddd")).await;
+        assert_eq!(cas_blobs(&dir), 3);
+        assert!(cas_path(base, &hashes[0]).exists(), "read since it was written");
+        assert!(!cas_path(base, &hashes[1]).exists(), "least recently used");
+        assert_eq!(ctx.metrics.fs_cas_evicted_total.get(), 1);
+
+        // A blob larger than the cap is still kept; everything else makes room
+        handle_fs_blob_put(&ctx, base, Some(12), &cas_put(&"e".repeat(20))).await;
+        assert_eq!(cas_blobs(&dir), 1);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,234 @@
+use crate::protocol::Job;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use super::{ExecContext, HandlerOutcome};
+
+const MAX_COUNT: u64 = 1_000;
+const MAX_LENGTH: u64 = 4_096;
+/// `count` times `length` (hex digits or lorem words), so a batch stays well under the result limits.
+const MAX_UNITS: u64 = 65_536;
+/// The default `random_int` upper bound: the largest integer JSON readers keep exact.
+const MAX_SAFE_INT: i64 = (1 << 53) - 1;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+const LOREM: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do", "eiusmod", "tempor",
+    "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua", "enim", "ad", "minim", "veniam", "quis", "nostrud",
+    "exercitation", "ullamco", "laboris", "nisi", "aliquip", "ex", "ea", "commodo", "consequat", "duis", "aute", "irure",
+    "in", "reprehenderit", "voluptate", "velit", "esse", "cillum", "fugiat", "nulla", "pariatur", "excepteur", "sint",
+    "occaecat", "cupidatat", "non", "proident", "sunt", "culpa", "qui", "officia", "deserunt", "mollit", "anim", "id", "est",
+];
+
+type GenerateError = (&'static str, String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    UuidV4,
+    UuidV7,
+    Ulid,
+    RandomHex,
+    RandomInt,
+    Lorem,
+}
+
+struct Request {
+    kind: Kind,
+    count: Option<u64>,
+    /// Hex digits for `random_hex`, words for `lorem`.
+    length: u64,
+    min: i64,
+    max: i64,
+    /// Timestamp of `uuid_v7` and `ulid` ids; the clock unless seeded.
+    timestamp_ms: Option<u64>,
+    seed: Option<[u8; 32]>,
+}
+
+impl Request {
+    fn parse(payload: &Value) -> Result<Self, GenerateError> {
+        let kind = match payload.get("kind").and_then(|v| v.as_str()) {
+            Some("uuid_v4") => Kind::UuidV4,
+            Some("uuid_v7") => Kind::UuidV7,
+            Some("ulid") => Kind::Ulid,
+            Some("random_hex") => Kind::RandomHex,
+            Some("random_int") => Kind::RandomInt,
+            Some("lorem") => Kind::Lorem,
+            _ => return Err(("INVALID_KIND", "'kind' must be one of uuid_v4, uuid_v7, ulid, random_hex, random_int, lorem".to_string())),
+        };
+        let count = match payload.get("count") {
+            None => None,
+            Some(v) => Some(v.as_u64().filter(|n| (1..=MAX_COUNT).contains(n))
+                .ok_or(("INVALID_COUNT", format!("'count' must be between 1 and {}", MAX_COUNT)))?),
+        };
+        let default_length = if kind == Kind::Lorem { 8 } else { 32 };
+        let length = match payload.get("length") {
+            None => default_length,
+            Some(v) => v.as_u64().filter(|n| (1..=MAX_LENGTH).contains(n))
+                .ok_or(("INVALID_LENGTH", format!("'length' must be between 1 and {}", MAX_LENGTH)))?,
+        };
+        if matches!(kind, Kind::RandomHex | Kind::Lorem) && count.unwrap_or(1) * length > MAX_UNITS {
+            return Err(("OUTPUT_TOO_LARGE", format!("'count' times 'length' must stay within {}", MAX_UNITS)));
+        }
+        let bound = |field: &str, default: i64| match payload.get(field) {
+            None => Ok(default),
+            Some(v) => v.as_i64().ok_or(("INVALID_RANGE", format!("'{}' must be an integer", field))),
+        };
+        let (min, max) = (bound("min", 0)?, bound("max", MAX_SAFE_INT)?);
+        if min > max {
+            return Err(("INVALID_RANGE", "'min' must not exceed 'max'".to_string()));
+        }
+        let timestamp_ms = match payload.get("timestamp_ms") {
+            None => None,
+            Some(v) => Some(v.as_u64().filter(|ms| *ms < 1 << 48)
+                .ok_or(("INVALID_TIMESTAMP", "'timestamp_ms' must be a 48-bit epoch millisecond count".to_string()))?),
+        };
+        // Integers seed directly; any other value is hashed, so `"run-42"` works as well as `42`
+        let seed = payload.get("seed").map(|seed| match seed.as_u64() {
+            Some(n) => {
+                let mut bytes = [0u8; 32];
+                ChaCha8Rng::seed_from_u64(n).fill_bytes(&mut bytes);
+                bytes
+            }
+            None => Sha256::digest(seed.to_string().as_bytes()).into(),
+        });
+        Ok(Self { kind, count, length, min, max, timestamp_ms, seed })
+    }
+
+    fn timestamp_ms(&self) -> u64 {
+        match (self.timestamp_ms, self.seed) {
+            (Some(ms), _) => ms,
+            // Replays must not depend on when they run
+            (None, Some(_)) => 0,
+            (None, None) => chrono::Utc::now().timestamp_millis().max(0) as u64,
+        }
+    }
+
+    fn value(&self, rng: &mut dyn RngCore) -> Value {
+        match self.kind {
+            Kind::UuidV4 => {
+                let mut bytes = [0u8; 16];
+                rng.fill_bytes(&mut bytes);
+                json!(uuid::Builder::from_random_bytes(bytes).into_uuid().to_string())
+            }
+            Kind::UuidV7 => {
+                let mut bytes = [0u8; 10];
+                rng.fill_bytes(&mut bytes);
+                json!(uuid::Builder::from_unix_timestamp_millis(self.timestamp_ms(), &bytes).into_uuid().to_string())
+            }
+            Kind::Ulid => {
+                let mut bytes = [0u8; 16];
+                rng.fill_bytes(&mut bytes[6..]);
+                json!(ulid(self.timestamp_ms(), u128::from_be_bytes(bytes)))
+            }
+            Kind::RandomHex => {
+                let hex: String = (0..self.length).map(|_| char::from(b"0123456789abcdef"[rng.gen_range(0..16)])).collect();
+                json!(hex)
+            }
+            Kind::RandomInt => json!(rng.gen_range(self.min..=self.max)),
+            Kind::Lorem => {
+                let words: Vec<&str> = (0..self.length).map(|_| LOREM[rng.gen_range(0..LOREM.len())]).collect();
+                json!(words.join(" "))
+            }
+        }
+    }
+}
+
+/// Crockford base32 of a 48-bit millisecond timestamp followed by 80 random bits.
+fn ulid(timestamp_ms: u64, random: u128) -> String {
+    let value = (u128::from(timestamp_ms) << 80) | (random & ((1 << 80) - 1));
+    (0..26).map(|i| char::from(CROCKFORD[((value >> (125 - 5 * i)) & 31) as usize])).collect()
+}
+
+/// Ids and filler data for test and seeding flows: one value, or an array when `count` is
+/// given. A `seed` replays the same output (`uuid_v7`/`ulid` then stamp `timestamp_ms`,
+/// default the Unix epoch); without one the OS RNG is used.
+pub async fn handle_generate(_ctx: &ExecContext, job: &Job) -> HandlerOutcome {
+    let request = match Request::parse(&job.payload) {
+        Ok(request) => request,
+        Err((code, message)) => return HandlerOutcome::error(code, message),
+    };
+    HandlerOutcome::success(generate(&request))
+}
+
+fn generate(request: &Request) -> Value {
+    let mut seeded = request.seed.map(ChaCha8Rng::from_seed);
+    let rng: &mut dyn RngCore = match seeded.as_mut() {
+        Some(rng) => rng,
+        None => &mut OsRng,
+    };
+    match request.count {
+        None => request.value(rng),
+        Some(count) => Value::Array((0..count).map(|_| request.value(rng)).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn run(payload: Value) -> Value {
+        generate(&Request::parse(&payload).unwrap_or_else(|e| panic!("{:?}", e)))
+    }
+
+    fn failure(payload: Value) -> &'static str {
+        Request::parse(&payload).err().unwrap().0
+    }
+
+    #[test]
+    fn test_seeded_output_replays() {
+        for kind in ["uuid_v4", "uuid_v7", "ulid", "random_hex", "random_int", "lorem"] {
+            let first = run(json!({"kind": kind, "count": 5, "seed": 42}));
+            assert_eq!(first, run(json!({"kind": kind, "count": 5, "seed": 42})), "{}", kind);
+            assert_ne!(first, run(json!({"kind": kind, "count": 5, "seed": 43})), "{}", kind);
+            assert_eq!(run(json!({"kind": kind, "seed": "run-7"})), run(json!({"kind": kind, "seed": "run-7"})), "{}", kind);
+        }
+        let v7 = run(json!({"kind": "uuid_v7", "seed": 1, "timestamp_ms": 1768478400000_u64}));
+        let parsed = uuid::Uuid::parse_str(v7.as_str().unwrap()).unwrap();
+        assert_eq!(parsed.get_version_num(), 7);
+        assert_eq!(parsed.get_timestamp().unwrap().to_unix(), (1768478400, 0));
+        assert!(run(json!({"kind": "ulid", "seed": 1})).as_str().unwrap().starts_with("0000000000"), "epoch timestamp");
+    }
+
+    #[test]
+    fn test_unseeded_values_are_unique_and_well_formed() {
+        for kind in ["uuid_v4", "uuid_v7", "ulid", "random_hex"] {
+            let values = run(json!({"kind": kind, "count": 1000}));
+            let unique: HashSet<&str> = values.as_array().unwrap().iter().map(|v| v.as_str().unwrap()).collect();
+            assert_eq!(unique.len(), 1000, "{}", kind);
+        }
+        let v4 = run(json!({"kind": "uuid_v4"}));
+        assert_eq!(uuid::Uuid::parse_str(v4.as_str().unwrap()).unwrap().get_version_num(), 4);
+        let ulid = run(json!({"kind": "ulid"}));
+        assert!(ulid.as_str().unwrap().len() == 26 && ulid.as_str().unwrap().bytes().all(|b| CROCKFORD.contains(&b)));
+
+        let hex = run(json!({"kind": "random_hex", "length": 7}));
+        assert!(hex.as_str().unwrap().len() == 7 && hex.as_str().unwrap().chars().all(|c| c.is_ascii_hexdigit()));
+        let ints = run(json!({"kind": "random_int", "count": 200, "min": -3, "max": 3}));
+        assert!(ints.as_array().unwrap().iter().all(|n| (-3..=3).contains(&n.as_i64().unwrap())));
+        assert_eq!(run(json!({"kind": "random_int", "min": 9, "max": 9})), json!(9));
+        assert_eq!(run(json!({"kind": "lorem", "length": 12})).as_str().unwrap().split(' ').count(), 12);
+    }
+
+    #[test]
+    fn test_ulid_encoding() {
+        assert_eq!(ulid(0, 0), "00000000000000000000000000");
+        assert_eq!(ulid(1, 0), "00000000010000000000000000");
+        assert_eq!(ulid((1 << 48) - 1, u128::MAX), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+    }
+
+    #[test]
+    fn test_caps_and_invalid_requests() {
+        assert_eq!(failure(json!({"kind": "uuid_v4", "count": 1001})), "INVALID_COUNT");
+        assert_eq!(failure(json!({"kind": "uuid_v4", "count": 0})), "INVALID_COUNT");
+        assert_eq!(failure(json!({"kind": "random_hex", "length": 4097})), "INVALID_LENGTH");
+        assert_eq!(failure(json!({"kind": "lorem", "count": 100, "length": 1000})), "OUTPUT_TOO_LARGE");
+        assert_eq!(run(json!({"kind": "random_hex", "count": 16, "length": 4096})).as_array().unwrap().len(), 16);
+        assert_eq!(failure(json!({"kind": "random_int", "min": 5, "max": 1})), "INVALID_RANGE");
+        assert_eq!(failure(json!({"kind": "ulid", "timestamp_ms": 1_u64 << 48})), "INVALID_TIMESTAMP");
+        assert_eq!(failure(json!({"kind": "snowflake"})), "INVALID_KIND");
+    }
+}
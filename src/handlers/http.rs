@@ -1,43 +1,44 @@
-use crate::protocol::{ExecStatus, Job};
+use crate::observability::{telemetry, truncate::truncate_value};
+use crate::protocol::Job;
+use crate::retry::{retry_with_backoff, RetryPolicy};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, LINK};
 use serde_json::{Value, json};
-use super::HandlerResult;
-use tokio::time::sleep;
 use std::time::Duration;
+use super::fs::sandboxed_path;
+use super::{ExecContext, HandlerOutcome};
 
-pub async fn handle_http(client: &reqwest::Client, job: &Job) -> HandlerResult {
+/// Time left on the job deadline below which pagination stops instead of starting another page.
+const PAGE_DEADLINE_MARGIN: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_PAGES: u64 = 100;
+const MAX_PAGES_LIMIT: u64 = 1000;
+const DEFAULT_MAX_ITEMS: u64 = 10_000;
+
+pub async fn handle_http(ctx: &ExecContext, client: &reqwest::Client, retry: &RetryPolicy, job: &Job) -> HandlerOutcome {
     let url = match job.payload.get("url").and_then(|v| v.as_str()) {
         Some(u) => u,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_URL".to_string()),
-            Some("Missing 'url' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_URL", "Missing 'url' in payload"),
     };
 
     let method_str = job.payload.get("method").and_then(|v| v.as_str()).unwrap_or("GET");
-    let method = match reqwest::Method::from_bytes(method_str.as_bytes()) {
-        Ok(m) => m,
-        Err(_) => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("INVALID_METHOD".to_string()),
-            Some(format!("Invalid HTTP method: {}", method_str))
-        ),
+    let method = match parse_method(method_str) {
+        Some(m) => m,
+        None => return HandlerOutcome::error("INVALID_METHOD", format!("Invalid HTTP method: {}", method_str)),
+    };
+    let headers = match request_headers(job) {
+        Ok(headers) => with_traceparent(ctx, headers),
+        Err(message) => return HandlerOutcome::error("INVALID_HEADER", message),
+    };
+    let pagination = match job.payload.get("paginate") {
+        Some(options) => match Pagination::parse(options) {
+            Ok(pagination) => Some(pagination),
+            Err(message) => return HandlerOutcome::error("INVALID_PAGINATE", message),
+        },
+        None => None,
     };
 
-    let mut req_builder = client.request(method, url);
-
-    if let Some(headers) = job.payload.get("headers").and_then(|v| v.as_object()) {
-        for (k, v) in headers {
-            if let Some(v_str) = v.as_str() {
-                req_builder = req_builder.header(k, v_str);
-            }
-        }
-    }
+    let mut req_builder = client.request(method, url).timeout(ctx.remaining()).headers(headers);
 
+    // Any method may carry a body, including GET (search APIs) and extension methods
     if let Some(body) = job.payload.get("body") {
         if let Some(s) = body.as_str() {
              req_builder = req_builder.body(s.to_string());
@@ -48,215 +49,503 @@ pub async fn handle_http(client: &reqwest::Client, job: &Job) -> HandlerResult {
 
     let request = match req_builder.build() {
         Ok(r) => r,
-        Err(e) => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("REQUEST_BUILD_ERROR".to_string()),
-            Some(e.to_string())
-        ),
+        Err(e) => return HandlerOutcome::error("REQUEST_BUILD_ERROR", e.to_string()),
     };
+    if let Some(pagination) = pagination {
+        return paginate(ctx, client, retry, request, &pagination).await;
+    }
+
+    match send_with_retry(ctx, client, retry, request).await {
+        Ok(res) => process_response(res, max_body_bytes(job)).await,
+        Err(e) => HandlerOutcome::error("HTTP_REQUEST_FAILED", e.to_string()).retryable(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PageMode {
+    /// The next cursor is read from the body with `cursor_path` and sent as `cursor_param`.
+    Cursor,
+    /// The next page is the `rel="next"` URL of the `Link` header.
+    LinkHeader,
+    /// `cursor_param` is set to the number of items received so far.
+    Offset,
+}
+
+/// `payload.paginate`. Expressions are kept as text and compiled where used, since a compiled
+/// JMESPath expression can't be held across an await in a `Send` future.
+#[derive(Debug)]
+struct Pagination {
+    mode: PageMode,
+    cursor_path: Option<String>,
+    cursor_param: String,
+    /// Where a page's items are; the body itself when unset.
+    items_path: Option<String>,
+    max_pages: u64,
+    max_items: usize,
+}
 
-    let mut attempt = 0;
-    let max_retries = 3;
+impl Pagination {
+    fn parse(options: &Value) -> Result<Self, String> {
+        let text = |key: &str| options.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        let mode = match options.get("mode").and_then(|v| v.as_str()) {
+            Some("cursor") => PageMode::Cursor,
+            Some("link_header") => PageMode::LinkHeader,
+            Some("offset") => PageMode::Offset,
+            _ => return Err("'paginate.mode' must be one of cursor, link_header, offset".to_string()),
+        };
+        let cursor_path = text("cursor_path");
+        if mode == PageMode::Cursor && cursor_path.is_none() {
+            return Err("'paginate.cursor_path' is required in cursor mode".to_string());
+        }
+        let items_path = text("items_path");
+        for expression in cursor_path.iter().chain(items_path.iter()) {
+            jmespath::compile(expression).map_err(|e| format!("Invalid expression '{}': {}", expression, e))?;
+        }
+        let default_param = if mode == PageMode::Offset { "offset" } else { "cursor" };
+        let max_pages = options.get("max_pages").and_then(|v| v.as_u64()).unwrap_or(DEFAULT_MAX_PAGES);
+        if !(1..=MAX_PAGES_LIMIT).contains(&max_pages) {
+            return Err(format!("'paginate.max_pages' must be between 1 and {}", MAX_PAGES_LIMIT));
+        }
+        let max_items = options.get("max_items").and_then(|v| v.as_u64()).unwrap_or(DEFAULT_MAX_ITEMS);
+        if max_items == 0 {
+            return Err("'paginate.max_items' must be at least 1".to_string());
+        }
+        Ok(Self {
+            mode,
+            cursor_path,
+            cursor_param: text("cursor_param").unwrap_or_else(|| default_param.to_string()),
+            items_path,
+            max_pages,
+            max_items: max_items as usize,
+        })
+    }
+}
 
-    loop {
-        let req_clone = match request.try_clone() {
-            Some(r) => r,
-            None => {
-                 // Cannot clone (e.g. stream body), execute once
-                 return execute_request(client, request, job).await;
+/// Evaluates an expression `Pagination::parse` already compiled once.
+fn search(expression: &str, data: &Value) -> Result<Value, String> {
+    let expression = jmespath::compile(expression).map_err(|e| e.to_string())?;
+    let found = expression.search(data).map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(&*found).unwrap_or(Value::Null))
+}
+
+/// Fetches pages of `first` until one is empty, there is no next page or a limit is hit, and
+/// returns `{items, pages_fetched, truncated}`. Every page goes through the usual retries and
+/// gets what is left of the job deadline; with too little left it stops with `truncated`.
+async fn paginate(ctx: &ExecContext, client: &reqwest::Client, retry: &RetryPolicy, first: reqwest::Request, pagination: &Pagination) -> HandlerOutcome {
+    let base_url = first.url().clone();
+    let mut next = Some(first);
+    let mut items: Vec<Value> = Vec::new();
+    let mut pages_fetched = 0u64;
+    let mut truncated = false;
+    while let Some(mut request) = next.take() {
+        if pages_fetched >= pagination.max_pages || items.len() >= pagination.max_items {
+            truncated = true;
+            break;
+        }
+        let remaining = ctx.remaining();
+        if remaining <= PAGE_DEADLINE_MARGIN {
+            ctx.info("Stopping pagination before the job deadline", Some(json!({"pages_fetched": pages_fetched})));
+            truncated = true;
+            break;
+        }
+        *request.timeout_mut() = Some(remaining - PAGE_DEADLINE_MARGIN);
+        let template = request.try_clone();
+        let response = match send_with_retry(ctx, client, retry, request).await {
+            Ok(response) => response,
+            Err(e) if e.is_timeout() && pages_fetched > 0 => {
+                truncated = true;
+                break;
             }
+            Err(e) => return HandlerOutcome::error("HTTP_REQUEST_FAILED", e.to_string()).retryable(),
+        };
+        let status = response.status();
+        if !status.is_success() {
+            let failure = HandlerOutcome::error("HTTP_PAGE_FAILED", format!("Page {} answered {}", pages_fetched + 1, status));
+            return if status.is_server_error() { failure.retryable() } else { failure };
+        }
+        let page_url = response.url().clone();
+        let next_link = next_link(response.headers());
+        let body: Value = match response.json().await {
+            Ok(body) => body,
+            Err(e) => return HandlerOutcome::error("HTTP_PAGE_INVALID", format!("Page {} is not JSON: {}", pages_fetched + 1, e)),
+        };
+        let page_items = match &pagination.items_path {
+            Some(path) => match search(path, &body) {
+                Ok(found) => found,
+                Err(e) => return HandlerOutcome::error("HTTP_PAGE_INVALID", e),
+            },
+            None => body.clone(),
         };
+        let page_items = match page_items {
+            Value::Array(page_items) => page_items,
+            Value::Null => Vec::new(),
+            _ => return HandlerOutcome::error("HTTP_PAGE_INVALID", format!("Items of page {} are not an array", pages_fetched + 1)),
+        };
+        pages_fetched += 1;
+        if page_items.is_empty() {
+            break;
+        }
+        let room = pagination.max_items - items.len();
+        if page_items.len() > room {
+            truncated = true;
+        }
+        items.extend(page_items.into_iter().take(room));
 
-        match client.execute(req_clone).await {
-            Ok(res) => {
-                if res.status().is_server_error() {
-                    if attempt < max_retries {
-                        attempt += 1;
-                        let backoff = Duration::from_millis(100 * 2_u64.pow(attempt));
-                        sleep(backoff).await;
-                        continue;
-                    }
-                }
-                // Success or client error, or max retries reached for server error
-                return process_response(res, job).await;
+        let next_url = match pagination.mode {
+            PageMode::Cursor => match search(pagination.cursor_path.as_deref().unwrap_or_default(), &body) {
+                Ok(Value::String(cursor)) if !cursor.is_empty() => Some(with_query_param(&base_url, &pagination.cursor_param, &cursor)),
+                Ok(Value::Number(cursor)) => Some(with_query_param(&base_url, &pagination.cursor_param, &cursor.to_string())),
+                _ => None,
             },
-            Err(e) => {
-                if attempt < max_retries {
-                    attempt += 1;
-                    let backoff = Duration::from_millis(100 * 2_u64.pow(attempt));
-                    sleep(backoff).await;
-                    continue;
-                }
-                return (
-                    ExecStatus::Error,
-                    job.r#type.clone(),
-                    None,
-                    Some("HTTP_REQUEST_FAILED".to_string()),
-                    Some(e.to_string())
-                );
+            PageMode::LinkHeader => next_link.and_then(|link| page_url.join(&link).ok()),
+            PageMode::Offset => Some(with_query_param(&base_url, &pagination.cursor_param, &items.len().to_string())),
+        };
+        if truncated {
+            break;
+        }
+        next = match (next_url, template) {
+            (Some(url), Some(mut request)) => {
+                *request.url_mut() = url;
+                Some(request)
             }
+            (Some(_), None) => return HandlerOutcome::error("REQUEST_BUILD_ERROR", "A streaming body can't be sent for every page"),
+            (None, _) => None,
+        };
+    }
+    HandlerOutcome::success(json!({"items": items, "pages_fetched": pages_fetched, "truncated": truncated}))
+}
+
+/// `url` with `name` set to `value`, replacing any earlier value.
+fn with_query_param(url: &reqwest::Url, name: &str, value: &str) -> reqwest::Url {
+    let kept: Vec<(String, String)> = url.query_pairs().filter(|(key, _)| key != name).map(|(k, v)| (k.into_owned(), v.into_owned())).collect();
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(kept).append_pair(name, value);
+    url
+}
+
+/// The `rel="next"` target of the `Link` headers, e.g. `<https://api/items?page=2>; rel="next"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(LINK).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let (target, params) = link.trim().split_once(';')?;
+            let target = target.trim().strip_prefix('<')?.strip_suffix('>')?;
+            let is_next = params.split(';').any(|param| {
+                let Some((key, value)) = param.split_once('=') else { return false };
+                key.trim().eq_ignore_ascii_case("rel") && value.trim().trim_matches('"').split_whitespace().any(|rel| rel.eq_ignore_ascii_case("next"))
+            });
+            is_next.then(|| target.to_string())
+        })
+}
+
+/// Standard methods match case-insensitively; anything else that is a valid token is sent
+/// exactly as written (`PURGE`, `PROPFIND`, ...).
+fn parse_method(method: &str) -> Option<reqwest::Method> {
+    let upper = method.to_ascii_uppercase();
+    let standard = ["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "CONNECT", "PATCH", "TRACE"];
+    let method = if standard.contains(&upper.as_str()) { upper.as_str() } else { method };
+    reqwest::Method::from_bytes(method.as_bytes()).ok()
+}
+
+/// The request body; the error is a code and message. A file input is checked against its
+/// limit before it is read.
+async fn graphql_body(base_dir: &str, limits: GraphqlLimits, payload: &Value) -> Result<Value, (&'static str, String)> {
+    let query = match graphql_input(base_dir, payload, "query", limits.max_query_bytes).await? {
+        Some(GraphqlInput::Inline(Value::String(query))) => query,
+        Some(GraphqlInput::Inline(_)) => return Err(("MISSING_QUERY", "'query' must be a string".to_string())),
+        Some(GraphqlInput::File(query)) => query,
+        None => return Err(("MISSING_QUERY", "Missing 'query' or 'query_path' in payload".to_string())),
+    };
+    let variables = match graphql_input(base_dir, payload, "variables", limits.max_variables_bytes).await? {
+        Some(GraphqlInput::Inline(variables)) => variables,
+        Some(GraphqlInput::File(text)) => serde_json::from_str(&text)
+            .map_err(|e| ("INPUT_FILE_ERROR", format!("variables_path does not hold JSON: {}", e)))?,
+        None => json!({}),
+    };
+    let operation_name = payload.get("operationName").and_then(|v| v.as_str());
+    Ok(json!({
+        "query": query,
+        "variables": variables,
+        "operationName": operation_name
+    }))
+}
+
+enum GraphqlInput {
+    Inline(Value),
+    File(String),
+}
+
+/// `field` inline or the contents of the file named by `<field>_path`, which must not both
+/// be given, at most `max_bytes` long either way (inline JSON as encoded).
+async fn graphql_input(base_dir: &str, payload: &Value, field: &str, max_bytes: u64) -> Result<Option<GraphqlInput>, (&'static str, String)> {
+    let path_field = format!("{}_path", field);
+    let too_large = |size: u64, source: &str| ("INPUT_TOO_LARGE", format!("{} is {} bytes, over the {} byte limit", source, size, max_bytes));
+    match (payload.get(field), payload.get(&path_field)) {
+        (Some(_), Some(_)) => Err(("CONFLICTING_INPUT", format!("Give either '{}' or '{}', not both", field, path_field))),
+        (Some(inline), None) => {
+            let size = match inline {
+                Value::String(s) => s.len() as u64,
+                other => serde_json::to_vec(other).map(|v| v.len() as u64).unwrap_or(u64::MAX),
+            };
+            if size > max_bytes {
+                return Err(too_large(size, field));
+            }
+            Ok(Some(GraphqlInput::Inline(inline.clone())))
         }
+        (None, Some(path)) => {
+            let full_path = path.as_str()
+                .and_then(|p| sandboxed_path(base_dir, p))
+                .ok_or_else(|| ("INVALID_PATH", format!("{} must be a relative path without '..'", path_field)))?;
+            let file_error = |e: std::io::Error| ("INPUT_FILE_ERROR", format!("{}: {}", path_field, e));
+            let size = tokio::fs::metadata(&full_path).await.map_err(file_error)?.len();
+            if size > max_bytes {
+                return Err(too_large(size, &path_field));
+            }
+            Ok(Some(GraphqlInput::File(tokio::fs::read_to_string(&full_path).await.map_err(file_error)?)))
+        }
+        (None, None) => Ok(None),
     }
 }
 
-async fn execute_request(client: &reqwest::Client, req: reqwest::Request, job: &Job) -> HandlerResult {
-    match client.execute(req).await {
-        Ok(res) => process_response(res, job).await,
-        Err(e) => (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("HTTP_REQUEST_FAILED".to_string()),
-            Some(e.to_string())
-        )
+/// `payload.headers`: each value is a string or an array of strings, sent as repeated headers.
+/// The error is the `INVALID_HEADER` message.
+fn request_headers(job: &Job) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    let Some(requested) = job.payload.get("headers") else {
+        return Ok(headers);
+    };
+    let Some(requested) = requested.as_object() else {
+        return Err("'headers' must be an object".to_string());
+    };
+    for (name, value) in requested {
+        let values: Vec<&str> = match value {
+            Value::String(s) => vec![s.as_str()],
+            Value::Array(items) if items.iter().all(Value::is_string) => items.iter().filter_map(Value::as_str).collect(),
+            _ => return Err(format!("Header {} must be a string or an array of strings", name)),
+        };
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name: {}", name))?;
+        for v in values {
+            let header_value = HeaderValue::from_str(v)
+                .map_err(|_| format!("Invalid value for header {}", name))?;
+            headers.append(header_name.clone(), header_value);
+        }
     }
+    Ok(headers)
 }
 
-async fn process_response(res: reqwest::Response, job: &Job) -> HandlerResult {
-    let status_code = res.status().as_u16();
-    let headers_map = res.headers().clone();
-    let mut headers_json = serde_json::Map::new();
-    for (k, v) in headers_map.iter() {
-            if let Ok(v_str) = v.to_str() {
-                headers_json.insert(k.to_string(), Value::String(v_str.to_string()));
+/// Adds a `traceparent` continuing the assignment's trace, unless the job sent its own or the
+/// trace id is not in W3C form.
+fn with_traceparent(ctx: &ExecContext, mut headers: HeaderMap) -> HeaderMap {
+    let traceparent = HeaderName::from_static("traceparent");
+    if !headers.contains_key(&traceparent) {
+        if let Some(value) = ctx.trace_id.as_deref().and_then(telemetry::traceparent).and_then(|v| HeaderValue::from_str(&v).ok()) {
+            headers.insert(traceparent, value);
+        }
+    }
+    headers
+}
+
+/// Response headers by name: a string, or an array in received order when repeated.
+fn response_headers(headers: &HeaderMap) -> serde_json::Map<String, Value> {
+    let mut out = serde_json::Map::new();
+    for name in headers.keys() {
+        let mut values: Vec<Value> = headers.get_all(name).iter()
+            .map(|v| Value::String(String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        let value = if values.len() == 1 { values.remove(0) } else { Value::Array(values) };
+        out.insert(name.to_string(), value);
+    }
+    out
+}
+
+enum AttemptFailure {
+    ServerError(reqwest::Response),
+    Transport(reqwest::Error),
+}
+
+/// Sends `request`, retrying 5xx responses and transport errors while the backoff fits the job deadline.
+///
+/// Once retries run out a 5xx response is returned as-is for the caller to report.
+async fn send_with_retry(ctx: &ExecContext, client: &reqwest::Client, policy: &RetryPolicy, request: reqwest::Request) -> Result<reqwest::Response, reqwest::Error> {
+    if request.try_clone().is_none() {
+        // Streaming bodies cannot be replayed, so they get a single attempt
+        return client.execute(request).await;
+    }
+    let outcome = retry_with_backoff(policy, |failure, retry, delay| {
+        if delay >= ctx.remaining() {
+            return false;
+        }
+        let reason = match failure {
+            AttemptFailure::ServerError(res) => format!("status {}", res.status()),
+            AttemptFailure::Transport(e) => e.to_string(),
+        };
+        ctx.info("Retrying request", Some(json!({"attempt": retry, "reason": reason, "backoff_ms": delay.as_millis() as u64})));
+        true
+    }, |_| {
+        let attempt = request.try_clone().expect("request was cloneable above");
+        async move {
+            match client.execute(attempt).await {
+                Ok(res) if res.status().is_server_error() => Err(AttemptFailure::ServerError(res)),
+                Ok(res) => Ok(res),
+                Err(e) => Err(AttemptFailure::Transport(e)),
             }
+        }
+    }).await;
+    match outcome {
+        Ok(res) | Err(AttemptFailure::ServerError(res)) => Ok(res),
+        Err(AttemptFailure::Transport(e)) => Err(e),
     }
+}
+
+/// `payload.max_body_bytes`: a response body encoding to more is truncated and the output marked.
+fn max_body_bytes(job: &Job) -> Option<usize> {
+    job.payload.get("max_body_bytes").and_then(|v| v.as_u64()).map(|n| n as usize)
+}
+
+async fn process_response(res: reqwest::Response, max_body_bytes: Option<usize>) -> HandlerOutcome {
+    let status_code = res.status().as_u16();
+    let headers_json = response_headers(res.headers());
+    let final_url = res.url().to_string();
 
     let body_result = res.text().await.unwrap_or_default();
     let body_json = serde_json::from_str::<Value>(&body_result).unwrap_or(Value::String(body_result));
+    let (body_json, truncated) = match max_body_bytes {
+        Some(max) => truncate_value(&body_json, max),
+        None => (body_json, false),
+    };
 
-    let output = json!({
+    let mut output = json!({
         "status": status_code,
         "headers": headers_json,
+        "final_url": final_url,
         "body": body_json
     });
+    if truncated {
+        output["truncated"] = Value::Bool(true);
+    }
+
+    HandlerOutcome::success(output)
+}
 
-    (ExecStatus::Success, job.r#type.clone(), Some(output), None, None)
+/// Bounds of a graphql document and its variables, from `GRAPHQL_MAX_QUERY_BYTES` and
+/// `GRAPHQL_MAX_VARIABLES_BYTES`, whether inline or read from a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GraphqlLimits {
+    pub max_query_bytes: u64,
+    pub max_variables_bytes: u64,
 }
 
-pub async fn handle_graphql(client: &reqwest::Client, job: &Job) -> HandlerResult {
+impl Default for GraphqlLimits {
+    fn default() -> Self {
+        Self { max_query_bytes: 1024 * 1024, max_variables_bytes: 4 * 1024 * 1024 }
+    }
+}
+
+/// `query` / `variables` come inline or, for documents too big to ship in an assignment, as
+/// `query_path` / `variables_path` under `base_dir`.
+pub async fn handle_graphql(ctx: &ExecContext, client: &reqwest::Client, retry: &RetryPolicy, base_dir: &str, limits: GraphqlLimits, job: &Job) -> HandlerOutcome {
     let url = match job.payload.get("url").and_then(|v| v.as_str()) {
          Some(u) => u,
-         None => return (
-             ExecStatus::Error,
-             job.r#type.clone(),
-             None,
-             Some("MISSING_URL".to_string()),
-             Some("Missing 'url' in payload".to_string())
-         ),
+         None => return HandlerOutcome::error("MISSING_URL", "Missing 'url' in payload"),
     };
 
-    let query = match job.payload.get("query").and_then(|v| v.as_str()) {
-         Some(q) => q,
-         None => return (
-             ExecStatus::Error,
-             job.r#type.clone(),
-             None,
-             Some("MISSING_QUERY".to_string()),
-             Some("Missing 'query' in payload".to_string())
-         ),
+    let body = match graphql_body(base_dir, limits, &job.payload).await {
+        Ok(body) => body,
+        Err((code, message)) => return HandlerOutcome::error(code, message),
     };
-    
-    let default_vars = json!({});
-    let variables = job.payload.get("variables").unwrap_or(&default_vars);
-    let operation_name = job.payload.get("operationName").and_then(|v| v.as_str());
-
-    let mut req_builder = client.post(url);
-    
-    if let Some(headers) = job.payload.get("headers").and_then(|v| v.as_object()) {
-        for (k, v) in headers {
-            if let Some(v_str) = v.as_str() {
-                req_builder = req_builder.header(k, v_str);
-            }
-        }
-    }
 
-    let body = json!({
-        "query": query,
-        "variables": variables,
-        "operationName": operation_name
-    });
+    let headers = match request_headers(job) {
+        Ok(headers) => with_traceparent(ctx, headers),
+        Err(message) => return HandlerOutcome::error("INVALID_HEADER", message),
+    };
+    let req_builder = client.post(url).timeout(ctx.remaining()).headers(headers);
 
     let request = match req_builder.json(&body).build() {
         Ok(r) => r,
-        Err(e) => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("REQUEST_BUILD_ERROR".to_string()),
-            Some(e.to_string())
-        ),
+        Err(e) => return HandlerOutcome::error("REQUEST_BUILD_ERROR", e.to_string()),
     };
 
-    let mut attempt = 0;
-    let max_retries = 3;
-
-    loop {
-        let req_clone = match request.try_clone() {
-            Some(r) => r,
-            None => {
-                 // Fallback to single execution
-                 return execute_graphql_request(client, request, job).await;
-            }
-        };
-
-        match client.execute(req_clone).await {
-            Ok(res) => {
-                if res.status().is_server_error() {
-                     if attempt < max_retries {
-                         attempt += 1;
-                         let backoff = Duration::from_millis(100 * 2_u64.pow(attempt));
-                         sleep(backoff).await;
-                         continue;
-                     }
-                }
-                return process_graphql_response(res, job).await;
-            },
-            Err(e) => {
-                if attempt < max_retries {
-                    attempt += 1;
-                    let backoff = Duration::from_millis(100 * 2_u64.pow(attempt));
-                    sleep(backoff).await;
-                    continue;
-                }
-                return (
-                     ExecStatus::Error,
-                     job.r#type.clone(),
-                     None,
-                     Some("GRAPHQL_REQUEST_FAILED".to_string()),
-                     Some(e.to_string())
-                );
-            }
-        }
+    match send_with_retry(ctx, client, retry, request).await {
+        Ok(res) => process_graphql_response(res).await,
+        Err(e) => HandlerOutcome::error("GRAPHQL_REQUEST_FAILED", e.to_string()).retryable(),
     }
 }
 
-async fn execute_graphql_request(client: &reqwest::Client, req: reqwest::Request, job: &Job) -> HandlerResult {
-    match client.execute(req).await {
-        Ok(res) => process_graphql_response(res, job).await,
-        Err(e) => (
-             ExecStatus::Error,
-             job.r#type.clone(),
-             None,
-             Some("GRAPHQL_REQUEST_FAILED".to_string()),
-             Some(e.to_string())
-        )
-    }
-}
-
-async fn process_graphql_response(res: reqwest::Response, job: &Job) -> HandlerResult {
+async fn process_graphql_response(res: reqwest::Response) -> HandlerOutcome {
     let body_json: Value = match res.json().await {
         Ok(v) => v,
-        Err(e) => return (
-             ExecStatus::Error,
-             job.r#type.clone(),
-             None,
-             Some("GRAPHQL_RESPONSE_PARSE_ERROR".to_string()),
-             Some(e.to_string())
-        ),
+        Err(e) => return HandlerOutcome::error("GRAPHQL_RESPONSE_PARSE_ERROR", e.to_string()),
     };
 
-    (ExecStatus::Success, job.r#type.clone(), Some(body_json), None, None)
+    HandlerOutcome::success(body_json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_link_is_found_among_other_relations() {
+        let mut headers = HeaderMap::new();
+        headers.append(LINK, HeaderValue::from_static(r#"<https://api.example/items?page=1>; rel="prev", <https://api.example/items?page=3>; rel="next""#));
+        assert_eq!(next_link(&headers).as_deref(), Some("https://api.example/items?page=3"));
+        let mut headers = HeaderMap::new();
+        headers.append(LINK, HeaderValue::from_static("</items?page=9>; rel=last"));
+        assert_eq!(next_link(&headers), None);
+        headers.append(LINK, HeaderValue::from_static("</items?page=2>; title=x; rel=\"next last\""));
+        assert_eq!(next_link(&headers).as_deref(), Some("/items?page=2"));
+    }
+
+    #[test]
+    fn test_pagination_options_are_validated() {
+        let parsed = Pagination::parse(&json!({"mode": "offset", "items_path": "data"})).unwrap();
+        assert_eq!((parsed.mode, parsed.cursor_param.as_str(), parsed.max_pages), (PageMode::Offset, "offset", DEFAULT_MAX_PAGES));
+        assert!(Pagination::parse(&json!({"mode": "cursor"})).unwrap_err().contains("cursor_path"));
+        assert!(Pagination::parse(&json!({"mode": "cursor", "cursor_path": "next["})).unwrap_err().contains("Invalid expression"));
+        assert!(Pagination::parse(&json!({"mode": "link_header", "max_pages": 0})).is_err());
+        assert!(Pagination::parse(&json!({"mode": "pages"})).is_err());
+
+        let url = reqwest::Url::parse("https://api.example/items?limit=10&cursor=old").unwrap();
+        assert_eq!(with_query_param(&url, "cursor", "a b").as_str(), "https://api.example/items?limit=10&cursor=a+b");
+    }
+
+    #[tokio::test]
+    async fn test_graphql_inputs_inline_from_files_and_bounded() {
+        let dir = std::env::temp_dir().join(format!("graphql-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("gql")).unwrap();
+        std::fs::write(dir.join("gql/report.graphql"), "query Report($id: ID!) { report(id: $id) { rows } }").unwrap();
+        std::fs::write(dir.join("gql/vars.json"), r#"{"id": "r-7"}"#).unwrap();
+        std::fs::write(dir.join("gql/broken.json"), "{").unwrap();
+        let base = dir.to_str().unwrap();
+        let limits = GraphqlLimits::default();
+
+        let inline = graphql_body(base, limits, &json!({"query": "{ me { id } }", "variables": {"a": 1}, "operationName": "Me"})).await.unwrap();
+        assert_eq!(inline, json!({"query": "{ me { id } }", "variables": {"a": 1}, "operationName": "Me"}));
+        let from_files = graphql_body(base, limits, &json!({"query_path": "gql/report.graphql", "variables_path": "gql/vars.json"})).await.unwrap();
+        assert_eq!(from_files["query"], "query Report($id: ID!) { report(id: $id) { rows } }");
+        assert_eq!(from_files["variables"], json!({"id": "r-7"}));
+        assert_eq!(graphql_body(base, limits, &json!({"query": "{ a }"})).await.unwrap()["variables"], json!({}));
+
+        let small = GraphqlLimits { max_query_bytes: 16, max_variables_bytes: 8 };
+        let error = |payload: Value| {
+            let base = base.to_string();
+            async move { graphql_body(&base, small, &payload).await.unwrap_err() }
+        };
+        let (code, message) = error(json!({"query_path": "gql/report.graphql"})).await;
+        assert_eq!(code, "INPUT_TOO_LARGE");
+        assert!(message.contains("query_path"), "{}", message);
+        assert_eq!(error(json!({"query": "{ a }", "variables": {"long": "value"}})).await.0, "INPUT_TOO_LARGE");
+        let (code, message) = error(json!({"query": "{ a }", "query_path": "gql/report.graphql"})).await;
+        assert_eq!(code, "CONFLICTING_INPUT");
+        assert!(message.contains("'query' or 'query_path'"), "{}", message);
+        let (code, message) = error(json!({"query": "{ a }", "variables_path": "gql/missing.json"})).await;
+        assert_eq!(code, "INPUT_FILE_ERROR");
+        assert!(message.starts_with("variables_path:"), "{}", message);
+        assert_eq!(error(json!({"query": "{ a }", "variables_path": "gql/broken.json"})).await.0, "INPUT_FILE_ERROR");
+        assert_eq!(error(json!({"query_path": "../etc/passwd"})).await.0, "INVALID_PATH");
+        assert_eq!(error(json!({"variables": {}})).await.0, "MISSING_QUERY");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
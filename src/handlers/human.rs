@@ -1,17 +1,11 @@
-use crate::protocol::{ExecStatus, Job};
+use crate::protocol::Job;
 use serde_json::json;
-use super::HandlerResult;
+use super::{ExecContext, HandlerOutcome};
 
-pub async fn handle_human_approval(job: &Job) -> HandlerResult {
+pub async fn handle_human_approval(_ctx: &ExecContext, job: &Job) -> HandlerOutcome {
     let prompt = match job.payload.get("prompt").and_then(|v| v.as_str()) {
         Some(p) => p,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_PROMPT".to_string()),
-            Some("Missing 'prompt' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_PROMPT", "Missing 'prompt' in payload"),
     };
 
     let default_options = json!(["Approve", "Reject"]);
@@ -27,5 +21,5 @@ pub async fn handle_human_approval(job: &Job) -> HandlerResult {
         "comment": "Auto-generated by worker stub"
     });
 
-    (ExecStatus::Success, job.r#type.clone(), Some(output), None, None)
+    HandlerOutcome::success(output)
 }
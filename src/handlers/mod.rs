@@ -1,11 +1,207 @@
-use crate::protocol::ExecStatus;
-use serde_json::Value;
+use crate::observability::{Logger, metrics::Metrics};
+use crate::protocol::{ExecAssignment, ExecStatus, Job};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
-pub type HandlerResult = (ExecStatus, String, Option<Value>, Option<String>, Option<String>);
+/// Per-assignment state handed to every handler.
+#[derive(Clone)]
+pub struct ExecContext {
+    pub assignment_id: String,
+    pub request_id: String,
+    pub tenant_id: String,
+    pub trace_id: Option<String>,
+    pub logger: Logger,
+    pub metrics: Arc<Metrics>,
+    /// Cancelled when the worker gives up on the assignment (e.g. on timeout).
+    pub cancel: CancellationToken,
+    pub deadline: Instant,
+}
 
+impl ExecContext {
+    /// The worker id is not repeated here: `logger` already stamps it on every entry.
+    pub fn for_assignment(
+        assignment: &ExecAssignment,
+        logger: Logger,
+        metrics: Arc<Metrics>,
+        cancel: CancellationToken,
+        deadline: Instant,
+    ) -> Self {
+        Self {
+            assignment_id: assignment.assignment_id.clone(),
+            request_id: assignment.request_id.clone(),
+            tenant_id: assignment.tenant_id.clone(),
+            trace_id: assignment.trace_id.clone(),
+            logger,
+            metrics,
+            cancel,
+            deadline,
+        }
+    }
+
+    /// Time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    pub fn info(&self, msg: &str, extra: Option<Value>) {
+        self.logger.info(msg, Some(&self.log_fields(extra)));
+    }
+
+    pub fn error(&self, msg: &str, extra: Option<Value>) {
+        self.logger.error(msg, Some(&self.log_fields(extra)));
+    }
+
+    fn log_fields(&self, extra: Option<Value>) -> Value {
+        let mut fields = json!({
+            "assignment_id": self.assignment_id,
+            "request_id": self.request_id,
+            "tenant_id": self.tenant_id,
+            "trace_id": self.trace_id,
+        });
+        if let (Some(obj), Some(Value::Object(extra))) = (fields.as_object_mut(), extra) {
+            obj.extend(extra);
+        }
+        fields
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HandlerError {
+    pub code: String,
+    pub message: String,
+    /// Whether resubmitting the same job could plausibly succeed; reported as the result's `retryable`.
+    pub retryable: bool,
+}
+
+/// What a handler produced; `Executor::execute` turns this into an `ExecResult`.
+#[derive(Debug, Clone)]
+pub struct HandlerOutcome {
+    pub status: ExecStatus,
+    pub output: Option<Value>,
+    pub error: Option<HandlerError>,
+    /// Overrides the executor's cost estimate when set.
+    pub cost: Option<f64>,
+    pub artifacts: Vec<Value>,
+}
+
+impl HandlerOutcome {
+    pub fn success(output: Value) -> Self {
+        Self {
+            status: ExecStatus::Success,
+            output: Some(output),
+            error: None,
+            cost: None,
+            artifacts: Vec::new(),
+        }
+    }
+    pub fn error<C: Into<String>, M: Into<String>>(code: C, message: M) -> Self {
+        Self::failure(ExecStatus::Error, code, message)
+    }
+    pub fn failure<C: Into<String>, M: Into<String>>(status: ExecStatus, code: C, message: M) -> Self {
+        Self {
+            status,
+            output: None,
+            error: Some(HandlerError { code: code.into(), message: message.into(), retryable: false }),
+            cost: None,
+            artifacts: Vec::new(),
+        }
+    }
+    pub fn retryable(mut self) -> Self {
+        if let Some(err) = self.error.as_mut() {
+            err.retryable = true;
+        }
+        self
+    }
+    pub fn with_cost(mut self, cost: f64) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+    /// Reported in the result's `artifacts`, after the output.
+    pub fn with_artifact(mut self, artifact: Value) -> Self {
+        self.artifacts.push(artifact);
+        self
+    }
+}
+
+/// A job type supplied by an application embedding the worker, registered through
+/// `Worker::builder().register_handler`.
+pub trait JobHandler: Send + Sync {
+    fn handle<'a>(&'a self, ctx: &'a ExecContext, job: &'a Job) -> BoxFuture<'a, HandlerOutcome>;
+}
+
+pub mod cache;
 pub mod common;
+pub mod datetime;
+pub mod diff;
 pub mod http;
 pub mod script;
 pub mod sql;
 pub mod fs;
+pub mod generate;
 pub mod human;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_outcome_constructors() {
+        let ok = HandlerOutcome::success(json!({"a": 1})).with_cost(0.5);
+        assert!(matches!(ok.status, ExecStatus::Success));
+        assert_eq!(ok.output, Some(json!({"a": 1})));
+        assert!(ok.error.is_none());
+        assert_eq!(ok.cost, Some(0.5));
+
+        let err = HandlerOutcome::error("HTTP_REQUEST_FAILED", "connection refused").retryable();
+        assert!(matches!(err.status, ExecStatus::Error));
+        assert!(err.output.is_none());
+        let e = err.error.unwrap();
+        assert_eq!(e.code, "HTTP_REQUEST_FAILED");
+        assert_eq!(e.message, "connection refused");
+        assert!(e.retryable);
+
+        assert!(!HandlerOutcome::error("MISSING_URL", "x").error.unwrap().retryable);
+    }
+
+    fn test_context(deadline: Instant) -> ExecContext {
+        let assignment: ExecAssignment = serde_json::from_value(json!({
+            "version": "1.0",
+            "assignment_id": "a1",
+            "request_id": "r1",
+            "tenant_id": "t1",
+            "trace_id": "trace-1",
+            "job": {"type": "echo", "payload": {}}
+        })).unwrap();
+        ExecContext::for_assignment(
+            &assignment,
+            Logger::new("worker-test".to_string()),
+            Arc::new(Metrics::new()),
+            CancellationToken::new(),
+            deadline,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_context_log_fields_carry_assignment() {
+        let ctx = test_context(Instant::now() + Duration::from_secs(5));
+        let fields = ctx.log_fields(Some(json!({"attempt": 2})));
+        assert_eq!(fields["assignment_id"], "a1");
+        assert_eq!(fields["trace_id"], "trace-1");
+        assert_eq!(fields["tenant_id"], "t1");
+        assert_eq!(fields["attempt"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_context_remaining_saturates() {
+        let ctx = test_context(Instant::now() + Duration::from_secs(5));
+        assert!(ctx.remaining() > Duration::from_secs(4));
+
+        let expired = test_context(Instant::now() - Duration::from_millis(10));
+        assert_eq!(expired.remaining(), Duration::ZERO);
+    }
+}
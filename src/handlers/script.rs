@@ -1,115 +1,227 @@
-use crate::protocol::{ExecStatus, Job};
+use crate::protocol::Job;
 use serde_json::{Value, json};
 use boa_engine::{Context, Source, JsString, JsValue};
 use boa_engine::property::Attribute;
-use super::HandlerResult;
+use super::{ExecContext, HandlerOutcome};
+use std::sync::{mpsc, Arc, Mutex, Once, Weak};
+use tokio::sync::oneshot;
 
-pub async fn handle_jmespath(job: &Job) -> HandlerResult {
+type ScriptError = (&'static str, String);
+
+/// Bounds of javascript and jmespath jobs. Boa cannot cap the heap, so these keep a job from
+/// being handed, looping over or returning more than the worker can hold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptLimits {
+    /// Javascript `args` and jmespath `data`, as encoded JSON, checked before conversion.
+    pub max_input_bytes: u64,
+    /// The javascript result, counted while it is converted, which stops once past it.
+    pub max_output_bytes: u64,
+    pub loop_iteration_limit: u64,
+    pub recursion_limit: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self { max_input_bytes: 16 * 1024 * 1024, max_output_bytes: 16 * 1024 * 1024, loop_iteration_limit: 100_000_000, recursion_limit: 512 }
+    }
+}
+
+impl ScriptLimits {
+    /// These limits lowered by the job's `limits` object; a value above the worker's is capped to it.
+    fn for_job(self, payload: &Value) -> Result<Self, ScriptError> {
+        let Some(requested) = payload.get("limits") else { return Ok(self) };
+        let requested = requested.as_object().ok_or(("INVALID_LIMITS", "'limits' must be an object".to_string()))?;
+        let get = |field: &str, max: u64| match requested.get(field) {
+            None => Ok(max),
+            Some(v) => v.as_u64().filter(|n| *n > 0).map(|n| n.min(max))
+                .ok_or(("INVALID_LIMITS", format!("'limits.{}' must be a positive integer", field))),
+        };
+        Ok(Self {
+            max_input_bytes: get("max_input_bytes", self.max_input_bytes)?,
+            max_output_bytes: get("max_output_bytes", self.max_output_bytes)?,
+            loop_iteration_limit: get("loop_iteration_limit", self.loop_iteration_limit)?,
+            recursion_limit: get("recursion_limit", self.recursion_limit as u64)? as usize,
+        })
+    }
+
+    fn check_input(&self, field: &str, value: &Value) -> Result<(), ScriptError> {
+        let size = serde_json::to_vec(value).map(|v| v.len() as u64).unwrap_or(u64::MAX);
+        if size > self.max_input_bytes {
+            return Err(("INPUT_TOO_LARGE", format!("'{}' is {} bytes, over the {} byte limit", field, size, self.max_input_bytes)));
+        }
+        Ok(())
+    }
+}
+
+pub async fn handle_jmespath(_ctx: &ExecContext, limits: ScriptLimits, job: &Job) -> HandlerOutcome {
     let expression = match job.payload.get("expression").and_then(|v| v.as_str()) {
         Some(e) => e,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_EXPRESSION".to_string()),
-            Some("Missing 'expression' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_EXPRESSION", "Missing 'expression' in payload"),
     };
 
     let data = job.payload.get("data").unwrap_or(&Value::Null);
+    if let Err((code, message)) = limits.for_job(&job.payload).and_then(|limits| limits.check_input("data", data)) {
+        return HandlerOutcome::error(code, message);
+    }
 
     let expr = match jmespath::compile(expression) {
         Ok(e) => e,
-        Err(e) => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("JMESPATH_COMPILE_ERROR".to_string()),
-            Some(e.to_string())
-        ),
+        Err(e) => return HandlerOutcome::error("JMESPATH_COMPILE_ERROR", e.to_string()),
     };
 
     let result = match expr.search(data) {
          Ok(r) => r,
-         Err(e) => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("JMESPATH_RUNTIME_ERROR".to_string()),
-            Some(e.to_string())
-        ),
+         Err(e) => return HandlerOutcome::error("JMESPATH_RUNTIME_ERROR", e.to_string()),
     };
 
     let output_json = serde_json::to_value(&*result).unwrap_or(Value::Null);
 
-    (ExecStatus::Success, job.r#type.clone(), Some(output_json), None, None)
+    HandlerOutcome::success(output_json)
+}
+
+type ScriptArgs = Option<serde_json::Map<String, Value>>;
+type ScriptResult = Result<Value, ScriptError>;
+
+struct JsRun {
+    code: String,
+    args: ScriptArgs,
+    limits: ScriptLimits,
+    reply: oneshot::Sender<ScriptResult>,
+    /// Handed back to the idle list once the thread has a fresh Context again.
+    back: mpsc::Sender<JsRun>,
+}
+
+/// Contexts built before the jobs that use them, so a short script doesn't pay for the
+/// intrinsics setup.
+///
+/// A `Context` is not `Send`, so each one lives on its own thread. Contexts are never reused: a
+/// script can leave globals or patched prototypes behind, so after every job the thread drops
+/// its Context and builds the next one before it takes more work. Threads start on the first
+/// job and exit with the pool. When none is idle the job builds a Context on the blocking pool.
+pub struct JsContextPool {
+    size: usize,
+    start: Once,
+    idle: Arc<Mutex<Vec<mpsc::Sender<JsRun>>>>,
+}
+
+impl JsContextPool {
+    pub fn new(size: usize) -> Self {
+        Self { size, start: Once::new(), idle: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    /// Threads holding a warm Context right now.
+    #[cfg(test)]
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Runs the script on an idle warm Context, or hands the script back when there is none.
+    fn try_run(&self, code: String, args: ScriptArgs, limits: ScriptLimits) -> Result<oneshot::Receiver<ScriptResult>, (String, ScriptArgs)> {
+        self.start.call_once(|| {
+            for _ in 0..self.size {
+                spawn_context_thread(Arc::downgrade(&self.idle));
+            }
+        });
+        let Some(sender) = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop() else {
+            return Err((code, args));
+        };
+        let (reply, result) = oneshot::channel();
+        // Fails only if the thread is gone, which leaves the job to the fallback
+        match sender.send(JsRun { code, args, limits, reply, back: sender.clone() }) {
+            Ok(()) => Ok(result),
+            Err(mpsc::SendError(run)) => Err((run.code, run.args)),
+        }
+    }
 }
 
-pub async fn handle_javascript(job: &Job) -> HandlerResult {
+fn spawn_context_thread(idle: Weak<Mutex<Vec<mpsc::Sender<JsRun>>>>) {
+    let (sender, runs) = mpsc::channel::<JsRun>();
+    // Without the thread the pool is just smaller; jobs fall back to fresh Contexts
+    let _ = std::thread::Builder::new().name("js-context".to_string()).spawn(move || {
+        let mut back = sender;
+        loop {
+            let mut context = Context::default();
+            // Only the idle list holds the sender while waiting, so dropping the pool ends `recv`
+            let Some(list) = idle.upgrade() else { return };
+            list.lock().unwrap_or_else(|e| e.into_inner()).push(back);
+            drop(list);
+            let Ok(run) = runs.recv() else { return };
+            let _ = run.reply.send(run_script(&mut context, &run.code, run.args, run.limits));
+            back = run.back;
+        }
+    });
+}
+
+pub async fn handle_javascript(ctx: &ExecContext, contexts: &JsContextPool, limits: ScriptLimits, job: &Job) -> HandlerOutcome {
     let code = match job.payload.get("code").and_then(|v| v.as_str()) {
         Some(c) => c,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_CODE".to_string()),
-            Some("Missing 'code' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_CODE", "Missing 'code' in payload"),
     };
 
     let args = job.payload.get("args").and_then(|v| v.as_object());
+    let limits = match limits.for_job(&job.payload) {
+        Ok(limits) => limits,
+        Err((code, message)) => return HandlerOutcome::error(code, message),
+    };
+    if let Some(Err((code, message))) = job.payload.get("args").map(|args| limits.check_input("args", args)) {
+        return HandlerOutcome::error(code, message);
+    }
 
     let code = code.to_string();
     let args = args.cloned();
-    
-    let result = tokio::task::spawn_blocking(move || {
-        let mut context = Context::default();
-        
-        if let Some(args_map) = args {
-            for (k, v) in args_map {
-                let boa_val = match serde_to_boa(&mut context, v) {
-                    Ok(val) => val,
-                    Err(e) => return Err(format!("Failed to convert arg {}: {}", k, e)),
-                };
-                
-                let js_key = JsString::from(k.as_str());
-                if let Err(e) = context.register_global_property(
-                    js_key, 
-                    boa_val, 
-                    Attribute::WRITABLE | Attribute::ENUMERABLE | Attribute::CONFIGURABLE
-                ) {
-                    return Err(format!("Failed to register global {}: {}", k, e.to_string()));
-                }
-            }
+
+    let result = match contexts.try_run(code, args, limits) {
+        Ok(pooled) => pooled.await.map_err(|_| "script thread exited before replying".to_string()),
+        Err((code, args)) => tokio::task::spawn_blocking(move || run_script(&mut Context::default(), &code, args, limits)).await
+            .map_err(|join_err| format!("Tokio join error: {}", join_err)),
+    };
+
+    match result {
+        Ok(Ok(output)) => HandlerOutcome::success(output),
+        Ok(Err((code, err_msg))) => {
+            ctx.error("Script failed", Some(json!({"error_code": code, "error": err_msg})));
+            HandlerOutcome::error(code, err_msg)
         }
+        Err(err_msg) => HandlerOutcome::error("INTERNAL_ERROR", err_msg),
+    }
+}
 
-        match context.eval(Source::from_bytes(code.as_bytes())) {
-            Ok(res) => {
-                match boa_to_serde(&mut context, res) {
-                    Ok(v) => Ok(v),
-                    Err(e) => Err(format!("Failed to convert result: {}", e)),
-                }
-            },
-            Err(e) => Err(format!("Script execution failed: {}", e.to_string())),
+fn run_script(context: &mut Context, code: &str, args: ScriptArgs, limits: ScriptLimits) -> ScriptResult {
+    let runtime_limits = context.runtime_limits_mut();
+    runtime_limits.set_loop_iteration_limit(limits.loop_iteration_limit);
+    runtime_limits.set_recursion_limit(limits.recursion_limit);
+    let script_error = |message: String| ("SCRIPT_ERROR", message);
+    if let Some(args_map) = args {
+        for (k, v) in args_map {
+            let boa_val = match serde_to_boa(context, v) {
+                Ok(val) => val,
+                Err(e) => return Err(script_error(format!("Failed to convert arg {}: {}", k, e))),
+            };
+
+            let js_key = JsString::from(k.as_str());
+            if let Err(e) = context.register_global_property(
+                js_key,
+                boa_val,
+                Attribute::WRITABLE | Attribute::ENUMERABLE | Attribute::CONFIGURABLE
+            ) {
+                return Err(script_error(format!("Failed to register global {}: {}", k, e)));
+            }
         }
-    }).await;
+    }
 
-    match result {
-        Ok(Ok(output)) => (ExecStatus::Success, job.r#type.clone(), Some(output), None, None),
-        Ok(Err(err_msg)) => (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("SCRIPT_ERROR".to_string()),
-            Some(err_msg)
-        ),
-        Err(join_err) => (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("INTERNAL_ERROR".to_string()),
-            Some(format!("Tokio join error: {}", join_err))
-        ),
+    match context.eval(Source::from_bytes(code.as_bytes())) {
+        Ok(res) => {
+            let mut budget = OutputBudget { remaining: limits.max_output_bytes, depth_left: MAX_OUTPUT_DEPTH };
+            boa_to_serde(context, res, &mut budget).map_err(|e| match e {
+                Conversion::TooLarge => ("OUTPUT_TOO_LARGE", format!("The script result exceeds the {} byte output limit", limits.max_output_bytes)),
+                Conversion::Failed(e) => script_error(format!("Failed to convert result: {}", e)),
+            })
+        },
+        // Loop and recursion limits surface as uncatchable runtime-limit errors
+        Err(e) if e.as_native().is_some_and(|native| native.is_runtime_limit()) => {
+            Err(("SCRIPT_LIMIT_EXCEEDED", format!("Script execution failed: {}", e)))
+        }
+        Err(e) => Err(script_error(format!("Script execution failed: {}", e))),
     }
 }
 
@@ -145,44 +257,225 @@ fn serde_to_boa(context: &mut Context, val: Value) -> Result<JsValue, String> {
     }
 }
 
-fn boa_to_serde(context: &mut Context, val: JsValue) -> Result<Value, String> {
+/// Why a result could not be converted; `TooLarge` is reported apart from script mistakes.
+enum Conversion {
+    TooLarge,
+    Failed(String),
+}
+
+impl From<String> for Conversion {
+    fn from(e: String) -> Self {
+        Conversion::Failed(e)
+    }
+}
+
+/// How deeply a javascript result may nest: serde_json's own parse limit, so anything converted
+/// can be read back, and a cyclic result fails here instead of recursing until the stack runs out.
+const MAX_OUTPUT_DEPTH: usize = 128;
+
+/// Approximately the encoded JSON bytes a result may still take, and how much deeper it may nest.
+struct OutputBudget {
+    remaining: u64,
+    depth_left: usize,
+}
+
+impl OutputBudget {
+    fn spend(&mut self, bytes: usize) -> Result<(), Conversion> {
+        self.remaining = self.remaining.checked_sub(bytes as u64).ok_or(Conversion::TooLarge)?;
+        Ok(())
+    }
+
+    /// Converts one level further down; only a cyclic or absurdly deep result runs out.
+    fn nested<T>(&mut self, convert: impl FnOnce(&mut Self) -> Result<T, Conversion>) -> Result<T, Conversion> {
+        let Some(depth_left) = self.depth_left.checked_sub(1) else {
+            return Err(Conversion::Failed("result nests too deeply or refers to itself".to_string()));
+        };
+        self.depth_left = depth_left;
+        let converted = convert(self);
+        self.depth_left += 1;
+        converted
+    }
+}
+
+fn boa_to_serde(context: &mut Context, val: JsValue, budget: &mut OutputBudget) -> Result<Value, Conversion> {
     if val.is_null() || val.is_undefined() {
-        return Ok(Value::Null);
+        budget.spend(4)?;
+        Ok(Value::Null)
     } else if let Some(b) = val.as_boolean() {
-        return Ok(Value::Bool(b));
+        budget.spend(5)?;
+        Ok(Value::Bool(b))
     } else if let Some(n) = val.as_number() {
+         budget.spend(8)?;
          if n.fract() == 0.0 {
              return Ok(json!(n as i64));
          }
-         return Ok(json!(n));
+         Ok(json!(n))
     } else if let Some(s) = val.as_string() {
-         return Ok(Value::String(s.to_std_string_escaped()));
+         // Counted before the copy, so an oversized string is never duplicated
+         budget.spend(s.len() + 2)?;
+         Ok(Value::String(s.to_std_string_escaped()))
     } else if let Some(obj) = val.as_object() {
          if obj.is_array() {
              let len_val = obj.get(JsString::from("length"), context).map_err(|e| e.to_string())?;
              let len = len_val.as_number().unwrap_or(0.0) as u32;
-             let mut arr = Vec::new();
-             for i in 0..len {
-                 let val = obj.get(i, context).map_err(|e| e.to_string())?;
-                 arr.push(boa_to_serde(context, val)?);
-             }
-             Ok(Value::Array(arr))
+             budget.spend(2 + len as usize)?;
+             budget.nested(|budget| {
+                 let mut arr = Vec::new();
+                 for i in 0..len {
+                     let val = obj.get(i, context).map_err(|e| e.to_string())?;
+                     arr.push(boa_to_serde(context, val, budget)?);
+                 }
+                 Ok(Value::Array(arr))
+             })
          } else if obj.is_callable() {
+             budget.spend(12)?;
              Ok(Value::String("[Function]".to_string()))
          } else {
-             let json_obj = context.global_object().get(JsString::from("JSON"), context).map_err(|e| e.to_string())?;
-             let stringify = json_obj.as_object().unwrap().get(JsString::from("stringify"), context).map_err(|e| e.to_string())?;
-             if let Some(func) = stringify.as_callable() {
-                  let json_str_val = func.call(&JsValue::undefined(), &[val.clone()], context).map_err(|e| e.to_string())?;
-                  if let Some(s) = json_str_val.as_string() {
-                       let s_str = s.to_std_string_escaped();
-                       let v: Value = serde_json::from_str(&s_str).map_err(|e| e.to_string())?;
-                       return Ok(v);
-                  }
+             // What JSON.stringify would give, walked under the budget instead of built whole first:
+             // `toJSON` is honoured (so Dates become strings), undefined and function values are left out
+             let to_json = obj.get(JsString::from("toJSON"), context).map_err(|e| e.to_string())?;
+             if let Some(to_json) = to_json.as_callable() {
+                 let replaced = to_json.call(&val, &[], context).map_err(|e| e.to_string())?;
+                 return budget.nested(|budget| boa_to_serde(context, replaced, budget));
              }
-             Ok(Value::String("[Object]".to_string()))
+             let object_ctor = context.global_object().get(JsString::from("Object"), context).map_err(|e| e.to_string())?;
+             let keys_fn = object_ctor.as_object().ok_or_else(|| "Object is not an object".to_string())?.get(JsString::from("keys"), context).map_err(|e| e.to_string())?;
+             let keys = keys_fn.as_callable().ok_or_else(|| "Object.keys is not callable".to_string())?.call(&object_ctor, std::slice::from_ref(&val), context).map_err(|e| e.to_string())?;
+             let keys = keys.as_object().ok_or_else(|| "Object.keys returned no array".to_string())?.clone();
+             let len = keys.get(JsString::from("length"), context).map_err(|e| e.to_string())?.as_number().unwrap_or(0.0) as u32;
+             budget.spend(2)?;
+             budget.nested(|budget| {
+                 let mut map = serde_json::Map::new();
+                 for i in 0..len {
+                     let key = keys.get(i, context).map_err(|e| e.to_string())?;
+                     let Some(key) = key.as_string() else { continue };
+                     let value = obj.get(key.clone(), context).map_err(|e| e.to_string())?;
+                     if value.is_undefined() || value.is_callable() {
+                         continue;
+                     }
+                     budget.spend(key.len() + 4)?;
+                     map.insert(key.to_std_string_escaped(), boa_to_serde(context, value, budget)?);
+                 }
+                 Ok(Value::Object(map))
+             })
          }
     } else {
-        Ok(Value::String(format!("{:?}", val)))
+        let debug = format!("{:?}", val);
+        budget.spend(debug.len() + 2)?;
+        Ok(Value::String(debug))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    async fn wait_idle(pool: &JsContextPool, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pool.idle() < n {
+            assert!(Instant::now() < deadline, "pool never refilled");
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    async fn run_pooled(pool: &JsContextPool, code: &str, args: ScriptArgs) -> ScriptResult {
+        let Ok(result) = pool.try_run(code.to_string(), args, ScriptLimits::default()) else { panic!("no idle context") };
+        result.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pooled_contexts_do_not_leak_between_jobs() {
+        let pool = JsContextPool::new(1);
+        // Starts the thread; nothing is warm yet
+        assert!(pool.try_run("1".to_string(), None, ScriptLimits::default()).is_err());
+        wait_idle(&pool, 1).await;
+
+        let args = json!({"secret": "s3cret"}).as_object().cloned();
+        let first = run_pooled(&pool, "globalThis.leak = 1; Array.prototype.evil = () => 42; Object.prototype.tainted = true; secret", args).await;
+        assert_eq!(first, Ok(json!("s3cret")));
+
+        wait_idle(&pool, 1).await;
+        let second = run_pooled(&pool, "[typeof leak, typeof [].evil, typeof ({}).tainted, typeof secret]", None).await;
+        assert_eq!(second, Ok(json!(["undefined", "undefined", "undefined", "undefined"])));
+    }
+
+    #[tokio::test]
+    async fn test_empty_pool_hands_the_script_back() {
+        let pool = JsContextPool::new(0);
+        let Err((code, args)) = pool.try_run("1 + 1".to_string(), None, ScriptLimits::default()) else { panic!("pool of 0 ran a script") };
+        assert_eq!((code.as_str(), args), ("1 + 1", None));
+    }
+
+    #[tokio::test]
+    async fn test_every_job_gets_a_context_built_before_it() {
+        let pool = JsContextPool::new(1);
+        let _ = pool.try_run("1".to_string(), None, ScriptLimits::default());
+        for _ in 0..10 {
+            // The thread builds its next Context before offering itself again, so `run_pooled` never falls back
+            wait_idle(&pool, 1).await;
+            assert_eq!(run_pooled(&pool, "1 + 1", None).await, Ok(json!(2)));
+        }
+        wait_idle(&pool, 1).await;
+        assert_eq!(pool.idle(), 1, "the pool never grows past its size");
+    }
+
+    fn run(code: &str, limits: ScriptLimits) -> ScriptResult {
+        run_script(&mut Context::default(), code, None, limits)
+    }
+
+    #[test]
+    fn test_huge_results_stop_at_the_output_limit() {
+        let limits = ScriptLimits { max_output_bytes: 1024 * 1024, ..ScriptLimits::default() };
+        let (code, message) = run("'x'.repeat(64 * 1024 * 1024)", limits).unwrap_err();
+        assert_eq!(code, "OUTPUT_TOO_LARGE");
+        assert!(message.contains("1048576 byte"), "{}", message);
+        assert_eq!(run("Array.from({length: 200000}, () => 'abcdefgh')", limits).unwrap_err().0, "OUTPUT_TOO_LARGE");
+        assert_eq!(run("({rows: Array.from({length: 200000}, (_, i) => i)})", limits).unwrap_err().0, "OUTPUT_TOO_LARGE");
+        assert_eq!(run("'x'.repeat(1000)", limits).unwrap().as_str().map(str::len), Some(1000));
+    }
+
+    #[test]
+    fn test_objects_convert_like_json_stringify_within_the_budget() {
+        let limits = ScriptLimits::default();
+        let converted = run("({a: 1, skip: undefined, f() {}, when: new Date(0), nested: {list: [true, null]}})", limits).unwrap();
+        assert_eq!(converted, json!({"a": 1, "when": "1970-01-01T00:00:00.000Z", "nested": {"list": [true, null]}}));
+
+        // Many small keys are counted one by one, not after the whole object was stringified
+        let limits = ScriptLimits { max_output_bytes: 64 * 1024, ..limits };
+        let wide = "const o = {}; for (let i = 0; i < 20000; i++) o['k' + i] = i; o";
+        assert_eq!(run(wide, limits).unwrap_err().0, "OUTPUT_TOO_LARGE");
+
+        let (code, message) = run("const o = {}; o.self = o; o", limits).unwrap_err();
+        assert_eq!(code, "SCRIPT_ERROR");
+        assert!(message.contains("refers to itself"), "{}", message);
+        assert_eq!(run("const a = []; a.push(a); a", limits).unwrap_err().0, "SCRIPT_ERROR");
+    }
+
+    #[test]
+    fn test_runaway_loops_and_recursion_hit_runtime_limits() {
+        let limits = ScriptLimits { loop_iteration_limit: 10_000, recursion_limit: 64, ..ScriptLimits::default() };
+        assert_eq!(run("let s = ''; for (;;) { s += 'x'; }", limits).unwrap_err().0, "SCRIPT_LIMIT_EXCEEDED");
+        assert_eq!(run("try { while (true) {} } catch (e) { 'caught' }", limits).unwrap_err().0, "SCRIPT_LIMIT_EXCEEDED", "not catchable");
+        assert_eq!(run("function f(n) { return f(n + 1); } f(0)", limits).unwrap_err().0, "SCRIPT_LIMIT_EXCEEDED");
+        assert_eq!(run("let n = 0; for (let i = 0; i < 5000; i++) { n += i; } n", limits), Ok(json!(12497500)));
+        assert_eq!(run("null.x", limits).unwrap_err().0, "SCRIPT_ERROR");
+    }
+
+    #[test]
+    fn test_job_limits_only_lower_the_worker_limits() {
+        let worker = ScriptLimits { max_input_bytes: 100, max_output_bytes: 1000, loop_iteration_limit: 50, recursion_limit: 8 };
+        assert_eq!(worker.for_job(&json!({})), Ok(worker));
+        let lowered = worker.for_job(&json!({"limits": {"max_output_bytes": 10, "recursion_limit": 4}})).unwrap();
+        assert_eq!((lowered.max_output_bytes, lowered.recursion_limit, lowered.max_input_bytes), (10, 4, 100));
+        let raised = worker.for_job(&json!({"limits": {"max_input_bytes": 1_000_000, "loop_iteration_limit": u64::MAX}})).unwrap();
+        assert_eq!(raised, worker);
+        assert_eq!(worker.for_job(&json!({"limits": {"max_output_bytes": 0}})).unwrap_err().0, "INVALID_LIMITS");
+        assert_eq!(worker.for_job(&json!({"limits": 5})).unwrap_err().0, "INVALID_LIMITS");
+
+        assert!(worker.check_input("args", &json!({"a": "x".repeat(50)})).is_ok());
+        let (code, message) = worker.check_input("data", &json!({"a": "x".repeat(100)})).unwrap_err();
+        assert_eq!(code, "INPUT_TOO_LARGE");
+        assert!(message.starts_with("'data' is 108 bytes"), "{}", message);
     }
 }
@@ -1,73 +1,113 @@
-use crate::protocol::{ExecStatus, Job};
+use crate::error::classify_sqlx;
+use crate::observability::metrics::Metrics;
+use crate::protocol::Job;
+use dashmap::DashMap;
+use futures::StreamExt;
 use serde_json::{Value, json};
-use sqlx::{postgres::PgPoolOptions, Row, Column, Pool, Postgres};
+use sqlx::{postgres::{PgPoolOptions, PgRow}, Row, Column, Pool, Postgres};
+use std::future::Future;
+use std::path::PathBuf;
 use std::time::Duration;
-use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use super::HandlerResult;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::OnceCell;
+use super::fs::{sandboxed_path, temp_path_for};
+use super::{ExecContext, HandlerOutcome};
 
+/// Bounds of `output: {"mode": "fs"}` exports, from `SQL_EXPORT_MAX_ROWS` and `SQL_EXPORT_MAX_BYTES`.
+/// An export stops at whichever comes first and reports `truncated`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportLimits {
+    pub max_rows: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for ExportLimits {
+    fn default() -> Self {
+        Self { max_rows: 1_000_000, max_bytes: 1024 * 1024 * 1024 }
+    }
+}
+
+/// Pools by connection string, shared by every sql job.
+///
+/// Lookups of a cached pool only take a shard read lock. Each key gets its cell before anyone
+/// connects, so concurrent cold starts for one database wait on a single connection attempt
+/// instead of each opening a pool; a failed attempt leaves the cell empty for the next job.
+pub struct PoolCache<P = Pool<Postgres>> {
+    pools: DashMap<String, Arc<OnceCell<P>>>,
+}
+
+impl<P> Default for PoolCache<P> {
+    fn default() -> Self {
+        Self { pools: DashMap::new() }
+    }
+}
+
+impl<P: Clone> PoolCache<P> {
+    /// The pool for `key`, running `connect` only if no pool exists and no other job is connecting.
+    pub async fn get_or_connect<F, Fut, E>(&self, key: &str, metrics: &Metrics, connect: F) -> Result<P, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<P, E>>,
+    {
+        let cell = match self.pools.get(key) {
+            Some(cell) => cell.clone(),
+            None => self.pools.entry(key.to_string()).or_default().clone(),
+        };
+        if let Some(pool) = cell.get() {
+            return Ok(pool.clone());
+        }
+        let pool = cell.get_or_try_init(connect).await?.clone();
+        metrics.sql_pools_cached.set(self.connected() as i64);
+        Ok(pool)
+    }
+
+    /// Pools that finished connecting.
+    pub fn connected(&self) -> usize {
+        self.pools.iter().filter(|cell| cell.initialized()).count()
+    }
+}
+
+/// Runs `query`; with `output: {"mode": "fs", "path", "format": "jsonl" | "csv"}` the rows are
+/// streamed into a file under `base_dir` instead of returned inline.
 pub async fn handle_sql(
-    pool_cache: &Arc<Mutex<HashMap<String, Pool<Postgres>>>>,
+    ctx: &ExecContext,
+    pool_cache: &PoolCache,
+    base_dir: &str,
+    limits: ExportLimits,
     job: &Job
-) -> HandlerResult {
+) -> HandlerOutcome {
     let connection_string = match job.payload.get("connection_string").and_then(|v| v.as_str()) {
         Some(s) => s,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_CONNECTION_STRING".to_string()),
-            Some("Missing 'connection_string' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_CONNECTION_STRING", "Missing 'connection_string' in payload"),
     };
 
     let query_str = match job.payload.get("query").and_then(|v| v.as_str()) {
         Some(s) => s,
-        None => return (
-            ExecStatus::Error,
-            job.r#type.clone(),
-            None,
-            Some("MISSING_QUERY".to_string()),
-            Some("Missing 'query' in payload".to_string())
-        ),
+        None => return HandlerOutcome::error("MISSING_QUERY", "Missing 'query' in payload"),
     };
-
-    // 1. Try to get from cache
-    let pool = {
-        let cache = pool_cache.lock().await;
-        cache.get(connection_string).cloned()
+    let export = match job.payload.get("output") {
+        Some(output) => match ExportRequest::parse(output, base_dir) {
+            Ok(export) => Some(export),
+            Err(e) => return HandlerOutcome::error("INVALID_OUTPUT", e),
+        },
+        None => None,
     };
 
+    let pool = pool_cache.get_or_connect(connection_string, &ctx.metrics, || async {
+        ctx.info("Opening database pool", None);
+        PgPoolOptions::new()
+            .max_connections(5) // Increased from 1 for better concurrency per DB
+            .acquire_timeout(Duration::from_secs(10).min(ctx.remaining()))
+            .connect(connection_string)
+            .await
+    }).await;
     let pool = match pool {
-        Some(p) => p,
-        None => {
-            // 2. Create new connection if not in cache
-            // We release the lock during connection establishment to avoid blocking other jobs
-            let new_pool = match PgPoolOptions::new()
-                .max_connections(5) // Increased from 1 for better concurrency per DB
-                .acquire_timeout(Duration::from_secs(10))
-                .connect(connection_string)
-                .await {
-                    Ok(p) => p,
-                    Err(e) => return (
-                        ExecStatus::Error,
-                        job.r#type.clone(),
-                        None,
-                        Some("DB_CONNECTION_ERROR".to_string()),
-                        Some(e.to_string())
-                    ),
-                };
-            
-            // 3. Insert into cache
-            let mut cache = pool_cache.lock().await;
-            // Double-check if another task inserted it while we were connecting
-            if let Some(p) = cache.get(connection_string) {
-                p.clone()
-            } else {
-                cache.insert(connection_string.to_string(), new_pool.clone());
-                new_pool
-            }
+        Ok(p) => p,
+        Err(e) => {
+            let classified = classify_sqlx(&e);
+            ctx.error("Database connection failed", Some(json!({"error": e.to_string(), "transient": classified.is_transient()})));
+            return sql_failure("DB_CONNECTION_ERROR", &e);
         }
     };
 
@@ -95,27 +135,17 @@ pub async fn handle_sql(
          }
     }
 
+    if let Some(export) = export {
+        return export_rows(ctx, query.fetch(&pool), export, limits).await;
+    }
+
     let result = match query.fetch_all(&pool).await {
         Ok(rows) => {
              let mut json_rows = Vec::new();
              for row in &rows {
                  let mut json_row = serde_json::Map::new();
                  for col in row.columns() {
-                     let col_name = col.name();
-                     let val: Value = if let Ok(v) = row.try_get::<bool, _>(col_name) {
-                         json!(v)
-                     } else if let Ok(v) = row.try_get::<i64, _>(col_name) {
-                         json!(v)
-                     } else if let Ok(v) = row.try_get::<f64, _>(col_name) {
-                         json!(v)
-                     } else if let Ok(v) = row.try_get::<String, _>(col_name) {
-                         json!(v)
-                     } else if let Ok(v) = row.try_get::<serde_json::Value, _>(col_name) {
-                         v
-                     } else {
-                         json!("UNSUPPORTED_TYPE")
-                     };
-                     json_row.insert(col_name.to_string(), val);
+                     json_row.insert(col.name().to_string(), column_value(row, col.name()));
                  }
                  json_rows.push(Value::Object(json_row));
              }
@@ -126,15 +156,314 @@ pub async fn handle_sql(
              })
         },
         Err(e) => {
-             return (
-                ExecStatus::Error,
-                job.r#type.clone(),
-                None,
-                Some("DB_QUERY_ERROR".to_string()),
-                Some(e.to_string())
-             );
+             return sql_failure("DB_QUERY_ERROR", &e);
         }
     };
 
-    (ExecStatus::Success, job.r#type.clone(), Some(result), None, None)
+    HandlerOutcome::success(result)
+}
+
+/// One column as JSON, trying the types the handler understands in turn.
+fn column_value(row: &PgRow, col_name: &str) -> Value {
+    if let Ok(v) = row.try_get::<bool, _>(col_name) {
+        json!(v)
+    } else if let Ok(v) = row.try_get::<i64, _>(col_name) {
+        json!(v)
+    } else if let Ok(v) = row.try_get::<f64, _>(col_name) {
+        json!(v)
+    } else if let Ok(v) = row.try_get::<String, _>(col_name) {
+        json!(v)
+    } else if let Ok(v) = row.try_get::<serde_json::Value, _>(col_name) {
+        v
+    } else {
+        json!("UNSUPPORTED_TYPE")
+    }
+}
+
+/// Writes rows to the export file as they arrive, so memory stays flat whatever the row count.
+async fn export_rows<'a>(
+    ctx: &ExecContext,
+    mut rows: futures::stream::BoxStream<'a, Result<PgRow, sqlx::Error>>,
+    export: ExportRequest,
+    limits: ExportLimits,
+) -> HandlerOutcome {
+    if let Some(parent) = export.full_path.parent() {
+        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+            return HandlerOutcome::error("DIR_CREATE_ERROR", e.to_string());
+        }
+    }
+    let mut file = match ExportFile::create(export.full_path.clone(), export.format, limits).await {
+        Ok(file) => file,
+        Err(e) => return HandlerOutcome::error("FILE_WRITE_ERROR", e.to_string()),
+    };
+    while let Some(row) = rows.next().await {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return sql_failure("DB_QUERY_ERROR", &e),
+        };
+        let values: Vec<(&str, Value)> = row.columns().iter().map(|c| (c.name(), column_value(&row, c.name()))).collect();
+        match file.write_row(&values).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => return HandlerOutcome::error("FILE_WRITE_ERROR", e.to_string()),
+        }
+    }
+    drop(rows);
+    match file.finish().await {
+        Ok((rows_written, bytes, truncated)) => {
+            ctx.info("SQL export written", Some(json!({"path": export.path, "rows_written": rows_written, "bytes": bytes, "truncated": truncated})));
+            HandlerOutcome::success(json!({
+                "path": export.path,
+                "rows_written": rows_written,
+                "bytes": bytes,
+                "truncated": truncated
+            }))
+        }
+        Err(e) => HandlerOutcome::error("FILE_WRITE_ERROR", e.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Jsonl,
+    Csv,
+}
+
+#[derive(Debug)]
+struct ExportRequest {
+    /// As given, relative to the fs base directory; echoed in the output.
+    path: String,
+    full_path: PathBuf,
+    format: ExportFormat,
+}
+
+impl ExportRequest {
+    fn parse(output: &Value, base_dir: &str) -> Result<Self, String> {
+        if output.get("mode").and_then(|v| v.as_str()) != Some("fs") {
+            return Err("'output.mode' must be \"fs\"".to_string());
+        }
+        let path = output.get("path").and_then(|v| v.as_str()).ok_or("Missing 'output.path'")?;
+        let full_path = sandboxed_path(base_dir, path).ok_or("Path traversal or absolute path not allowed in 'output.path'")?;
+        let format = match output.get("format").and_then(|v| v.as_str()).unwrap_or("jsonl") {
+            "jsonl" => ExportFormat::Jsonl,
+            "csv" => ExportFormat::Csv,
+            other => return Err(format!("'output.format' must be jsonl or csv, not '{}'", other)),
+        };
+        Ok(Self { path: path.to_string(), full_path, format })
+    }
+}
+
+/// Removes the temp file unless it was renamed into place, including when the job's future is
+/// dropped on timeout.
+struct TempFile(Option<PathBuf>);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// An export being written to a temp file beside its destination, renamed into place by
+/// `finish` so readers never see a partial export.
+struct ExportFile {
+    writer: BufWriter<tokio::fs::File>,
+    tmp: TempFile,
+    path: PathBuf,
+    format: ExportFormat,
+    limits: ExportLimits,
+    /// Reused for every row.
+    line: Vec<u8>,
+    rows_written: u64,
+    bytes: u64,
+    truncated: bool,
+}
+
+impl ExportFile {
+    async fn create(path: PathBuf, format: ExportFormat, limits: ExportLimits) -> std::io::Result<Self> {
+        let tmp_path = temp_path_for(&path);
+        let file = tokio::fs::File::create(&tmp_path).await?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            tmp: TempFile(Some(tmp_path)),
+            path,
+            format,
+            limits,
+            line: Vec::new(),
+            rows_written: 0,
+            bytes: 0,
+            truncated: false,
+        })
+    }
+
+    /// Appends one row; `false` once a limit is reached and no more rows should be read.
+    async fn write_row(&mut self, row: &[(&str, Value)]) -> std::io::Result<bool> {
+        if self.rows_written >= self.limits.max_rows {
+            self.truncated = true;
+            return Ok(false);
+        }
+        self.line.clear();
+        match self.format {
+            ExportFormat::Jsonl => {
+                let object: serde_json::Map<String, Value> = row.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+                serde_json::to_writer(&mut self.line, &object)?;
+                self.line.push(b'\n');
+            }
+            ExportFormat::Csv => {
+                if self.rows_written == 0 {
+                    let names: Vec<_> = row.iter().map(|(name, _)| Value::String(name.to_string())).collect();
+                    csv_line(&mut self.line, names.iter());
+                }
+                csv_line(&mut self.line, row.iter().map(|(_, value)| value));
+            }
+        }
+        if self.bytes + self.line.len() as u64 > self.limits.max_bytes {
+            self.truncated = true;
+            return Ok(false);
+        }
+        self.writer.write_all(&self.line).await?;
+        self.bytes += self.line.len() as u64;
+        self.rows_written += 1;
+        Ok(true)
+    }
+
+    /// Renames the file into place; returns rows written, bytes and whether a limit cut it short.
+    async fn finish(mut self) -> std::io::Result<(u64, u64, bool)> {
+        self.writer.flush().await?;
+        if let Some(tmp_path) = &self.tmp.0 {
+            tokio::fs::rename(tmp_path, &self.path).await?;
+        }
+        self.tmp.0 = None;
+        Ok((self.rows_written, self.bytes, self.truncated))
+    }
+}
+
+/// One RFC 4180 line: NULL is an empty field, strings as is, other values as their JSON.
+fn csv_line<'a>(line: &mut Vec<u8>, values: impl Iterator<Item = &'a Value>) {
+    for (index, value) in values.enumerate() {
+        if index > 0 {
+            line.push(b',');
+        }
+        let text = match value {
+            Value::Null => continue,
+            Value::String(s) => std::borrow::Cow::Borrowed(s.as_str()),
+            other => std::borrow::Cow::Owned(other.to_string()),
+        };
+        if text.contains([',', '"', '\n', '\r']) {
+            line.push(b'"');
+            line.extend_from_slice(text.replace('"', "\"\"").as_bytes());
+            line.push(b'"');
+        } else {
+            line.extend_from_slice(text.as_bytes());
+        }
+    }
+    line.extend_from_slice(b"\r\n");
+}
+
+/// Deadlocks, serialization failures and dropped connections are worth another attempt.
+fn sql_failure(code: &str, e: &sqlx::Error) -> HandlerOutcome {
+    let outcome = HandlerOutcome::error(code, e.to_string());
+    if classify_sqlx(e).is_transient() {
+        outcome.retryable()
+    } else {
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_concurrent_cold_starts_connect_once() {
+        let cache: Arc<PoolCache<u32>> = Arc::new(PoolCache::default());
+        let metrics = Arc::new(Metrics::new());
+        let connects = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<_> = (0..32).map(|_| {
+            let (cache, metrics, connects) = (cache.clone(), metrics.clone(), connects.clone());
+            tokio::spawn(async move {
+                cache.get_or_connect("postgres://db/one", &metrics, || async {
+                    connects.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, String>(7)
+                }).await
+            })
+        }).collect();
+        for job in jobs {
+            assert_eq!(job.await.unwrap(), Ok(7));
+        }
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert_eq!(metrics.sql_pools_cached.get(), 1);
+
+        cache.get_or_connect("postgres://db/two", &metrics, || async { Ok::<_, String>(8) }).await.unwrap();
+        assert_eq!(metrics.sql_pools_cached.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_connect_is_retried_by_the_next_job() {
+        let cache: PoolCache<u32> = PoolCache::default();
+        let metrics = Metrics::new();
+        let failed = cache.get_or_connect("postgres://db", &metrics, || async { Err::<u32, _>("refused") }).await;
+        assert_eq!(failed, Err("refused"));
+        assert_eq!(cache.connected(), 0);
+        let pool = cache.get_or_connect("postgres://db", &metrics, || async { Ok::<_, &str>(1) }).await;
+        assert_eq!(pool, Ok(1));
+        assert_eq!(cache.connected(), 1);
+    }
+
+    fn export_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sql-export-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_export_output_is_validated() {
+        let parse = |output: Value| ExportRequest::parse(&output, "/base");
+        let export = parse(json!({"mode": "fs", "path": "exports/a.csv", "format": "csv"})).unwrap();
+        assert_eq!((export.full_path, export.format), (PathBuf::from("/base/exports/a.csv"), ExportFormat::Csv));
+        assert_eq!(parse(json!({"mode": "fs", "path": "a.jsonl"})).unwrap().format, ExportFormat::Jsonl);
+        assert!(parse(json!({"mode": "inline", "path": "a"})).is_err());
+        assert!(parse(json!({"mode": "fs", "path": "../a"})).unwrap_err().contains("traversal"));
+        assert!(parse(json!({"mode": "fs", "path": "a", "format": "xml"})).unwrap_err().contains("xml"));
+    }
+
+    #[tokio::test]
+    async fn test_csv_export_escapes_and_renames_into_place() {
+        let dir = export_dir();
+        let path = dir.join("out.csv");
+        let mut file = ExportFile::create(path.clone(), ExportFormat::Csv, ExportLimits::default()).await.unwrap();
+        assert!(file.write_row(&[("id", json!(1)), ("note", json!("a, \"b\"")), ("gone", Value::Null)]).await.unwrap());
+        assert!(file.write_row(&[("id", json!(2)), ("note", json!({"k": true})), ("gone", json!(false))]).await.unwrap());
+        assert!(!path.exists(), "nothing visible before finish");
+        let (rows, bytes, truncated) = file.finish().await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "id,note,gone\r\n1,\"a, \"\"b\"\"\",\r\n2,\"{\"\"k\"\":true}\",false\r\n");
+        assert_eq!((rows, bytes, truncated), (2, written.len() as u64, false));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "temp file renamed");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_export_limits_truncate_and_dropped_exports_leave_nothing() {
+        let dir = export_dir();
+        let path = dir.join("out.jsonl");
+        let mut file = ExportFile::create(path.clone(), ExportFormat::Jsonl, ExportLimits { max_rows: 2, max_bytes: u64::MAX }).await.unwrap();
+        for n in 0..2 {
+            assert!(file.write_row(&[("n", json!(n))]).await.unwrap());
+        }
+        assert!(!file.write_row(&[("n", json!(2))]).await.unwrap());
+        assert_eq!(file.finish().await.unwrap(), (2, 16, true));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"n\":0}\n{\"n\":1}\n");
+
+        let mut file = ExportFile::create(dir.join("small.jsonl"), ExportFormat::Jsonl, ExportLimits { max_rows: 10, max_bytes: 10 }).await.unwrap();
+        assert!(file.write_row(&[("n", json!(0))]).await.unwrap());
+        assert!(!file.write_row(&[("n", json!(1))]).await.unwrap(), "the second line would pass max_bytes");
+        drop(file);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "an unfinished export is removed");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,32 +1,308 @@
-use axum::{routing::get, Router, extract::State, http::StatusCode};
-use tokio::net::TcpListener;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use axum::{routing::{get, post}, Router, extract::{Path, Query, Request, State}, Json, http::{HeaderMap, StatusCode}, middleware::{self, Next}, response::{IntoResponse, Response}};
+use axum_server::tls_rustls::RustlsConfig;
+use std::sync::{Arc, Mutex, OnceLock, atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering}};
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::build_info::BuildInfo;
+use crate::concurrency::ConcurrencyLimit;
+use crate::history::TaskHistory;
+use crate::inflight::InflightTracker;
+use crate::reload::ConfigReloader;
+use crate::resources::{ResourceMonitor, WORKER_RESOURCE_PRESSURE};
+use crate::selftest::SelftestState;
 use crate::observability::metrics::Metrics;
+use crate::observability::Logger;
+use async_nats::Event;
 use serde_json::json;
 
 #[derive(Clone)]
 pub struct HealthState {
     pub readiness: Arc<AtomicBool>,
-    pub version: String,
+    pub build: BuildInfo,
     pub metrics: Arc<Metrics>,
     pub draining: Arc<AtomicBool>,
-    pub max_concurrency: usize,
+    pub concurrency: Arc<ConcurrencyLimit>,
+    pub liveness: Arc<Liveness>,
+    pub control: Arc<WorkerControl>,
+    pub inflight: Arc<InflightTracker>,
+    pub history: Arc<TaskHistory>,
+    /// Redacted effective config for `/config`; `None` disables the endpoint.
+    pub config: Option<Arc<serde_json::Value>>,
+    /// Required on everything but the probe endpoints when set.
+    pub bearer_token: Option<String>,
+    pub dlq_path: String,
+    /// Set once NATS is connected; DLQ replay publishes through it.
+    pub nats: Arc<OnceLock<async_nats::Client>>,
+    /// False when a startup check failed and `STRICT_STARTUP` let the worker run anyway.
+    pub startup_ok: bool,
+    /// `STARTUP_SELFTEST` progress; readiness waits on it when enabled.
+    pub selftest: Arc<SelftestState>,
+    /// Backs `POST /admin/reload`; `None` disables the endpoint.
+    pub reloader: Option<Arc<ConfigReloader>>,
+    /// Readiness is off while it reports pressure; `None` never does.
+    pub resources: Option<Arc<ResourceMonitor>>,
 }
 
-pub async fn start_server(bind_addr: String, state: HealthState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let app = Router::new()
+impl HealthState {
+    fn under_pressure(&self) -> bool {
+        self.resources.as_ref().is_some_and(|r| r.under_pressure())
+    }
+}
+
+/// Whether the processing loop is consuming assignments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Paused,
+    Draining,
+}
+
+impl RunState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunState::Running => "running",
+            RunState::Paused => "paused",
+            RunState::Draining => "draining",
+        }
+    }
+}
+
+/// Operator controls behind `/admin/*`. The processing loop watches the state to
+/// unsubscribe and resubscribe; draining is one-way and also sets the shutdown flag.
+pub struct WorkerControl {
+    draining: Arc<AtomicBool>,
+    state: watch::Sender<RunState>,
+    admin_token: Option<String>,
+}
+
+impl WorkerControl {
+    /// Without an `admin_token` every admin request is rejected.
+    pub fn new(draining: Arc<AtomicBool>, admin_token: Option<String>) -> Self {
+        let (state, _) = watch::channel(RunState::Running);
+        Self { draining, state, admin_token }
+    }
+
+    pub fn state(&self) -> RunState {
+        *self.state.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<RunState> {
+        self.state.subscribe()
+    }
+
+    pub fn pause(&self) -> Result<(), &'static str> {
+        self.transition(RunState::Paused)
+    }
+
+    pub fn resume(&self) -> Result<(), &'static str> {
+        self.transition(RunState::Running)
+    }
+
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.state.send_replace(RunState::Draining);
+    }
+
+    fn transition(&self, next: RunState) -> Result<(), &'static str> {
+        if self.state() == RunState::Draining {
+            return Err("worker is draining");
+        }
+        self.state.send_replace(next);
+        Ok(())
+    }
+
+    pub fn has_admin_token(&self) -> bool {
+        self.admin_token.is_some()
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        token_matches(headers, self.admin_token.as_deref())
+    }
+}
+
+fn token_matches(headers: &HeaderMap, expected: Option<&str>) -> bool {
+    let Some(expected) = expected else {
+        return false;
+    };
+    let presented = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match presented {
+        Some(token) => constant_time_eq(token.as_bytes(), expected.as_bytes()),
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// NATS link as seen by the liveness probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatsLink {
+    Connecting = 0,
+    Connected = 1,
+    Reconnecting = 2,
+    /// The assignment subscription is no longer being consumed.
+    Closed = 3,
+}
+
+impl NatsLink {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => NatsLink::Connecting,
+            1 => NatsLink::Connected,
+            2 => NatsLink::Reconnecting,
+            _ => NatsLink::Closed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NatsLink::Connecting => "connecting",
+            NatsLink::Connected => "connected",
+            NatsLink::Reconnecting => "reconnecting",
+            NatsLink::Closed => "closed",
+        }
+    }
+}
+
+/// Signals behind `/livez`: the last time the processing loop or heartbeat made progress,
+/// the NATS link state, and supervised tasks that panicked too often. A worker that stops ticking should be restarted, one that is
+/// merely reconnecting should not.
+pub struct Liveness {
+    last_activity_ms: AtomicU64,
+    nats: AtomicU8,
+    stall_after: Duration,
+    failed_tasks: Mutex<Vec<&'static str>>,
+}
+
+impl Liveness {
+    pub fn new(stall_after: Duration) -> Self {
+        Self {
+            last_activity_ms: AtomicU64::new(now_ms()),
+            nats: AtomicU8::new(NatsLink::Connecting as u8),
+            stall_after,
+            failed_tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// `task` will not be restarted again; `/livez` fails from now on.
+    pub fn task_failed(&self, task: &'static str) {
+        let mut failed = self.failed_tasks.lock().unwrap();
+        if !failed.contains(&task) {
+            failed.push(task);
+        }
+    }
+
+    pub fn failed_tasks(&self) -> Vec<&'static str> {
+        self.failed_tasks.lock().unwrap().clone()
+    }
+
+    pub fn touch(&self) {
+        self.last_activity_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn set_nats(&self, link: NatsLink) {
+        self.nats.store(link as u8, Ordering::Relaxed);
+    }
+
+    pub fn nats(&self) -> NatsLink {
+        NatsLink::from_u8(self.nats.load(Ordering::Relaxed))
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.last_activity_ms.load(Ordering::Relaxed)))
+    }
+
+    /// Names of the failed checks; empty when the worker is live.
+    pub fn failures(&self) -> Vec<&'static str> {
+        let mut failed = Vec::new();
+        if self.idle_for() > self.stall_after {
+            failed.push("event_loop_stalled");
+        }
+        if self.nats() == NatsLink::Closed {
+            failed.push("nats_closed");
+        }
+        if !self.failed_tasks.lock().unwrap().is_empty() {
+            failed.push("supervised_task_failed");
+        }
+        failed
+    }
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis().max(0) as u64
+}
+
+pub async fn start_server(bind_addr: String, state: HealthState, tls: Option<RustlsConfig>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let app = router(state);
+    let addr: std::net::SocketAddr = bind_addr.parse()?;
+    match tls {
+        Some(tls) => axum_server::bind_rustls(addr, tls).serve(app.into_make_service()).await?,
+        None => axum_server::bind(addr).serve(app.into_make_service()).await?,
+    }
+    Ok(())
+}
+
+/// Probes and `/_build` stay open so the orchestrator and version checks never need the
+/// token; everything else sits behind `require_bearer`.
+pub fn router(state: HealthState) -> Router {
+    let protected = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/_state", get(state_handler))
+        .route("/config", get(config_handler))
+        .route("/inflight", get(inflight_handler))
+        .route("/inflight/:assignment_id", get(inflight_one_handler))
+        .route("/history", get(history_handler))
+        .route("/dlq", get(dlq_list_handler))
+        .route("/dlq/replay", post(dlq_replay_handler))
+        .route("/admin/drain", post(admin_drain_handler))
+        .route("/admin/pause", post(admin_pause_handler))
+        .route("/admin/resume", post(admin_resume_handler))
+        .route("/admin/reload", post(admin_reload_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_bearer));
+    Router::new()
         .route("/_health", get(health_handler))
         .route("/readyz", get(ready_handler))
-        .route("/metrics", get(metrics_handler))
+        .route("/livez", get(live_handler))
         .route("/_build", get(build_handler))
-        .route("/_state", get(state_handler))
-        .with_state(state);
-    
-    let listener = TcpListener::bind(&bind_addr).await?;
-    
-    axum::serve(listener, app).await?;
-    
-    Ok(())
+        .merge(protected)
+        .with_state(state)
+}
+
+/// Accepts `HEALTH_BEARER_TOKEN` or the admin token, so admin calls need only one header.
+async fn require_bearer(State(state): State<HealthState>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.bearer_token {
+        let headers = request.headers();
+        if !token_matches(headers, Some(token)) && !state.control.authorized(headers) {
+            return unauthorized().into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Loads a PEM certificate chain and private key; any problem is a startup error.
+pub fn load_tls(cert_file: &str, key_file: &str) -> Result<RustlsConfig, String> {
+    let cert_pem = std::fs::read(cert_file).map_err(|e| format!("HEALTH_TLS_CERT_FILE {}: {}", cert_file, e))?;
+    let key_pem = std::fs::read(key_file).map_err(|e| format!("HEALTH_TLS_KEY_FILE {}: {}", key_file, e))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("HEALTH_TLS_CERT_FILE {} is not valid PEM: {}", cert_file, e))?;
+    if certs.is_empty() {
+        return Err(format!("HEALTH_TLS_CERT_FILE {} contains no certificates", cert_file));
+    }
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .map_err(|e| format!("HEALTH_TLS_KEY_FILE {} is not valid PEM: {}", key_file, e))?
+        .ok_or_else(|| format!("HEALTH_TLS_KEY_FILE {} contains no private key", key_file))?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS setup failed: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("health TLS certificate rejected: {}", e))?;
+    Ok(RustlsConfig::from_config(Arc::new(config)))
 }
 
 async fn health_handler() -> &'static str {
@@ -37,6 +313,12 @@ async fn ready_handler(State(state): State<HealthState>) -> (StatusCode, &'stati
     let draining = state.draining.load(Ordering::SeqCst);
     if draining {
         (StatusCode::SERVICE_UNAVAILABLE, "DRAINING")
+    } else if !state.startup_ok {
+        (StatusCode::SERVICE_UNAVAILABLE, "STARTUP_CHECKS_FAILED")
+    } else if let Some(why) = state.selftest.blocks_readiness() {
+        (StatusCode::SERVICE_UNAVAILABLE, why)
+    } else if state.under_pressure() {
+        (StatusCode::SERVICE_UNAVAILABLE, WORKER_RESOURCE_PRESSURE)
     } else if state.readiness.load(Ordering::SeqCst) {
         (StatusCode::OK, "READY")
     } else {
@@ -44,26 +326,631 @@ async fn ready_handler(State(state): State<HealthState>) -> (StatusCode, &'stati
     }
 }
 
-async fn build_handler(State(state): State<HealthState>) -> String {
-    state.version.clone()
+async fn live_handler(State(state): State<HealthState>) -> (StatusCode, String) {
+    let failed = state.liveness.failures();
+    let body = json!({
+        "status": if failed.is_empty() { "ok" } else { "failed" },
+        "failed": failed,
+        "idle_seconds": state.liveness.idle_for().as_secs_f64(),
+        "nats": state.liveness.nats().as_str(),
+        "failed_tasks": state.liveness.failed_tasks(),
+    }).to_string();
+    let code = if failed.is_empty() { StatusCode::OK } else { StatusCode::INTERNAL_SERVER_ERROR };
+    (code, body)
+}
+
+async fn build_handler(State(state): State<HealthState>) -> (StatusCode, String) {
+    (StatusCode::OK, serde_json::to_string(&state.build).unwrap_or_default())
 }
 
 async fn metrics_handler(State(state): State<HealthState>) -> (StatusCode, String) {
+    state.metrics.oldest_task_age_seconds.set(state.inflight.oldest_age().as_secs_f64());
     let data = state.metrics.encode();
     (StatusCode::OK, String::from_utf8_lossy(&data).to_string())
 }
 
+/// Tenants listed by `/_state`, busiest first.
+const STATE_TOP_TENANTS: usize = 10;
+
 async fn state_handler(State(state): State<HealthState>) -> (StatusCode, String) {
     let draining = state.draining.load(Ordering::SeqCst);
-    let ready = state.readiness.load(Ordering::SeqCst) && !draining;
+    let ready = state.readiness.load(Ordering::SeqCst) && !draining && state.selftest.blocks_readiness().is_none() && !state.under_pressure();
     let running = state.metrics.tasks_in_progress.get() as f64;
-    let max = state.max_concurrency as f64;
+    let max = state.concurrency.limit() as f64;
     let load = if max == 0.0 { 0.0 } else { (running / max).clamp(0.0, 1.0) };
+    let top_tenants: Vec<_> = state.inflight.top_tenants(STATE_TOP_TENANTS).into_iter()
+        .map(|(tenant_id, in_flight)| json!({"tenant_id": tenant_id, "in_flight": in_flight}))
+        .collect();
     let body = json!({
         "ready": ready,
         "draining": draining,
+        "state": state.control.state().as_str(),
         "load": load,
+        "top_tenants": top_tenants,
+        "selftest": state.selftest.report(),
+        "nats_server": state.nats.get().map(active_server),
+        "resources": state.resources.as_ref().map(|r| r.state()),
+        "uptime_s": state.metrics.uptime_seconds(),
+        "last_activity_at": state.metrics.last_activity_at(),
     }).to_string();
     let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
     (code, body)
 }
+
+/// Open when no admin token is configured, since the body is already redacted.
+async fn config_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    let Some(config) = &state.config else {
+        return (StatusCode::NOT_FOUND, json!({"error": "config endpoint disabled"}).to_string());
+    };
+    if state.control.has_admin_token() && !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    (StatusCode::OK, config.to_string())
+}
+
+/// Metadata only (job type, tenant, trace, timing); payloads are never exposed.
+async fn inflight_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let tasks = state.inflight.snapshot();
+    (StatusCode::OK, json!({"count": tasks.len(), "tasks": tasks}).to_string())
+}
+
+async fn inflight_one_handler(State(state): State<HealthState>, Path(assignment_id): Path<String>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    match state.inflight.get(&assignment_id) {
+        Some(task) => (StatusCode::OK, serde_json::to_string(&task).unwrap_or_default()),
+        None => (StatusCode::NOT_FOUND, json!({"error": "not in flight"}).to_string()),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HistoryQuery {
+    status: Option<String>,
+    job_type: Option<String>,
+}
+
+/// Summaries of recently finished tasks, newest first; like `/inflight`, no payloads or outputs.
+async fn history_handler(State(state): State<HealthState>, Query(query): Query<HistoryQuery>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let entries = state.history.snapshot(query.status.as_deref(), query.job_type.as_deref());
+    (StatusCode::OK, json!({"count": entries.len(), "entries": entries}).to_string())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DlqQuery {
+    limit: Option<usize>,
+    reason: Option<String>,
+}
+
+const DLQ_DEFAULT_LIMIT: usize = 50;
+const DLQ_MAX_LIMIT: usize = 1000;
+
+async fn dlq_list_handler(State(state): State<HealthState>, Query(query): Query<DlqQuery>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let limit = query.limit.unwrap_or(DLQ_DEFAULT_LIMIT).clamp(1, DLQ_MAX_LIMIT);
+    let path = state.dlq_path.clone();
+    // Reading rotations is blocking file IO
+    let listed = tokio::task::spawn_blocking(move || crate::dlq::read_entries(&path, limit, query.reason.as_deref())).await;
+    match listed {
+        Ok(Ok(entries)) => {
+            let entries: Vec<_> = entries.iter().map(|e| e.summary()).collect();
+            (StatusCode::OK, json!({"count": entries.len(), "entries": entries}).to_string())
+        }
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}).to_string()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}).to_string()),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DlqReplayRequest {
+    ids: Vec<String>,
+}
+
+/// Re-publishes each entry's original bytes to its original subject, then tombstones it.
+async fn dlq_replay_handler(State(state): State<HealthState>, headers: HeaderMap, Json(request): Json<DlqReplayRequest>) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let Some(nats) = state.nats.get().cloned() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, json!({"error": "NATS not connected"}).to_string());
+    };
+    let path = state.dlq_path.clone();
+    let ids = request.ids.clone();
+    let found = match tokio::task::spawn_blocking(move || crate::dlq::find_entries(&path, &ids)).await {
+        Ok(Ok(found)) => found,
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}).to_string()),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}).to_string()),
+    };
+
+    let mut replayed = Vec::new();
+    let mut skipped = Vec::new();
+    for id in &request.ids {
+        let Some(entry) = found.iter().find(|e| &e.id == id) else {
+            skipped.push(json!({"id": id, "reason": "not found"}));
+            continue;
+        };
+        if entry.replayed {
+            skipped.push(json!({"id": id, "reason": "already replayed"}));
+            continue;
+        }
+        if entry.record.truncated {
+            skipped.push(json!({"id": id, "reason": "payload truncated"}));
+            continue;
+        }
+        let (Some(subject), Some(payload)) = (entry.record.subject.clone(), entry.record.original_payload()) else {
+            skipped.push(json!({"id": id, "reason": "no original payload"}));
+            continue;
+        };
+        if let Err(e) = nats.publish(subject, payload.into()).await {
+            skipped.push(json!({"id": id, "reason": format!("publish failed: {}", e)}));
+            continue;
+        }
+        if let Err(e) = crate::dlq::mark_replayed(&state.dlq_path, id) {
+            skipped.push(json!({"id": id, "reason": format!("replayed but not marked: {}", e)}));
+            continue;
+        }
+        replayed.push(id.clone());
+    }
+    (StatusCode::OK, json!({"replayed": replayed, "skipped": skipped}).to_string())
+}
+
+fn admin_response(state: &HealthState, result: Result<(), &'static str>) -> (StatusCode, String) {
+    match result {
+        Ok(()) => (StatusCode::OK, json!({"state": state.control.state().as_str()}).to_string()),
+        Err(e) => (StatusCode::CONFLICT, json!({"error": e, "state": state.control.state().as_str()}).to_string()),
+    }
+}
+
+fn unauthorized() -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, json!({"error": "unauthorized"}).to_string())
+}
+
+async fn admin_drain_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    state.control.drain();
+    admin_response(&state, Ok(()))
+}
+
+async fn admin_pause_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let result = state.control.pause();
+    admin_response(&state, result)
+}
+
+async fn admin_resume_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let result = state.control.resume();
+    admin_response(&state, result)
+}
+
+async fn admin_reload_handler(State(state): State<HealthState>, headers: HeaderMap) -> (StatusCode, String) {
+    if !state.control.authorized(&headers) {
+        return unauthorized();
+    }
+    let Some(reloader) = &state.reloader else {
+        return (StatusCode::NOT_FOUND, json!({"error": "reload unavailable"}).to_string());
+    };
+    match reloader.reload("admin") {
+        Ok(changes) => (StatusCode::OK, json!({"changes": changes}).to_string()),
+        Err(errors) => (StatusCode::UNPROCESSABLE_ENTITY, json!({"errors": errors}).to_string()),
+    }
+}
+
+/// The server `client` is currently connected to, as that server describes itself.
+pub fn active_server(client: &async_nats::Client) -> serde_json::Value {
+    let info = client.server_info();
+    json!({"name": info.server_name, "id": info.server_id, "host": info.host, "port": info.port})
+}
+
+/// Keeps readiness and `nats_connected` in step with the NATS client's connection events.
+///
+/// The client reconnects and resubscribes on its own; this only reports the outage so the
+/// worker stops receiving traffic from the orchestrator until the connection is back.
+pub struct ConnectionMonitor {
+    readiness: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    liveness: Arc<Liveness>,
+    logger: Logger,
+    disconnected: AtomicBool,
+    /// Names the server a reconnect landed on, once the client is set.
+    client: Arc<OnceLock<async_nats::Client>>,
+}
+
+impl ConnectionMonitor {
+    pub fn new(readiness: Arc<AtomicBool>, metrics: Arc<Metrics>, liveness: Arc<Liveness>, logger: Logger) -> Self {
+        Self { readiness, metrics, liveness, logger, disconnected: AtomicBool::new(false), client: Arc::new(OnceLock::new()) }
+    }
+
+    pub fn with_client(mut self, client: Arc<OnceLock<async_nats::Client>>) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn on_event(&self, event: &Event) {
+        match event {
+            Event::Disconnected => {
+                self.disconnected.store(true, Ordering::SeqCst);
+                self.liveness.set_nats(NatsLink::Reconnecting);
+                self.readiness.store(false, Ordering::SeqCst);
+                self.metrics.nats_connected.set(0);
+                self.logger.error("NATS connection lost", None);
+            }
+            // The initial connect also reports Connected; only a prior disconnect makes it a reconnect
+            Event::Connected if self.disconnected.swap(false, Ordering::SeqCst) => {
+                self.readiness.store(true, Ordering::SeqCst);
+                self.liveness.set_nats(NatsLink::Connected);
+                self.metrics.nats_connected.set(1);
+                self.metrics.nats_reconnects_total.inc();
+                let server = self.client.get().map(active_server);
+                self.logger.info("NATS connection restored", Some(&json!({"server": server})));
+            }
+            Event::Connected => self.liveness.set_nats(NatsLink::Connected),
+            other => self.logger.warn("NATS client event", Some(&json!({"event": other.to_string()}))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_monitor_flips_readiness() {
+        let readiness = Arc::new(AtomicBool::new(true));
+        let metrics = Arc::new(Metrics::new());
+        metrics.nats_connected.set(1);
+        let liveness = Arc::new(Liveness::new(Duration::from_secs(30)));
+        let monitor = ConnectionMonitor::new(readiness.clone(), metrics.clone(), liveness.clone(), Logger::new("w1".to_string()));
+
+        monitor.on_event(&Event::Connected);
+        assert_eq!(metrics.nats_reconnects_total.get(), 0);
+
+        monitor.on_event(&Event::Disconnected);
+        assert!(!readiness.load(Ordering::SeqCst));
+        assert_eq!(liveness.nats(), NatsLink::Reconnecting);
+        assert!(liveness.failures().is_empty());
+        assert_eq!(metrics.nats_connected.get(), 0);
+
+        monitor.on_event(&Event::Connected);
+        assert!(readiness.load(Ordering::SeqCst));
+        assert_eq!(metrics.nats_connected.get(), 1);
+        assert_eq!(metrics.nats_reconnects_total.get(), 1);
+    }
+
+    fn state_with(liveness: Liveness) -> HealthState {
+        let draining = Arc::new(AtomicBool::new(false));
+        HealthState {
+            readiness: Arc::new(AtomicBool::new(true)),
+            build: crate::build_info::current(),
+            metrics: Arc::new(Metrics::new()),
+            draining: draining.clone(),
+            concurrency: Arc::new(ConcurrencyLimit::new(1)),
+            liveness: Arc::new(liveness),
+            control: Arc::new(WorkerControl::new(draining, Some("s3cret".to_string()))),
+            inflight: Arc::new(InflightTracker::new()),
+            history: Arc::new(TaskHistory::new(10)),
+            config: Some(Arc::new(json!({"worker_id": "w1", "admin_token": "***"}))),
+            bearer_token: None,
+            dlq_path: std::env::temp_dir().join(format!("health-dlq-{}.jsonl", uuid::Uuid::new_v4())).to_string_lossy().to_string(),
+            nats: Arc::new(OnceLock::new()),
+            startup_ok: true,
+            selftest: Arc::new(SelftestState::new(false, true)),
+            reloader: None,
+            resources: None,
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(axum::http::header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_livez_ok_when_ticking() {
+        let liveness = Liveness::new(Duration::from_secs(30));
+        liveness.set_nats(NatsLink::Reconnecting);
+        let (code, body) = live_handler(State(state_with(liveness))).await;
+        assert_eq!(code, StatusCode::OK);
+        assert!(body.contains(r#""nats":"reconnecting""#));
+    }
+
+    #[tokio::test]
+    async fn test_livez_fails_on_stale_activity_and_closed_nats() {
+        let liveness = Liveness::new(Duration::from_secs(30));
+        liveness.last_activity_ms.store(now_ms() - 120_000, Ordering::Relaxed);
+        liveness.set_nats(NatsLink::Closed);
+        let (code, body) = live_handler(State(state_with(liveness))).await;
+        assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["failed"], json!(["event_loop_stalled", "nats_closed"]));
+
+        let liveness = Liveness::new(Duration::from_secs(30));
+        liveness.task_failed("heartbeat");
+        liveness.task_failed("heartbeat");
+        let (code, body) = live_handler(State(state_with(liveness))).await;
+        assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((&body["failed"], &body["failed_tasks"]), (&json!(["supervised_task_failed"]), &json!(["heartbeat"])));
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let (code, _) = admin_pause_handler(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+        let (code, _) = admin_drain_handler(State(state.clone()), bearer("wrong")).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+        assert_eq!(state.control.state(), RunState::Running);
+
+        let open = WorkerControl::new(Arc::new(AtomicBool::new(false)), None);
+        assert!(!open.authorized(&bearer("")));
+    }
+
+    #[tokio::test]
+    async fn test_admin_pause_resume_and_drain() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let mut rx = state.control.subscribe();
+
+        let (code, _) = admin_pause_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*rx.borrow_and_update(), RunState::Paused);
+        let (_, body) = state_handler(State(state.clone())).await;
+        assert!(body.contains(r#""state":"paused""#));
+
+        admin_resume_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(state.control.state(), RunState::Running);
+
+        let (code, _) = admin_drain_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        assert!(state.draining.load(Ordering::SeqCst));
+        let (code, _) = ready_handler(State(state.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        // Draining is one-way
+        let (code, _) = admin_resume_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_inflight_lists_running_sleep_job() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let (code, _) = inflight_handler(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        let tracker = state.inflight.clone();
+        let job = tokio::spawn(async move {
+            let _guard = tracker.start("a-sleep", "sleep", "t1", Some("trace-1".to_string()));
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (code, body) = inflight_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["tasks"][0]["job_type"], "sleep");
+        assert!(body["tasks"][0].get("payload").is_none());
+        let (code, _) = inflight_one_handler(State(state.clone()), Path("a-sleep".to_string()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        let (_, body) = state_handler(State(state.clone())).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["top_tenants"], json!([{"tenant_id": "t1", "in_flight": 1}]));
+        metrics_handler(State(state.clone())).await;
+        assert!(state.metrics.oldest_task_age_seconds.get() > 0.0);
+
+        job.await.unwrap();
+        let (code, _) = inflight_one_handler(State(state.clone()), Path("a-sleep".to_string()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+        assert_eq!(state.inflight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_config_endpoint_auth_and_disable() {
+        let mut state = state_with(Liveness::new(Duration::from_secs(30)));
+        let (code, _) = config_handler(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+        let (code, body) = config_handler(State(state.clone()), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        assert!(body.contains(r#""worker_id":"w1""#));
+
+        state.control = Arc::new(WorkerControl::new(state.draining.clone(), None));
+        let (code, _) = config_handler(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::OK);
+
+        state.config = None;
+        let (code, _) = config_handler(State(state), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+    }
+
+    async fn get_status(app: &Router, uri: &str, token: Option<&str>) -> StatusCode {
+        use tower::ServiceExt;
+        let mut request = axum::http::Request::get(uri);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        app.clone().oneshot(request.body(axum::body::Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_guards_all_but_probes() {
+        let mut state = state_with(Liveness::new(Duration::from_secs(30)));
+        state.bearer_token = Some("scrape".to_string());
+        let app = router(state);
+
+        assert_eq!(get_status(&app, "/metrics", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(get_status(&app, "/_state", Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(get_status(&app, "/metrics", Some("scrape")).await, StatusCode::OK);
+        assert_eq!(get_status(&app, "/_state", Some("scrape")).await, StatusCode::OK);
+        // The admin token also passes the outer check
+        assert_eq!(get_status(&app, "/config", Some("s3cret")).await, StatusCode::OK);
+
+        assert_eq!(get_status(&app, "/_health", None).await, StatusCode::OK);
+        assert_eq!(get_status(&app, "/readyz", None).await, StatusCode::OK);
+        assert_eq!(get_status(&app, "/livez", None).await, StatusCode::OK);
+        assert_eq!(get_status(&app, "/_build", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_failed_startup_checks_keep_worker_not_ready() {
+        let mut state = state_with(Liveness::new(Duration::from_secs(30)));
+        state.startup_ok = false;
+        assert_eq!(ready_handler(State(state)).await, (StatusCode::SERVICE_UNAVAILABLE, "STARTUP_CHECKS_FAILED"));
+    }
+
+    #[tokio::test]
+    async fn test_selftest_holds_back_readiness() {
+        let mut state = state_with(Liveness::new(Duration::from_secs(30)));
+        state.readiness.store(true, Ordering::SeqCst);
+        state.selftest = Arc::new(SelftestState::new(true, true));
+        assert_eq!(ready_handler(State(state.clone())).await, (StatusCode::SERVICE_UNAVAILABLE, "SELFTEST_PENDING"));
+
+        let check = crate::selftest::SelftestCheck { handler: "echo".to_string(), target: None, ok: false, duration_ms: 1, error: Some("boom".to_string()) };
+        state.selftest.set(crate::selftest::SelftestReport { passed: false, checks: vec![check], skipped: Vec::new() });
+        assert_eq!(ready_handler(State(state.clone())).await, (StatusCode::SERVICE_UNAVAILABLE, "SELFTEST_FAILED"));
+        let (code, body) = state_handler(State(state)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["selftest"]["checks"][0]["error"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_resource_pressure_holds_back_readiness() {
+        struct Full;
+        impl crate::resources::ResourceSampler for Full {
+            fn sample(&self) -> crate::resources::ResourceSample {
+                crate::resources::ResourceSample { disk_free_bytes: Some(10), ..Default::default() }
+            }
+        }
+        let mut state = state_with(Liveness::new(Duration::from_secs(30)));
+        let monitor = Arc::new(ResourceMonitor::new(Box::new(Full), Some(1024), None));
+        state.resources = Some(monitor.clone());
+        assert_eq!(ready_handler(State(state.clone())).await, (StatusCode::OK, "READY"));
+
+        monitor.check(&state.metrics, &Logger::new("health-test".to_string()));
+        assert_eq!(ready_handler(State(state.clone())).await, (StatusCode::SERVICE_UNAVAILABLE, "WORKER_RESOURCE_PRESSURE"));
+        let (_, body) = state_handler(State(state)).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["resources"]["reasons"], json!(["disk"]));
+        assert_eq!(body["resources"]["disk_free_bytes"], 10);
+    }
+
+    #[tokio::test]
+    async fn test_state_and_metrics_report_uptime_and_last_activity() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let (_, body) = state_handler(State(state.clone())).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(body["uptime_s"].is_u64());
+        assert!(body["last_activity_at"].is_null(), "nothing has run yet");
+
+        state.metrics.mark_assignment_completed();
+        let (_, body) = state_handler(State(state.clone())).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(body["last_activity_at"].as_str().unwrap()).is_ok());
+
+        let (_, metrics) = metrics_handler(State(state)).await;
+        for name in ["worker_start_time_seconds", "worker_uptime_seconds", "last_assignment_completed_timestamp_seconds"] {
+            assert!(metrics.contains(&format!("# TYPE {} gauge", name)), "{} missing", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_bearer_token_leaves_metrics_open() {
+        let app = router(state_with(Liveness::new(Duration::from_secs(30))));
+        assert_eq!(get_status(&app, "/metrics", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_load_tls_rejects_bad_paths_and_pem() {
+        assert!(load_tls("/nonexistent/cert.pem", "/nonexistent/key.pem").unwrap_err().contains("HEALTH_TLS_CERT_FILE"));
+        let dir = std::env::temp_dir().join(format!("health-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        std::fs::write(&cert, "not a certificate").unwrap();
+        std::fs::write(&key, "not a key").unwrap();
+        let err = load_tls(cert.to_str().unwrap(), key.to_str().unwrap()).unwrap_err();
+        assert!(err.contains("no certificates"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_build_endpoint_shape() {
+        let (code, body) = build_handler(State(state_with(Liveness::new(Duration::from_secs(30))))).await;
+        assert_eq!(code, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        for key in ["git_sha", "build_timestamp", "rustc_version"] {
+            assert!(body[key].as_str().is_some_and(|v| !v.is_empty()), "missing {}", key);
+        }
+        assert_eq!(body.as_object().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_dlq_listing_and_replay_without_nats() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let policy = crate::rotation::RotationPolicy { max_bytes: u64::MAX, max_rotations: 5, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+        let replayable = crate::protocol::DeadLetter::new(crate::protocol::DeadLetterReason::PublishError, json!({"assignment_id": "a1"}))
+            .with_original("caf.exec.result.v1", b"{}", 1024);
+        crate::dlq::write_deadletter_to_file(&replayable, &state.dlq_path, &policy).unwrap();
+        let legacy = crate::protocol::DeadLetter::new(crate::protocol::DeadLetterReason::ParseError, json!({"len": 3}));
+        crate::dlq::write_deadletter_to_file(&legacy, &state.dlq_path, &policy).unwrap();
+
+        let query = |limit, reason: Option<&str>| Query(DlqQuery { limit, reason: reason.map(str::to_string) });
+        let (code, _) = dlq_list_handler(State(state.clone()), query(None, None), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        let (code, body) = dlq_list_handler(State(state.clone()), query(None, None), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["count"], 2);
+        assert_eq!(body["entries"][0]["reason"], "PARSE_ERROR");
+        assert_eq!(body["entries"][0]["replayable"], false);
+        assert_eq!(body["entries"][1]["replayable"], true);
+        assert!(body["entries"][1].get("payload_b64").is_none());
+
+        let (_, body) = dlq_list_handler(State(state.clone()), query(Some(1), Some("PUBLISH_ERROR")), bearer("s3cret")).await;
+        assert!(body.contains(r#""count":1"#));
+
+        let request = Json(DlqReplayRequest { ids: vec!["x".to_string()] });
+        let (code, _) = dlq_replay_handler(State(state.clone()), bearer("s3cret"), request).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        let _ = std::fs::remove_file(&state.dlq_path);
+    }
+
+    #[tokio::test]
+    async fn test_history_endpoint_filters_and_requires_token() {
+        let state = state_with(Liveness::new(Duration::from_secs(30)));
+        let (code, _) = history_handler(State(state.clone()), Query(HistoryQuery { status: None, job_type: None }), HeaderMap::new()).await;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        for (id, status) in [("a1", crate::protocol::ExecStatus::Success), ("a2", crate::protocol::ExecStatus::Timeout)] {
+            let mut result: crate::protocol::ExecResult = serde_json::from_value(json!({
+                "version": "1.0", "assignment_id": id, "request_id": "r1", "status": "success",
+                "provider_id": "w1", "job_type": "sleep", "latency_ms": 5, "cost": 0.0
+            })).unwrap();
+            result.status = status;
+            state.history.record(&result);
+        }
+        let query = HistoryQuery { status: Some("timeout".to_string()), job_type: Some("sleep".to_string()) };
+        let (code, body) = history_handler(State(state.clone()), Query(query), bearer("s3cret")).await;
+        assert_eq!(code, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["entries"][0]["assignment_id"], "a2");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::health::{Liveness, NatsLink};
+use crate::observability::{metrics::Metrics, Logger};
+use crate::pipeline::ResultPublisher;
+use crate::retry::Backoff;
+use rand::Rng;
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Each delay is moved by up to this fraction either way, so workers started together drift
+/// apart instead of publishing in lockstep.
+pub const HEARTBEAT_JITTER: f64 = 0.1;
+
+/// The wait before the next heartbeat after `failures` consecutive failed ones, with `jitter`
+/// in `[-1, 1]` scaling the ±10% spread. Healthy heartbeats wait `interval`; after a failure
+/// the next attempt comes at an eighth of it, doubling per failure up to `interval` again.
+pub fn heartbeat_delay(interval: Duration, failures: u32, jitter: f64) -> Duration {
+    let base = if failures == 0 {
+        interval
+    } else {
+        Backoff::new(interval / 8, interval).with_jitter(false).ceiling(failures)
+    };
+    base.mul_f64(1.0 + HEARTBEAT_JITTER * jitter.clamp(-1.0, 1.0))
+}
+
+/// Publishes heartbeats and tracks whether the control plane is still seeing them.
+///
+/// Failures are logged once when they start and once when they reach
+/// `HEARTBEAT_FAILURES_BEFORE_UNREADY`, where readiness goes off; the first heartbeat that
+/// goes out again brings it back if NATS is connected. Zero never flips readiness.
+pub struct HeartbeatMonitor {
+    interval: Duration,
+    failures_before_unready: u32,
+    consecutive_failures: u32,
+    readiness: Arc<AtomicBool>,
+    liveness: Arc<Liveness>,
+    metrics: Arc<Metrics>,
+    logger: Logger,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(interval: Duration, failures_before_unready: u32, readiness: Arc<AtomicBool>, liveness: Arc<Liveness>, metrics: Arc<Metrics>, logger: Logger) -> Self {
+        Self { interval, failures_before_unready, consecutive_failures: 0, readiness, liveness, metrics, logger }
+    }
+
+    /// How long to wait before the next heartbeat.
+    pub fn next_delay(&self) -> Duration {
+        heartbeat_delay(self.interval, self.consecutive_failures, rand::thread_rng().gen_range(-1.0..=1.0))
+    }
+
+    /// Publishes one encoded heartbeat and records the outcome.
+    pub async fn send(&mut self, publisher: &dyn ResultPublisher, subject: &str, payload: &[u8]) -> bool {
+        match publisher.publish(subject, None, payload).await {
+            Ok(()) => {
+                self.metrics.heartbeat_sent_total.inc();
+                self.record_success();
+                true
+            }
+            Err(failure) => {
+                self.metrics.heartbeat_failed_total.inc();
+                self.record_failure(&failure.error);
+                false
+            }
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.consecutive_failures == 0 {
+            return;
+        }
+        let unready = self.marks_unready();
+        self.logger.info("Heartbeats are going out again", Some(&json!({"failed": self.consecutive_failures})));
+        self.consecutive_failures = 0;
+        self.metrics.heartbeat_consecutive_failures.set(0);
+        if unready && self.liveness.nats() == NatsLink::Connected {
+            self.readiness.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn record_failure(&mut self, error: &str) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.metrics.heartbeat_consecutive_failures.set(self.consecutive_failures as i64);
+        if self.consecutive_failures == 1 {
+            self.logger.warn("Heartbeat failed to publish, retrying with backoff", Some(&json!({"error": error})));
+        }
+        if self.failures_before_unready > 0 && self.consecutive_failures == self.failures_before_unready {
+            self.logger.error("Heartbeats keep failing, marking the worker not ready", Some(&json!({"error": error, "failed": self.consecutive_failures})));
+            self.readiness.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn marks_unready(&self) -> bool {
+        self.failures_before_unready > 0 && self.consecutive_failures >= self.failures_before_unready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::WorkerError;
+    use crate::pipeline::PublishFailure;
+    use async_nats::HeaderMap;
+    use futures::future::BoxFuture;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Fails the first `failures` publishes.
+    struct FlakyPublisher {
+        failures: AtomicUsize,
+    }
+
+    impl ResultPublisher for FlakyPublisher {
+        fn publish<'a>(&'a self, _subject: &'a str, _headers: Option<&'a HeaderMap>, _payload: &'a [u8]) -> BoxFuture<'a, Result<(), PublishFailure>> {
+            Box::pin(async move {
+                if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                    return Err(PublishFailure { error: "no responders".to_string(), classified: WorkerError::transient("no responders") });
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn heartbeat_monitor(failures_before_unready: u32) -> (HeartbeatMonitor, Arc<AtomicBool>, Arc<Liveness>, Arc<Metrics>) {
+        let readiness = Arc::new(AtomicBool::new(true));
+        let liveness = Arc::new(Liveness::new(Duration::from_secs(60)));
+        liveness.set_nats(NatsLink::Connected);
+        let metrics = Arc::new(Metrics::new());
+        let monitor = HeartbeatMonitor::new(Duration::from_secs(8), failures_before_unready, readiness.clone(), liveness.clone(), metrics.clone(), Logger::new("w1".to_string()));
+        (monitor, readiness, liveness, metrics)
+    }
+
+    #[test]
+    fn test_delay_jitters_and_backs_off_up_to_the_interval() {
+        let interval = Duration::from_secs(8);
+        assert_eq!(heartbeat_delay(interval, 0, 0.0), interval);
+        assert_eq!(heartbeat_delay(interval, 0, -1.0), Duration::from_millis(7200));
+        assert_eq!(heartbeat_delay(interval, 0, 1.0), Duration::from_millis(8800));
+        let backoff: Vec<_> = (1..=6).map(|n| heartbeat_delay(interval, n, 0.0).as_millis()).collect();
+        assert_eq!(backoff, vec![1000, 2000, 4000, 8000, 8000, 8000]);
+        assert_eq!(heartbeat_delay(interval, 2, 5.0), Duration::from_millis(2200), "jitter is clamped");
+
+        let (monitor, ..) = heartbeat_monitor(3);
+        for _ in 0..100 {
+            let delay = monitor.next_delay();
+            assert!(delay >= Duration::from_millis(7200) && delay <= Duration::from_millis(8800), "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_flip_readiness_until_recovery() {
+        let (mut monitor, readiness, _, metrics) = heartbeat_monitor(3);
+        let publisher = FlakyPublisher { failures: AtomicUsize::new(4) };
+        for n in 1..=2 {
+            assert!(!monitor.send(&publisher, "hb", b"{}").await);
+            assert_eq!(metrics.heartbeat_consecutive_failures.get(), n);
+        }
+        assert!(readiness.load(Ordering::SeqCst));
+        assert!(!monitor.send(&publisher, "hb", b"{}").await);
+        assert!(!readiness.load(Ordering::SeqCst), "third failure in a row");
+        assert!(monitor.next_delay() <= Duration::from_millis(4400));
+
+        assert!(!monitor.send(&publisher, "hb", b"{}").await);
+        assert!(monitor.send(&publisher, "hb", b"{}").await);
+        assert!(readiness.load(Ordering::SeqCst));
+        assert_eq!(monitor.consecutive_failures, 0);
+        assert_eq!(metrics.heartbeat_consecutive_failures.get(), 0);
+        assert_eq!((metrics.heartbeat_failed_total.get(), metrics.heartbeat_sent_total.get()), (4, 1));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_leaves_readiness_to_the_connection() {
+        let (mut monitor, readiness, liveness, _) = heartbeat_monitor(1);
+        let publisher = FlakyPublisher { failures: AtomicUsize::new(1) };
+        monitor.send(&publisher, "hb", b"{}").await;
+        assert!(!readiness.load(Ordering::SeqCst));
+        liveness.set_nats(NatsLink::Reconnecting);
+        assert!(monitor.send(&publisher, "hb", b"{}").await);
+        assert!(!readiness.load(Ordering::SeqCst), "still reconnecting");
+
+        let (mut monitor, readiness, ..) = heartbeat_monitor(0);
+        let publisher = FlakyPublisher { failures: AtomicUsize::new(10) };
+        for _ in 0..10 {
+            monitor.send(&publisher, "hb", b"{}").await;
+        }
+        assert!(readiness.load(Ordering::SeqCst), "zero never flips readiness");
+    }
+}
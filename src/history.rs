@@ -0,0 +1,110 @@
+use crate::protocol::ExecResult;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A finished task as served by `GET /history`; like `InflightView` it never holds payloads or outputs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub assignment_id: String,
+    pub job_type: String,
+    pub tenant_id: Option<String>,
+    pub status: String,
+    pub error_code: Option<String>,
+    pub latency_ms: u64,
+    pub finished_at: String,
+}
+
+/// The last `capacity` finished tasks, oldest evicted first; a capacity of 0 keeps nothing.
+#[derive(Debug)]
+pub struct TaskHistory {
+    capacity: usize,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl TaskHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, entries: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    pub fn record(&self, result: &ExecResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = HistoryEntry {
+            assignment_id: result.assignment_id.clone(),
+            job_type: result.job_type.clone(),
+            tenant_id: result.tenant_id.clone(),
+            status: result.status.as_str().to_string(),
+            error_code: result.error_code.clone(),
+            latency_ms: result.latency_ms,
+            finished_at: result.finished_at.clone().unwrap_or_else(|| chrono::Utc::now().to_rfc3339()),
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Newest first, keeping only entries matching the filters that are set.
+    pub fn snapshot(&self, status: Option<&str>, job_type: Option<&str>) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev()
+            .filter(|e| status.is_none_or(|s| e.status == s))
+            .filter(|e| job_type.is_none_or(|t| e.job_type == t))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ExecStatus;
+
+    fn result(id: &str, status: ExecStatus) -> ExecResult {
+        ExecResult {
+            version: "1.0".to_string(),
+            assignment_id: id.to_string(),
+            request_id: "r1".to_string(),
+            status,
+            provider_id: "w1".to_string(),
+            job_type: "echo".to_string(),
+            output: Some(serde_json::json!({"secret": "value"})),
+            latency_ms: 3,
+            cost: 0.0,
+            trace_id: None,
+            trace_generated: None,
+            tenant_id: Some("t1".to_string()),
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            error_code: None,
+            error_message: None,
+            retryable: false,
+            artifacts: Vec::new(),
+            queue_latency_ms: None,
+            serialization_wait_ms: None,
+            started_at: None,
+            finished_at: Some("2026-01-01T00:00:00Z".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_bounded_and_filtered() {
+        let history = TaskHistory::new(2);
+        history.record(&result("a1", ExecStatus::Success));
+        history.record(&result("a2", ExecStatus::Error));
+        history.record(&result("a3", ExecStatus::Success));
+        let ids: Vec<_> = history.snapshot(None, None).into_iter().map(|e| e.assignment_id).collect();
+        assert_eq!(ids, vec!["a3", "a2"]);
+        assert_eq!(history.snapshot(Some("error"), None)[0].assignment_id, "a2");
+        assert!(history.snapshot(None, Some("http")).is_empty());
+        assert!(!serde_json::to_string(&history.snapshot(None, None)).unwrap().contains("secret"));
+
+        let disabled = TaskHistory::new(0);
+        disabled.record(&result("a1", ExecStatus::Success));
+        assert!(disabled.snapshot(None, None).is_empty());
+    }
+}
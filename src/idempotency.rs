@@ -0,0 +1,126 @@
+use crate::protocol::{ExecAssignment, ExecResult, ExecStatus};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+type Key = (String, String);
+
+struct Entry {
+    result: ExecResult,
+    stored: Instant,
+}
+
+/// Results by `(tenant_id, idempotency_key)`, so a controller retry that comes back under a new
+/// `assignment_id` is answered without running the job again.
+///
+/// Bounded like the result cache: at most `capacity` entries, least recently used evicted
+/// first, entries older than `ttl` gone. Only successes are kept unless `cache_failures`.
+pub struct IdempotencyCache {
+    capacity: usize,
+    ttl: Duration,
+    cache_failures: bool,
+    inner: Mutex<(HashMap<Key, Entry>, VecDeque<Key>)>,
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize, ttl: Duration, cache_failures: bool) -> Self {
+        Self { capacity, ttl, cache_failures, inner: Mutex::new((HashMap::new(), VecDeque::new())) }
+    }
+
+    /// Remembers `result` for later assignments of the tenant carrying the same key.
+    pub fn insert(&self, tenant_id: &str, idempotency_key: &str, result: &ExecResult) {
+        if self.capacity == 0 || (!self.cache_failures && !matches!(result.status, ExecStatus::Success)) {
+            return;
+        }
+        let key = (tenant_id.to_string(), idempotency_key.to_string());
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        if entries.insert(key.clone(), Entry { result: result.clone(), stored: Instant::now() }).is_some() {
+            order.retain(|k| *k != key);
+        }
+        order.push_back(key);
+        while order.len() > self.capacity {
+            if let Some(old) = order.pop_front() {
+                entries.remove(&old);
+            }
+        }
+    }
+
+    /// The earlier result readdressed to `assignment`, so the controller can correlate it.
+    pub fn get(&self, assignment: &ExecAssignment) -> Option<ExecResult> {
+        let key = (assignment.tenant_id.clone(), assignment.idempotency_key.clone()?);
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        let expired = entries.get(&key)?.stored.elapsed() > self.ttl;
+        order.retain(|k| *k != key);
+        if expired {
+            entries.remove(&key);
+            return None;
+        }
+        let mut result = entries.get(&key)?.result.clone();
+        order.push_back(key);
+        result.assignment_id = assignment.assignment_id.clone();
+        result.request_id = assignment.request_id.clone();
+        result.trace_id = assignment.trace_id.clone();
+        result.run_id = assignment.run_id.clone();
+        result.flow_id = assignment.flow_id.clone();
+        result.step_id = assignment.step_id.clone();
+        result.queue_latency_ms = None;
+        result.serialization_wait_ms = None;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn assignment(id: &str, tenant: &str, key: &str) -> ExecAssignment {
+        serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": id, "request_id": format!("r-{}", id), "tenant_id": tenant,
+            "job": {"type": "echo", "payload": {}}, "idempotency_key": key
+        })).unwrap()
+    }
+
+    fn result(status: ExecStatus) -> ExecResult {
+        let a = assignment("a1", "t1", "k1");
+        let mut result: ExecResult = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": a.assignment_id, "request_id": a.request_id, "status": "success",
+            "provider_id": "w1", "job_type": "echo", "output": {"n": 1}, "latency_ms": 3, "cost": 0.0
+        })).unwrap();
+        result.status = status;
+        result
+    }
+
+    #[test]
+    fn test_hits_are_readdressed_and_scoped_by_tenant() {
+        let cache = IdempotencyCache::new(4, Duration::from_secs(60), false);
+        cache.insert("t1", "k1", &result(ExecStatus::Success));
+        let hit = cache.get(&assignment("a2", "t1", "k1")).unwrap();
+        assert_eq!((hit.assignment_id.as_str(), hit.request_id.as_str()), ("a2", "r-a2"));
+        assert_eq!(hit.output, Some(json!({"n": 1})));
+        assert!(cache.get(&assignment("a3", "t2", "k1")).is_none());
+        assert!(cache.get(&assignment("a4", "t1", "k2")).is_none());
+    }
+
+    #[test]
+    fn test_failures_are_kept_only_when_asked() {
+        let cache = IdempotencyCache::new(4, Duration::from_secs(60), false);
+        cache.insert("t1", "k1", &result(ExecStatus::Error));
+        assert!(cache.get(&assignment("a2", "t1", "k1")).is_none());
+        let cache = IdempotencyCache::new(4, Duration::from_secs(60), true);
+        cache.insert("t1", "k1", &result(ExecStatus::Error));
+        assert!(matches!(cache.get(&assignment("a2", "t1", "k1")).unwrap().status, ExecStatus::Error));
+    }
+
+    #[test]
+    fn test_bounded_and_expiring() {
+        let cache = IdempotencyCache::new(1, Duration::from_millis(20), false);
+        cache.insert("t1", "k1", &result(ExecStatus::Success));
+        cache.insert("t1", "k2", &result(ExecStatus::Success));
+        assert!(cache.get(&assignment("a2", "t1", "k1")).is_none());
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(cache.get(&assignment("a3", "t1", "k2")).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::protocol::HeartbeatTask;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Metadata about a running assignment; payloads are deliberately never stored.
+#[derive(Debug, Clone)]
+pub struct InflightInfo {
+    pub job_type: String,
+    pub tenant_id: String,
+    pub trace_id: Option<String>,
+    pub started_at: Instant,
+    pub started_ts: DateTime<Utc>,
+}
+
+/// `InflightInfo` as served by `GET /inflight`.
+#[derive(Debug, Clone, Serialize)]
+pub struct InflightView {
+    pub assignment_id: String,
+    pub job_type: String,
+    pub tenant_id: String,
+    pub trace_id: Option<String>,
+    pub started_at: String,
+    pub elapsed_ms: u64,
+}
+
+/// Assignments holding a concurrency permit, keyed by assignment id.
+#[derive(Debug, Default)]
+pub struct InflightTracker {
+    tasks: DashMap<String, InflightInfo>,
+}
+
+impl InflightTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a task; it is removed when the returned guard drops, even if the task panics.
+    pub fn start(self: &Arc<Self>, assignment_id: &str, job_type: &str, tenant_id: &str, trace_id: Option<String>) -> InflightGuard {
+        self.tasks.insert(assignment_id.to_string(), InflightInfo {
+            job_type: job_type.to_string(),
+            tenant_id: tenant_id.to_string(),
+            trace_id,
+            started_at: Instant::now(),
+            started_ts: Utc::now(),
+        });
+        InflightGuard { tracker: self.clone(), assignment_id: assignment_id.to_string() }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn get(&self, assignment_id: &str) -> Option<InflightView> {
+        self.tasks.get(assignment_id).map(|e| view(e.key(), e.value()))
+    }
+
+    /// All in-flight tasks, oldest first.
+    pub fn snapshot(&self) -> Vec<InflightView> {
+        let mut views: Vec<InflightView> = self.tasks.iter().map(|e| view(e.key(), e.value())).collect();
+        views.sort_by_key(|v| std::cmp::Reverse(v.elapsed_ms));
+        views
+    }
+
+    /// The `max` longest-running tasks for a heartbeat, and whether any were left out.
+    pub fn heartbeat_tasks(&self, max: usize) -> (Vec<HeartbeatTask>, bool) {
+        let snapshot = self.snapshot();
+        let truncated = snapshot.len() > max;
+        let tasks = snapshot.into_iter().take(max)
+            .map(|v| HeartbeatTask { assignment_id: v.assignment_id, job_type: v.job_type, running_ms: v.elapsed_ms })
+            .collect();
+        (tasks, truncated)
+    }
+
+    /// The `n` tenants with the most running tasks, busiest first (ties by tenant id).
+    pub fn top_tenants(&self, n: usize) -> Vec<(String, usize)> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for entry in self.tasks.iter() {
+            *counts.entry(entry.value().tenant_id.clone()).or_default() += 1;
+        }
+        let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts
+    }
+
+    pub fn oldest_age(&self) -> Duration {
+        self.tasks.iter().map(|e| e.value().started_at.elapsed()).max().unwrap_or_default()
+    }
+}
+
+fn view(assignment_id: &str, info: &InflightInfo) -> InflightView {
+    InflightView {
+        assignment_id: assignment_id.to_string(),
+        job_type: info.job_type.clone(),
+        tenant_id: info.tenant_id.clone(),
+        trace_id: info.trace_id.clone(),
+        started_at: info.started_ts.to_rfc3339(),
+        elapsed_ms: info.started_at.elapsed().as_millis() as u64,
+    }
+}
+
+pub struct InflightGuard {
+    tracker: Arc<InflightTracker>,
+    assignment_id: String,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.tracker.tasks.remove(&self.assignment_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_tracks_and_releases() {
+        let tracker = Arc::new(InflightTracker::new());
+        let first = tracker.start("a1", "sleep", "t1", Some("trace-1".to_string()));
+        std::thread::sleep(Duration::from_millis(5));
+        let second = tracker.start("a2", "http", "t2", None);
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].assignment_id, "a1");
+        assert!(tracker.oldest_age() >= Duration::from_millis(5));
+        assert_eq!(tracker.get("a2").unwrap().job_type, "http");
+
+        drop(first);
+        assert!(tracker.get("a1").is_none());
+        drop(second);
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.oldest_age(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_heartbeat_tasks_are_capped() {
+        let tracker = Arc::new(InflightTracker::new());
+        let guards: Vec<_> = (0..3).map(|i| tracker.start(&format!("a{}", i), "echo", "t1", None)).collect();
+        let (tasks, truncated) = tracker.heartbeat_tasks(2);
+        assert_eq!(tasks.len(), 2);
+        assert!(truncated);
+        assert_eq!(tasks[0].job_type, "echo");
+
+        let (tasks, truncated) = tracker.heartbeat_tasks(3);
+        assert_eq!(tasks.len(), 3);
+        assert!(!truncated);
+        drop(guards);
+        assert_eq!(tracker.heartbeat_tasks(50), (Vec::new(), false));
+    }
+
+    #[test]
+    fn test_top_tenants_by_inflight_count() {
+        let tracker = Arc::new(InflightTracker::new());
+        let tenants = ["t2", "t1", "t2", "t3", "t1", "t2"];
+        let _guards: Vec<_> = tenants.iter().enumerate().map(|(i, t)| tracker.start(&format!("a{}", i), "echo", t, None)).collect();
+        assert_eq!(tracker.top_tenants(2), vec![("t2".to_string(), 3), ("t1".to_string(), 2)]);
+        assert_eq!(tracker.top_tenants(10).len(), 3);
+    }
+}
@@ -5,3 +5,34 @@ pub mod protocol;
 pub mod executor;
 pub mod handlers;
 pub mod error;
+pub mod signing;
+pub mod compression;
+pub mod cost;
+pub mod rotation;
+pub mod inflight;
+pub mod build_info;
+pub mod dlq;
+pub mod retry;
+pub mod cli;
+pub mod startup;
+pub mod concurrency;
+pub mod reload;
+pub mod pipeline;
+pub mod result_queue;
+pub mod worker;
+pub mod chaos;
+pub mod loadgen;
+pub mod history;
+pub mod idempotency;
+pub mod outbox;
+pub mod params;
+pub mod redaction;
+pub mod resources;
+pub mod result_cache;
+pub mod selftest;
+pub mod heartbeat;
+pub mod receipts;
+pub mod recorder;
+pub mod supervisor;
+
+pub use worker::{Error, Worker};
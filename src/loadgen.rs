@@ -0,0 +1,291 @@
+use crate::compression;
+use crate::config::{Config, SUBJECT_PLACEHOLDERS};
+use crate::pipeline::{self, NatsPublisher};
+use crate::protocol::{self, EventEnvelopeV1, ExecAssignment, ExecResult, ExecStatus, Job};
+use crate::signing::EnvelopeSigner;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Assignments sent when neither `--count` nor `--duration` is given.
+pub const DEFAULT_COUNT: u64 = 1000;
+/// How long to keep waiting for results after the last assignment went out.
+pub const DEFAULT_RESULT_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct LoadgenOptions {
+    /// Assignments published per second.
+    pub rate: u32,
+    /// Stop after this many; at least one of `count` and `duration` should be set.
+    pub count: Option<u64>,
+    /// Stop publishing once this has passed.
+    pub duration: Option<Duration>,
+    /// Assignments allowed to be waiting for their result at once.
+    pub concurrency: usize,
+    pub job_type: String,
+    /// Rendered per assignment with `render_payload`.
+    pub payload: Value,
+    pub result_wait: Duration,
+}
+
+/// Payload template for the job types loadgen knows without `--payload-file`.
+pub fn default_payload(job_type: &str) -> Result<Value, String> {
+    match job_type {
+        "echo" => Ok(json!({"seq": "{{seq}}", "assignment_id": "{{assignment_id}}"})),
+        "sleep" => Ok(json!({"ms": 10})),
+        "jmespath" => Ok(json!({
+            "expression": "items[?id > `2`].id",
+            "data": {"seq": "{{seq}}", "items": [{"id": 1}, {"id": 2}, {"id": 3}, {"id": 4}]}
+        })),
+        other => Err(format!("no default payload for job type '{}'; pass --payload-file", other)),
+    }
+}
+
+/// Fills `{{seq}}` and `{{assignment_id}}` in the template's strings. A string that is exactly
+/// `{{seq}}` becomes the number.
+pub fn render_payload(template: &Value, seq: u64, assignment_id: &str) -> Value {
+    match template {
+        Value::String(s) if s == "{{seq}}" => json!(seq),
+        Value::String(s) => Value::String(s.replace("{{seq}}", &seq.to_string()).replace("{{assignment_id}}", assignment_id)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| render_payload(v, seq, assignment_id)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render_payload(v, seq, assignment_id))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Subject that receives every result of a `CAF_RESULT_SUBJECT` template: tokens with a
+/// placeholder become `*`.
+pub fn result_subscription(template: &str) -> String {
+    template
+        .split('.')
+        .map(|token| if SUBJECT_PLACEHOLDERS.iter().any(|p| token.contains(p)) { "*" } else { token })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+pub fn assignment(options: &LoadgenOptions, run: &str, seq: u64) -> ExecAssignment {
+    let assignment_id = format!("loadgen-{}-{}", run, seq);
+    ExecAssignment {
+        version: "1.0".to_string(),
+        request_id: format!("req-{}", assignment_id),
+        tenant_id: "loadgen".to_string(),
+        job: Job { r#type: options.job_type.clone(), payload: render_payload(&options.payload, seq, &assignment_id) },
+        assignment_id,
+        trace_id: None,
+        run_id: Some(run.to_string()),
+        flow_id: None,
+        step_id: None,
+        published_at: Some(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        concurrency_key: None,
+        idempotency_key: None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Nearest-rank percentiles; None without samples.
+pub fn percentiles(samples: &mut [f64]) -> Option<Percentiles> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_by(|a, b| a.total_cmp(b));
+    let rank = |p: f64| samples[((p * samples.len() as f64).ceil() as usize).clamp(1, samples.len()) - 1];
+    Some(Percentiles { p50: rank(0.50), p90: rank(0.90), p99: rank(0.99), max: samples[samples.len() - 1] })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadgenReport {
+    pub job_type: String,
+    pub sent: u64,
+    pub received: u64,
+    pub succeeded: u64,
+    /// Results with any status but `success`.
+    pub failed: u64,
+    /// Sent but no result arrived in time.
+    pub lost: u64,
+    pub elapsed_s: f64,
+    /// Results received per second over the whole run.
+    pub throughput_per_s: f64,
+    /// Publish to result received, on this process's clock.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<Percentiles>,
+    /// The worker's `queue_latency_ms`, from the assignment's `published_at` to it starting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_latency_ms: Option<Percentiles>,
+    /// The worker's `latency_ms`: time spent running the job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec_latency_ms: Option<Percentiles>,
+}
+
+impl LoadgenReport {
+    pub fn exit_code(&self) -> u8 {
+        if self.lost == 0 { 0 } else { crate::cli::EXIT_RESULTS_MISSING }
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    sent: u64,
+    succeeded: u64,
+    failed: u64,
+    latency: Vec<f64>,
+    queue: Vec<f64>,
+    exec: Vec<f64>,
+}
+
+impl Samples {
+    fn record(&mut self, result: &ExecResult, sent_at: Instant) {
+        match result.status {
+            ExecStatus::Success => self.succeeded += 1,
+            _ => self.failed += 1,
+        }
+        self.latency.push(sent_at.elapsed().as_secs_f64() * 1000.0);
+        self.exec.push(result.latency_ms as f64);
+        if let Some(queue) = result.queue_latency_ms {
+            self.queue.push(queue as f64);
+        }
+    }
+}
+
+fn decode_result(msg: &async_nats::Message, config: &Config) -> Option<ExecResult> {
+    let payload = compression::decode_incoming(msg.headers.as_ref(), &msg.payload, config.envelope_max_inflated_bytes).ok()?;
+    let env = protocol::decode_envelope(&payload, &config.envelope_accept_versions).ok()?;
+    serde_json::from_value(env.data).ok()
+}
+
+/// Publishes assignments to `CAF_ASSIGN_SUBJECT` as the controller would (signed and compressed
+/// per `config`) and times the results that come back.
+///
+/// Results for other assignments on the same subject are ignored, so loadgen can run against a
+/// worker that is also serving real traffic.
+pub async fn run(nc: &async_nats::Client, config: &Config, options: &LoadgenOptions) -> Result<LoadgenReport, String> {
+    let signer = EnvelopeSigner::new(config.envelope_hmac_keys.clone());
+    let publisher = NatsPublisher(nc.clone());
+    let mut results = nc.subscribe(result_subscription(&config.caf_result_subject)).await.map_err(|e| format!("cannot subscribe to results: {}", e))?;
+    nc.flush().await.map_err(|e| format!("cannot flush NATS connection: {}", e))?;
+
+    let run_id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+    let count = options.count.unwrap_or(if options.duration.is_some() { u64::MAX } else { DEFAULT_COUNT });
+    let started = Instant::now();
+    let stop_sending = options.duration.map(|d| started + d);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1) / options.rate.max(1));
+    let mut pending: HashMap<String, Instant> = HashMap::new();
+    let mut samples = Samples::default();
+    let mut wait_until = None;
+
+    loop {
+        let sending = samples.sent < count && stop_sending.is_none_or(|at| Instant::now() < at);
+        if !sending && wait_until.is_none() {
+            nc.flush().await.map_err(|e| format!("cannot flush NATS connection: {}", e))?;
+            wait_until = Some(Instant::now() + options.result_wait);
+        }
+        if !sending && pending.is_empty() {
+            break;
+        }
+        let deadline = wait_until.or(stop_sending).unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+        tokio::select! {
+            _ = ticker.tick(), if sending && pending.len() < options.concurrency => {
+                let assignment = assignment(options, &run_id, samples.sent);
+                let env = EventEnvelopeV1::wrap_assignment(&assignment).signed(signer.as_ref());
+                pipeline::publish_encoded(&publisher, config, &config.caf_assign_subject, &env).await?;
+                pending.insert(assignment.assignment_id, Instant::now());
+                samples.sent += 1;
+            }
+            msg = results.next() => {
+                let Some(msg) = msg else { return Err("result subscription closed".to_string()) };
+                if let Some(result) = decode_result(&msg, config) {
+                    if let Some(sent_at) = pending.remove(&result.assignment_id) {
+                        samples.record(&result, sent_at);
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                if wait_until.is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let received = samples.succeeded + samples.failed;
+    Ok(LoadgenReport {
+        job_type: options.job_type.clone(),
+        sent: samples.sent,
+        received,
+        succeeded: samples.succeeded,
+        failed: samples.failed,
+        lost: pending.len() as u64,
+        elapsed_s: elapsed,
+        throughput_per_s: if elapsed > 0.0 { received as f64 / elapsed } else { 0.0 },
+        latency_ms: percentiles(&mut samples.latency),
+        queue_latency_ms: percentiles(&mut samples.queue),
+        exec_latency_ms: percentiles(&mut samples.exec),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(job_type: &str) -> LoadgenOptions {
+        LoadgenOptions {
+            rate: 10,
+            count: Some(1),
+            duration: None,
+            concurrency: 1,
+            job_type: job_type.to_string(),
+            payload: default_payload(job_type).unwrap(),
+            result_wait: DEFAULT_RESULT_WAIT,
+        }
+    }
+
+    #[test]
+    fn test_payload_templates() {
+        let template = json!({"seq": "{{seq}}", "label": "item-{{seq}} of {{assignment_id}}", "nested": [{"n": "{{seq}}"}, 5]});
+        assert_eq!(render_payload(&template, 7, "a-7"), json!({"seq": 7, "label": "item-7 of a-7", "nested": [{"n": 7}, 5]}));
+        for job_type in ["echo", "sleep", "jmespath"] {
+            assert!(default_payload(job_type).is_ok());
+        }
+        assert!(default_payload("sql").unwrap_err().contains("--payload-file"));
+    }
+
+    #[tokio::test]
+    async fn test_assignments_use_the_wire_format() {
+        let a = assignment(&options("echo"), "run1", 3);
+        assert_eq!(a.assignment_id, "loadgen-run1-3");
+        assert_eq!(a.job.payload, json!({"seq": 3, "assignment_id": "loadgen-run1-3"}));
+        let env = serde_json::to_value(EventEnvelopeV1::wrap_assignment(&a)).unwrap();
+        assert_eq!(env["kind"], "exec_assign");
+        let decoded: ExecAssignment = serde_json::from_value(env["data"].clone()).unwrap();
+        assert!(decoded.queue_latency_ms(chrono::Utc::now()).unwrap() >= 0);
+
+        let jmespath = assignment(&options("jmespath"), "run1", 0);
+        let outcome = crate::executor::Executor::new("t".to_string(), ".".to_string()).execute(jmespath).await;
+        assert!(matches!(outcome.status, ExecStatus::Success), "{:?}", outcome.error_message);
+        assert_eq!(outcome.output.unwrap(), json!([3, 4]));
+    }
+
+    #[test]
+    fn test_result_subscription_covers_templates() {
+        assert_eq!(result_subscription("caf.exec.result.v1"), "caf.exec.result.v1");
+        assert_eq!(result_subscription("caf.exec.result.v1.{tenant_id}.{job_type}-out"), "caf.exec.result.v1.*.*");
+    }
+
+    #[test]
+    fn test_percentiles() {
+        assert_eq!(percentiles(&mut []), None);
+        let mut samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        assert_eq!(percentiles(&mut samples), Some(Percentiles { p50: 50.0, p90: 90.0, p99: 99.0, max: 100.0 }));
+        assert_eq!(percentiles(&mut [4.0]), Some(Percentiles { p50: 4.0, p90: 4.0, p99: 4.0, max: 4.0 }));
+    }
+}
@@ -1,12 +1,55 @@
 use prometheus::{
-    Encoder, Histogram, IntCounter, IntGauge, Registry, TextEncoder,
+    CounterVec, Encoder, Gauge, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
 };
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Duration buckets in seconds, 10ms to 10 minutes; jobs routinely run for minutes.
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+/// Phase timings for one task, filled in by the processing task and observed once at the end.
+#[derive(Debug, Clone)]
+pub struct TaskTimings {
+    received_at: Instant,
+    execution: Duration,
+    publish: Duration,
+}
+
+impl TaskTimings {
+    pub fn new(received_at: Instant) -> Self {
+        Self { received_at, execution: Duration::ZERO, publish: Duration::ZERO }
+    }
+
+    pub fn set_execution(&mut self, execution: Duration) {
+        self.execution = execution;
+    }
+
+    pub fn set_publish(&mut self, publish: Duration) {
+        self.publish = publish;
+    }
+
+    pub fn execution(&self) -> Duration {
+        self.execution
+    }
+
+    pub fn publish(&self) -> Duration {
+        self.publish
+    }
+
+    /// Receipt to now; call once publishing has completed.
+    pub fn total(&self) -> Duration {
+        self.received_at.elapsed()
+    }
+}
 
 #[derive(Clone)]
 pub struct Metrics {
     pub registry: Arc<Registry>,
     pub nats_connect_attempts: IntCounter,
+    pub nats_reconnects_total: IntCounter,
     pub nats_connected: IntGauge,
     pub subs_active: IntGauge,
     pub task_received: IntCounter,
@@ -14,8 +57,72 @@ pub struct Metrics {
     pub task_failed: IntCounter,
     pub task_timeout: IntCounter,
     pub tasks_in_progress: IntGauge,
-    pub dlq_published_total: IntCounter,
+    pub dlq_published_total: IntCounterVec,
+    /// Receipt to publish-complete; kept as an alias of `task_total_seconds` for existing dashboards.
     pub task_duration_seconds: Histogram,
+    pub task_execution_seconds: Histogram,
+    pub task_publish_seconds: Histogram,
+    pub task_total_seconds: Histogram,
+    pub signature_failures_total: IntCounter,
+    pub task_cost_total: CounterVec,
+    pub tasks_by_type_total: IntCounterVec,
+    pub task_duration_by_type_seconds: HistogramVec,
+    pub result_publish_duration_seconds: Histogram,
+    pub result_publish_failures_total: IntCounter,
+    pub heartbeat_sent_total: IntCounter,
+    pub heartbeat_failed_total: IntCounter,
+    pub task_queue_wait_seconds: Histogram,
+    pub oldest_task_age_seconds: Gauge,
+    pub dlq_dropped_total: IntCounter,
+    pub dlq_replayed_total: IntCounter,
+    pub dlq_pending: IntGauge,
+    pub dlq_file_bytes: IntGauge,
+    pub dlq_rotations: IntGauge,
+    pub handler_panics_total: IntCounter,
+    pub drain_rejected_total: IntCounter,
+    pub unsupported_job_type_total: IntCounter,
+    pub payload_rejected_total: IntCounter,
+    pub backpressure_waits_total: IntCounter,
+    pub backpressure_rejected_total: IntCounter,
+    pub batch_remaining: IntGauge,
+    pub backpressure_wait_seconds: Histogram,
+    pub result_queue_depth: IntGauge,
+    pub sql_pools_cached: IntGauge,
+    pub task_queue_latency_seconds: Histogram,
+    pub chaos_injected_total: IntCounterVec,
+    pub task_completed_by_tenant: IntCounterVec,
+    pub task_failed_by_tenant: IntCounterVec,
+    pub task_duration_by_tenant: HistogramVec,
+    /// Tenants with their own label value on the `*_by_tenant` series.
+    tenant_allowlist: Arc<HashSet<String>>,
+    pub unexpected_envelope_total: IntCounterVec,
+    pub validation_failures_total: IntCounter,
+    pub duplicate_results_republished_total: IntCounter,
+    pub job_type_denied_total: IntCounterVec,
+    pub result_payload_too_large_total: IntCounter,
+    pub outbox_results_republished_total: IntCounter,
+    pub possibly_executed_total: IntCounter,
+    pub disk_free_bytes: IntGauge,
+    pub process_rss_bytes: IntGauge,
+    pub resource_pressure_rejected_total: IntCounter,
+    pub idempotent_hits_total: IntCounter,
+    pub tenant_rejected_total: IntCounter,
+    pub cache_entries: IntGauge,
+    pub metrics_push_failures_total: IntCounter,
+    pub worker_start_time_seconds: Gauge,
+    pub worker_uptime_seconds: Gauge,
+    pub last_assignment_completed_timestamp_seconds: Gauge,
+    pub bare_assignment_total: IntCounter,
+    pub heartbeat_consecutive_failures: IntGauge,
+    pub fs_cas_evicted_total: IntCounter,
+    pub canary_skipped_total: IntCounter,
+    pub shadow_results_total: IntCounter,
+    pub recordings_written_total: IntCounter,
+    pub recording_failures_total: IntCounter,
+    pub task_supervisor_restarts_total: IntCounterVec,
+    pub receipts_written_total: IntCounterVec,
+    pub receipt_failures_total: IntCounterVec,
+    pub drain_aborted_total: IntCounter,
 }
 
 impl Default for Metrics {
@@ -26,9 +133,15 @@ impl Default for Metrics {
 
 impl Metrics {
     pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_DURATION_BUCKETS.to_vec())
+    }
+
+    /// Builds the metrics with `buckets` applied to every duration histogram.
+    pub fn with_buckets(buckets: Vec<f64>) -> Self {
         let registry = Arc::new(Registry::new());
 
         let nats_connect_attempts = IntCounter::new("nats_connect_attempts", "Total NATS connect attempts").unwrap();
+        let nats_reconnects_total = IntCounter::new("nats_reconnects_total", "NATS connections restored after a disconnect").unwrap();
         let nats_connected = IntGauge::new("nats_connected", "NATS connected gauge 1/0").unwrap();
         let subs_active = IntGauge::new("subs_active", "Active subscriptions count").unwrap();
         let task_received = IntCounter::new("task_received", "Tasks received").unwrap();
@@ -36,12 +149,138 @@ impl Metrics {
         let task_failed = IntCounter::new("task_failed", "Tasks failed").unwrap();
         let task_timeout = IntCounter::new("task_timeout", "Tasks timed out").unwrap();
         let tasks_in_progress = IntGauge::new("tasks_in_progress", "Currently running tasks").unwrap();
-        let dlq_published_total = IntCounter::new("dlq_published_total", "Deadletters published total").unwrap();
+        let dlq_published_total = IntCounterVec::new(
+            prometheus::Opts::new("dlq_published_total", "Deadletters published total by reason"),
+            &["reason"],
+        ).unwrap();
         let task_duration_seconds = Histogram::with_opts(
-            prometheus::HistogramOpts::new("task_duration_seconds", "Task execution duration in seconds")
+            prometheus::HistogramOpts::new("task_duration_seconds", "Task duration from receipt to result published (alias of task_total_seconds)")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let task_execution_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("task_execution_seconds", "Handler execution time in seconds")
+                .buckets(buckets.clone())
         ).unwrap();
+        let task_publish_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("task_publish_seconds", "Result publish time in seconds, including retries")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let task_total_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("task_total_seconds", "Time from message receipt to result published")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let signature_failures_total = IntCounter::new("signature_failures_total", "Envelopes rejected for a missing or invalid signature").unwrap();
+        let task_cost_total = CounterVec::new(
+            prometheus::Opts::new("task_cost_total", "Accumulated execution cost by job type"),
+            &["job_type"],
+        ).unwrap();
+        let tasks_by_type_total = IntCounterVec::new(
+            prometheus::Opts::new("tasks_by_type_total", "Finished tasks by job type and status"),
+            &["job_type", "status"],
+        ).unwrap();
+        let task_duration_by_type_seconds = HistogramVec::new(
+            prometheus::HistogramOpts::new("task_duration_by_type_seconds", "Task execution duration in seconds by job type")
+                .buckets(buckets.clone()),
+            &["job_type"],
+        ).unwrap();
+        let result_publish_duration_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("result_publish_duration_seconds", "Time to publish a result, including retries")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let result_publish_failures_total = IntCounter::new("result_publish_failures_total", "Results that could not be published after retries").unwrap();
+        let heartbeat_sent_total = IntCounter::new("heartbeat_sent_total", "Heartbeats published").unwrap();
+        let heartbeat_failed_total = IntCounter::new("heartbeat_failed_total", "Heartbeats that failed to publish").unwrap();
+        let task_queue_wait_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("task_queue_wait_seconds", "Time from message receipt to acquiring a concurrency permit")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let oldest_task_age_seconds = Gauge::new("oldest_task_age_seconds", "Age of the longest-running in-flight task").unwrap();
+        let build_info = IntGaugeVec::new(prometheus::Opts::new("build_info", "Build metadata; always 1"), &["version", "sha"]).unwrap();
+        let dlq_dropped_total = IntCounter::new("dlq_dropped_total", "Deadletters dropped because the DLQ writer fell behind").unwrap();
+        let dlq_replayed_total = IntCounter::new("dlq_replayed_total", "File-only deadletters published to the DLQ subject by recovery").unwrap();
+        let dlq_pending = IntGauge::new("dlq_pending", "Deadletters in the DLQ file not yet published to the DLQ subject").unwrap();
+        let dlq_file_bytes = IntGauge::new("dlq_file_bytes", "Bytes held by the DLQ file and its rotations").unwrap();
+        let dlq_rotations = IntGauge::new("dlq_rotations", "Rotated DLQ files currently kept").unwrap();
+        let handler_panics_total = IntCounter::new("handler_panics_total", "Assignment tasks that panicked instead of producing a result").unwrap();
+        let drain_rejected_total = IntCounter::new("drain_rejected_total", "Assignments turned away by DRAIN_POLICY while draining").unwrap();
+        let unsupported_job_type_total = IntCounter::new("unsupported_job_type_total", "Assignments handed back because this worker does not accept their job type").unwrap();
+        let payload_rejected_total = IntCounter::new("payload_rejected_total", "Incoming messages dead-lettered for exceeding ASSIGNMENT_MAX_BYTES").unwrap();
+        let backpressure_waits_total = IntCounter::new("backpressure_waits_total", "Assignments that had to wait for a concurrency permit").unwrap();
+        let backpressure_rejected_total = IntCounter::new("backpressure_rejected_total", "Assignments rejected as WORKER_OVERLOADED after BACKPRESSURE_MAX_WAIT_MS").unwrap();
+        let batch_remaining = IntGauge::new("batch_remaining", "Assignments of the message being dispatched, this one included, left waiting for a concurrency permit").unwrap();
+        let backpressure_wait_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("backpressure_wait_seconds", "Time spent waiting for a concurrency permit once the limit was reached")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let result_queue_depth = IntGauge::new("result_queue_depth", "Results waiting for the publisher task").unwrap();
+        let sql_pools_cached = IntGauge::new("sql_pools_cached", "Database pools held by the sql handler").unwrap();
+        let task_queue_latency_seconds = Histogram::with_opts(
+            prometheus::HistogramOpts::new("task_queue_latency_seconds", "Time from the controller publishing an assignment (published_at) to the worker starting it")
+                .buckets(buckets.clone())
+        ).unwrap();
+        let chaos_injected_total = IntCounterVec::new(
+            prometheus::Opts::new("chaos_injected_total", "Faults injected by CHAOS_* settings, by kind"),
+            &["kind"],
+        ).unwrap();
+        let task_completed_by_tenant = IntCounterVec::new(
+            prometheus::Opts::new("task_completed_by_tenant", "Tasks completed, by tenant (tenants outside METRICS_TENANT_ALLOWLIST are \"other\")"),
+            &["tenant_id"],
+        ).unwrap();
+        let task_failed_by_tenant = IntCounterVec::new(
+            prometheus::Opts::new("task_failed_by_tenant", "Tasks failed or timed out, by tenant (tenants outside METRICS_TENANT_ALLOWLIST are \"other\")"),
+            &["tenant_id"],
+        ).unwrap();
+        let task_duration_by_tenant = HistogramVec::new(
+            prometheus::HistogramOpts::new("task_duration_by_tenant_seconds", "Task execution duration in seconds by tenant (tenants outside METRICS_TENANT_ALLOWLIST are \"other\")")
+                .buckets(buckets),
+            &["tenant_id"],
+        ).unwrap();
+        let unexpected_envelope_total = IntCounterVec::new(
+            prometheus::Opts::new("unexpected_envelope_total", "Envelopes of a kind the assign subject never carries, by kind"),
+            &["kind"],
+        ).unwrap();
+        let validation_failures_total = IntCounter::new("validation_failures_total", "Assignments dead-lettered as VALIDATION_ERROR").unwrap();
+        let duplicate_results_republished_total = IntCounter::new("duplicate_results_republished_total", "Duplicate assignments answered with their cached result").unwrap();
+        let job_type_denied_total = IntCounterVec::new(
+            prometheus::Opts::new("job_type_denied_total", "Assignments refused by the executor because their job type is disabled"),
+            &["job_type"],
+        ).unwrap();
+        let result_payload_too_large_total = IntCounter::new("result_payload_too_large_total", "Results dead-lettered without a publish attempt because they exceed the server max_payload").unwrap();
+        let outbox_results_republished_total = IntCounter::new("outbox_results_republished_total", "Results persisted in the outbox by a previous run and republished at startup").unwrap();
+        let possibly_executed_total = IntCounter::new("possibly_executed_total", "Redeliveries refused with POSSIBLY_EXECUTED because the assignment was running when the worker stopped").unwrap();
+        let disk_free_bytes = IntGauge::new("disk_free_bytes", "Free space on the filesystem holding FS_BASE_DIR, as last sampled").unwrap();
+        let process_rss_bytes = IntGauge::new("process_rss_bytes", "Resident set size of the worker process, as last sampled").unwrap();
+        let resource_pressure_rejected_total = IntCounter::new("resource_pressure_rejected_total", "Assignments turned away with WORKER_RESOURCE_PRESSURE").unwrap();
+        let idempotent_hits_total = IntCounter::new("idempotent_hits_total", "Assignments answered from the idempotency cache instead of being executed").unwrap();
+        let tenant_rejected_total = IntCounter::new("tenant_rejected_total", "Assignments of tenants missing from TENANT_ALLOWLIST, rejected or requeued").unwrap();
+        let cache_entries = IntGauge::new("cache_entries", "Entries held by the cache job type store").unwrap();
+        let metrics_push_failures_total = IntCounter::new("metrics_push_failures_total", "Metrics pushes to METRICS_PUSH_URL that failed after retries").unwrap();
+        let worker_start_time_seconds = Gauge::new("worker_start_time_seconds", "Unix time the worker started").unwrap();
+        let worker_uptime_seconds = Gauge::new("worker_uptime_seconds", "Seconds since the worker started, as of the last heartbeat").unwrap();
+        let last_assignment_completed_timestamp_seconds = Gauge::new("last_assignment_completed_timestamp_seconds", "Unix time the last assignment finished; 0 before the first").unwrap();
+        let bare_assignment_total = IntCounter::new("bare_assignment_total", "Bare assignments accepted without an envelope").unwrap();
+        let heartbeat_consecutive_failures = IntGauge::new("heartbeat_consecutive_failures", "Heartbeats that failed to publish since the last one that went out").unwrap();
+        let fs_cas_evicted_total = IntCounter::new("fs_cas_evicted_total", "Content-addressed blobs removed to stay under FS_CAS_MAX_BYTES").unwrap();
+        let canary_skipped_total = IntCounter::new("canary_skipped_total", "Assignments a canary worker cancelled outside its CANARY_SAMPLE_RATE sample").unwrap();
+        let shadow_results_total = IntCounter::new("shadow_results_total", "Results a CANARY_SHADOW worker published to the shadow result subject").unwrap();
+        let recordings_written_total = IntCounter::new("recordings_written_total", "Assignment/result pairs written under RECORD_DIR").unwrap();
+        let recording_failures_total = IntCounter::new("recording_failures_total", "Recordings that could not be written; the job is unaffected").unwrap();
+        let task_supervisor_restarts_total = IntCounterVec::new(
+            prometheus::Opts::new("task_supervisor_restarts_total", "Panicked heartbeat and processing tasks restarted, by task"),
+            &["task"],
+        ).unwrap();
+        let receipts_written_total = IntCounterVec::new(
+            prometheus::Opts::new("receipts_written_total", "Execution receipts published or appended to RECEIPTS_FILE, by sink"),
+            &["sink"],
+        ).unwrap();
+        let receipt_failures_total = IntCounterVec::new(
+            prometheus::Opts::new("receipt_failures_total", "Execution receipts that failed to publish or be written, by sink"),
+            &["sink"],
+        ).unwrap();
+        let drain_aborted_total = IntCounter::new("drain_aborted_total", "Running tasks aborted when the drain deadline passed").unwrap();
 
         registry.register(Box::new(nats_connect_attempts.clone())).unwrap();
+        registry.register(Box::new(nats_reconnects_total.clone())).unwrap();
         registry.register(Box::new(nats_connected.clone())).unwrap();
         registry.register(Box::new(subs_active.clone())).unwrap();
         registry.register(Box::new(task_received.clone())).unwrap();
@@ -51,10 +290,76 @@ impl Metrics {
         registry.register(Box::new(tasks_in_progress.clone())).unwrap();
         registry.register(Box::new(dlq_published_total.clone())).unwrap();
         registry.register(Box::new(task_duration_seconds.clone())).unwrap();
+        registry.register(Box::new(task_execution_seconds.clone())).unwrap();
+        registry.register(Box::new(task_publish_seconds.clone())).unwrap();
+        registry.register(Box::new(task_total_seconds.clone())).unwrap();
+        registry.register(Box::new(signature_failures_total.clone())).unwrap();
+        registry.register(Box::new(task_cost_total.clone())).unwrap();
+        registry.register(Box::new(tasks_by_type_total.clone())).unwrap();
+        registry.register(Box::new(task_duration_by_type_seconds.clone())).unwrap();
+        registry.register(Box::new(result_publish_duration_seconds.clone())).unwrap();
+        registry.register(Box::new(result_publish_failures_total.clone())).unwrap();
+        registry.register(Box::new(heartbeat_sent_total.clone())).unwrap();
+        registry.register(Box::new(heartbeat_failed_total.clone())).unwrap();
+        registry.register(Box::new(task_queue_wait_seconds.clone())).unwrap();
+        registry.register(Box::new(oldest_task_age_seconds.clone())).unwrap();
+        registry.register(Box::new(build_info.clone())).unwrap();
+        // Only ever set here, so it isn't kept on the struct
+        build_info.with_label_values(&[crate::build_info::VERSION, crate::build_info::GIT_SHA]).set(1);
+        worker_start_time_seconds.set(unix_now());
+        registry.register(Box::new(dlq_dropped_total.clone())).unwrap();
+        registry.register(Box::new(dlq_replayed_total.clone())).unwrap();
+        registry.register(Box::new(dlq_pending.clone())).unwrap();
+        registry.register(Box::new(dlq_file_bytes.clone())).unwrap();
+        registry.register(Box::new(dlq_rotations.clone())).unwrap();
+        registry.register(Box::new(handler_panics_total.clone())).unwrap();
+        registry.register(Box::new(drain_rejected_total.clone())).unwrap();
+        registry.register(Box::new(unsupported_job_type_total.clone())).unwrap();
+        registry.register(Box::new(payload_rejected_total.clone())).unwrap();
+        registry.register(Box::new(backpressure_waits_total.clone())).unwrap();
+        registry.register(Box::new(backpressure_rejected_total.clone())).unwrap();
+        registry.register(Box::new(batch_remaining.clone())).unwrap();
+        registry.register(Box::new(backpressure_wait_seconds.clone())).unwrap();
+        registry.register(Box::new(result_queue_depth.clone())).unwrap();
+        registry.register(Box::new(sql_pools_cached.clone())).unwrap();
+        registry.register(Box::new(task_queue_latency_seconds.clone())).unwrap();
+        registry.register(Box::new(chaos_injected_total.clone())).unwrap();
+        registry.register(Box::new(task_completed_by_tenant.clone())).unwrap();
+        registry.register(Box::new(task_failed_by_tenant.clone())).unwrap();
+        registry.register(Box::new(task_duration_by_tenant.clone())).unwrap();
+        registry.register(Box::new(unexpected_envelope_total.clone())).unwrap();
+        registry.register(Box::new(validation_failures_total.clone())).unwrap();
+        registry.register(Box::new(duplicate_results_republished_total.clone())).unwrap();
+        registry.register(Box::new(job_type_denied_total.clone())).unwrap();
+        registry.register(Box::new(result_payload_too_large_total.clone())).unwrap();
+        registry.register(Box::new(outbox_results_republished_total.clone())).unwrap();
+        registry.register(Box::new(possibly_executed_total.clone())).unwrap();
+        registry.register(Box::new(disk_free_bytes.clone())).unwrap();
+        registry.register(Box::new(process_rss_bytes.clone())).unwrap();
+        registry.register(Box::new(resource_pressure_rejected_total.clone())).unwrap();
+        registry.register(Box::new(idempotent_hits_total.clone())).unwrap();
+        registry.register(Box::new(tenant_rejected_total.clone())).unwrap();
+        registry.register(Box::new(cache_entries.clone())).unwrap();
+        registry.register(Box::new(metrics_push_failures_total.clone())).unwrap();
+        registry.register(Box::new(worker_start_time_seconds.clone())).unwrap();
+        registry.register(Box::new(worker_uptime_seconds.clone())).unwrap();
+        registry.register(Box::new(last_assignment_completed_timestamp_seconds.clone())).unwrap();
+        registry.register(Box::new(bare_assignment_total.clone())).unwrap();
+        registry.register(Box::new(heartbeat_consecutive_failures.clone())).unwrap();
+        registry.register(Box::new(fs_cas_evicted_total.clone())).unwrap();
+        registry.register(Box::new(canary_skipped_total.clone())).unwrap();
+        registry.register(Box::new(shadow_results_total.clone())).unwrap();
+        registry.register(Box::new(recordings_written_total.clone())).unwrap();
+        registry.register(Box::new(recording_failures_total.clone())).unwrap();
+        registry.register(Box::new(task_supervisor_restarts_total.clone())).unwrap();
+        registry.register(Box::new(receipts_written_total.clone())).unwrap();
+        registry.register(Box::new(receipt_failures_total.clone())).unwrap();
+        registry.register(Box::new(drain_aborted_total.clone())).unwrap();
 
         Self {
             registry,
             nats_connect_attempts,
+            nats_reconnects_total,
             nats_connected,
             subs_active,
             task_received,
@@ -64,9 +369,129 @@ impl Metrics {
             tasks_in_progress,
             dlq_published_total,
             task_duration_seconds,
+            task_execution_seconds,
+            task_publish_seconds,
+            task_total_seconds,
+            signature_failures_total,
+            task_cost_total,
+            tasks_by_type_total,
+            task_duration_by_type_seconds,
+            result_publish_duration_seconds,
+            result_publish_failures_total,
+            heartbeat_sent_total,
+            heartbeat_failed_total,
+            task_queue_wait_seconds,
+            oldest_task_age_seconds,
+            dlq_dropped_total,
+            dlq_replayed_total,
+            dlq_pending,
+            dlq_file_bytes,
+            dlq_rotations,
+            handler_panics_total,
+            drain_rejected_total,
+            unsupported_job_type_total,
+            payload_rejected_total,
+            backpressure_waits_total,
+            backpressure_rejected_total,
+            batch_remaining,
+            backpressure_wait_seconds,
+            result_queue_depth,
+            sql_pools_cached,
+            task_queue_latency_seconds,
+            chaos_injected_total,
+            task_completed_by_tenant,
+            task_failed_by_tenant,
+            task_duration_by_tenant,
+            tenant_allowlist: Arc::new(HashSet::new()),
+            unexpected_envelope_total,
+            validation_failures_total,
+            duplicate_results_republished_total,
+            job_type_denied_total,
+            result_payload_too_large_total,
+            outbox_results_republished_total,
+            possibly_executed_total,
+            disk_free_bytes,
+            process_rss_bytes,
+            resource_pressure_rejected_total,
+            idempotent_hits_total,
+            tenant_rejected_total,
+            cache_entries,
+            metrics_push_failures_total,
+            worker_start_time_seconds,
+            worker_uptime_seconds,
+            last_assignment_completed_timestamp_seconds,
+            bare_assignment_total,
+            heartbeat_consecutive_failures,
+            fs_cas_evicted_total,
+            canary_skipped_total,
+            shadow_results_total,
+            recordings_written_total,
+            recording_failures_total,
+            task_supervisor_restarts_total,
+            receipts_written_total,
+            receipt_failures_total,
+            drain_aborted_total,
         }
     }
 
+    /// Observes each phase of a finished task exactly once.
+    pub fn observe_timings(&self, timings: &TaskTimings) {
+        let total = timings.total().as_secs_f64();
+        self.task_execution_seconds.observe(timings.execution().as_secs_f64());
+        self.task_publish_seconds.observe(timings.publish().as_secs_f64());
+        self.task_total_seconds.observe(total);
+        self.task_duration_seconds.observe(total);
+    }
+
+    /// Records a finished task on the labeled series; `job_type` must already be sanitized.
+    pub fn observe_task(&self, job_type: &str, status: &str, seconds: f64, cost: f64) {
+        self.tasks_by_type_total.with_label_values(&[job_type, status]).inc();
+        self.task_duration_by_type_seconds.with_label_values(&[job_type]).observe(seconds);
+        if cost > 0.0 {
+            self.task_cost_total.with_label_values(&[job_type]).inc_by(cost);
+        }
+    }
+
+    /// Gives these tenants their own label value on the `*_by_tenant` series.
+    pub fn with_tenant_allowlist(mut self, tenants: &[String]) -> Self {
+        self.tenant_allowlist = Arc::new(tenants.iter().cloned().collect());
+        self
+    }
+
+    /// `tenant_id` if it is allowlisted, else `"other"`, so unknown tenants can't grow the series count.
+    pub fn tenant_label<'a>(&self, tenant_id: &'a str) -> &'a str {
+        if self.tenant_allowlist.contains(tenant_id) { tenant_id } else { "other" }
+    }
+
+    /// Records a finished task on the per-tenant series; cancelled tasks count as neither.
+    pub fn observe_tenant(&self, tenant_id: &str, status: &str, seconds: f64) {
+        let tenant = self.tenant_label(tenant_id);
+        match status {
+            "success" => self.task_completed_by_tenant.with_label_values(&[tenant]).inc(),
+            "error" | "timeout" => self.task_failed_by_tenant.with_label_values(&[tenant]).inc(),
+            _ => {}
+        }
+        self.task_duration_by_tenant.with_label_values(&[tenant]).observe(seconds);
+    }
+
+    /// Stamps `last_assignment_completed_timestamp_seconds` with now.
+    pub fn mark_assignment_completed(&self) {
+        self.last_assignment_completed_timestamp_seconds.set(unix_now());
+    }
+
+    pub fn uptime_seconds(&self) -> u64 {
+        (unix_now() - self.worker_start_time_seconds.get()).max(0.0) as u64
+    }
+
+    /// When the last assignment finished, as RFC 3339; `None` before the first.
+    pub fn last_activity_at(&self) -> Option<String> {
+        let at = self.last_assignment_completed_timestamp_seconds.get();
+        if at <= 0.0 {
+            return None;
+        }
+        chrono::DateTime::from_timestamp_millis((at * 1000.0) as i64).map(|at| at.to_rfc3339())
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let metric_families = self.registry.gather();
         let mut buffer = Vec::new();
@@ -75,3 +500,105 @@ impl Metrics {
         buffer
     }
 }
+
+fn unix_now() -> f64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labeled_task_series_are_encoded() {
+        let metrics = Metrics::new();
+        metrics.observe_task("http", "success", 0.2, 0.0);
+        metrics.observe_task("http", "error", 1.5, 0.0);
+        metrics.observe_task("other", "error", 0.1, 0.5);
+
+        let text = String::from_utf8(metrics.encode()).unwrap();
+        assert!(text.contains(r#"tasks_by_type_total{job_type="http",status="success"} 1"#));
+        assert!(text.contains(r#"tasks_by_type_total{job_type="http",status="error"} 1"#));
+        assert!(text.contains(r#"task_duration_by_type_seconds_count{job_type="http"} 2"#));
+        assert!(text.contains(r#"task_cost_total{job_type="other"} 0.5"#));
+        assert!(!text.contains(r#"task_cost_total{job_type="http"}"#));
+    }
+
+    #[test]
+    fn test_publish_heartbeat_and_queue_metrics_are_encoded() {
+        let metrics = Metrics::new();
+        metrics.result_publish_duration_seconds.observe(0.05);
+        metrics.result_publish_failures_total.inc();
+        metrics.heartbeat_sent_total.inc();
+        metrics.heartbeat_sent_total.inc();
+        metrics.heartbeat_failed_total.inc();
+        metrics.task_queue_wait_seconds.observe(0.01);
+
+        let text = String::from_utf8(metrics.encode()).unwrap();
+        assert!(text.contains("result_publish_duration_seconds_count 1"));
+        assert!(text.contains("result_publish_failures_total 1"));
+        assert!(text.contains("heartbeat_sent_total 2"));
+        assert!(text.contains("heartbeat_failed_total 1"));
+        assert!(text.contains("task_queue_wait_seconds_count 1"));
+    }
+
+    #[test]
+    fn test_configured_buckets_are_encoded() {
+        let metrics = Metrics::with_buckets(vec![30.0, 300.0]);
+        metrics.task_duration_seconds.observe(45.0);
+        metrics.task_queue_wait_seconds.observe(1.0);
+
+        let text = String::from_utf8(metrics.encode()).unwrap();
+        assert!(text.contains(r#"task_duration_seconds_bucket{le="30"} 0"#));
+        assert!(text.contains(r#"task_duration_seconds_bucket{le="300"} 1"#));
+        assert!(text.contains(r#"task_queue_wait_seconds_bucket{le="300"} 1"#));
+        assert!(!text.contains(r#"task_duration_seconds_bucket{le="10"}"#));
+    }
+
+    #[test]
+    fn test_task_timings_observed_once_per_phase() {
+        let metrics = Metrics::new();
+        let mut timings = TaskTimings::new(Instant::now() - Duration::from_secs(3));
+        timings.set_execution(Duration::from_millis(1200));
+        timings.set_publish(Duration::from_millis(40));
+        assert!(timings.total() >= Duration::from_secs(3));
+
+        metrics.observe_timings(&timings);
+
+        let text = String::from_utf8(metrics.encode()).unwrap();
+        assert!(text.contains("task_execution_seconds_count 1"));
+        assert!(text.contains("task_execution_seconds_sum 1.2"));
+        assert!(text.contains("task_publish_seconds_sum 0.04"));
+        assert!(text.contains("task_total_seconds_count 1"));
+        assert!(text.contains("task_duration_seconds_count 1"));
+        assert!(!text.contains(r#"task_total_seconds_bucket{le="2.5"} 1"#));
+    }
+
+    #[test]
+    fn test_build_info_gauge() {
+        let text = String::from_utf8(Metrics::new().encode()).unwrap();
+        let expected = format!(r#"build_info{{sha="{}",version="{}"}} 1"#, crate::build_info::GIT_SHA, crate::build_info::VERSION);
+        assert!(text.contains(&expected), "{}", text);
+    }
+
+    #[test]
+    fn test_tenants_outside_the_allowlist_are_folded() {
+        let metrics = Metrics::new().with_tenant_allowlist(&["acme".to_string()]);
+        metrics.observe_tenant("acme", "success", 0.5);
+        metrics.observe_tenant("acme", "timeout", 2.0);
+        metrics.observe_tenant("globex", "error", 0.1);
+        metrics.observe_tenant("initech", "success", 0.1);
+        metrics.observe_tenant("initech", "cancelled", 0.0);
+        assert_eq!(metrics.task_completed_by_tenant.with_label_values(&["acme"]).get(), 1);
+        assert_eq!(metrics.task_failed_by_tenant.with_label_values(&["acme"]).get(), 1);
+        assert_eq!(metrics.task_failed_by_tenant.with_label_values(&["other"]).get(), 1);
+        assert_eq!(metrics.task_completed_by_tenant.with_label_values(&["other"]).get(), 1);
+
+        let text = String::from_utf8(metrics.encode()).unwrap();
+        assert!(text.contains(r#"task_completed_by_tenant{tenant_id="acme"} 1"#));
+        assert!(text.contains(r#"task_duration_by_tenant_seconds_count{tenant_id="other"} 3"#));
+        assert!(!text.contains("globex") && !text.contains("initech"));
+        // Without an allowlist every tenant is "other"
+        assert_eq!(Metrics::new().tenant_label("acme"), "other");
+    }
+}
@@ -1,33 +1,273 @@
 pub mod pii;
 pub mod metrics;
+pub mod push;
+pub mod sink;
+pub mod telemetry;
+pub mod truncate;
 
 use chrono::Utc;
-use serde_json::{json, Value};
-use self::pii::mask_pii;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use self::pii::{PiiMasker, REDACTED};
+use self::sink::{LogSink, StdoutSink};
 
+/// Log verbosity, ordered from least to most verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => LogLevel::Error,
+            1 => LogLevel::Warn,
+            2 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            other => Err(format!("unknown log level '{}' (expected error|warn|info|debug)", other)),
+        }
+    }
+}
+
+/// Default for `LOG_MAX_VALUE_BYTES`.
+pub const DEFAULT_LOG_MAX_VALUE_BYTES: usize = 16 * 1024;
+
+/// Default for `LOG_MAX_LINE_BYTES`: container runtimes split longer lines.
+pub const DEFAULT_LOG_MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// Written first and in this order on every line; context keys with these names are dropped.
+const CORE_FIELDS: [&str; 4] = ["ts", "level", "msg", "worker_id"];
+
+/// Smallest per-value budget tried before an oversized entry loses its context.
+const MIN_VALUE_BUDGET: usize = 64;
+
+/// Structured JSON logger. Clones share the level, so `set_level` applies to all of them at runtime.
 #[derive(Clone)]
 pub struct Logger {
     worker_id: String,
+    level: Arc<AtomicU8>,
+    sinks: Arc<Vec<Arc<dyn LogSink>>>,
+    masker: Arc<PiiMasker>,
+    fields: Arc<Map<String, Value>>,
+    /// Literal values (e.g. substituted `${secret.*}`) replaced with `***` anywhere in an entry.
+    secrets: Arc<Vec<String>>,
+    /// Context values encoding to more than this are truncated and the entry marked `truncated`.
+    max_value_bytes: usize,
+    /// Entries encoding to more than this are shrunk until they fit and marked `log_truncated`.
+    max_line_bytes: usize,
 }
 
 impl Logger {
     pub fn new(worker_id: String) -> Self {
-        Self { worker_id }
+        Self::with_level(worker_id, LogLevel::Info)
+    }
+
+    pub fn with_level(worker_id: String, level: LogLevel) -> Self {
+        Self::with_sinks(worker_id, level, vec![Arc::new(StdoutSink)])
+    }
+
+    /// Every entry is written to each of `sinks`.
+    pub fn with_sinks(worker_id: String, level: LogLevel, sinks: Vec<Arc<dyn LogSink>>) -> Self {
+        Self {
+            worker_id,
+            level: Arc::new(AtomicU8::new(level as u8)),
+            sinks: Arc::new(sinks),
+            masker: Arc::new(PiiMasker::default()),
+            fields: Arc::new(Map::new()),
+            secrets: Arc::new(Vec::new()),
+            max_value_bytes: DEFAULT_LOG_MAX_VALUE_BYTES,
+            max_line_bytes: DEFAULT_LOG_MAX_LINE_BYTES,
+        }
+    }
+
+    pub fn with_masker(mut self, masker: PiiMasker) -> Self {
+        self.masker = Arc::new(masker);
+        self
+    }
+
+    /// The masking applied to this logger's messages and fields.
+    pub fn masker(&self) -> &PiiMasker {
+        &self.masker
+    }
+
+    pub fn with_max_value_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_value_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_max_line_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_line_bytes = max_bytes;
+        self
+    }
+
+    /// A child logger that adds `fields` to every entry; call-site context wins on key clashes.
+    ///
+    /// Fields accumulate across nested calls and share this logger's level and sinks.
+    pub fn with_fields(&self, fields: Value) -> Logger {
+        let mut child = self.clone();
+        if let Value::Object(extra) = fields {
+            let mut merged = (*self.fields).clone();
+            merged.extend(extra);
+            child.fields = Arc::new(merged);
+        }
+        child
+    }
+
+    /// A child logger that also redacts `secrets` wherever they appear.
+    pub fn with_secrets(&self, secrets: &[String]) -> Logger {
+        let mut child = self.clone();
+        if !secrets.is_empty() {
+            let mut merged = (*self.secrets).clone();
+            merged.extend(secrets.iter().filter(|s| !s.is_empty()).cloned());
+            child.secrets = Arc::new(merged);
+        }
+        child
+    }
+
+    pub fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    pub fn set_level(&self, level: LogLevel) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level()
+    }
+
+    pub fn debug(&self, msg: &str, context: Option<&Value>) {
+        self.emit(LogLevel::Debug, msg, context);
     }
 
     pub fn info(&self, msg: &str, context: Option<&Value>) {
-        let entry = self.build_entry("INFO", msg, context);
-        println!("{}", serde_json::to_string(&entry).unwrap_or_default());
+        self.emit(LogLevel::Info, msg, context);
+    }
+
+    pub fn warn(&self, msg: &str, context: Option<&Value>) {
+        self.emit(LogLevel::Warn, msg, context);
     }
 
     pub fn error(&self, msg: &str, context: Option<&Value>) {
-        let entry = self.build_entry("ERROR", msg, context);
-        eprintln!("{}", serde_json::to_string(&entry).unwrap_or_default());
+        self.emit(LogLevel::Error, msg, context);
+    }
+
+    fn emit(&self, level: LogLevel, msg: &str, context: Option<&Value>) {
+        if let Some(line) = self.render(level, msg, context) {
+            // Mirror as a tracing event so it lands on the active span when OTel export is enabled
+            match level {
+                LogLevel::Error => tracing::error!("{}", line),
+                LogLevel::Warn => tracing::warn!("{}", line),
+                LogLevel::Info => tracing::info!("{}", line),
+                LogLevel::Debug => tracing::debug!("{}", line),
+            }
+            for sink in self.sinks.iter() {
+                sink.write_line(level, &line);
+            }
+        }
+    }
+
+    /// The JSON line for an entry, or `None` when `level` is suppressed.
+    fn render(&self, level: LogLevel, msg: &str, context: Option<&Value>) -> Option<String> {
+        if !self.enabled(level) {
+            return None;
+        }
+        let line = self.encode(&self.build_entry(level.as_str(), msg, context));
+        if line.len() <= self.max_line_bytes {
+            return Some(line);
+        }
+        Some(self.shrink(level.as_str(), msg, context))
+    }
+
+    /// Renders an entry over `max_line_bytes` again with ever smaller context values, then
+    /// without context, then with a shortened message, until it fits.
+    fn shrink(&self, level: &str, msg: &str, context: Option<&Value>) -> String {
+        let mut budget = self.max_value_bytes.min(self.max_line_bytes) / 2;
+        while budget >= MIN_VALUE_BUDGET {
+            let line = self.encode(&Self::mark_truncated(self.entry(level, msg, context, Some(budget))));
+            if line.len() <= self.max_line_bytes {
+                return line;
+            }
+            budget /= 2;
+        }
+        let mut msg_budget = self.max_line_bytes;
+        loop {
+            let short = truncate::truncate_value(&Value::String(msg.to_string()), msg_budget).0;
+            let line = self.encode(&Self::mark_truncated(self.entry(level, short.as_str().unwrap_or_default(), None, None)));
+            if line.len() <= self.max_line_bytes || msg_budget == 0 {
+                return line;
+            }
+            msg_budget /= 2;
+        }
+    }
+
+    fn mark_truncated(mut entry: Value) -> Value {
+        if let Some(fields) = entry.as_object_mut() {
+            fields.insert("log_truncated".to_string(), Value::Bool(true));
+        }
+        entry
+    }
+
+    /// One JSON line with `CORE_FIELDS` leading, so parsers can rely on them, and `secrets` redacted.
+    fn encode(&self, entry: &Value) -> String {
+        let empty = Map::new();
+        let fields = entry.as_object().unwrap_or(&empty);
+        let core = CORE_FIELDS.iter().filter_map(|key| fields.get(*key).map(|value| (*key, value)));
+        let rest = fields.iter().filter(|(key, _)| !CORE_FIELDS.contains(&key.as_str())).map(|(key, value)| (key.as_str(), value));
+        let mut line = String::from("{");
+        for (i, (key, value)) in core.chain(rest).enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            line.push_str(&serde_json::to_string(key).unwrap_or_default());
+            line.push(':');
+            line.push_str(&serde_json::to_string(value).unwrap_or_default());
+        }
+        line.push('}');
+        for secret in self.secrets.iter() {
+            // Match the secret as it is escaped inside the line
+            let encoded = serde_json::to_string(secret).unwrap_or_default();
+            line = line.replace(&encoded[1..encoded.len() - 1], REDACTED);
+        }
+        line
     }
 
     fn build_entry(&self, level: &str, msg: &str, context: Option<&Value>) -> Value {
+        self.entry(level, msg, context, Some(self.max_value_bytes))
+    }
+
+    /// Context values are cut to `value_budget` bytes; `None` leaves out context and sticky fields.
+    fn entry(&self, level: &str, msg: &str, context: Option<&Value>, value_budget: Option<usize>) -> Value {
         let now = Utc::now().to_rfc3339();
-        let safe_msg = mask_pii(msg);
+        let safe_msg = self.masker.mask_str(msg);
 
         let mut base = json!({
             "ts": now,
@@ -36,22 +276,33 @@ impl Logger {
             "worker_id": self.worker_id,
         });
 
-        if let Some(ctx) = context {
+        let Some(max_value_bytes) = value_budget else {
+            return base;
+        };
+        let mut extra = (*self.fields).clone();
+        if let Some(Value::Object(ctx_obj)) = context {
+            extra.extend(ctx_obj.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        extra.retain(|key, _| !CORE_FIELDS.contains(&key.as_str()));
+        let mut truncated = false;
+        for value in extra.values_mut() {
+            if !truncate::fits(value, max_value_bytes) {
+                *value = truncate::truncate_value(value, max_value_bytes).0;
+                truncated = true;
+            }
+        }
+        if !extra.is_empty() {
             if let Some(base_obj) = base.as_object_mut() {
-                if let Some(ctx_obj) = ctx.as_object() {
-                    for (k, v) in ctx_obj {
-                        // Apply PII masking to string values in context
-                        let safe_v = if let Some(s) = v.as_str() {
-                            json!(mask_pii(s))
-                        } else {
-                            v.clone()
-                        };
-                        base_obj.insert(k.clone(), safe_v);
-                    }
+                // Masks nested strings and redacts sensitive keys at any depth
+                if let Value::Object(safe) = self.masker.mask_value(&Value::Object(extra)) {
+                    base_obj.extend(safe);
+                }
+                if truncated {
+                    base_obj.insert("truncated".to_string(), Value::Bool(true));
                 }
             }
         }
-        
+
         base
     }
 }
@@ -78,4 +329,179 @@ mod tests {
         assert_eq!(entry["tenant_id"], "tenant-1");
         assert_eq!(entry["user_email"], "***@***.***");
     }
+
+    #[test]
+    fn test_suppressed_levels_produce_no_output() {
+        let logger = Logger::with_level("worker-test".to_string(), LogLevel::Warn);
+        assert!(logger.render(LogLevel::Debug, "noisy", None).is_none());
+        assert!(logger.render(LogLevel::Info, "noisy", None).is_none());
+
+        let line = logger.render(LogLevel::Warn, "careful", None).unwrap();
+        let entry: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["level"], "WARN");
+        assert!(logger.render(LogLevel::Error, "broken", None).is_some());
+    }
+
+    #[test]
+    fn test_level_change_applies_to_clones() {
+        let logger = Logger::new("worker-test".to_string());
+        let clone = logger.clone();
+        assert!(clone.render(LogLevel::Debug, "hidden", None).is_none());
+
+        logger.set_level(LogLevel::Debug);
+        assert_eq!(clone.level(), LogLevel::Debug);
+        assert!(clone.render(LogLevel::Debug, "visible", None).is_some());
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!("DEBUG".parse::<LogLevel>(), Ok(LogLevel::Debug));
+        assert_eq!("warning".parse::<LogLevel>(), Ok(LogLevel::Warn));
+        assert!("verbose".parse::<LogLevel>().is_err());
+    }
+
+    struct MemorySink(std::sync::Mutex<Vec<String>>);
+
+    impl LogSink for MemorySink {
+        fn write_line(&self, _level: LogLevel, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+    }
+
+    #[test]
+    fn test_entries_reach_every_sink() {
+        let a = Arc::new(MemorySink(Default::default()));
+        let b = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![a.clone(), b.clone()]);
+
+        logger.debug("suppressed", None);
+        logger.info("hello", Some(&json!({"k": "v"})));
+
+        for sink in [&a, &b] {
+            let lines = sink.0.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+            assert_eq!(entry["msg"], "hello");
+            assert_eq!(entry["k"], "v");
+        }
+    }
+
+    #[test]
+    fn test_nested_context_is_masked_and_redacted() {
+        let logger = Logger::new("worker-test".to_string());
+        let context = json!({
+            "job": {"payload": {"customer": "a@b.com", "headers": {"Authorization": "Bearer xyz"}}},
+            "password": "hunter2"
+        });
+
+        let entry = logger.build_entry("INFO", "Processing", Some(&context));
+
+        assert_eq!(entry["job"]["payload"]["customer"], "***@***.***");
+        assert_eq!(entry["job"]["payload"]["headers"]["Authorization"], "***");
+        assert_eq!(entry["password"], "***");
+    }
+
+    #[test]
+    fn test_custom_redact_keys() {
+        let logger = Logger::new("worker-test".to_string()).with_masker(PiiMasker::new(vec!["api_key".to_string()]));
+        let entry = logger.build_entry("INFO", "x", Some(&json!({"api_key": "k", "password": "p"})));
+        assert_eq!(entry["api_key"], "***");
+        assert_eq!(entry["password"], "p");
+    }
+
+    #[test]
+    fn test_sticky_fields_on_nested_loggers() {
+        let sink = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![sink.clone()]);
+        let task = logger.with_fields(json!({"assignment_id": "a1", "trace_id": "t1", "contact": "a@b.com"}));
+        let nested = task.with_fields(json!({"job_type": "http"}));
+
+        nested.info("Result published", Some(&json!({"status": "Success", "trace_id": "override"})));
+        logger.info("No sticky fields", None);
+
+        let lines = sink.0.lock().unwrap();
+        let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(entry["assignment_id"], "a1");
+        assert_eq!(entry["job_type"], "http");
+        assert_eq!(entry["status"], "Success");
+        assert_eq!(entry["trace_id"], "override");
+        assert_eq!(entry["contact"], "***@***.***");
+
+        let parent: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert!(parent.get("assignment_id").is_none());
+    }
+
+    #[test]
+    fn test_large_context_values_are_truncated() {
+        let sink = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![sink.clone()]).with_max_value_bytes(64);
+        logger.info("Response", Some(&json!({"status": 200, "body": "x".repeat(10_000)})));
+        logger.info("Small", Some(&json!({"status": 200})));
+
+        let lines = sink.0.lock().unwrap();
+        let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["truncated"], true);
+        let body = entry["body"].as_str().unwrap();
+        assert!(body.len() <= 64 && body.contains("...<truncated "), "{}", body);
+        assert!(lines[0].len() < 300);
+        let small: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert!(small.get("truncated").is_none());
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_in_every_field() {
+        let sink = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![sink.clone()]);
+        let task = logger.with_secrets(&["sk-\"live\"-123".to_string(), String::new()]);
+        task.info("Calling with sk-\"live\"-123", Some(&json!({"url": "https://x/?key=sk-\"live\"-123"})));
+        logger.info("Parent sk-\"live\"-123", None);
+
+        let lines = sink.0.lock().unwrap();
+        let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(entry["msg"], "Calling with ***");
+        assert_eq!(entry["url"], "https://x/?key=***");
+        assert!(lines[1].contains("live"), "the parent logger is unaffected");
+    }
+
+    #[test]
+    fn test_oversized_lines_are_shrunk_to_valid_json() {
+        let sink = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![sink.clone()]).with_max_line_bytes(4096);
+        let items: Vec<Value> = (0..20_000).map(|i| json!({"id": i, "name": format!("item-{}", i)})).collect();
+        let many: Map<String, Value> = (0..50_000).map(|i| (format!("k{}", i), json!(i))).collect();
+        logger.info("Payload echo", Some(&json!({"status": 200, "body": "x".repeat(3 * 1024 * 1024), "items": items})));
+        logger.info("Wide context", Some(&Value::Object(many)));
+        logger.info(&"m".repeat(10_000), None);
+        logger.info("Small", Some(&json!({"status": 200})));
+
+        let lines = sink.0.lock().unwrap();
+        for line in lines.iter().take(3) {
+            assert!(line.len() <= 4096, "{} bytes", line.len());
+            let entry: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(entry["log_truncated"], true);
+            assert_eq!(entry["worker_id"], "worker-test");
+        }
+        let echo: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!((echo["msg"].as_str(), echo["status"].as_i64()), (Some("Payload echo"), Some(200)));
+        let wide: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert!(wide.get("k0").is_none(), "context is dropped once values cannot shrink enough");
+        assert!(serde_json::from_str::<Value>(&lines[2]).unwrap()["msg"].as_str().unwrap().starts_with("mmm"));
+        assert!(serde_json::from_str::<Value>(&lines[3]).unwrap().get("log_truncated").is_none());
+    }
+
+    #[test]
+    fn test_core_fields_lead_and_cannot_be_overridden() {
+        let sink = Arc::new(MemorySink(Default::default()));
+        let logger = Logger::with_sinks("worker-test".to_string(), LogLevel::Info, vec![sink.clone()])
+            .with_fields(json!({"worker_id": "spoofed"}));
+        logger.info("Hello", Some(&json!({"aaa": 1, "level": "DEBUG", "msg": "other", "ts": 0})));
+
+        let line = &sink.0.lock().unwrap()[0];
+        assert!(line.starts_with(r#"{"ts":""#), "{}", line);
+        let keys: Vec<&str> = line.split(',').skip(1).take(4).map(|field| field.split(':').next().unwrap()).collect();
+        assert_eq!(keys, [r#""level""#, r#""msg""#, r#""worker_id""#, r#""aaa""#]);
+        let entry: Value = serde_json::from_str(line).unwrap();
+        assert_eq!((entry["level"].as_str(), entry["msg"].as_str(), entry["worker_id"].as_str()), (Some("INFO"), Some("Hello"), Some("worker-test")));
+    }
 }
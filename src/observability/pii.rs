@@ -1,17 +1,209 @@
-use regex::Regex;
+use regex::{Captures, Regex};
 use lazy_static::lazy_static;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+const EMAIL_PATTERN: &str = r"(?i:[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,4})";
+// 13-19 digits, optionally grouped by spaces or dashes; confirmed with a Luhn check
+const CARD_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+// +CC international numbers, (NNN) NNN-NNNN, or NNN-NNN-NNNN; bare dates and IDs don't match
+const PHONE_PATTERN: &str = r"\+\d{1,3}(?:[ .-]?\(?\d{1,4}\)?){2,5}|\(\d{3}\) ?\d{3}[ .-]\d{4}|\b\d{3}[ .-]\d{3}[ .-]\d{4}\b";
+const IPV4_PATTERN: &str = r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b";
+const IPV6_PATTERN: &str = r"(?i:\b(?:[0-9a-f]{1,4}:){7}[0-9a-f]{1,4}\b|(?:\b[0-9a-f]{1,4})?(?::[0-9a-f]{1,4}){0,6}::(?:[0-9a-f]{1,4}(?::[0-9a-f]{1,4}){0,6}\b)?)";
+
+const EMAIL_MASK: &str = "***@***.***";
+const CARD_MASK: &str = "***CARD***";
+const PHONE_MASK: &str = "***PHONE***";
+const IP_MASK: &str = "***IP***";
 
 lazy_static! {
-    static ref EMAIL_REGEX: Regex = Regex::new(r"(?i)[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,4}").unwrap();
+    static ref DEFAULT_PATTERNS: Arc<PiiPatterns> = Arc::new(PiiPatterns::new(false, &[]).unwrap());
 }
 
+pub const REDACTED: &str = "***";
+/// Replaces whatever lies past the depth or node limits, so nothing is logged unmasked.
+pub const TRUNCATED: &str = "***TRUNCATED***";
+
+pub const DEFAULT_REDACT_KEYS: &[&str] = &["authorization", "password", "token", "secret", "connection_string"];
+const DEFAULT_MAX_DEPTH: usize = 16;
+const DEFAULT_MAX_NODES: usize = 10_000;
+
+#[cfg(test)]
 pub fn mask_pii(input: &str) -> String {
-    EMAIL_REGEX.replace_all(input, "***@***.***").to_string()
+    DEFAULT_PATTERNS.mask(input)
+}
+
+/// All masking patterns compiled into one alternation, so each string is scanned once.
+///
+/// Built-ins are tried before custom patterns; every match is replaced with its own
+/// placeholder (`***PHONE***`, `***CARD***`, custom `***NAME***`) to keep logs debuggable.
+#[derive(Debug)]
+pub struct PiiPatterns {
+    combined: Regex,
+    custom_masks: Vec<String>,
+}
+
+impl PiiPatterns {
+    /// `custom` holds `(name, regex)` pairs, typically from [`parse_custom_patterns`].
+    pub fn new(mask_ips: bool, custom: &[(String, String)]) -> Result<Self, String> {
+        let mut parts = vec![
+            format!("(?P<pii_email>{})", EMAIL_PATTERN),
+            format!("(?P<pii_card>{})", CARD_PATTERN),
+            format!("(?P<pii_phone>{})", PHONE_PATTERN),
+        ];
+        if mask_ips {
+            parts.push(format!("(?P<pii_ipv4>{})", IPV4_PATTERN));
+            parts.push(format!("(?P<pii_ipv6>{})", IPV6_PATTERN));
+        }
+        let mut custom_masks = Vec::with_capacity(custom.len());
+        for (i, (name, pattern)) in custom.iter().enumerate() {
+            Regex::new(pattern).map_err(|e| format!("PII pattern '{}' is invalid: {}", name, e))?;
+            parts.push(format!("(?P<pii_custom_{}>{})", i, pattern));
+            custom_masks.push(format!("***{}***", name.to_ascii_uppercase()));
+        }
+        let combined = Regex::new(&parts.join("|")).map_err(|e| format!("PII patterns are invalid: {}", e))?;
+        Ok(Self { combined, custom_masks })
+    }
+
+    pub fn mask(&self, input: &str) -> String {
+        self.combined.replace_all(input, |caps: &Captures| self.replacement(caps)).into_owned()
+    }
+
+    fn replacement(&self, caps: &Captures) -> String {
+        if caps.name("pii_email").is_some() {
+            return EMAIL_MASK.to_string();
+        }
+        if let Some(m) = caps.name("pii_card") {
+            // Long order IDs that fail the checksum are left alone
+            return if luhn_valid(m.as_str()) { CARD_MASK.to_string() } else { m.as_str().to_string() };
+        }
+        if caps.name("pii_phone").is_some() {
+            return PHONE_MASK.to_string();
+        }
+        if caps.name("pii_ipv4").is_some() || caps.name("pii_ipv6").is_some() {
+            return IP_MASK.to_string();
+        }
+        for (i, mask) in self.custom_masks.iter().enumerate() {
+            if caps.name(&format!("pii_custom_{}", i)).is_some() {
+                return mask.clone();
+            }
+        }
+        caps[0].to_string()
+    }
+}
+
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits.iter().rev().enumerate().map(|(i, &d)| {
+        if i % 2 == 1 {
+            let doubled = d * 2;
+            if doubled > 9 { doubled - 9 } else { doubled }
+        } else {
+            d
+        }
+    }).sum();
+    sum.is_multiple_of(10)
+}
+
+/// Parses `name=regex;name2=regex2`. Names are alphanumeric/underscore and each regex must compile.
+pub fn parse_custom_patterns(raw: &str) -> Result<Vec<(String, String)>, String> {
+    let mut patterns = Vec::new();
+    for entry in raw.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, pattern) = entry.split_once('=')
+            .ok_or_else(|| format!("PII_CUSTOM_PATTERNS entry '{}' must be name=regex", entry))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("PII_CUSTOM_PATTERNS name '{}' must be alphanumeric", name));
+        }
+        Regex::new(pattern).map_err(|e| format!("PII_CUSTOM_PATTERNS '{}' is not a valid regex: {}", name, e))?;
+        patterns.push((name.to_string(), pattern.to_string()));
+    }
+    Ok(patterns)
+}
+
+/// Masks PII throughout a JSON value and redacts values stored under sensitive keys.
+#[derive(Debug, Clone)]
+pub struct PiiMasker {
+    patterns: Arc<PiiPatterns>,
+    redact_keys: Vec<String>,
+    max_depth: usize,
+    max_nodes: usize,
+}
+
+impl Default for PiiMasker {
+    fn default() -> Self {
+        Self::new(DEFAULT_REDACT_KEYS.iter().map(|k| k.to_string()).collect())
+    }
+}
+
+impl PiiMasker {
+    /// `redact_keys` are matched case-insensitively against object keys at any depth.
+    pub fn new(redact_keys: Vec<String>) -> Self {
+        Self {
+            patterns: DEFAULT_PATTERNS.clone(),
+            redact_keys: redact_keys.into_iter().map(|k| k.to_ascii_lowercase()).collect(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_nodes: DEFAULT_MAX_NODES,
+        }
+    }
+
+    pub fn with_patterns(mut self, patterns: PiiPatterns) -> Self {
+        self.patterns = Arc::new(patterns);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_limits(mut self, max_depth: usize, max_nodes: usize) -> Self {
+        self.max_depth = max_depth;
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    pub fn mask_str(&self, input: &str) -> String {
+        self.patterns.mask(input)
+    }
+
+    pub fn is_redacted_key(&self, key: &str) -> bool {
+        self.redact_keys.iter().any(|k| k.eq_ignore_ascii_case(key))
+    }
+
+    pub fn mask_value(&self, value: &Value) -> Value {
+        let mut budget = self.max_nodes;
+        self.mask_inner(value, 0, &mut budget)
+    }
+
+    fn mask_inner(&self, value: &Value, depth: usize, budget: &mut usize) -> Value {
+        if *budget == 0 || depth > self.max_depth {
+            return Value::String(TRUNCATED.to_string());
+        }
+        *budget -= 1;
+        match value {
+            Value::String(s) => Value::String(self.mask_str(s)),
+            Value::Array(items) => Value::Array(items.iter().map(|v| self.mask_inner(v, depth + 1, budget)).collect()),
+            Value::Object(map) => {
+                let mut out = Map::with_capacity(map.len());
+                for (k, v) in map {
+                    let masked = if self.is_redacted_key(k) {
+                        Value::String(REDACTED.to_string())
+                    } else {
+                        self.mask_inner(v, depth + 1, budget)
+                    };
+                    out.insert(k.clone(), masked);
+                }
+                Value::Object(out)
+            }
+            other => other.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn test_mask_email() {
@@ -26,4 +218,87 @@ mod tests {
         let masked = mask_pii(input);
         assert_eq!(masked, input);
     }
+
+    #[test]
+    fn test_mask_nested_values() {
+        let masker = PiiMasker::default();
+        let masked = masker.mask_value(&json!({
+            "job": {"payload": {"customer": "a@b.com", "emails": ["x@y.org", 7]}}
+        }));
+        assert_eq!(masked["job"]["payload"]["customer"], "***@***.***");
+        assert_eq!(masked["job"]["payload"]["emails"], json!(["***@***.***", 7]));
+    }
+
+    #[test]
+    fn test_redact_keys_at_any_depth() {
+        let masker = PiiMasker::new(vec!["Authorization".to_string(), "connection_string".to_string()]);
+        let masked = masker.mask_value(&json!({
+            "headers": {"authorization": "Bearer abc", "accept": "json"},
+            "connection_string": {"host": "db", "pass": "hunter2"}
+        }));
+        assert_eq!(masked["headers"]["authorization"], REDACTED);
+        assert_eq!(masked["headers"]["accept"], "json");
+        assert_eq!(masked["connection_string"], REDACTED);
+    }
+
+    #[test]
+    fn test_limits_truncate_instead_of_leaking() {
+        let masker = PiiMasker::default().with_limits(2, 1000);
+        let masked = masker.mask_value(&json!({"a": {"b": {"c": "deep@example.com"}}}));
+        assert_eq!(masked["a"]["b"]["c"], TRUNCATED);
+
+        let masker = PiiMasker::default().with_limits(16, 3);
+        let masked = masker.mask_value(&json!(["a@b.com", "c@d.com", "e@f.com", "g@h.com"]));
+        assert_eq!(masked, json!(["***@***.***", "***@***.***", TRUNCATED, TRUNCATED]));
+    }
+
+    #[test]
+    fn test_mask_phone_numbers() {
+        assert_eq!(mask_pii("call +44 20 7946 0958 today"), "call ***PHONE*** today");
+        assert_eq!(mask_pii("cell (415) 555-2671"), "cell ***PHONE***");
+        assert_eq!(mask_pii("cell 415-555-2671"), "cell ***PHONE***");
+        // Dates and short numbers are not phones
+        assert_eq!(mask_pii("on 2024-01-15 retry 3"), "on 2024-01-15 retry 3");
+    }
+
+    #[test]
+    fn test_mask_cards_with_luhn() {
+        assert_eq!(mask_pii("card 4111 1111 1111 1111 ok"), "card ***CARD*** ok");
+        assert_eq!(mask_pii("card 5500-0000-0000-0004"), "card ***CARD***");
+        // Fails the checksum: an order id, not a card
+        assert_eq!(mask_pii("order 1234567890123456"), "order 1234567890123456");
+    }
+
+    #[test]
+    fn test_ips_only_when_enabled() {
+        assert_eq!(mask_pii("from 10.1.2.3"), "from 10.1.2.3");
+        let patterns = PiiPatterns::new(true, &[]).unwrap();
+        assert_eq!(patterns.mask("from 10.1.2.3"), "from ***IP***");
+        assert_eq!(patterns.mask("from 2001:db8::8a2e:370:7334"), "from ***IP***");
+        assert_eq!(patterns.mask("from fe80:0:0:0:202:b3ff:fe1e:8329"), "from ***IP***");
+    }
+
+    #[test]
+    fn test_custom_patterns() {
+        let custom = parse_custom_patterns(r"ssn=\b\d{3}-\d{2}-\d{4}\b; iban=\bDE\d{20}\b").unwrap();
+        assert_eq!(custom.len(), 2);
+        let patterns = PiiPatterns::new(false, &custom).unwrap();
+        assert_eq!(patterns.mask("ssn 123-45-6789 iban DE89370400440532013000"), "ssn ***SSN*** iban ***IBAN***");
+
+        assert!(parse_custom_patterns("ssn").is_err());
+        assert!(parse_custom_patterns("bad name=x").is_err());
+        assert!(parse_custom_patterns("broken=(").unwrap_err().contains("broken"));
+    }
+
+    #[test]
+    fn test_masking_throughput() {
+        let patterns = PiiPatterns::new(true, &[]).unwrap();
+        let line = "Processing assignment a-123 for tenant t-9 at 2024-01-15T10:00:00Z attempt 2 latency_ms 1532";
+        let start = std::time::Instant::now();
+        for _ in 0..20_000 {
+            assert_eq!(patterns.mask(line), line);
+        }
+        // Generous bound for debug builds; a per-pattern loop would be several times slower
+        assert!(start.elapsed() < std::time::Duration::from_secs(5), "took {:?}", start.elapsed());
+    }
 }
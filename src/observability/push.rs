@@ -0,0 +1,176 @@
+use super::{metrics::Metrics, Logger};
+use crate::config::MetricsPushMode;
+use crate::retry::{retry_with_backoff, Backoff, RetryPolicy};
+use base64::{Engine as _, engine::general_purpose};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The `job` grouping label pushed series carry on a pushgateway.
+pub const PUSH_JOB: &str = "beamline_worker";
+
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// A failed push; transient ones (no response, 5xx, 429) are retried.
+struct PushError {
+    transient: bool,
+    message: String,
+}
+
+/// Pushes the registry to `METRICS_PUSH_URL` for sites where nothing can scrape the health
+/// port. `/metrics` keeps serving alongside it.
+pub struct MetricsPusher {
+    client: reqwest::Client,
+    url: String,
+    mode: MetricsPushMode,
+    worker_id: String,
+    retry: RetryPolicy,
+}
+
+impl MetricsPusher {
+    /// Each request gives up after `timeout`; failures are retried up to three times.
+    pub fn new(url: String, mode: MetricsPushMode, worker_id: String, timeout: Duration) -> Self {
+        Self {
+            client: reqwest::Client::builder().timeout(timeout).build().unwrap_or_default(),
+            url,
+            mode,
+            worker_id,
+            retry: RetryPolicy::new(Backoff::new(Duration::from_millis(500), Duration::from_secs(10)), Some(3)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, body: Vec<u8>) -> reqwest::RequestBuilder {
+        let request = match self.mode {
+            MetricsPushMode::Pushgateway => {
+                let url = format!("{}/metrics/job/{}/{}", self.url.trim_end_matches('/'), PUSH_JOB, grouping_label(&self.worker_id));
+                self.client.put(url)
+            }
+            MetricsPushMode::Post => self.client.post(&self.url).query(&[("worker_id", &self.worker_id)]),
+        };
+        request.header(CONTENT_TYPE, TEXT_FORMAT).body(body)
+    }
+
+    /// One push of the current registry in the text format, retried while failures look transient.
+    pub async fn push(&self, metrics: &Metrics) -> Result<(), String> {
+        let body = metrics.encode();
+        let pushed = retry_with_backoff(&self.retry, |e: &PushError, _, _| e.transient, |_| {
+            let request = self.request(body.clone());
+            async move {
+                // The URL may carry credentials, so it stays out of the message
+                let response = request.send().await.map_err(|e| PushError { transient: true, message: e.without_url().to_string() })?;
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+                Err(PushError {
+                    transient: status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                    message: format!("collector answered {}", status),
+                })
+            }
+        })
+        .await;
+        pushed.map_err(|e| e.message)
+    }
+
+    /// Pushes every `interval` until the task is dropped, counting each push that still
+    /// failed after its retries.
+    pub async fn run(self, interval: Duration, metrics: Arc<Metrics>, logger: Logger) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(error) = self.push(&metrics).await {
+                metrics.metrics_push_failures_total.inc();
+                logger.warn("Metrics push failed", Some(&json!({"error": error, "mode": self.mode})));
+            }
+        }
+    }
+}
+
+/// The `worker_id` path segment; ids a path can't hold use the pushgateway's base64 form.
+fn grouping_label(worker_id: &str) -> String {
+    if !worker_id.is_empty() && worker_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        format!("worker_id/{}", worker_id)
+    } else {
+        format!("worker_id@base64/{}", general_purpose::URL_SAFE_NO_PAD.encode(worker_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::{Request, State}, http::StatusCode as AxumStatus, routing::any, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Method, path with query, and body of each request; the first `failures` get a 503.
+    #[derive(Default)]
+    struct Collector {
+        received: Mutex<Vec<(String, String, String)>>,
+        failures: AtomicUsize,
+    }
+
+    async fn collector(failures: usize) -> (String, Arc<Collector>) {
+        let state = Arc::new(Collector { failures: AtomicUsize::new(failures), ..Default::default() });
+        let app = Router::new().fallback(any(|State(state): State<Arc<Collector>>, request: Request| async move {
+            let (parts, body) = request.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+            let path = parts.uri.path_and_query().map(|p| p.to_string()).unwrap_or_default();
+            state.received.lock().unwrap().push((parts.method.to_string(), path, String::from_utf8_lossy(&body).to_string()));
+            if state.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                AxumStatus::SERVICE_UNAVAILABLE
+            } else {
+                AxumStatus::OK
+            }
+        })).with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, state)
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy::new(Backoff::new(Duration::from_millis(1), Duration::from_millis(5)), Some(3))
+    }
+
+    #[tokio::test]
+    async fn test_pushgateway_receives_text_format_grouped_by_worker() {
+        let (url, collector) = collector(1).await;
+        let metrics = Metrics::new();
+        metrics.task_received.inc();
+        let pusher = MetricsPusher::new(format!("{}/", url), MetricsPushMode::Pushgateway, "worker-1".to_string(), Duration::from_secs(5))
+            .with_retry(fast_retry());
+
+        pusher.push(&metrics).await.unwrap();
+        let received = collector.received.lock().unwrap();
+        assert_eq!(received.len(), 2, "the 503 is retried");
+        let (method, path, body) = &received[1];
+        assert_eq!((method.as_str(), path.as_str()), ("PUT", "/metrics/job/beamline_worker/worker_id/worker-1"));
+        assert!(body.contains("# TYPE") && body.contains("task_received"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_post_mode_and_exhausted_retries() {
+        let (url, collector) = collector(usize::MAX).await;
+        let pusher = MetricsPusher::new(format!("{}/ingest", url), MetricsPushMode::Post, "w 1".to_string(), Duration::from_secs(5))
+            .with_retry(fast_retry());
+
+        assert!(pusher.push(&Metrics::new()).await.unwrap_err().contains("503"));
+        let received = collector.received.lock().unwrap();
+        assert_eq!(received.len(), 4);
+        assert_eq!((received[0].0.as_str(), received[0].1.as_str()), ("POST", "/ingest?worker_id=w+1"));
+    }
+
+    #[test]
+    fn test_unsafe_worker_ids_use_base64_labels() {
+        assert_eq!(grouping_label("pod/a"), format!("worker_id@base64/{}", general_purpose::URL_SAFE_NO_PAD.encode("pod/a")));
+    }
+}
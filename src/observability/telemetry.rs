@@ -0,0 +1,256 @@
+use crate::protocol::{ExecAssignment, ExecResult, ExecStatus};
+use async_nats::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, SpanId, Status, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+pub const SERVICE_NAME: &str = "beamline-worker";
+
+/// Keeps the tracer provider alive; dropping it flushes buffered spans.
+pub struct TelemetryGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        let _ = self.provider.shutdown();
+    }
+}
+
+/// Installs an OTLP/HTTP span exporter as the global `tracing` subscriber.
+///
+/// Returns `Ok(None)` when no endpoint is configured, in which case spans are no-ops. Spans are
+/// exported from a background batch processor, so a down collector never slows job processing.
+pub fn init(endpoint: Option<&str>, worker_id: &str) -> Result<Option<TelemetryGuard>, String> {
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_endpoint(endpoint))
+        .build()
+        .map_err(|e| format!("failed to build OTLP exporter: {}", e))?;
+    let resource = Resource::builder()
+        .with_service_name(SERVICE_NAME)
+        .with_attribute(KeyValue::new("service.instance.id", worker_id.to_string()))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("worker")));
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| format!("failed to install tracing subscriber: {}", e))?;
+    Ok(Some(TelemetryGuard { provider }))
+}
+
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` is the collector base URL; the HTTP exporter wants the traces path.
+fn traces_endpoint(endpoint: &str) -> String {
+    let trimmed = endpoint.trim_end_matches('/');
+    if trimmed.ends_with("/v1/traces") {
+        trimmed.to_string()
+    } else {
+        format!("{}/v1/traces", trimmed)
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(k, _)| k.as_ref()).collect()
+    }
+}
+
+/// The remote parent carried in a W3C `traceparent` message header, if any.
+pub fn parent_context(headers: Option<&HeaderMap>) -> Option<Context> {
+    let headers = headers?;
+    let cx = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if cx.span().span_context().is_valid() {
+        Some(cx)
+    } else {
+        None
+    }
+}
+
+/// A W3C-format trace id (32 lowercase hex digits) for an assignment that arrived without one.
+pub fn generate_trace_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// `trace_id` as a W3C trace id, if it is one; controllers may send ids of any shape.
+fn w3c_trace_id(trace_id: &str) -> Option<TraceId> {
+    if trace_id.len() != 32 || !trace_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    TraceId::from_hex(trace_id).ok().filter(|id| *id != TraceId::INVALID)
+}
+
+fn new_span_id() -> SpanId {
+    SpanId::from_bytes(rand::random::<u64>().max(1).to_be_bytes())
+}
+
+/// A `traceparent` header value continuing `trace_id` under a new span id.
+pub fn traceparent(trace_id: &str) -> Option<String> {
+    let trace_id = w3c_trace_id(trace_id)?;
+    Some(format!("00-{}-{}-01", trace_id, new_span_id()))
+}
+
+/// A remote parent carrying `trace_id`, so spans of an assignment whose id the worker
+/// generated are found under that id.
+pub fn generated_parent(trace_id: &str) -> Option<Context> {
+    let trace_id = w3c_trace_id(trace_id)?;
+    let span = SpanContext::new(trace_id, new_span_id(), TraceFlags::SAMPLED, true, TraceState::default());
+    Some(Context::new().with_remote_span_context(span))
+}
+
+pub fn assignment_attributes(assignment: &ExecAssignment) -> Vec<KeyValue> {
+    let mut attrs = vec![
+        KeyValue::new("assignment_id", assignment.assignment_id.clone()),
+        KeyValue::new("request_id", assignment.request_id.clone()),
+        KeyValue::new("tenant_id", assignment.tenant_id.clone()),
+        KeyValue::new("job_type", assignment.job.r#type.clone()),
+    ];
+    // Without a traceparent the controller's trace_id is still searchable as an attribute
+    if let Some(trace_id) = &assignment.trace_id {
+        attrs.push(KeyValue::new("trace_id", trace_id.clone()));
+    }
+    if let Some(run_id) = &assignment.run_id {
+        attrs.push(KeyValue::new("run_id", run_id.clone()));
+    }
+    attrs
+}
+
+pub fn result_attributes(result: &ExecResult) -> Vec<KeyValue> {
+    let mut attrs = vec![
+        KeyValue::new("status", result.status.as_str()),
+        KeyValue::new("latency_ms", result.latency_ms as i64),
+    ];
+    if let Some(code) = &result.error_code {
+        attrs.push(KeyValue::new("error_code", code.clone()));
+    }
+    attrs
+}
+
+/// Root span for one assignment, parented on the incoming trace context when present.
+pub fn assignment_span(assignment: &ExecAssignment, parent: Option<Context>) -> Span {
+    let span = tracing::info_span!("assignment", otel.kind = "consumer");
+    if let Some(cx) = parent {
+        let _ = span.set_parent(cx);
+    }
+    for kv in assignment_attributes(assignment) {
+        span.set_attribute(kv.key, kv.value);
+    }
+    span
+}
+
+pub fn record_result(span: &Span, result: &ExecResult) {
+    for kv in result_attributes(result) {
+        span.set_attribute(kv.key, kv.value);
+    }
+    match result.status {
+        ExecStatus::Success => span.set_status(Status::Ok),
+        _ => span.set_status(Status::error(result.error_message.clone().unwrap_or_default())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Job;
+    use opentelemetry::{Key, Value};
+    use serde_json::json;
+
+    fn attr<'a>(attrs: &'a [KeyValue], key: &str) -> Option<&'a Value> {
+        attrs.iter().find(|kv| kv.key == Key::from(key.to_string())).map(|kv| &kv.value)
+    }
+
+    #[test]
+    fn test_assignment_attributes() {
+        let assignment = ExecAssignment {
+            version: "1.0".to_string(),
+            assignment_id: "a1".to_string(),
+            request_id: "r1".to_string(),
+            tenant_id: "t1".to_string(),
+            job: Job { r#type: "http".to_string(), payload: json!({}) },
+            trace_id: Some("trace-1".to_string()),
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
+        };
+        let attrs = assignment_attributes(&assignment);
+        assert_eq!(attr(&attrs, "assignment_id"), Some(&Value::from("a1")));
+        assert_eq!(attr(&attrs, "job_type"), Some(&Value::from("http")));
+        assert_eq!(attr(&attrs, "trace_id"), Some(&Value::from("trace-1")));
+        assert!(attr(&attrs, "run_id").is_none());
+    }
+
+    #[test]
+    fn test_result_attributes_include_error_code() {
+        let result: ExecResult = serde_json::from_value(json!({
+            "version": "1.0",
+            "assignment_id": "a1",
+            "request_id": "r1",
+            "status": "error",
+            "provider_id": "w1",
+            "job_type": "http",
+            "latency_ms": 12,
+            "cost": 0.0,
+            "error_code": "HTTP_REQUEST_FAILED"
+        })).unwrap();
+        let attrs = result_attributes(&result);
+        assert_eq!(attr(&attrs, "status"), Some(&Value::from("error")));
+        assert_eq!(attr(&attrs, "latency_ms"), Some(&Value::from(12_i64)));
+        assert_eq!(attr(&attrs, "error_code"), Some(&Value::from("HTTP_REQUEST_FAILED")));
+    }
+
+    #[test]
+    fn test_parent_context_from_traceparent_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let cx = parent_context(Some(&headers)).unwrap();
+        assert_eq!(cx.span().span_context().trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        let mut bad = HeaderMap::new();
+        bad.insert("traceparent", "garbage");
+        assert!(parent_context(Some(&bad)).is_none());
+        assert!(parent_context(None).is_none());
+    }
+
+    #[test]
+    fn test_generated_trace_ids_are_w3c() {
+        let trace_id = generate_trace_id();
+        assert_eq!(trace_id.len(), 32);
+        let header = traceparent(&trace_id).unwrap();
+        assert!(header.starts_with(&format!("00-{}-", trace_id)) && header.ends_with("-01"), "{}", header);
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", header.as_str());
+        assert_eq!(parent_context(Some(&headers)).unwrap().span().span_context().trace_id().to_string(), trace_id);
+        assert_eq!(generated_parent(&trace_id).unwrap().span().span_context().trace_id().to_string(), trace_id);
+
+        assert!(traceparent("tr1").is_none(), "not a W3C id");
+        assert!(traceparent(&"0".repeat(32)).is_none());
+    }
+
+    #[test]
+    fn test_traces_endpoint() {
+        assert_eq!(traces_endpoint("http://otel:4318"), "http://otel:4318/v1/traces");
+        assert_eq!(traces_endpoint("http://otel:4318/"), "http://otel:4318/v1/traces");
+        assert_eq!(traces_endpoint("http://otel:4318/v1/traces"), "http://otel:4318/v1/traces");
+    }
+}
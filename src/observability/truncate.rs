@@ -0,0 +1,156 @@
+use serde_json::{Map, Value};
+
+/// Key of the entry that stands in for the object entries dropped by `truncate_value`.
+pub const TRUNCATED_KEY: &str = "...";
+
+fn marker(bytes: usize) -> String {
+    format!("...<truncated {} bytes>", bytes)
+}
+
+/// Whether `value` encodes to at most `max_bytes`, without building the truncated copy.
+pub fn fits(value: &Value, max_bytes: usize) -> bool {
+    encoded_len(value) <= max_bytes
+}
+
+/// Cuts `value` down to roughly `max_bytes` of JSON, keeping its shape: long strings keep a prefix,
+/// and arrays and objects keep their leading entries (objects in key order). What was dropped is replaced with a
+/// `"...<truncated N bytes>"` marker; the flag is true if anything was.
+///
+/// Sizes ignore string escaping, and markers may push the result slightly past `max_bytes`.
+pub fn truncate_value(value: &Value, max_bytes: usize) -> (Value, bool) {
+    if fits(value, max_bytes) {
+        return (value.clone(), false);
+    }
+    let mut budget = max_bytes;
+    (prune(value, &mut budget), true)
+}
+
+fn prune(value: &Value, budget: &mut usize) -> Value {
+    let len = encoded_len(value);
+    if len <= *budget {
+        *budget -= len;
+        return value.clone();
+    }
+    match value {
+        Value::String(s) => {
+            let kept = truncate_str(s, budget.saturating_sub(2));
+            *budget = 0;
+            Value::String(kept)
+        }
+        Value::Array(items) => {
+            *budget = budget.saturating_sub(2);
+            let mut kept = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                if *budget == 0 {
+                    kept.push(Value::String(marker(items[i..].iter().map(encoded_len).sum())));
+                    break;
+                }
+                kept.push(prune(item, budget));
+            }
+            Value::Array(kept)
+        }
+        Value::Object(map) => {
+            *budget = budget.saturating_sub(2);
+            let mut kept = Map::new();
+            for (i, (key, item)) in map.iter().enumerate() {
+                if *budget == 0 {
+                    let rest = map.iter().skip(i).map(|(k, v)| str_len(k) + 1 + encoded_len(v)).sum();
+                    kept.insert(TRUNCATED_KEY.to_string(), Value::String(marker(rest)));
+                    break;
+                }
+                *budget = budget.saturating_sub(str_len(key) + 1);
+                kept.insert(key.clone(), prune(item, budget));
+            }
+            Value::Object(kept)
+        }
+        // Scalars are too small to be worth cutting
+        _ => {
+            *budget = 0;
+            value.clone()
+        }
+    }
+}
+
+/// The longest prefix of `s` that, with its marker, fits in `max_bytes`, cut on a char boundary.
+fn truncate_str(s: &str, max_bytes: usize) -> String {
+    let mut end = max_bytes.saturating_sub(marker(s.len()).len()).min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &s[..end], marker(s.len() - end))
+}
+
+fn str_len(s: &str) -> usize {
+    s.len() + 2
+}
+
+fn encoded_len(value: &Value) -> usize {
+    match value {
+        Value::Null => 4,
+        Value::Bool(b) => if *b { 4 } else { 5 },
+        Value::Number(n) => n.to_string().len(),
+        Value::String(s) => str_len(s),
+        Value::Array(items) => 2 + items.len().saturating_sub(1) + items.iter().map(encoded_len).sum::<usize>(),
+        Value::Object(map) => 2 + map.len().saturating_sub(1) + map.iter().map(|(k, v)| str_len(k) + 1 + encoded_len(v)).sum::<usize>(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_small_values_are_untouched() {
+        let value = json!({"a": [1, 2, {"b": "short"}], "c": null, "d": true});
+        assert_eq!(encoded_len(&value), serde_json::to_vec(&value).unwrap().len());
+        assert_eq!(truncate_value(&value, 1024), (value.clone(), false));
+        let exact = encoded_len(&value);
+        assert_eq!(truncate_value(&value, exact), (value, false));
+    }
+
+    #[test]
+    fn test_long_string_keeps_a_prefix() {
+        let (cut, truncated) = truncate_value(&json!("x".repeat(1000)), 100);
+        assert!(truncated);
+        let cut = cut.as_str().unwrap();
+        assert!(cut.starts_with("xxxx"));
+        let kept = cut.find("...").unwrap();
+        assert!(cut.ends_with(&format!("...<truncated {} bytes>", 1000 - kept)));
+        assert!(cut.len() <= 100);
+    }
+
+    #[test]
+    fn test_nested_structures_keep_their_shape() {
+        let value = json!({
+            "a_status": 200,
+            "b_body": {"items": (0..100).map(|i| json!({"id": i, "name": format!("item-{}", i)})).collect::<Vec<_>>()},
+            "c_tail": "after"
+        });
+        let (cut, truncated) = truncate_value(&value, 300);
+        assert!(truncated);
+        assert_eq!(cut["a_status"], 200);
+        let items = cut["b_body"]["items"].as_array().unwrap();
+        assert!(items.len() > 1 && items.len() < 100);
+        assert_eq!(items[0], json!({"id": 0, "name": "item-0"}));
+        assert!(items.last().unwrap().as_str().unwrap().starts_with("...<truncated "));
+        // Entries after the budget ran out collapse into one marker
+        assert!(cut.get("c_tail").is_none());
+        assert!(cut[TRUNCATED_KEY].as_str().unwrap().starts_with("...<truncated "));
+        assert!(serde_json::to_vec(&cut).unwrap().len() < 400);
+    }
+
+    #[test]
+    fn test_multibyte_strings_cut_on_char_boundaries() {
+        let text = "é£€😀".repeat(200);
+        for max in 30..60 {
+            let (cut, truncated) = truncate_value(&json!({"text": text}), max);
+            assert!(truncated);
+            let kept = cut["text"].as_str().unwrap();
+            let prefix = &kept[..kept.find("...<truncated").unwrap()];
+            assert!(text.starts_with(prefix));
+        }
+        let (cut, _) = truncate_value(&json!("😀😀"), 1);
+        assert_eq!(cut, json!("...<truncated 8 bytes>"));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::compression;
+use crate::config::Config;
+use crate::observability::{metrics::Metrics, Logger};
+use crate::pipeline::ResultPublisher;
+use crate::protocol::ExecAssignment;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Error code answering a redelivery of an assignment that was running when the worker died.
+pub const POSSIBLY_EXECUTED: &str = "POSSIBLY_EXECUTED";
+
+const STARTED: &str = "started";
+const RESULT: &str = "result";
+const INTERRUPTED: &str = "interrupted";
+
+/// What is known about an assignment once it has started; never holds the payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StartedRecord {
+    pub assignment_id: String,
+    pub request_id: String,
+    pub tenant_id: String,
+    pub job_type: String,
+    pub trace_id: Option<String>,
+    pub run_id: Option<String>,
+    pub result_subject: String,
+    pub started_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ResultRecord {
+    assignment_id: String,
+    subject: String,
+    payload_b64: String,
+}
+
+/// A result envelope that was persisted but never confirmed published.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingResult {
+    pub assignment_id: String,
+    pub subject: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub results: Vec<PendingResult>,
+    /// Started but never finished: their side effects may or may not have happened.
+    pub interrupted: Vec<StartedRecord>,
+}
+
+/// File-backed record of each assignment between starting it and publishing its result
+/// (`OUTBOX_ENABLED`), so a crash in between neither loses the result nor re-runs the job.
+///
+/// One file per assignment and stage, each written to a temporary name, fsynced and renamed
+/// into place: `<id>.started` before executing, `<id>.result` once finished, nothing once
+/// published. Startup turns leftover `started` files into `interrupted` markers, which stay
+/// until removed by hand, and redeliveries of those are refused with `POSSIBLY_EXECUTED`.
+pub struct Outbox {
+    dir: PathBuf,
+    interrupted: Mutex<HashMap<String, StartedRecord>>,
+}
+
+impl Outbox {
+    /// Creates `dir` if needed and loads the interrupted markers left by earlier runs.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut interrupted = HashMap::new();
+        for (path, stage) in entries(&dir)? {
+            if stage == INTERRUPTED {
+                if let Some(record) = read_json::<StartedRecord>(&path) {
+                    interrupted.insert(record.assignment_id.clone(), record);
+                }
+            }
+        }
+        Ok(Self { dir, interrupted: Mutex::new(interrupted) })
+    }
+
+    pub fn started(&self, assignment: &ExecAssignment, result_subject: &str) -> io::Result<()> {
+        let record = StartedRecord {
+            assignment_id: assignment.assignment_id.clone(),
+            request_id: assignment.request_id.clone(),
+            tenant_id: assignment.tenant_id.clone(),
+            job_type: assignment.job.r#type.clone(),
+            trace_id: assignment.trace_id.clone(),
+            run_id: assignment.run_id.clone(),
+            result_subject: result_subject.to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+        };
+        self.write(&assignment.assignment_id, STARTED, &record)
+    }
+
+    /// Persists the encoded result envelope, after which the job no longer counts as running.
+    pub fn finished(&self, assignment_id: &str, subject: &str, payload: &[u8]) -> io::Result<()> {
+        let record = ResultRecord {
+            assignment_id: assignment_id.to_string(),
+            subject: subject.to_string(),
+            payload_b64: general_purpose::STANDARD.encode(payload),
+        };
+        self.write(assignment_id, RESULT, &record)?;
+        remove_if_exists(&self.path(assignment_id, STARTED))
+    }
+
+    pub fn published(&self, assignment_id: &str) -> io::Result<()> {
+        remove_if_exists(&self.path(assignment_id, RESULT))?;
+        remove_if_exists(&self.path(assignment_id, STARTED))
+    }
+
+    /// Collects what a previous run left behind: results to republish, and started
+    /// assignments, which become interrupted markers. Call once, before consuming.
+    pub fn recover(&self) -> io::Result<Recovered> {
+        let mut recovered = Recovered::default();
+        let mut finished = Vec::new();
+        for (path, stage) in entries(&self.dir)? {
+            if stage == RESULT {
+                if let Some(record) = read_json::<ResultRecord>(&path) {
+                    if let Ok(payload) = general_purpose::STANDARD.decode(&record.payload_b64) {
+                        finished.push(record.assignment_id.clone());
+                        recovered.results.push(PendingResult { assignment_id: record.assignment_id, subject: record.subject, payload });
+                    }
+                }
+            }
+        }
+        for (path, stage) in entries(&self.dir)? {
+            if stage != STARTED {
+                continue;
+            }
+            let Some(record) = read_json::<StartedRecord>(&path) else {
+                continue;
+            };
+            // The crash came after the result was persisted but before the started file went
+            if finished.contains(&record.assignment_id) {
+                remove_if_exists(&path)?;
+                continue;
+            }
+            self.write(&record.assignment_id, INTERRUPTED, &record)?;
+            remove_if_exists(&path)?;
+            self.interrupted.lock().unwrap_or_else(|e| e.into_inner()).insert(record.assignment_id.clone(), record.clone());
+            recovered.interrupted.push(record);
+        }
+        Ok(recovered)
+    }
+
+    pub fn interrupted(&self, assignment_id: &str) -> Option<StartedRecord> {
+        self.interrupted.lock().unwrap_or_else(|e| e.into_inner()).get(assignment_id).cloned()
+    }
+
+    /// Ids can be long or hold path separators, so files are named by a digest instead.
+    fn path(&self, assignment_id: &str, stage: &str) -> PathBuf {
+        let digest = Sha256::digest(assignment_id.as_bytes());
+        let name: String = digest.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.{}", name, stage))
+    }
+
+    fn write<T: Serialize>(&self, assignment_id: &str, stage: &str, record: &T) -> io::Result<()> {
+        let path = self.path(assignment_id, stage);
+        let tmp = path.with_extension(format!("{}.tmp", stage));
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(record).map_err(io::Error::other)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+/// Republishes the results a previous run persisted but never confirmed, and logs the
+/// assignments it was running when it stopped; returns how many results went out.
+pub async fn recover(outbox: &Outbox, publisher: &dyn ResultPublisher, config: &Config, metrics: &Metrics, logger: &Logger) -> usize {
+    let recovered = match outbox.recover() {
+        Ok(recovered) => recovered,
+        Err(e) => {
+            logger.error("Failed to read the outbox", Some(&json!({"error": e.to_string()})));
+            return 0;
+        }
+    };
+    for record in &recovered.interrupted {
+        logger.warn("Assignment was running when the worker stopped; redeliveries get POSSIBLY_EXECUTED", Some(&json!({
+            "assignment_id": record.assignment_id,
+            "job_type": record.job_type,
+            "tenant_id": record.tenant_id,
+            "started_at": record.started_at
+        })));
+    }
+    let mut republished = 0;
+    for pending in recovered.results {
+        let (headers, payload) = compression::encode_for_publish(&pending.payload, config.envelope_compress_threshold_bytes);
+        match publisher.publish(&pending.subject, headers.as_ref(), &payload).await {
+            Ok(()) => {
+                republished += 1;
+                metrics.outbox_results_republished_total.inc();
+                if let Err(e) = outbox.published(&pending.assignment_id) {
+                    logger.error("Failed to clear outbox entry", Some(&json!({"assignment_id": pending.assignment_id, "error": e.to_string()})));
+                }
+            }
+            // Left in place for the next startup
+            Err(e) => logger.error("Failed to republish result from the outbox", Some(&json!({
+                "assignment_id": pending.assignment_id,
+                "error": e.error
+            }))),
+        }
+    }
+    if republished > 0 {
+        logger.info("Republished results from the outbox", Some(&json!({"count": republished})));
+    }
+    republished
+}
+
+/// Outbox files in `dir` with their stage, leaving out half-written temporaries.
+fn entries(dir: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(stage) = path.extension().and_then(|e| e.to_str()) {
+            if matches!(stage, STARTED | RESULT | INTERRUPTED) {
+                entries.push((path.clone(), stage.to_string()));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Job;
+
+    fn assignment(id: &str) -> ExecAssignment {
+        ExecAssignment {
+            version: "1.0".to_string(),
+            assignment_id: id.to_string(),
+            request_id: "r1".to_string(),
+            tenant_id: "t1".to_string(),
+            job: Job { r#type: "sql".to_string(), payload: json!({"query": "INSERT INTO t VALUES (1)"}) },
+            trace_id: None,
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_published_results_leave_nothing_behind() {
+        let dir = temp_dir();
+        let outbox = Outbox::open(&dir).unwrap();
+        outbox.started(&assignment("a/1"), "results").unwrap();
+        outbox.finished("a/1", "results", b"envelope").unwrap();
+        outbox.published("a/1").unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_crash_before_publish_recovers_the_result() {
+        let dir = temp_dir();
+        let outbox = Outbox::open(&dir).unwrap();
+        outbox.started(&assignment("a1"), "results").unwrap();
+        outbox.finished("a1", "results", b"envelope").unwrap();
+        drop(outbox);
+
+        let outbox = Outbox::open(&dir).unwrap();
+        let recovered = outbox.recover().unwrap();
+        assert_eq!(recovered.results, vec![PendingResult { assignment_id: "a1".to_string(), subject: "results".to_string(), payload: b"envelope".to_vec() }]);
+        assert!(recovered.interrupted.is_empty());
+        assert!(outbox.interrupted("a1").is_none());
+        outbox.published("a1").unwrap();
+        assert!(outbox.recover().unwrap().results.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_crash_during_execution_marks_the_assignment_interrupted() {
+        let dir = temp_dir();
+        let outbox = Outbox::open(&dir).unwrap();
+        outbox.started(&assignment("a1"), "results").unwrap();
+        drop(outbox);
+
+        let outbox = Outbox::open(&dir).unwrap();
+        let recovered = outbox.recover().unwrap();
+        assert!(recovered.results.is_empty());
+        assert_eq!(recovered.interrupted.len(), 1);
+        assert_eq!(recovered.interrupted[0].job_type, "sql");
+        assert!(outbox.interrupted("a1").is_some());
+        drop(outbox);
+
+        // The marker outlives further restarts
+        let outbox = Outbox::open(&dir).unwrap();
+        assert!(outbox.recover().unwrap().interrupted.is_empty());
+        assert_eq!(outbox.interrupted("a1").unwrap().result_subject, "results");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
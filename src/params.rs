@@ -0,0 +1,215 @@
+use crate::observability::pii::REDACTED;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Error code for a `${params.*}` or `${secret.*}` reference that doesn't resolve.
+pub const UNKNOWN_PARAM: &str = "UNKNOWN_PARAM";
+
+/// Payload key that turns substitution off: `true` for the whole payload, or a list of
+/// top-level fields to leave verbatim.
+pub const NO_SUBSTITUTION_KEY: &str = "no_substitution";
+
+/// `WORKER_PARAMS_JSON` values substituted into job payloads before dispatch.
+///
+/// String values anywhere in a payload may reference `${params.name}`, or `${secret.NAME}` for
+/// the environment variable `NAME`; `$${...}` stands for a literal `${...}`. Other `${...}`
+/// text is left alone, since scripts and templates use the same syntax.
+#[derive(Debug, Clone)]
+pub struct Params {
+    values: HashMap<String, String>,
+    /// Fail the job on an unknown reference instead of leaving it in place.
+    strict: bool,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self::new(HashMap::new(), true)
+    }
+}
+
+impl Params {
+    pub fn new(values: HashMap<String, String>, strict: bool) -> Self {
+        Self { values, strict }
+    }
+
+    /// Substitutes in place and returns the secret values used, for redaction; the error
+    /// names the first reference that didn't resolve when strict.
+    pub fn substitute(&self, payload: &mut Value) -> Result<Vec<String>, String> {
+        let mut secrets = Vec::new();
+        let skipped: Vec<String> = match payload.get(NO_SUBSTITUTION_KEY) {
+            Some(Value::Bool(true)) => return Ok(secrets),
+            Some(Value::Array(fields)) => fields.iter().filter_map(|f| f.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        };
+        match payload {
+            Value::Object(map) => {
+                for (_, value) in map.iter_mut().filter(|(k, _)| !skipped.contains(k)) {
+                    self.walk(value, &mut secrets)?;
+                }
+            }
+            other => self.walk(other, &mut secrets)?,
+        }
+        Ok(secrets)
+    }
+
+    fn walk(&self, value: &mut Value, secrets: &mut Vec<String>) -> Result<(), String> {
+        match value {
+            Value::String(s) if s.contains("${") => {
+                *s = self.expand(s, secrets)?;
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.walk(item, secrets)?;
+                }
+            }
+            Value::Object(map) => {
+                for item in map.values_mut() {
+                    self.walk(item, secrets)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn expand(&self, input: &str, secrets: &mut Vec<String>) -> Result<String, String> {
+        let mut out = String::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(at) = rest.find("${") {
+            if rest[..at].ends_with('$') {
+                // `$${` is an escaped `${`
+                out.push_str(&rest[..at]);
+                out.push('{');
+                rest = &rest[at + 2..];
+                continue;
+            }
+            out.push_str(&rest[..at]);
+            let Some(len) = rest[at..].find('}') else {
+                out.push_str(&rest[at..]);
+                return Ok(out);
+            };
+            let reference = &rest[at + 2..at + len];
+            let literal = &rest[at..=at + len];
+            let resolved = if let Some(name) = reference.strip_prefix("params.") {
+                Some(self.values.get(name).cloned())
+            } else if let Some(name) = reference.strip_prefix("secret.") {
+                let value = std::env::var(name).ok();
+                if let Some(v) = value.as_ref().filter(|v| !v.is_empty()) {
+                    secrets.push(v.clone());
+                }
+                Some(value)
+            } else {
+                None
+            };
+            match resolved {
+                Some(Some(value)) => out.push_str(&value),
+                Some(None) if self.strict => return Err(format!("Unknown parameter {}", literal)),
+                _ => out.push_str(literal),
+            }
+            rest = &rest[at + len + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// Replaces every occurrence of `secrets` in the strings of `value` with `***`.
+pub fn redact(value: &mut Value, secrets: &[String]) {
+    match value {
+        Value::String(s) => redact_str(s, secrets),
+        Value::Array(items) => items.iter_mut().for_each(|item| redact(item, secrets)),
+        Value::Object(map) => map.values_mut().for_each(|item| redact(item, secrets)),
+        _ => {}
+    }
+}
+
+pub fn redact_str(s: &mut String, secrets: &[String]) {
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        if s.contains(secret.as_str()) {
+            *s = s.replace(secret.as_str(), REDACTED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use serial_test::serial;
+
+    fn params(strict: bool) -> Params {
+        let values = HashMap::from([
+            ("base_url".to_string(), "https://api.internal".to_string()),
+            ("bucket".to_string(), "prod-blobs".to_string()),
+        ]);
+        Params::new(values, strict)
+    }
+
+    #[test]
+    fn test_nested_objects_and_arrays() {
+        let mut payload = json!({
+            "url": "${params.base_url}/v1/items",
+            "headers": {"x-bucket": "${params.bucket}"},
+            "steps": [{"path": "${params.bucket}/${params.bucket}"}, 42, "plain"],
+            "count": 3
+        });
+        assert!(params(true).substitute(&mut payload).unwrap().is_empty());
+        assert_eq!(payload, json!({
+            "url": "https://api.internal/v1/items",
+            "headers": {"x-bucket": "prod-blobs"},
+            "steps": [{"path": "prod-blobs/prod-blobs"}, 42, "plain"],
+            "count": 3
+        }));
+    }
+
+    #[test]
+    fn test_escapes_and_foreign_references_stay_literal() {
+        let mut payload = json!({
+            "text": "$${params.base_url} costs $5",
+            "code": "const s = `${name}`;",
+            "open": "${params.base_url"
+        });
+        params(true).substitute(&mut payload).unwrap();
+        assert_eq!(payload["text"], "${params.base_url} costs $5");
+        assert_eq!(payload["code"], "const s = `${name}`;");
+        assert_eq!(payload["open"], "${params.base_url");
+    }
+
+    #[test]
+    fn test_strict_and_lenient_unknown_params() {
+        let mut payload = json!({"url": "${params.missing}/x"});
+        assert_eq!(params(true).substitute(&mut payload.clone()).unwrap_err(), "Unknown parameter ${params.missing}");
+        params(false).substitute(&mut payload).unwrap();
+        assert_eq!(payload["url"], "${params.missing}/x");
+    }
+
+    #[test]
+    fn test_no_substitution_opt_out() {
+        let mut payload = json!({"no_substitution": true, "body": "${params.bucket}"});
+        params(true).substitute(&mut payload).unwrap();
+        assert_eq!(payload["body"], "${params.bucket}");
+
+        let mut payload = json!({"no_substitution": ["body"], "body": "${params.missing}", "url": "${params.base_url}"});
+        params(true).substitute(&mut payload).unwrap();
+        assert_eq!(payload["body"], "${params.missing}");
+        assert_eq!(payload["url"], "https://api.internal");
+    }
+
+    #[test]
+    #[serial]
+    fn test_secrets_resolve_from_env_and_redact() {
+        std::env::set_var("PARAMS_TEST_API_KEY", "sk-live-123");
+        let mut payload = json!({"headers": {"authorization": "Bearer ${secret.PARAMS_TEST_API_KEY}"}});
+        let secrets = params(true).substitute(&mut payload).unwrap();
+        std::env::remove_var("PARAMS_TEST_API_KEY");
+        assert_eq!(payload["headers"]["authorization"], "Bearer sk-live-123");
+        assert_eq!(secrets, vec!["sk-live-123"]);
+
+        let mut output = json!({"echo": ["Bearer sk-live-123"], "n": 1});
+        redact(&mut output, &secrets);
+        assert_eq!(output, json!({"echo": ["Bearer ***"], "n": 1}));
+
+        let mut payload = json!({"key": "${secret.PARAMS_TEST_API_KEY}"});
+        assert!(params(true).substitute(&mut payload).unwrap_err().contains("secret.PARAMS_TEST_API_KEY"));
+    }
+}
@@ -0,0 +1,2041 @@
+use crate::compression;
+use crate::concurrency::{ConcurrencyLimit, KeyedLocks};
+use crate::config::{self, Config, DrainPolicy, TenantRejectPolicy};
+use crate::dlq::DlqWriter;
+use crate::error::{classify_nats_publish, WorkerError};
+use crate::executor::{self, Executor};
+use crate::inflight::InflightTracker;
+use crate::history::TaskHistory;
+use crate::receipts::{self, Receipts};
+use crate::recorder::Recorder;
+use crate::idempotency::IdempotencyCache;
+use crate::outbox::{self, Outbox};
+use crate::resources::{ResourceMonitor, WORKER_RESOURCE_PRESSURE};
+use crate::result_cache::{CachedResult, ResultCache};
+use crate::observability::{Logger, metrics::{Metrics, TaskTimings}, telemetry};
+use crate::protocol::{self, ExecAssignment, ExecStatus, EventEnvelopeV1, AssignmentDecodeError, EnvelopeKind, IncomingAssignment, TaskState, DeadLetter, DeadLetterReason, AssignmentValidator, BatchSummary, map_status_to_task_state};
+use crate::result_queue::{QueuedResult, ResultQueue};
+use crate::signing::EnvelopeSigner;
+use arc_swap::ArcSwap;
+use async_nats::HeaderMap;
+use bytes::Bytes;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::time::{Duration, Instant};
+use tokio::task::{JoinError, JoinSet};
+use tracing::Instrument;
+
+/// The "Backpressure" error is logged at most this often.
+pub const BACKPRESSURE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tasks that may sit in the `JoinSet` after releasing their permit but before they are reaped.
+pub const SPAWN_BUFFER: usize = 8;
+
+/// A publish that did not reach NATS: the client's error text plus its classification.
+#[derive(Debug, Clone)]
+pub struct PublishFailure {
+    pub error: String,
+    pub classified: WorkerError,
+}
+
+/// Where results, dead letters and batch summaries go; NATS in production, memory in tests.
+pub trait ResultPublisher: Send + Sync {
+    fn publish<'a>(&'a self, subject: &'a str, headers: Option<&'a HeaderMap>, payload: &'a [u8]) -> BoxFuture<'a, Result<(), PublishFailure>>;
+
+    /// Largest message the server accepts, if known. The server drops the connection on a
+    /// larger one, so callers check before publishing rather than retrying.
+    fn max_payload(&self) -> Option<usize> {
+        None
+    }
+}
+
+pub struct NatsPublisher(pub async_nats::Client);
+
+impl ResultPublisher for NatsPublisher {
+    fn publish<'a>(&'a self, subject: &'a str, headers: Option<&'a HeaderMap>, payload: &'a [u8]) -> BoxFuture<'a, Result<(), PublishFailure>> {
+        Box::pin(async move {
+            let published = match headers {
+                Some(h) => self.0.publish_with_headers(subject.to_string(), h.clone(), payload.to_vec().into()).await,
+                None => self.0.publish(subject.to_string(), payload.to_vec().into()).await,
+            };
+            published.map_err(|e| PublishFailure { error: e.to_string(), classified: classify_nats_publish(&e) })
+        })
+    }
+
+    fn max_payload(&self) -> Option<usize> {
+        Some(self.0.server_info().max_payload).filter(|max| *max > 0)
+    }
+}
+
+/// Everything `process_message` needs; cloned into each spawned task.
+#[derive(Clone)]
+pub struct PipelineDeps {
+    pub config: Arc<Config>,
+    pub executor: Executor,
+    pub publisher: Arc<dyn ResultPublisher>,
+    pub dedup: Arc<Mutex<Dedup>>,
+    pub metrics: Arc<Metrics>,
+    pub logger: Logger,
+    pub signer: Option<EnvelopeSigner>,
+    pub validator: Arc<AssignmentValidator>,
+    pub dlq_writer: Arc<DlqWriter>,
+    pub results: ResultQueue,
+    pub concurrency: Arc<ConcurrencyLimit>,
+    /// Serializes assignments sharing a `concurrency_key`.
+    pub key_locks: Arc<KeyedLocks>,
+    /// `OUTBOX_ENABLED`; `None` keeps nothing on disk.
+    pub outbox: Option<Arc<Outbox>>,
+    /// New assignments are turned away while it reports pressure; `None` never does.
+    pub resources: Option<Arc<ResourceMonitor>>,
+    pub inflight: Arc<InflightTracker>,
+    pub history: Arc<TaskHistory>,
+    pub result_cache: Arc<ResultCache>,
+    /// Answers controller retries carrying an `idempotency_key` that already ran.
+    pub idempotency: Arc<IdempotencyCache>,
+    /// `RECORD_DIR`; `None` records nothing.
+    pub recorder: Option<Arc<Recorder>>,
+    /// `RECEIPTS_ENABLED`; `None` emits no receipts.
+    pub receipts: Option<Arc<Receipts>>,
+    /// May hold `{tenant_id}`, `{job_type}` and `{flow_id}`; see `result_subject_for`.
+    pub result_subject: String,
+    /// Set on shutdown or admin drain; assignments seen afterwards follow `DRAIN_POLICY`.
+    pub draining: Arc<AtomicBool>,
+    /// `TENANT_ALLOWLIST`, swapped on reload; empty serves every tenant.
+    pub tenant_allowlist: Arc<ArcSwap<Vec<String>>>,
+    pub backpressure_log: Arc<LogThrottle>,
+}
+
+impl PipelineDeps {
+    /// The result subject template filled in for one assignment; a shadow canary's results go
+    /// to `CAF_SHADOW_RESULT_SUBJECT` instead.
+    pub fn result_subject_for(&self, assignment: &ExecAssignment) -> String {
+        let template = if self.config.canary_shadow { &self.config.caf_shadow_result_subject } else { &self.result_subject };
+        config::expand_subject(template, &assignment.tenant_id, &assignment.job.r#type, assignment.flow_id.as_deref())
+    }
+}
+
+/// Lets a log line through at most once per `interval`, counting the ones it held back.
+pub struct LogThrottle {
+    interval: Duration,
+    state: Mutex<(Option<Instant>, u64)>,
+}
+
+impl LogThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, state: Mutex::new((None, 0)) }
+    }
+
+    /// `Some(lines suppressed since the last one)` when a line may be logged now.
+    pub fn ready(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (last, suppressed) = &mut *state;
+        if last.is_some_and(|at| at.elapsed() < self.interval) {
+            *suppressed += 1;
+            return None;
+        }
+        *last = Some(Instant::now());
+        Some(std::mem::take(suppressed))
+    }
+}
+
+/// Decodes one delivery on `subject` and spawns a task per assignment it carries into `tasks`.
+///
+/// Returns once every assignment has a permit (or was rejected); the caller owns `tasks` and
+/// reaps them with `reap`.
+pub async fn process_message(deps: &PipelineDeps, tasks: &mut JoinSet<()>, msg_payload: Bytes, headers: Option<&HeaderMap>, subject: &str) {
+    let config = &deps.config;
+    let assign_logger = &deps.logger;
+    let metrics = &deps.metrics;
+    let publisher = deps.publisher.as_ref();
+    let received_at = std::time::Instant::now();
+    // 0. Refuse oversized messages before touching their contents
+    if msg_payload.len() > config.assignment_max_bytes {
+        metrics.payload_rejected_total.inc();
+        assign_logger.error("Assignment payload too large", Some(&json!({
+            "subject": subject,
+            "len": msg_payload.len(),
+            "max_bytes": config.assignment_max_bytes
+        })));
+        let dlq = DeadLetter::new(DeadLetterReason::PayloadTooLarge, json!({"subject": subject, "len": msg_payload.len(), "max_bytes": config.assignment_max_bytes}))
+            .with_worker(&config.worker_id);
+        publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+        return;
+    }
+
+    // 0a. Inflate compressed payloads
+    let payload = match compression::decode_incoming(headers, &msg_payload, config.envelope_max_inflated_bytes) {
+        Ok(p) => p,
+        Err(e) => {
+            assign_logger.error("Failed to decompress payload", Some(&json!({"error": e, "subject": subject})));
+            let dlq = DeadLetter::new(DeadLetterReason::DecompressError, json!({"subject": subject, "len": msg_payload.len(), "error": e}))
+                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                .with_error(e.clone())
+                .with_worker(&config.worker_id);
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            return;
+        }
+    };
+
+    // 1. Parse
+    let mut batch: Option<Arc<BatchTracker>> = None;
+    let assignments: Vec<ExecAssignment> = match protocol::decode_assignment(&payload, &config.envelope_accept_versions, config.accept_bare_assignments) {
+        Ok(IncomingAssignment::Envelope(env)) => {
+            if matches!(env.kind, EnvelopeKind::ExecAssign | EnvelopeKind::ExecAssignBatch) {
+                if let Some(signer) = &deps.signer {
+                    if config.envelope_require_signature || env.signature.is_some() {
+                        if let Err(e) = signer.verify(&env.data, env.signature.as_deref()) {
+                            assign_logger.error("Envelope signature rejected", Some(&json!({
+                                "subject": subject,
+                                "error": format!("{:?}", e)
+                            })));
+                            metrics.signature_failures_total.inc();
+                            let dlq = DeadLetter::new(DeadLetterReason::SignatureInvalid, json!({"subject": subject, "len": msg_payload.len(), "error": format!("{:?}", e)}))
+                                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                                .with_error(format!("envelope signature rejected: {:?}", e))
+                                .with_worker(&config.worker_id);
+                            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+                            return;
+                        }
+                    }
+                }
+            }
+            match env.kind {
+                EnvelopeKind::ExecAssign => {
+                    match serde_json::from_value::<ExecAssignment>(env.data) {
+                        Ok(a) => vec![a],
+                        Err(e) => {
+                            assign_logger.error("Failed to decode envelope data", Some(&json!({"error": e.to_string()})));
+                            let dlq = DeadLetter::new(DeadLetterReason::DecodeError, json!({"subject": subject, "len": msg_payload.len()}))
+                                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                                .with_error(e.to_string())
+                                .with_worker(&config.worker_id);
+                            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+                            return;
+                        }
+                    }
+                }
+                EnvelopeKind::ExecAssignBatch => {
+                    match protocol::decode_batch(env.data, config.batch_max_size) {
+                        Ok(decoded) => {
+                            let tracker = Arc::new(BatchTracker::new(
+                                decoded.batch_id.clone(),
+                                decoded.assignments.len() + decoded.errors.len(),
+                                decoded.summary,
+                            ));
+                            for (index, error) in &decoded.errors {
+                                assign_logger.error("Failed to decode batch entry", Some(&json!({
+                                    "batch_id": decoded.batch_id,
+                                    "index": index,
+                                    "error": error
+                                })));
+                                let dlq = DeadLetter::new(DeadLetterReason::DecodeError, json!({"subject": subject, "batch_id": decoded.batch_id, "index": index}))
+                                    .with_error(error.clone())
+                                    .with_worker(&config.worker_id);
+                                publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+                                // No assignment to fill the template from
+                                let subject = config::expand_subject(&deps.result_subject, "", "", None);
+                                finish_batch_entry(&tracker, None, publisher, &subject, deps.signer.as_ref()).await;
+                            }
+                            batch = Some(tracker);
+                            decoded.assignments
+                        }
+                        Err(e) => {
+                            assign_logger.error("Failed to decode batch", Some(&json!({"error": e, "subject": subject})));
+                            let dlq = DeadLetter::new(DeadLetterReason::DecodeError, json!({"subject": subject, "len": msg_payload.len(), "error": e}))
+                                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                                .with_error(e.clone())
+                                .with_worker(&config.worker_id);
+                            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+                            return;
+                        }
+                    }
+                }
+                kind => {
+                    assign_logger.error("Unexpected envelope kind", Some(&json!({"kind": kind.as_str(), "subject": subject})));
+                    metrics.unexpected_envelope_total.with_label_values(&[kind.as_str()]).inc();
+                    let dlq = DeadLetter::new(DeadLetterReason::UnexpectedKind, json!({"subject": subject, "kind": kind.as_str(), "len": msg_payload.len()}))
+                        .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                        .with_error(format!("envelope kind {} is not accepted on {}", kind.as_str(), subject))
+                        .with_worker(&config.worker_id);
+                    publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+                    return;
+                }
+            }
+        }
+        Err(AssignmentDecodeError::UnsupportedVersion(version)) => {
+            assign_logger.error("Unsupported envelope version", Some(&json!({"subject": subject, "version": version})));
+            let dlq = DeadLetter::new(DeadLetterReason::UnsupportedVersion, json!({
+                "subject": subject,
+                "version": version,
+                "accepted": config.envelope_accept_versions
+            }))
+                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                .with_error(format!("envelope version {:?} is not accepted", version))
+                .with_worker(&config.worker_id);
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            return;
+        }
+        Ok(IncomingAssignment::Bare(_)) if config.envelope_require_signature => {
+            // A bare assignment cannot carry a signature
+            assign_logger.error("Unsigned bare assignment rejected", Some(&json!({"subject": subject})));
+            metrics.signature_failures_total.inc();
+            let dlq = DeadLetter::new(DeadLetterReason::SignatureInvalid, json!({"subject": subject, "len": msg_payload.len(), "error": "Missing"}))
+                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                .with_error("bare assignment cannot carry a signature and ENVELOPE_REQUIRE_SIGNATURE is on".to_string())
+                .with_worker(&config.worker_id);
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            return;
+        }
+        Ok(IncomingAssignment::Bare(a)) => {
+            metrics.bare_assignment_total.inc();
+            assign_logger.warn("Bare assignment accepted; publish an exec_assign envelope instead, bare assignments are deprecated", Some(&json!({
+                "subject": subject,
+                "assignment_id": a.assignment_id
+            })));
+            vec![*a]
+        }
+        Err(AssignmentDecodeError::EnvelopeRequired) => {
+            assign_logger.error("Bare assignment rejected, ACCEPT_BARE_ASSIGNMENTS is off", Some(&json!({"subject": subject})));
+            let dlq = DeadLetter::new(DeadLetterReason::EnvelopeRequired, json!({"subject": subject, "len": msg_payload.len()}))
+                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                .with_error("bare assignments are not accepted; wrap it in an exec_assign envelope".to_string())
+                .with_worker(&config.worker_id);
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            return;
+        }
+        Err(AssignmentDecodeError::Malformed(e)) => {
+            assign_logger.error("Failed to parse assignment", Some(&json!({
+                "error": e,
+                "subject": subject,
+                "payload_len": msg_payload.len()
+            })));
+            let dlq = DeadLetter::new(DeadLetterReason::ParseError, json!({"subject": subject, "len": msg_payload.len()}))
+                .with_original(subject, &msg_payload, config.dlq_max_payload_bytes)
+                .with_error(e)
+                .with_worker(&config.worker_id);
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            return;
+        }
+    };
+
+    let trace_parent = telemetry::parent_context(headers);
+    let total = assignments.len();
+    for (index, mut assignment) in assignments.into_iter().enumerate() {
+        // Without one, nothing downstream of this job could be correlated
+        let trace_generated = config.generate_trace_ids && assignment.trace_id.is_none();
+        if trace_generated {
+            assignment.trace_id = Some(telemetry::generate_trace_id());
+        }
+        let task_logger = assign_logger.with_fields(json!({
+            "assignment_id": assignment.assignment_id,
+            "request_id": assignment.request_id,
+            "trace_id": assignment.trace_id,
+            "tenant_id": assignment.tenant_id,
+            "job_type": assignment.job.r#type
+        }));
+        let result_subject = deps.result_subject_for(&assignment);
+        // 1a. Validate before consuming a permit
+        if let Err(violations) = deps.validator.validate(&assignment) {
+            task_logger.error("Assignment failed validation", Some(&json!({
+                "violations": violations
+            })));
+            metrics.validation_failures_total.inc();
+            let dlq = DeadLetter::new(DeadLetterReason::ValidationError, json!({
+                "subject": subject,
+                "len": msg_payload.len(),
+                "assignment_id": assignment.assignment_id,
+                "violations": violations
+            }))
+                .with_error(format!("assignment failed validation: {}", violations.join("; ")))
+                .with_worker(&config.worker_id)
+                .with_tenant(Some(&assignment.tenant_id));
+            // Replaying a whole batch would run its valid entries again, so only a lone assignment keeps its original
+            let dlq = if batch.is_none() { dlq.with_original(subject, &msg_payload, config.dlq_max_payload_bytes) } else { dlq };
+            publish_deadletter(&dlq, &deps.dlq_writer, config, publisher, metrics).await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1b. Job types this worker is not meant to run go straight back
+        if !config.accepts_job_type(&assignment.job.r#type) {
+            metrics.unsupported_job_type_total.inc();
+            let target = match &config.caf_unsupported_subject {
+                Some(subject) => HandBack::Requeue(subject),
+                None => HandBack::Reject("UNSUPPORTED_JOB_TYPE", "Job type is not accepted by this worker"),
+            };
+            hand_back(deps, &assignment, &task_logger, target, "unsupported_job_type").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1c. Tenants this worker doesn't serve, checked against the reloadable allowlist
+        let tenant_allowlist = deps.tenant_allowlist.load();
+        if !tenant_allowlist.is_empty() && !tenant_allowlist.contains(&assignment.tenant_id) {
+            metrics.tenant_rejected_total.inc();
+            task_logger.warn("Tenant not served by this worker, not executed", Some(&json!({
+                "audit": "tenant_rejected",
+                "job_type": assignment.job.r#type,
+                "policy": config.tenant_reject_policy
+            })));
+            let target = match (config.tenant_reject_policy, &config.tenant_overflow_subject) {
+                (TenantRejectPolicy::Requeue, Some(subject)) => HandBack::Requeue(subject),
+                _ => HandBack::Fail("TENANT_NOT_ALLOWED", "Tenant is not served by this worker"),
+            };
+            hand_back(deps, &assignment, &task_logger, target, "tenant_not_allowed").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1d. Nothing new starts once draining
+        if deps.draining.load(Ordering::SeqCst) {
+            metrics.drain_rejected_total.inc();
+            let target = match config.drain_policy {
+                DrainPolicy::Requeue => HandBack::Requeue(&config.caf_requeue_subject),
+                DrainPolicy::Reject => HandBack::Reject("WORKER_DRAINING", "Worker is draining and did not start the assignment"),
+            };
+            hand_back(deps, &assignment, &task_logger, target, "draining").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1e. Disk or memory is running out; what is already running may finish
+        if deps.resources.as_ref().is_some_and(|r| r.under_pressure()) {
+            metrics.resource_pressure_rejected_total.inc();
+            hand_back(deps, &assignment, &task_logger, HandBack::Reject(WORKER_RESOURCE_PRESSURE, "Worker is low on disk or memory and did not start the assignment"), "resource_pressure").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1f. Running when a previous process died: the side effects may already have happened
+        if let Some(record) = deps.outbox.as_ref().and_then(|outbox| outbox.interrupted(&assignment.assignment_id)) {
+            metrics.possibly_executed_total.inc();
+            task_logger.warn("Assignment was interrupted by a restart, not executing it again", Some(&json!({"started_at": record.started_at})));
+            hand_back(deps, &assignment, &task_logger, HandBack::Fail(outbox::POSSIBLY_EXECUTED, "Assignment was running when the worker stopped and may have executed"), "possibly_executed").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1g. A canary runs only its sample; redeliveries of an id always get the same answer
+        if !canary_sampled(&assignment.assignment_id, config.canary_sample_rate) {
+            metrics.canary_skipped_total.inc();
+            hand_back(deps, &assignment, &task_logger, HandBack::Reject("CANARY_SKIPPED", "Canary worker did not sample the assignment"), "canary_skipped").await;
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1h. Dedup at-least-once; validation already ran, so empty or junk ids never get here
+        let duplicate = {
+            let mut dedup = deps.dedup.lock().unwrap_or_else(|e| e.into_inner());
+            let seen = dedup.contains(&assignment.assignment_id);
+            if !seen {
+                dedup.insert(assignment.assignment_id.clone());
+            }
+            seen
+        };
+        if duplicate {
+            // The controller resends when it never saw a result, so answer with the one it missed
+            match deps.result_cache.get(&assignment.assignment_id) {
+                Some(CachedResult::Envelope { subject: cached_subject, payload }) => {
+                    let (headers, payload) = compression::encode_for_publish(&payload, config.envelope_compress_threshold_bytes);
+                    match publisher.publish(&cached_subject, headers.as_ref(), &payload).await {
+                        Ok(()) => {
+                            metrics.duplicate_results_republished_total.inc();
+                            task_logger.info("Duplicate assignment answered with its cached result", Some(&json!({"subject": cached_subject})));
+                        }
+                        Err(e) => task_logger.error("Failed to republish cached result", Some(&json!({"error": e.error}))),
+                    }
+                }
+                Some(CachedResult::TooLarge) => {
+                    task_logger.info("Duplicate of a completed assignment whose result was too large to cache, skipping", None);
+                }
+                None if deps.inflight.get(&assignment.assignment_id).is_some() => {
+                    task_logger.info("Duplicate of an assignment still in flight, skipping", None);
+                }
+                None => task_logger.debug("Duplicate assignment detected, skipping", None),
+            }
+            if let Some(tracker) = &batch {
+                finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+            }
+            continue;
+        }
+
+        // 1i. A retry of an operation that already ran under another assignment_id
+        if let Some(mut result) = deps.idempotency.get(&assignment) {
+            result.trace_generated = trace_generated.then_some(true);
+            metrics.idempotent_hits_total.inc();
+            task_logger.info("Assignment answered from the idempotency cache", Some(&json!({"idempotency_key": assignment.idempotency_key})));
+            let envelope = EventEnvelopeV1::wrap_result(&result).signed(deps.signer.as_ref());
+            if deps.result_cache.is_enabled() {
+                if let Ok(payload) = protocol::encode_envelope(&envelope) {
+                    deps.result_cache.insert(&result.assignment_id, &result_subject, payload);
+                }
+            }
+            deps.results.enqueue(QueuedResult {
+                subject: result_subject,
+                envelope,
+                result,
+                logger: task_logger,
+                timings: TaskTimings::new(received_at),
+                batch: batch.clone(),
+                span: tracing::Span::current(),
+                outbox: None,
+            }).await;
+            continue;
+        }
+
+        task_logger.debug("Task state changed", Some(&json!({
+            "state": serde_json::to_string(&TaskState::Queued).unwrap_or_default()
+        })));
+
+        // Backpressure via the (reloadable) concurrency limit
+        let permit = match deps.concurrency.try_acquire() {
+            Some(p) => p,
+            None => {
+                metrics.backpressure_waits_total.inc();
+                metrics.batch_remaining.set((total - index) as i64);
+                if let Some(suppressed) = deps.backpressure_log.ready() {
+                    task_logger.error("Backpressure: concurrency limit reached", Some(&json!({
+                        "max_concurrency": deps.concurrency.limit(),
+                        "suppressed": suppressed
+                    })));
+                }
+                let wait_started = Instant::now();
+                let acquired = match config.backpressure_max_wait_ms {
+                    Some(ms) => tokio::time::timeout(Duration::from_millis(ms), deps.concurrency.acquire()).await.ok(),
+                    // Wait for a permit to avoid dropping messages
+                    None => Some(deps.concurrency.acquire().await),
+                };
+                metrics.backpressure_wait_seconds.observe(wait_started.elapsed().as_secs_f64());
+                metrics.batch_remaining.set(0);
+                match acquired {
+                    Some(p) => p,
+                    None => {
+                        metrics.backpressure_rejected_total.inc();
+                        // Never ran, so a redelivery must not be skipped as a duplicate
+                        deps.dedup.lock().unwrap_or_else(|e| e.into_inner()).remove(&assignment.assignment_id);
+                        hand_back(deps, &assignment, &task_logger, HandBack::Reject("WORKER_OVERLOADED", "No concurrency permit became free in time"), "overloaded").await;
+                        if let Some(tracker) = &batch {
+                            finish_batch_entry(tracker, None, publisher, &result_subject, deps.signer.as_ref()).await;
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        metrics.task_queue_wait_seconds.observe(received_at.elapsed().as_secs_f64());
+        let inflight_guard = deps.inflight.start(&assignment.assignment_id, &assignment.job.r#type, &assignment.tenant_id, assignment.trace_id.clone());
+        let in_use_after_acquire = deps.concurrency.in_use();
+        metrics.tasks_in_progress.set(in_use_after_acquire as i64);
+
+        let (timeout, timeout_source) = deps.executor.resolve_timeout(&assignment.job);
+        let started_at = chrono::Utc::now();
+        let queue_latency_ms = assignment.queue_latency_ms(started_at).map(|ms| {
+            if ms < 0 {
+                task_logger.debug("Assignment published_at is ahead of the worker clock", Some(&json!({
+                    "published_at": assignment.published_at,
+                    "skew_ms": -ms
+                })));
+            }
+            ms.max(0) as u64
+        });
+        if let Some(ms) = queue_latency_ms {
+            metrics.task_queue_latency_seconds.observe(ms as f64 / 1000.0);
+        }
+        task_logger.debug("Task state changed", Some(&json!({
+            "state": serde_json::to_string(&TaskState::Running).unwrap_or_default(),
+            "timeout_ms": timeout.as_millis() as u64,
+            "timeout_source": timeout_source.as_str(),
+            "queue_latency_ms": queue_latency_ms
+        })));
+
+        task_logger.info("Processing assignment", None);
+        metrics.task_received.inc();
+
+        // Each live task holds a permit, so only finished-but-unreaped ones can push past this
+        let cap = deps.concurrency.capacity() + SPAWN_BUFFER;
+        while tasks.len() >= cap {
+            match tasks.join_next().await {
+                Some(joined) => reap(joined, deps),
+                None => break,
+            }
+        }
+        debug_assert!(tasks.len() < cap);
+
+        let deps = deps.clone();
+        let batch = batch.clone();
+        let parent = match (&trace_parent, &assignment.trace_id) {
+            (None, Some(trace_id)) if trace_generated => telemetry::generated_parent(trace_id),
+            _ => trace_parent.clone(),
+        };
+        let span = telemetry::assignment_span(&assignment, parent);
+
+        tasks.spawn(async move {
+            let PipelineDeps { executor, metrics, signer, concurrency, key_locks, results, history, result_cache, idempotency, outbox, recorder, receipts, .. } = deps;
+            let mut timings = TaskTimings::new(received_at);
+            // 2. Execute
+            let cancel = tokio_util::sync::CancellationToken::new();
+            // Taken before the assignment moves into the handler, so a timeout can still be answered
+            let routing = assignment.without_payload();
+            let recorded = recorder.as_ref().map(|_| assignment.clone());
+            // Hashed as received, before the handler resolves any `${secret}` in it
+            let input_hash = receipts.as_ref().map(|_| receipts::content_hash(&assignment.job.payload));
+            let timed_out = |message: &str| {
+                let elapsed = (chrono::Utc::now() - started_at).to_std().unwrap_or_default();
+                let mut result = protocol::ExecResult::for_failure(&routing, executor.id(), ExecStatus::Timeout, "TIMEOUT", message, elapsed);
+                result.output = Some(json!({"timeout_ms": timeout.as_millis() as u64}));
+                result
+            };
+            // The wait for a shared concurrency key counts against the job's timeout
+            let deadline = tokio::time::Instant::now() + timeout;
+            let key = assignment.concurrency_key.clone();
+            let idempotency_key = assignment.idempotency_key.clone().map(|k| (assignment.tenant_id.clone(), k));
+            let wait_started = std::time::Instant::now();
+            let key_guard = match &key {
+                Some(key) => tokio::time::timeout_at(deadline, key_locks.lock(key)).await.map(Some),
+                None => Ok(None),
+            };
+            let serialization_wait_ms = key.as_ref().map(|_| wait_started.elapsed().as_millis() as u64);
+            if let Some(ms) = serialization_wait_ms.filter(|ms| *ms > 0) {
+                task_logger.debug("Waited for concurrency key", Some(&json!({"concurrency_key": key, "serialization_wait_ms": ms})));
+            }
+            let mut result = match key_guard {
+                Ok(key_guard) => {
+                    if let Some(Err(e)) = outbox.as_ref().map(|outbox| outbox.started(&assignment, &result_subject)) {
+                        task_logger.error("Failed to record assignment in the outbox", Some(&json!({"error": e.to_string()})));
+                    }
+                    let exec_span = tracing::info_span!("execute", job_type = %assignment.job.r#type);
+                    let exec_fut = executor.execute_with_cancel(assignment, cancel.clone()).instrument(exec_span);
+                    let result = match tokio::time::timeout_at(deadline, exec_fut).await {
+                        Ok(res) => res,
+                        Err(_) => {
+                            // Let handlers holding work outside the dropped future know to stop
+                            cancel.cancel();
+                            timed_out("Task timed out")
+                        }
+                    };
+                    drop(key_guard);
+                    result
+                }
+                Err(_) => timed_out("Task timed out waiting for its concurrency key"),
+            };
+            result.queue_latency_ms = queue_latency_ms;
+            result.serialization_wait_ms = serialization_wait_ms;
+            result.trace_generated = trace_generated.then_some(true);
+
+            let final_state = map_status_to_task_state(&result.status);
+            task_logger.debug("Task state changed", Some(&json!({
+                "state": serde_json::to_string(&final_state).unwrap_or_default()
+            })));
+            match final_state {
+                TaskState::Completed => metrics.task_completed.inc(),
+                TaskState::Failed => metrics.task_failed.inc(),
+                TaskState::Timeout => metrics.task_timeout.inc(),
+                _ => {}
+            }
+            metrics.mark_assignment_completed();
+            timings.set_execution(Duration::from_millis(result.latency_ms));
+            telemetry::record_result(&tracing::Span::current(), &result);
+            metrics.observe_task(
+                executor::job_type_label(&result.job_type),
+                result.status.as_str(),
+                result.latency_ms as f64 / 1000.0,
+                result.cost,
+            );
+            metrics.observe_tenant(result.tenant_id.as_deref().unwrap_or_default(), result.status.as_str(), result.latency_ms as f64 / 1000.0);
+            history.record(&result);
+            if let (Some(recorder), Some(recorded)) = (recorder, recorded) {
+                // Best effort and off the task's path; a failed write only costs the recording
+                let (result, metrics, logger) = (result.clone(), metrics.clone(), task_logger.clone());
+                tokio::task::spawn_blocking(move || match recorder.record(&recorded, &result) {
+                    Ok(_) => metrics.recordings_written_total.inc(),
+                    Err(e) => {
+                        metrics.recording_failures_total.inc();
+                        logger.warn("Failed to record assignment", Some(&json!({"error": e.to_string()})));
+                    }
+                });
+            }
+            let receipt = input_hash.map(|input_hash| receipts::receipt(input_hash, &result));
+            if let Some((tenant_id, key)) = &idempotency_key {
+                idempotency.insert(tenant_id, key, &result);
+            }
+
+            if deps.config.canary_shadow {
+                metrics.shadow_results_total.inc();
+            }
+
+            // 3. Hand the result to the publisher task so the permit is free while it retries
+            let envelope = EventEnvelopeV1::wrap_result(&result).signed(signer.as_ref());
+            if result_cache.is_enabled() || outbox.is_some() {
+                if let Ok(payload) = protocol::encode_envelope(&envelope) {
+                    if let Some(Err(e)) = outbox.as_ref().map(|outbox| outbox.finished(&result.assignment_id, &result_subject, &payload)) {
+                        task_logger.error("Failed to persist result in the outbox", Some(&json!({"error": e.to_string()})));
+                    }
+                    result_cache.insert(&result.assignment_id, &result_subject, payload);
+                }
+            }
+            results.enqueue(QueuedResult {
+                subject: result_subject,
+                envelope,
+                result,
+                logger: task_logger,
+                timings,
+                batch,
+                span: tracing::Span::current(),
+                outbox,
+            }).await;
+            drop(inflight_guard);
+            drop(permit);
+            let in_use_after_release = concurrency.in_use();
+            metrics.tasks_in_progress.set(in_use_after_release as i64);
+            // Still part of the task, so a drain waits for the receipt as it does for the result
+            if let (Some(receipts), Some(receipt)) = (receipts, receipt) {
+                receipts.emit(&receipt).await;
+            }
+        }.instrument(span));
+    }
+}
+
+/// Where `hand_back` sends an assignment this worker will not run.
+enum HandBack<'a> {
+    /// Republish the assignment for another worker.
+    Requeue(&'a str),
+    /// Publish a `cancelled` result with this error code and message.
+    Reject(&'static str, &'static str),
+    /// Publish an `error` result with this error code and message.
+    Fail(&'static str, &'static str),
+}
+
+/// Encodes `message` and publishes it once, gzipped above the compression threshold.
+pub async fn publish_encoded<T: Serialize + ?Sized>(publisher: &dyn ResultPublisher, config: &Config, subject: &str, message: &T) -> Result<(), String> {
+    let payload = protocol::encode_envelope(message)?;
+    let (headers, payload) = compression::encode_for_publish(&payload, config.envelope_compress_threshold_bytes);
+    if let Some(max) = publisher.max_payload().filter(|max| payload.len() > *max) {
+        return Err(format!("message of {} bytes exceeds the server max_payload ({})", payload.len(), max));
+    }
+    publisher.publish(subject, headers.as_ref(), &payload).await.map_err(|e| e.error)
+}
+
+/// Returns an assignment to the controller without taking a permit; `why` goes in the log.
+async fn hand_back(deps: &PipelineDeps, assignment: &ExecAssignment, logger: &Logger, target: HandBack<'_>, why: &str) {
+    let config = &deps.config;
+    let result_subject = deps.result_subject_for(assignment);
+    let (subject, envelope) = match target {
+        HandBack::Requeue(subject) => (subject, EventEnvelopeV1::wrap_assignment(assignment)),
+        HandBack::Reject(code, message) => (result_subject.as_str(), EventEnvelopeV1::wrap_result(&protocol::ExecResult::for_failure(assignment, deps.executor.id(), ExecStatus::Cancelled, code, message, Duration::ZERO))),
+        HandBack::Fail(code, message) => (result_subject.as_str(), EventEnvelopeV1::wrap_result(&protocol::ExecResult::for_failure(assignment, deps.executor.id(), ExecStatus::Error, code, message, Duration::ZERO))),
+    };
+    let envelope = envelope.signed(deps.signer.as_ref());
+    match publish_encoded(deps.publisher.as_ref(), config, subject, &envelope).await {
+        Ok(()) => logger.info("Assignment handed back", Some(&json!({
+            "reason": why,
+            "subject": subject
+        }))),
+        Err(e) => logger.error("Failed to hand back assignment", Some(&json!({
+            "reason": why,
+            "subject": subject,
+            "error": e
+        }))),
+    }
+}
+
+/// Whether a canary at `rate` executes `assignment_id`: the id's sha256 picks a stable point
+/// in `[0, 1)`, so every worker and every redelivery decides the same way.
+pub fn canary_sampled(assignment_id: &str, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let digest = Sha256::digest(assignment_id.as_bytes());
+    let point = u64::from_be_bytes(digest[..8].try_into().unwrap_or_default());
+    (point as f64 / (u64::MAX as f64 + 1.0)) < rate
+}
+
+/// Runs deliveries the client already buffered before an unsubscribe took effect through
+/// `process_message`, which turns them away now that `draining` is set.
+pub async fn turn_away_buffered<S>(deps: &PipelineDeps, tasks: &mut JoinSet<()>, subscription: &mut S, deadline: Duration)
+where
+    S: Stream<Item = async_nats::Message> + Unpin,
+{
+    let _ = tokio::time::timeout(deadline, async {
+        while let Some(msg) = subscription.next().await {
+            process_message(deps, tasks, msg.payload, msg.headers.as_ref(), &msg.subject).await;
+        }
+    }).await;
+}
+
+/// Handles one joined task: panics are logged and counted, aborted tasks are expected on drain.
+pub fn reap(joined: Result<(), JoinError>, deps: &PipelineDeps) {
+    if let Err(e) = joined {
+        if e.is_panic() {
+            deps.metrics.handler_panics_total.inc();
+            deps.logger.error("Assignment task panicked", Some(&json!({"error": e.to_string()})));
+        }
+    }
+}
+
+/// Waits up to `deadline` for every task, then aborts the rest. Returns how many were aborted.
+pub async fn drain(tasks: &mut JoinSet<()>, deps: &PipelineDeps, deadline: Duration) -> usize {
+    let joined_all = tokio::time::timeout(deadline, async {
+        while let Some(joined) = tasks.join_next().await {
+            reap(joined, deps);
+        }
+    }).await;
+    if joined_all.is_ok() {
+        return 0;
+    }
+    let aborted = tasks.len();
+    deps.metrics.drain_aborted_total.inc_by(aborted as u64);
+    deps.logger.error("Drain deadline passed, aborting running tasks", Some(&json!({
+        "aborted": aborted,
+        "drain_timeout_ms": deadline.as_millis() as u64
+    })));
+    tasks.abort_all();
+    while let Some(joined) = tasks.join_next().await {
+        reap(joined, deps);
+    }
+    aborted
+}
+
+pub async fn publish_deadletter(dlq: &DeadLetter, writer: &DlqWriter, config: &Config, publisher: &dyn ResultPublisher, metrics: &Metrics) {
+    metrics.dlq_published_total.with_label_values(&[dlq.reason.as_str()]).inc();
+    let id = writer.send(dlq);
+    // Entries left unmarked are picked up by DLQ recovery once NATS is back
+    if send_deadletter_envelope(dlq, config, publisher).await {
+        if let Some(id) = id {
+            writer.mark_published(&id);
+        }
+    }
+}
+
+pub async fn send_deadletter_envelope(dlq: &DeadLetter, config: &Config, publisher: &dyn ResultPublisher) -> bool {
+    let env = EventEnvelopeV1 {
+        version: "v1".to_string(),
+        kind: EnvelopeKind::DeadLetter,
+        data: serde_json::to_value(dlq).unwrap_or(serde_json::Value::Null),
+        signature: None,
+    };
+    // The file copy is already written, so a failure here only leaves the entry pending
+    publish_encoded(publisher, config, &config.dlq_subject_for(dlq.tenant_id.as_deref()), &env).await.is_ok()
+}
+
+/// Counts batch entries as they finish so the last one can publish the summary.
+pub struct BatchTracker {
+    batch_id: String,
+    total: usize,
+    summary: bool,
+    remaining: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+impl BatchTracker {
+    pub fn new(batch_id: String, total: usize, summary: bool) -> Self {
+        Self {
+            batch_id,
+            total,
+            summary,
+            remaining: AtomicUsize::new(total),
+            succeeded: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+        }
+    }
+    /// Records one finished entry (`None` = never executed). Returns true for the last one.
+    pub fn record(&self, status: Option<&ExecStatus>) -> bool {
+        match status {
+            Some(ExecStatus::Success) => self.succeeded.fetch_add(1, Ordering::SeqCst),
+            Some(_) => self.failed.fetch_add(1, Ordering::SeqCst),
+            None => self.rejected.fetch_add(1, Ordering::SeqCst),
+        };
+        self.remaining.fetch_sub(1, Ordering::SeqCst) == 1
+    }
+    pub fn summary(&self) -> BatchSummary {
+        BatchSummary {
+            batch_id: self.batch_id.clone(),
+            total: self.total,
+            succeeded: self.succeeded.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::SeqCst),
+            ts: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+pub async fn finish_batch_entry(tracker: &BatchTracker, status: Option<&ExecStatus>, publisher: &dyn ResultPublisher, subject: &str, signer: Option<&EnvelopeSigner>) {
+    if tracker.record(status) && tracker.summary {
+        let env = EventEnvelopeV1::wrap_batch_summary(&tracker.summary()).signed(signer);
+        if let Ok(payload) = protocol::encode_envelope(&env) {
+            let _ = publisher.publish(subject, None, &payload).await;
+        }
+    }
+}
+
+pub struct Dedup {
+    set: HashSet<String>,
+    queue: VecDeque<String>,
+    capacity: usize,
+}
+
+impl Dedup {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            set: HashSet::new(),
+            queue: VecDeque::new(),
+            capacity,
+        }
+    }
+    pub fn insert(&mut self, key: String) {
+        if self.set.insert(key.clone()) {
+            self.queue.push_back(key);
+            if self.queue.len() > self.capacity {
+                if let Some(old) = self.queue.pop_front() {
+                    self.set.remove(&old);
+                }
+            }
+        }
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.set.contains(key)
+    }
+    pub fn remove(&mut self, key: &str) {
+        if self.set.remove(key) {
+            self.queue.retain(|k| k != key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DeadLetterReason;
+    use crate::rotation::RotationPolicy;
+    use crate::concurrency::KEY_LOCKS_MAX_IDLE;
+    use serial_test::serial;
+
+    /// Records every publish after `delay`; subjects in `failing` return a transient error instead.
+    #[derive(Default)]
+    struct MemoryPublisher {
+        sent: Mutex<Vec<(String, Vec<u8>)>>,
+        failing: Vec<String>,
+        failures: AtomicUsize,
+        delay: Duration,
+        max_payload: Option<usize>,
+    }
+
+    impl MemoryPublisher {
+        fn envelopes(&self, subject: &str) -> Vec<EventEnvelopeV1> {
+            self.sent.lock().unwrap().iter()
+                .filter(|(s, _)| s == subject)
+                .map(|(_, payload)| serde_json::from_slice(payload).unwrap())
+                .collect()
+        }
+    }
+
+    impl ResultPublisher for MemoryPublisher {
+        fn publish<'a>(&'a self, subject: &'a str, _headers: Option<&'a HeaderMap>, payload: &'a [u8]) -> BoxFuture<'a, Result<(), PublishFailure>> {
+            Box::pin(async move {
+                tokio::time::sleep(self.delay).await;
+                if self.failing.iter().any(|s| s == subject) {
+                    self.failures.fetch_add(1, Ordering::SeqCst);
+                    let classified = WorkerError::transient("connection reset").with_code("IO_CONNECTIONRESET");
+                    return Err(PublishFailure { error: "failed to publish message: connection reset".to_string(), classified });
+                }
+                self.sent.lock().unwrap().push((subject.to_string(), payload.to_vec()));
+                Ok(())
+            })
+        }
+
+        fn max_payload(&self) -> Option<usize> {
+            self.max_payload
+        }
+    }
+
+    fn deps(publisher: Arc<MemoryPublisher>) -> (PipelineDeps, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("pipeline-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("WORKER_ID", "pipeline-test");
+        let mut config = Config::from_env().unwrap();
+        std::env::remove_var("WORKER_ID");
+        config.result_publish_max_retries = 2;
+        config.result_publish_backoff_base_ms = 1;
+        config.result_publish_backoff_max_ms = 2;
+        let logger = Logger::new(config.worker_id.clone());
+        let metrics = Arc::new(Metrics::new());
+        let policy = RotationPolicy { max_bytes: u64::MAX, max_rotations: 1, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+        let dlq_path = dir.join("dlq.jsonl").to_string_lossy().to_string();
+        let config = Arc::new(config);
+        let dlq_writer = Arc::new(DlqWriter::new(dlq_path, policy, metrics.clone(), logger.clone()));
+        let deps = PipelineDeps {
+            executor: Executor::new(config.worker_id.clone(), dir.to_string_lossy().to_string()),
+            results: ResultQueue::start(config.clone(), publisher.clone(), dlq_writer.clone(), metrics.clone(), None),
+            publisher,
+            dedup: Arc::new(Mutex::new(Dedup::new(16))),
+            dlq_writer,
+            metrics,
+            logger,
+            signer: None,
+            validator: Arc::new(AssignmentValidator::new()),
+            concurrency: Arc::new(ConcurrencyLimit::new(4)),
+            key_locks: Arc::new(KeyedLocks::new(KEY_LOCKS_MAX_IDLE)),
+            outbox: None,
+            resources: None,
+            inflight: Arc::new(InflightTracker::new()),
+            history: Arc::new(TaskHistory::new(config.task_history_size)),
+            result_cache: Arc::new(ResultCache::new(config.result_cache_size, Duration::from_secs(config.result_cache_ttl_seconds), config.result_cache_max_entry_bytes)),
+            idempotency: Arc::new(IdempotencyCache::new(config.idempotency_cache_size, Duration::from_secs(config.idempotency_cache_ttl_seconds), config.idempotency_cache_failures)),
+            recorder: None,
+            receipts: None,
+            result_subject: config.caf_result_subject.clone(),
+            draining: Arc::new(AtomicBool::new(false)),
+            tenant_allowlist: Arc::new(ArcSwap::from_pointee(config.tenant_allowlist.clone())),
+            backpressure_log: Arc::new(LogThrottle::new(BACKPRESSURE_LOG_INTERVAL)),
+            config,
+        };
+        (deps, dir)
+    }
+
+    fn assignment(id: &str) -> serde_json::Value {
+        json!({
+            "version": "1.0",
+            "assignment_id": id,
+            "request_id": "r1",
+            "tenant_id": "t1",
+            "job": {"type": "echo", "payload": {"hello": "world"}}
+        })
+    }
+
+    async fn deliver(deps: &PipelineDeps, payload: Vec<u8>) {
+        let mut tasks = JoinSet::new();
+        process_message(deps, &mut tasks, Bytes::from(payload), None, &deps.config.caf_assign_subject).await;
+        assert_eq!(drain(&mut tasks, deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+    }
+
+    fn dead_letters(publisher: &MemoryPublisher, deps: &PipelineDeps) -> Vec<DeadLetter> {
+        publisher.envelopes(&deps.config.caf_dlq_subject).into_iter()
+            .map(|env| serde_json::from_value(env.data).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_dedup_basic() {
+        let mut d = Dedup::new(2);
+        d.insert("a".to_string());
+        assert!(d.contains("a"));
+        d.insert("b".to_string());
+        assert!(d.contains("b"));
+        d.insert("c".to_string()); // evicts "a"
+        assert!(!d.contains("a"));
+        assert!(d.contains("b"));
+        assert!(d.contains("c"));
+    }
+
+    #[test]
+    fn test_batch_tracker_mixed_outcomes() {
+        let t = BatchTracker::new("b1".to_string(), 4, true);
+        assert!(!t.record(None)); // decode failure
+        assert!(!t.record(Some(&ExecStatus::Success)));
+        assert!(!t.record(Some(&ExecStatus::Error)));
+        assert!(t.record(Some(&ExecStatus::Timeout)));
+
+        let s = t.summary();
+        assert_eq!(s.batch_id, "b1");
+        assert_eq!(s.total, 4);
+        assert_eq!(s.succeeded, 1);
+        assert_eq!(s.failed, 2);
+        assert_eq!(s.rejected, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_bare_assignment_falls_back_and_duplicates_get_the_cached_result() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        // Not an envelope, so it is parsed as a bare ExecAssignment
+        let bare = serde_json::to_vec(&assignment("a1")).unwrap();
+        deliver(&deps, bare.clone()).await;
+        deliver(&deps, bare).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 1, "the redelivery must not run again");
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0].kind, EnvelopeKind::ExecResult));
+        assert_eq!(results[0].data["assignment_id"], "a1");
+        assert_eq!(results[0].data["status"], "success");
+        assert_eq!(results[1].data, results[0].data);
+        assert_eq!(deps.metrics.duplicate_results_republished_total.get(), 1);
+        assert_eq!(deps.metrics.bare_assignment_total.get(), 2);
+        assert!(dead_letters(&publisher, &deps).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_bare_assignments_can_be_refused() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.accept_bare_assignments = false;
+        deps.config = Arc::new(config);
+        deliver(&deps, serde_json::to_vec(&assignment("a1")).unwrap()).await;
+        let envelope = serde_json::to_vec(&EventEnvelopeV1 {
+            version: "v1".to_string(),
+            kind: EnvelopeKind::ExecAssign,
+            data: assignment("a2"),
+            signature: None,
+        }).unwrap();
+        deliver(&deps, envelope).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 1, "only the envelope runs");
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].reason, DeadLetterReason::EnvelopeRequired);
+        assert!(dead[0].is_replayable());
+        assert_eq!(deps.metrics.bare_assignment_total.get(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unsigned_and_forged_assignments_are_dead_lettered_with_details() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.envelope_require_signature = true;
+        deps.config = Arc::new(config);
+        deps.signer = EnvelopeSigner::new(vec!["key".to_string()]);
+        deliver(&deps, serde_json::to_vec(&assignment("a1")).unwrap()).await;
+        let forged = EventEnvelopeV1 { version: "v1".to_string(), kind: EnvelopeKind::ExecAssign, data: assignment("a2"), signature: Some("forged".to_string()) };
+        deliver(&deps, serde_json::to_vec(&forged).unwrap()).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 2);
+        assert!(dead.iter().all(|d| d.reason == DeadLetterReason::SignatureInvalid && d.is_replayable()));
+        assert!(dead.iter().all(|d| d.worker_id == Some(deps.config.worker_id.clone())));
+        assert_eq!(dead[0].error_detail.as_deref(), Some("bare assignment cannot carry a signature and ENVELOPE_REQUIRE_SIGNATURE is on"));
+        assert!(dead[1].error_detail.as_deref().is_some_and(|e| e.starts_with("envelope signature rejected")), "{:?}", dead[1].error_detail);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_duplicates_of_running_assignments_are_skipped() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let mut job = assignment("a1");
+        job["job"] = json!({"type": "sleep", "payload": {"ms": 100}});
+        let payload = Bytes::from(serde_json::to_vec(&job).unwrap());
+        let mut tasks = JoinSet::new();
+        process_message(&deps, &mut tasks, payload.clone(), None, &deps.config.caf_assign_subject).await;
+        assert!(deps.inflight.get("a1").is_some());
+        process_message(&deps, &mut tasks, payload, None, &deps.config.caf_assign_subject).await;
+        assert_eq!(drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+
+        assert_eq!(publisher.envelopes(&deps.result_subject).len(), 1);
+        assert_eq!(deps.metrics.duplicate_results_republished_total.get(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_result_subject_template_is_filled_per_assignment() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        deps.result_subject = "caf.exec.result.v1.{tenant_id}.{job_type}".to_string();
+        let mut hostile = assignment("a2");
+        hostile["tenant_id"] = json!("x.>");
+        deliver(&deps, serde_json::to_vec(&assignment("a1")).unwrap()).await;
+        deliver(&deps, serde_json::to_vec(&hostile).unwrap()).await;
+
+        let ours = publisher.envelopes("caf.exec.result.v1.t1.echo");
+        assert_eq!(ours.len(), 1);
+        assert_eq!(ours[0].data["assignment_id"], "a1");
+        let theirs = publisher.envelopes("caf.exec.result.v1.x__.echo");
+        assert_eq!(theirs.len(), 1);
+        assert_eq!(theirs[0].data["assignment_id"], "a2");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_undecodable_envelope_goes_to_dlq() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let env = json!({"version": "v1", "kind": "exec_assign", "data": {"assignment_id": "broken"}});
+        deliver(&deps, serde_json::to_vec(&env).unwrap()).await;
+
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].reason, DeadLetterReason::DecodeError);
+        assert_eq!(dead[0].worker_id.as_deref(), Some("pipeline-test"));
+        assert!(dead[0].payload_b64.is_some(), "original bytes kept for replay");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_publish_retries_then_dead_letters_the_result() {
+        let publisher = Arc::new(MemoryPublisher { failing: vec!["caf.exec.result.v1".to_string()], ..Default::default() });
+        let (deps, dir) = deps(publisher.clone());
+        assert_eq!(deps.result_subject, "caf.exec.result.v1");
+        deliver(&deps, serde_json::to_vec(&assignment("a2")).unwrap()).await;
+
+        // One attempt plus RESULT_PUBLISH_MAX_RETRIES retries
+        assert_eq!(publisher.failures.load(Ordering::SeqCst), 3);
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].reason, DeadLetterReason::PublishError);
+        assert_eq!(dead[0].attempts, Some(3));
+        assert_eq!(dead[0].subject.as_deref(), Some("caf.exec.result.v1"));
+        assert_eq!(dead[0].payload_ref["assignment_id"], "a2");
+        assert_eq!(deps.metrics.result_publish_failures_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_results_over_the_server_max_payload_are_dead_lettered_without_retries() {
+        let publisher = Arc::new(MemoryPublisher { max_payload: Some(64), ..Default::default() });
+        let (deps, dir) = deps(publisher.clone());
+        deliver(&deps, serde_json::to_vec(&assignment("a3")).unwrap()).await;
+
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        assert_eq!(publisher.failures.load(Ordering::SeqCst), 0);
+        // The dead letter is over 64 bytes too, so it stays in the file pending recovery
+        assert!(dead_letters(&publisher, &deps).is_empty());
+        deps.dlq_writer.flush();
+        let pending = crate::dlq::pending_entries(&dir.join("dlq.jsonl").to_string_lossy()).unwrap();
+        assert_eq!(pending.len(), 1);
+        let dead = &pending[0].record;
+        assert_eq!(dead.reason, DeadLetterReason::PayloadTooLarge);
+        assert_eq!(dead.attempts, Some(0));
+        assert_eq!(dead.payload_ref["assignment_id"], "a3");
+        assert_eq!(dead.payload_ref["max_payload"], 64);
+        assert!(dead.payload_b64.is_some() && !dead.truncated, "kept for /dlq/replay");
+        assert_eq!(deps.metrics.result_payload_too_large_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_results_queue_behind_a_slow_publisher_until_flushed() {
+        let publisher = Arc::new(MemoryPublisher { delay: Duration::from_millis(50), ..Default::default() });
+        let (deps, dir) = deps(publisher.clone());
+        let mut tasks = JoinSet::new();
+        for id in ["q1", "q2", "q3"] {
+            process_message(&deps, &mut tasks, Bytes::from(serde_json::to_vec(&assignment(id)).unwrap()), None, &deps.config.caf_assign_subject).await;
+        }
+        assert_eq!(drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+
+        // Every task is done and has released its permit while results are still queued
+        assert_eq!(deps.concurrency.in_use(), 0);
+        assert!(publisher.envelopes(&deps.result_subject).len() < 3);
+        assert!(deps.metrics.result_queue_depth.get() > 0);
+        assert!(!deps.results.flush(Duration::from_millis(1)).await);
+
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+        let published: Vec<_> = publisher.envelopes(&deps.result_subject).iter()
+            .map(|env| env.data["assignment_id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(published.len(), 3);
+        assert_eq!(deps.metrics.result_queue_depth.get(), 0);
+        assert_eq!(deps.metrics.task_total_seconds.get_sample_count(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    async fn deliver_while_draining(policy: DrainPolicy) -> (Arc<MemoryPublisher>, PipelineDeps, std::path::PathBuf) {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.drain_policy = policy;
+        config.caf_requeue_subject = "caf.exec.requeue.v1".to_string();
+        deps.config = Arc::new(config);
+        deps.draining.store(true, Ordering::SeqCst);
+        for id in ["d1", "d2", "d3"] {
+            deliver(&deps, serde_json::to_vec(&assignment(id)).unwrap()).await;
+        }
+        (publisher, deps, dir)
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_draining_rejects_without_executing() {
+        let (publisher, deps, dir) = deliver_while_draining(DrainPolicy::Reject).await;
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        assert_eq!(deps.metrics.drain_rejected_total.get(), 3);
+        let results = publisher.envelopes(&deps.result_subject);
+        let ids: Vec<_> = results.iter().map(|env| env.data["assignment_id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["d1", "d2", "d3"]);
+        for env in &results {
+            assert_eq!(env.data["status"], "cancelled");
+            assert_eq!(env.data["error_code"], "WORKER_DRAINING");
+        }
+        assert!(publisher.envelopes("caf.exec.requeue.v1").is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_draining_requeues_without_executing() {
+        let (publisher, deps, dir) = deliver_while_draining(DrainPolicy::Requeue).await;
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        assert_eq!(deps.metrics.drain_rejected_total.get(), 3);
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        let requeued = publisher.envelopes("caf.exec.requeue.v1");
+        assert_eq!(requeued.len(), 3);
+        assert!(matches!(requeued[0].kind, EnvelopeKind::ExecAssign));
+        let back: ExecAssignment = serde_json::from_value(requeued[0].data.clone()).unwrap();
+        assert_eq!(back.assignment_id, "d1");
+        // Turned away, not remembered: a requeued copy coming back later is not a duplicate
+        assert!(!deps.dedup.lock().unwrap().contains("d1"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unaccepted_job_types_are_rejected_or_rerouted() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.job_type_denylist = vec!["echo".to_string()];
+        deps.config = Arc::new(config.clone());
+        deliver(&deps, serde_json::to_vec(&assignment("u1")).unwrap()).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].data["status"], "cancelled");
+        assert_eq!(results[0].data["error_code"], "UNSUPPORTED_JOB_TYPE");
+
+        config.caf_unsupported_subject = Some("caf.exec.assign.v1.fallback".to_string());
+        deps.config = Arc::new(config);
+        deliver(&deps, serde_json::to_vec(&assignment("u2")).unwrap()).await;
+        let rerouted = publisher.envelopes("caf.exec.assign.v1.fallback");
+        assert_eq!(rerouted.len(), 1);
+        assert_eq!(rerouted[0].data["assignment_id"], "u2");
+        assert_eq!(publisher.envelopes(&deps.result_subject).len(), 1);
+        assert_eq!(deps.metrics.unsupported_job_type_total.get(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_oversized_payload_is_dead_lettered_unparsed() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.assignment_max_bytes = 1024;
+        deps.config = Arc::new(config);
+        // Valid JSON, so only the size check can stop it
+        let mut big = assignment("big");
+        big["job"]["payload"]["blob"] = json!("x".repeat(2048));
+        deliver(&deps, serde_json::to_vec(&big).unwrap()).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        assert_eq!(deps.metrics.payload_rejected_total.get(), 1);
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].reason, DeadLetterReason::PayloadTooLarge);
+        assert_eq!(dead[0].payload_ref["max_bytes"], 1024);
+        assert!(dead[0].payload_b64.is_none(), "oversized bytes are never embedded");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    async fn saturate(max_wait_ms: Option<u64>) -> (Arc<MemoryPublisher>, PipelineDeps, std::path::PathBuf) {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let mut config = (*deps.config).clone();
+        config.backpressure_max_wait_ms = max_wait_ms;
+        deps.config = Arc::new(config);
+        deps.concurrency = Arc::new(ConcurrencyLimit::new(1));
+        let mut tasks = JoinSet::new();
+        for id in ["s1", "s2", "s3"] {
+            let mut job = assignment(id);
+            job["job"] = json!({"type": "sleep", "payload": {"ms": 150}});
+            process_message(&deps, &mut tasks, Bytes::from(serde_json::to_vec(&job).unwrap()), None, &deps.config.caf_assign_subject).await;
+        }
+        drain(&mut tasks, &deps, Duration::from_secs(5)).await;
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+        (publisher, deps, dir)
+    }
+
+    fn statuses(publisher: &MemoryPublisher, deps: &PipelineDeps) -> Vec<(String, String)> {
+        let mut statuses: Vec<_> = publisher.envelopes(&deps.result_subject).iter()
+            .map(|env| (env.data["assignment_id"].as_str().unwrap().to_string(), env.data["error_code"].as_str().unwrap_or("").to_string()))
+            .collect();
+        statuses.sort();
+        statuses
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backpressure_waits_are_measured() {
+        let (publisher, deps, dir) = saturate(None).await;
+        assert_eq!(deps.metrics.backpressure_waits_total.get(), 2);
+        assert_eq!(deps.metrics.backpressure_wait_seconds.get_sample_count(), 2);
+        assert!(deps.metrics.backpressure_wait_seconds.get_sample_sum() >= 0.2);
+        assert_eq!(deps.metrics.batch_remaining.get(), 0);
+        assert_eq!(deps.metrics.backpressure_rejected_total.get(), 0);
+        let done: Vec<_> = statuses(&publisher, &deps).into_iter().map(|(id, code)| (id, code.is_empty())).collect();
+        assert_eq!(done, vec![("s1".to_string(), true), ("s2".to_string(), true), ("s3".to_string(), true)]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backpressure_rejects_after_max_wait() {
+        let (publisher, deps, dir) = saturate(Some(20)).await;
+        assert_eq!(deps.metrics.backpressure_waits_total.get(), 2);
+        assert_eq!(deps.metrics.backpressure_rejected_total.get(), 2);
+        assert_eq!(deps.metrics.task_received.get(), 1);
+        assert_eq!(statuses(&publisher, &deps), vec![
+            ("s1".to_string(), "".to_string()),
+            ("s2".to_string(), "WORKER_OVERLOADED".to_string()),
+            ("s3".to_string(), "WORKER_OVERLOADED".to_string()),
+        ]);
+        // Rejected assignments can run when redelivered
+        assert!(!deps.dedup.lock().unwrap().contains("s2"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_log_throttle_counts_suppressed_lines() {
+        let throttle = LogThrottle::new(Duration::from_millis(30));
+        assert_eq!(throttle.ready(), Some(0));
+        assert_eq!(throttle.ready(), None);
+        assert_eq!(throttle.ready(), None);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(throttle.ready(), Some(2));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_panicked_tasks_are_reaped_and_counted() {
+        let (deps, dir) = deps(Arc::new(MemoryPublisher::default()));
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async { panic!("handler bug") });
+        tasks.spawn(async {});
+        while let Some(joined) = tasks.join_next().await {
+            reap(joined, &deps);
+        }
+        assert_eq!(deps.metrics.handler_panics_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_drain_aborts_tasks_past_the_deadline() {
+        let (deps, dir) = deps(Arc::new(MemoryPublisher::default()));
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async {});
+        tasks.spawn(tokio::time::sleep(Duration::from_secs(60)));
+        let aborted = drain(&mut tasks, &deps, Duration::from_millis(50)).await;
+        assert_eq!((aborted, deps.metrics.drain_aborted_total.get()), (1, 1));
+        assert!(tasks.is_empty());
+        // Aborting is not a panic
+        assert_eq!(deps.metrics.handler_panics_total.get(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Fails to serialize, as a map with non-string keys would.
+    struct Unencodable;
+
+    impl Serialize for Unencodable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("key must be a string"))
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unencodable_message_is_an_error_not_a_panic() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let err = publish_encoded(publisher.as_ref(), &deps.config, "caf.exec.result.v1", &Unencodable).await.unwrap_err();
+        assert!(err.contains("key must be a string"), "{}", err);
+        assert!(publisher.sent.lock().unwrap().is_empty());
+
+        publish_encoded(publisher.as_ref(), &deps.config, "caf.exec.result.v1", &json!({"ok": true})).await.unwrap();
+        assert_eq!(publisher.sent.lock().unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_dead_lettered_result_embeds_the_published_bytes() {
+        let publisher = Arc::new(MemoryPublisher { failing: vec!["caf.exec.result.v1".to_string()], ..Default::default() });
+        let (deps, dir) = deps(publisher.clone());
+        deliver(&deps, serde_json::to_vec(&assignment("a3")).unwrap()).await;
+        let dead = dead_letters(&publisher, &deps);
+        let original: EventEnvelopeV1 = serde_json::from_slice(&dead[0].original_payload().unwrap()).unwrap();
+        assert!(matches!(original.kind, EnvelopeKind::ExecResult));
+        assert_eq!(original.data["assignment_id"], "a3");
+        assert_eq!(original.data["status"], "success");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_outbox_refuses_interrupted_assignments_and_clears_published_ones() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let outbox_dir = dir.join("outbox");
+        // A previous run died while running a1
+        let crashed = Outbox::open(&outbox_dir).unwrap();
+        crashed.started(&serde_json::from_value(assignment("a1")).unwrap(), &deps.result_subject).unwrap();
+        drop(crashed);
+        let outbox = Arc::new(Outbox::open(&outbox_dir).unwrap());
+        assert_eq!(outbox.recover().unwrap().interrupted.len(), 1);
+        deps.outbox = Some(outbox);
+
+        for id in ["a1", "a1", "a2"] {
+            deliver(&deps, serde_json::to_vec(&assignment(id)).unwrap()).await;
+        }
+        let results = publisher.envelopes(&deps.result_subject);
+        let refused: Vec<_> = results.iter().filter(|r| r.data["assignment_id"] == "a1").collect();
+        assert_eq!(refused.len(), 2, "every redelivery is answered");
+        assert_eq!(refused[0].data["status"], "error");
+        assert_eq!(refused[0].data["error_code"], "POSSIBLY_EXECUTED");
+        assert!(refused[0].data.get("output").is_none());
+        assert_eq!(deps.metrics.possibly_executed_total.get(), 2);
+        let a2 = results.iter().find(|r| r.data["assignment_id"] == "a2").unwrap();
+        assert_eq!(a2.data["status"], "success");
+        // Only the interrupted marker is left
+        let names: Vec<_> = std::fs::read_dir(&outbox_dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        assert!(names.len() == 1 && names[0].ends_with(".interrupted"), "{:?}", names);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_assignments_sharing_a_concurrency_key_do_not_overlap() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let sleeper = |id: &str, key: &str| {
+            let mut a = assignment(id);
+            a["job"] = json!({"type": "sleep", "payload": {"ms": 200}});
+            a["concurrency_key"] = json!(key);
+            Bytes::from(serde_json::to_vec(&a).unwrap())
+        };
+        let mut tasks = JoinSet::new();
+        for (id, key) in [("k1", "db-1"), ("k2", "db-1"), ("k3", "db-2")] {
+            process_message(&deps, &mut tasks, sleeper(id, key), None, &deps.config.caf_assign_subject).await;
+        }
+        assert_eq!(drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+
+        let results = publisher.envelopes(&deps.result_subject);
+        let span = |id: &str| {
+            let data = &results.iter().find(|r| r.data["assignment_id"] == id).unwrap().data;
+            let time = |field: &str| chrono::DateTime::parse_from_rfc3339(data[field].as_str().unwrap()).unwrap();
+            assert_eq!(data["status"], "success", "{}", data);
+            (time("started_at"), time("finished_at"), data["serialization_wait_ms"].as_u64().unwrap())
+        };
+        let (k1, k2, k3) = (span("k1"), span("k2"), span("k3"));
+        let (first, second) = if k1.0 <= k2.0 { (k1, k2) } else { (k2, k1) };
+        assert!(second.0 >= first.1, "same key ran concurrently");
+        assert!(second.2 >= 150, "waited {}ms", second.2);
+        assert!(k3.0 < first.1 && k3.2 < 150, "a different key runs alongside");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_queue_latency_is_measured_from_published_at() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let mut waited = assignment("a1");
+        waited["published_at"] = json!((chrono::Utc::now() - chrono::Duration::seconds(2)).to_rfc3339());
+        let mut skewed = assignment("a2");
+        skewed["published_at"] = json!((chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc3339());
+        for message in [&waited, &skewed, &assignment("a3")] {
+            deliver(&deps, serde_json::to_vec(message).unwrap()).await;
+        }
+
+        let results = publisher.envelopes(&deps.result_subject);
+        let latency = |id: &str| results.iter().find(|r| r.data["assignment_id"] == id).unwrap().data["queue_latency_ms"].clone();
+        let waited_ms = latency("a1").as_u64().unwrap();
+        assert!((2000..10_000).contains(&waited_ms), "{}", waited_ms);
+        assert_eq!(latency("a2"), json!(0), "skew is clamped");
+        assert!(latency("a3").is_null(), "absent without published_at");
+        assert_eq!(deps.metrics.task_queue_latency_seconds.get_sample_count(), 2);
+        for result in &results {
+            let started = chrono::DateTime::parse_from_rfc3339(result.data["started_at"].as_str().unwrap()).unwrap();
+            let finished = chrono::DateTime::parse_from_rfc3339(result.data["finished_at"].as_str().unwrap()).unwrap();
+            assert!(finished >= started);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_chaos_faults_are_labeled_and_counted() {
+        let chaos_vars = [("CHAOS_ENABLED", "true"), ("CHAOS_FAIL_RATE", "1"), ("CHAOS_SEED", "42")];
+        for (var, value) in chaos_vars {
+            std::env::set_var(var, value);
+        }
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut failing, dir) = deps(publisher.clone());
+        std::env::set_var("CHAOS_DROP_RESULT_RATE", "1");
+        // Read when the result queue starts, so only this set of deps drops results
+        let (dropping, drop_dir) = deps(publisher.clone());
+        for (var, _) in chaos_vars {
+            std::env::remove_var(var);
+        }
+        std::env::remove_var("CHAOS_DROP_RESULT_RATE");
+        failing.executor = failing.executor.clone().with_chaos(crate::chaos::Chaos::from_settings(failing.config.chaos.as_ref()));
+
+        deliver(&failing, serde_json::to_vec(&assignment("a1")).unwrap()).await;
+        let results = publisher.envelopes(&failing.result_subject);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].data["status"], "error");
+        assert_eq!(results[0].data["error_code"], crate::chaos::CHAOS_ERROR_CODE);
+
+        deliver(&dropping, serde_json::to_vec(&assignment("a2")).unwrap()).await;
+        assert_eq!(publisher.envelopes(&failing.result_subject).len(), 1, "the second result is dropped");
+        assert_eq!(dropping.metrics.chaos_injected_total.with_label_values(&["drop_result"]).get(), 1);
+        assert_eq!(dropping.metrics.task_completed.get(), 1, "the task itself still ran");
+        let _ = std::fs::remove_dir_all(&dir);
+        let _ = std::fs::remove_dir_all(&drop_dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_history_records_finished_tasks_in_order() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        deps.history = Arc::new(TaskHistory::new(3));
+        for id in ["a1", "a2", "a3"] {
+            deliver(&deps, serde_json::to_vec(&assignment(id)).unwrap()).await;
+        }
+        let mut failing = assignment("a4");
+        failing["job"] = json!({"type": "jmespath", "payload": {"data": {}}});
+        deliver(&deps, serde_json::to_vec(&failing).unwrap()).await;
+
+        let history = deps.history.snapshot(None, None);
+        let ids: Vec<_> = history.iter().map(|e| e.assignment_id.as_str()).collect();
+        assert_eq!(ids, vec!["a4", "a3", "a2"], "newest first, a1 evicted");
+        assert_eq!(history[0].status, "error");
+        assert_eq!(history[0].error_code.as_deref(), Some("MISSING_EXPRESSION"));
+        assert_eq!(history[1].tenant_id.as_deref(), Some("t1"));
+        assert!(history[1].finished_at.starts_with("20"));
+        let encoded = serde_json::to_string(&history).unwrap();
+        assert!(!encoded.contains("hello"), "no payloads or outputs: {}", encoded);
+
+        assert_eq!(deps.history.snapshot(Some("success"), Some("echo")).len(), 2);
+        assert!(deps.history.snapshot(Some("error"), Some("echo")).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_echo_forced_status_reaches_the_result() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let mut forced = assignment("a1");
+        forced["job"]["payload"] = json!({"force_status": "error", "error_code": "E2E_FAILURE"});
+        deliver(&deps, serde_json::to_vec(&forced).unwrap()).await;
+
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].data["status"], "error");
+        assert_eq!(results[0].data["error_code"], "E2E_FAILURE");
+        assert_eq!(deps.metrics.task_failed.get(), 1);
+        assert_eq!(deps.history.snapshot(Some("error"), Some("echo")).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_misrouted_envelopes_are_dead_lettered() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let heartbeat = EventEnvelopeV1::wrap_heartbeat(&crate::protocol::WorkerHeartbeat { worker_id: "w2".to_string(), ..Default::default() });
+        deliver(&deps, serde_json::to_vec(&heartbeat).unwrap()).await;
+        let result = EventEnvelopeV1::wrap_result(&protocol::ExecResult::for_failure(&serde_json::from_value(assignment("a1")).unwrap(), "w2", ExecStatus::Success, "", "", Duration::from_millis(1)));
+        deliver(&deps, serde_json::to_vec(&result).unwrap()).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 2);
+        assert!(dead.iter().all(|d| d.reason == DeadLetterReason::UnexpectedKind && d.is_replayable()));
+        assert_eq!(dead[0].payload_ref["kind"], "heartbeat");
+        assert_eq!(dead[1].payload_ref["kind"], "exec_result");
+        assert_eq!(dead[1].payload_ref["subject"], deps.config.caf_assign_subject);
+        assert_eq!(deps.metrics.unexpected_envelope_total.with_label_values(&["heartbeat"]).get(), 1);
+        assert_eq!(deps.metrics.unexpected_envelope_total.with_label_values(&["exec_result"]).get(), 1);
+        assert_eq!(deps.metrics.dlq_published_total.with_label_values(&["UNEXPECTED_KIND"]).get(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unsupported_envelope_versions_are_dead_lettered() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let mut env = serde_json::to_value(EventEnvelopeV1::wrap_assignment(&serde_json::from_value(assignment("a1")).unwrap())).unwrap();
+        env["version"] = json!("v2");
+        deliver(&deps, serde_json::to_vec(&env).unwrap()).await;
+
+        assert_eq!(deps.metrics.task_received.get(), 0);
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].reason, DeadLetterReason::UnsupportedVersion);
+        assert_eq!(dead[0].payload_ref["version"], "v2");
+        assert_eq!(dead[0].payload_ref["accepted"], json!(["v1"]));
+        assert!(dead[0].is_replayable());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_malformed_identifiers_never_reach_dedup() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let mut oversized = assignment("a2");
+        oversized["request_id"] = json!("r".repeat(protocol::MAX_IDENTIFIER_LEN + 1));
+        let mut control = assignment("a3");
+        control["tenant_id"] = json!("t1\nforged=1");
+        for bad in [assignment(""), assignment(""), oversized, control] {
+            deliver(&deps, serde_json::to_vec(&bad).unwrap()).await;
+        }
+
+        assert!(publisher.envelopes(&deps.result_subject).is_empty());
+        let dead = dead_letters(&publisher, &deps);
+        assert_eq!(dead.len(), 4);
+        assert!(dead.iter().all(|d| d.reason == DeadLetterReason::ValidationError));
+        let violations: Vec<String> = dead.iter().map(|d| d.payload_ref["violations"][0].as_str().unwrap().to_string()).collect();
+        assert_eq!(violations, vec![
+            "assignment_id must not be empty",
+            "assignment_id must not be empty",
+            "request_id must be at most 256 characters",
+            "tenant_id must not contain control characters",
+        ]);
+        assert_eq!(deps.metrics.validation_failures_total.get(), 4);
+        assert!(dead.iter().all(|d| d.is_replayable() && d.worker_id == Some(deps.config.worker_id.clone())));
+        assert_eq!(dead[2].error_detail.as_deref(), Some("assignment failed validation: request_id must be at most 256 characters"));
+        assert!(!deps.dedup.lock().unwrap().contains(""));
+        assert!(!deps.dedup.lock().unwrap().contains("a2"));
+
+        // The rejected ids didn't claim a dedup slot
+        let mut fixed = assignment("a2");
+        fixed["request_id"] = json!("r2");
+        deliver(&deps, serde_json::to_vec(&fixed).unwrap()).await;
+        assert_eq!(publisher.envelopes(&deps.result_subject).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_resource_pressure_turns_new_assignments_away() {
+        struct Sampler(Arc<AtomicBool>);
+        impl crate::resources::ResourceSampler for Sampler {
+            fn sample(&self) -> crate::resources::ResourceSample {
+                let rss = if self.0.load(Ordering::SeqCst) { 2048 } else { 10 };
+                crate::resources::ResourceSample { rss_bytes: Some(rss), ..Default::default() }
+            }
+        }
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let full = Arc::new(AtomicBool::new(true));
+        let monitor = Arc::new(ResourceMonitor::new(Box::new(Sampler(full.clone())), None, Some(1024)));
+        deps.resources = Some(monitor.clone());
+
+        monitor.check(&deps.metrics, &deps.logger);
+        deliver(&deps, serde_json::to_vec(&assignment("a1")).unwrap()).await;
+        full.store(false, Ordering::SeqCst);
+        monitor.check(&deps.metrics, &deps.logger);
+        deliver(&deps, serde_json::to_vec(&assignment("a2")).unwrap()).await;
+
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results[0].data["assignment_id"], "a1");
+        assert_eq!(results[0].data["status"], "cancelled");
+        assert_eq!(results[0].data["error_code"], "WORKER_RESOURCE_PRESSURE");
+        assert_eq!(results[1].data["status"], "success");
+        assert_eq!(deps.metrics.resource_pressure_rejected_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_idempotency_key_answers_retries_without_running_again() {
+        use crate::handlers::{ExecContext, HandlerOutcome, JobHandler};
+        use crate::protocol::Job;
+        struct Counting(AtomicUsize);
+        impl JobHandler for Counting {
+            fn handle<'a>(&'a self, _ctx: &'a ExecContext, _job: &'a Job) -> futures::future::BoxFuture<'a, HandlerOutcome> {
+                let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+                Box::pin(async move { HandlerOutcome::success(json!({"run": n})) })
+            }
+        }
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let handler = Arc::new(Counting(AtomicUsize::new(0)));
+        deps.executor = deps.executor.clone().with_handler("charge", handler.clone());
+        let charge = |id: &str, tenant: &str| {
+            let mut a = assignment(id);
+            a["request_id"] = json!(format!("req-{}", id));
+            a["tenant_id"] = json!(tenant);
+            a["job"] = json!({"type": "charge", "payload": {}});
+            a["idempotency_key"] = json!("order-42");
+            serde_json::to_vec(&a).unwrap()
+        };
+        for (id, tenant) in [("a1", "t1"), ("a2", "t1"), ("a3", "t2")] {
+            deliver(&deps, charge(id, tenant)).await;
+        }
+
+        assert_eq!(handler.0.load(Ordering::SeqCst), 2, "t2 has its own key space");
+        let results = publisher.envelopes(&deps.result_subject);
+        let by_id = |id: &str| results.iter().find(|r| r.data["assignment_id"] == id).unwrap().data.clone();
+        assert_eq!(by_id("a2")["output"], by_id("a1")["output"]);
+        assert_eq!(by_id("a2")["request_id"], "req-a2");
+        assert_eq!(by_id("a3")["output"], json!({"run": 2}));
+        assert_eq!(deps.metrics.idempotent_hits_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_tenants_off_the_allowlist_are_rejected_or_requeued() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        deps.tenant_allowlist.store(Arc::new(vec!["t1".to_string()]));
+        let from = |id: &str, tenant: &str| {
+            let mut a = assignment(id);
+            a["tenant_id"] = json!(tenant);
+            serde_json::to_vec(&a).unwrap()
+        };
+        deliver(&deps, from("a1", "t1")).await;
+        deliver(&deps, from("a2", "t2")).await;
+
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].data["status"], "success");
+        assert_eq!(results[1].data["assignment_id"], "a2");
+        assert_eq!(results[1].data["status"], "error");
+        assert_eq!(results[1].data["error_code"], "TENANT_NOT_ALLOWED");
+        assert_eq!(deps.metrics.task_received.get(), 1);
+
+        let mut config = (*deps.config).clone();
+        config.tenant_reject_policy = TenantRejectPolicy::Requeue;
+        config.tenant_overflow_subject = Some("caf.exec.assign.v1.overflow".to_string());
+        deps.config = Arc::new(config);
+        deliver(&deps, from("a3", "t3")).await;
+        let requeued = publisher.envelopes("caf.exec.assign.v1.overflow");
+        assert_eq!(requeued.len(), 1);
+        assert_eq!(requeued[0].data["assignment_id"], "a3");
+        assert_eq!(publisher.envelopes(&deps.result_subject).len(), 2);
+
+        // A reload that adds the tenant lets its next assignment through
+        deps.tenant_allowlist.store(Arc::new(vec!["t1".to_string(), "t3".to_string()]));
+        deliver(&deps, from("a4", "t3")).await;
+        assert_eq!(publisher.envelopes(&deps.result_subject).last().unwrap().data["status"], "success");
+        assert_eq!(deps.metrics.tenant_rejected_total.get(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_missing_trace_ids_are_generated_for_the_handler_and_result() {
+        use crate::handlers::{ExecContext, HandlerOutcome, JobHandler};
+        use crate::protocol::Job;
+        struct TraceEcho;
+        impl JobHandler for TraceEcho {
+            fn handle<'a>(&'a self, ctx: &'a ExecContext, _job: &'a Job) -> futures::future::BoxFuture<'a, HandlerOutcome> {
+                Box::pin(async move { HandlerOutcome::success(json!({"trace_id": ctx.trace_id})) })
+            }
+        }
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        deps.executor = deps.executor.clone().with_handler("trace", Arc::new(TraceEcho));
+        let traced = |id: &str, trace_id: Option<&str>| {
+            let mut a = assignment(id);
+            a["job"] = json!({"type": "trace", "payload": {}});
+            if let Some(trace_id) = trace_id {
+                a["trace_id"] = json!(trace_id);
+            }
+            serde_json::to_vec(&a).unwrap()
+        };
+        deliver(&deps, traced("a1", None)).await;
+        deliver(&deps, traced("a2", Some("tr-controller"))).await;
+        let mut config = (*deps.config).clone();
+        config.generate_trace_ids = false;
+        deps.config = Arc::new(config);
+        deliver(&deps, traced("a3", None)).await;
+
+        let results = publisher.envelopes(&deps.result_subject);
+        let generated = results[0].data["trace_id"].as_str().unwrap();
+        assert_eq!(generated.len(), 32);
+        assert_eq!(results[0].data["output"]["trace_id"], generated, "the handler saw the same id");
+        assert_eq!(results[0].data["trace_generated"], true);
+        assert_eq!(results[1].data["trace_id"], "tr-controller");
+        assert_eq!(results[1].data["output"]["trace_id"], "tr-controller");
+        assert!(results[1].data.get("trace_generated").is_none());
+        assert!(results[2].data.get("trace_id").is_none());
+        assert_eq!(results[2].data["output"]["trace_id"], json!(null));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_canary_sampling_is_deterministic_per_assignment() {
+        let ids: Vec<String> = (0..2000).map(|n| format!("assignment-{}", n)).collect();
+        let sampled = ids.iter().filter(|id| canary_sampled(id, 0.25)).count();
+        assert!((400..600).contains(&sampled), "{} of 2000", sampled);
+        assert!(ids.iter().all(|id| canary_sampled(id, 0.25) == canary_sampled(id, 0.25)));
+        assert!(ids.iter().filter(|id| canary_sampled(id, 0.25)).all(|id| canary_sampled(id, 0.5)), "a larger sample keeps the smaller one");
+        assert!(ids.iter().all(|id| canary_sampled(id, 1.0) && !canary_sampled(id, 0.0)));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_canary_skips_unsampled_and_shadow_reroutes_results() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let skipped = (0..).map(|n| format!("a{}", n)).find(|id| !canary_sampled(id, 0.5)).unwrap();
+        let sampled = (0..).map(|n| format!("a{}", n)).find(|id| canary_sampled(id, 0.5)).unwrap();
+        let mut config = (*deps.config).clone();
+        config.canary_sample_rate = 0.5;
+        deps.config = Arc::new(config);
+        for _ in 0..2 {
+            deliver(&deps, serde_json::to_vec(&assignment(&skipped)).unwrap()).await;
+        }
+        deliver(&deps, serde_json::to_vec(&assignment(&sampled)).unwrap()).await;
+
+        let results = publisher.envelopes(&deps.result_subject);
+        assert_eq!(results.len(), 3);
+        for redelivery in &results[..2] {
+            assert_eq!(redelivery.data["assignment_id"], skipped.as_str());
+            assert_eq!(redelivery.data["status"], "cancelled");
+            assert_eq!(redelivery.data["error_code"], "CANARY_SKIPPED");
+        }
+        assert_eq!(results[2].data["status"], "success");
+        assert_eq!((deps.metrics.canary_skipped_total.get(), deps.metrics.task_received.get()), (2, 1));
+
+        let mut config = (*deps.config).clone();
+        config.canary_sample_rate = 1.0;
+        config.canary_shadow = true;
+        config.caf_shadow_result_subject = "caf.exec.result.shadow.v1.{tenant_id}".to_string();
+        deps.config = Arc::new(config);
+        deliver(&deps, serde_json::to_vec(&assignment("shadowed")).unwrap()).await;
+        assert_eq!(publisher.envelopes(&deps.result_subject).len(), 3, "nothing more for the controller");
+        let shadow = publisher.envelopes("caf.exec.result.shadow.v1.t1");
+        assert_eq!(shadow.len(), 1);
+        assert_eq!((shadow[0].data["assignment_id"].as_str(), shadow[0].data["status"].as_str()), (Some("shadowed"), Some("success")));
+        assert_eq!(deps.metrics.shadow_results_total.get(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_results_echo_flow_and_step_on_success_and_timeout() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (deps, dir) = deps(publisher.clone());
+        let routed = |id: &str, job: serde_json::Value| {
+            let mut a = assignment(id);
+            a["job"] = job;
+            a["flow_id"] = json!("flow-1");
+            a["step_id"] = json!(format!("step-{}", id));
+            Bytes::from(serde_json::to_vec(&a).unwrap())
+        };
+        let mut tasks = JoinSet::new();
+        process_message(&deps, &mut tasks, routed("ok", json!({"type": "echo", "payload": {}})), None, &deps.config.caf_assign_subject).await;
+        let slow = json!({"type": "sleep", "payload": {"ms": 2000, "timeout_ms": 50}});
+        process_message(&deps, &mut tasks, routed("slow", slow), None, &deps.config.caf_assign_subject).await;
+        assert_eq!(drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+
+        let results = publisher.envelopes(&deps.result_subject);
+        for (id, status) in [("ok", "success"), ("slow", "timeout")] {
+            let data = &results.iter().find(|r| r.data["assignment_id"] == id).unwrap().data;
+            assert_eq!(data["status"], status);
+            if status == "timeout" {
+                let latency = data["latency_ms"].as_u64().unwrap();
+                assert!((50..2000).contains(&latency), "elapsed time, not the timeout: {}", latency);
+                assert_eq!(data["output"], json!({"timeout_ms": 50}));
+                assert!(data["started_at"].is_string() && data["finished_at"].is_string());
+            }
+            assert_eq!((data["flow_id"].as_str(), data["step_id"].as_str()), (Some("flow-1"), Some(format!("step-{}", id).as_str())), "{}", data);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_finished_assignments_are_recorded_when_enabled() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let recordings = dir.join("recordings");
+        deps.recorder = Some(Arc::new(Recorder::new(recordings.clone(), 1024, crate::observability::pii::PiiMasker::default())));
+        let mut tasks = JoinSet::new();
+        process_message(&deps, &mut tasks, Bytes::from(serde_json::to_vec(&assignment("rec-1")).unwrap()), None, &deps.config.caf_assign_subject).await;
+        assert_eq!(drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while deps.metrics.recordings_written_total.get() == 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let day = std::fs::read_dir(&recordings).unwrap().next().unwrap().unwrap().path();
+        let recording = crate::recorder::load(&day.join("rec-1.json")).unwrap();
+        assert_eq!(recording.assignment.data["job"]["payload"], json!({"hello": "world"}));
+        assert_eq!(recording.result.data["status"], "success");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_receipts_hash_payload_and_output_when_enabled() {
+        let publisher = Arc::new(MemoryPublisher::default());
+        let (mut deps, dir) = deps(publisher.clone());
+        let receipts_file = dir.join("receipts.jsonl");
+        let mut config = (*deps.config).clone();
+        config.receipts_sink = config::ReceiptSink::Both;
+        config.receipts_file = receipts_file.to_string_lossy().to_string();
+        deps.config = Arc::new(config);
+        let signer = EnvelopeSigner::new(vec!["receipt-key".to_string()]).unwrap();
+        deps.receipts = Some(Arc::new(Receipts::new(deps.config.clone(), publisher.clone(), Some(signer.clone()), deps.metrics.clone(), deps.logger.clone())));
+        // Draining the tasks is enough: receipts are sent before a task finishes
+        deliver(&deps, serde_json::to_vec(&assignment("rct-1")).unwrap()).await;
+        assert_eq!(deps.metrics.receipts_written_total.with_label_values(&["file"]).get(), 1);
+        let published = publisher.envelopes(&deps.config.caf_receipt_subject);
+        assert_eq!(published.len(), 1);
+        let receipt = &published[0];
+        assert_eq!(receipt.kind.as_str(), "exec_receipt");
+        assert!(signer.verify(&receipt.data, receipt.signature.as_deref()).is_ok());
+        assert_eq!((receipt.data["assignment_id"].as_str(), receipt.data["status"].as_str()), (Some("rct-1"), Some("success")));
+        assert_eq!(receipt.data["input_hash"], receipts::content_hash(&json!({"hello": "world"})));
+        let result = &publisher.envelopes(&deps.config.caf_result_subject)[0].data;
+        assert_eq!(receipt.data["output_hash"], receipts::content_hash(&result["output"]));
+        assert!(receipt.data.get("output").is_none() && receipt.data["finished_at"] == result["finished_at"]);
+
+        let line = std::fs::read_to_string(&receipts_file).unwrap();
+        let written: EventEnvelopeV1 = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(written.data, receipt.data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
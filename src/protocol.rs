@@ -1,16 +1,41 @@
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::signing::EnvelopeSigner;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum EnvelopeKind {
     #[serde(rename = "exec_assign")]
     ExecAssign,
+    #[serde(rename = "exec_assign_batch")]
+    ExecAssignBatch,
+    #[serde(rename = "exec_batch_summary")]
+    ExecBatchSummary,
     #[serde(rename = "exec_result")]
     ExecResult,
     #[serde(rename = "heartbeat")]
     Heartbeat,
     #[serde(rename = "dead_letter")]
     DeadLetter,
+    #[serde(rename = "exec_receipt")]
+    ExecReceipt,
+}
+
+impl EnvelopeKind {
+    /// Wire name, also used as the `kind` metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnvelopeKind::ExecAssign => "exec_assign",
+            EnvelopeKind::ExecAssignBatch => "exec_assign_batch",
+            EnvelopeKind::ExecBatchSummary => "exec_batch_summary",
+            EnvelopeKind::ExecResult => "exec_result",
+            EnvelopeKind::Heartbeat => "heartbeat",
+            EnvelopeKind::DeadLetter => "dead_letter",
+            EnvelopeKind::ExecReceipt => "exec_receipt",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,22 +43,231 @@ pub struct EventEnvelopeV1 {
     pub version: String,
     pub kind: EnvelopeKind,
     pub data: Value,
+    /// Base64 HMAC-SHA256 over the canonicalized `data`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Why a message ended up in the DLQ; the wire strings are what alerts and `/dlq?reason=` match on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeadLetterReason {
+    #[serde(rename = "DECOMPRESS_ERROR")]
+    DecompressError,
+    #[serde(rename = "SIGNATURE_INVALID")]
+    SignatureInvalid,
+    #[serde(rename = "DECODE_ERROR")]
+    DecodeError,
+    #[serde(rename = "PARSE_ERROR")]
+    ParseError,
+    #[serde(rename = "VALIDATION_ERROR")]
+    ValidationError,
+    #[serde(rename = "PUBLISH_ERROR")]
+    PublishError,
+    #[serde(rename = "PAYLOAD_TOO_LARGE")]
+    PayloadTooLarge,
+    /// A well-formed envelope of a kind the assign subject never carries, e.g. a misrouted result.
+    #[serde(rename = "UNEXPECTED_KIND")]
+    UnexpectedKind,
+    /// An envelope whose `version` is not in `ENVELOPE_ACCEPT_VERSIONS`.
+    #[serde(rename = "UNSUPPORTED_VERSION")]
+    UnsupportedVersion,
+    /// A bare assignment while `ACCEPT_BARE_ASSIGNMENTS=false`.
+    #[serde(rename = "ENVELOPE_REQUIRED")]
+    EnvelopeRequired,
+    /// Reasons written by other versions stay readable.
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl DeadLetterReason {
+    /// Wire name, also used as the `reason` metric label.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeadLetterReason::DecompressError => "DECOMPRESS_ERROR",
+            DeadLetterReason::SignatureInvalid => "SIGNATURE_INVALID",
+            DeadLetterReason::DecodeError => "DECODE_ERROR",
+            DeadLetterReason::ParseError => "PARSE_ERROR",
+            DeadLetterReason::ValidationError => "VALIDATION_ERROR",
+            DeadLetterReason::PublishError => "PUBLISH_ERROR",
+            DeadLetterReason::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            DeadLetterReason::UnexpectedKind => "UNEXPECTED_KIND",
+            DeadLetterReason::UnsupportedVersion => "UNSUPPORTED_VERSION",
+            DeadLetterReason::EnvelopeRequired => "ENVELOPE_REQUIRED",
+            DeadLetterReason::Other(reason) => reason,
+        }
+    }
+}
+
+impl std::fmt::Display for DeadLetterReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeadLetter {
-    pub reason: String,
+    pub reason: DeadLetterReason,
     pub payload_ref: Value,
     pub ts: String,
+    /// Where the original message was headed; with `payload_b64` this makes the entry replayable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_b64: Option<String>,
+    /// `payload_b64` holds only a prefix of the original bytes and cannot be replayed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    /// Known once the assignment decoded; picks the per-tenant file and subject under `DLQ_PARTITION_BY_TENANT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+impl DeadLetter {
+    pub fn new(reason: DeadLetterReason, payload_ref: Value) -> Self {
+        Self {
+            reason,
+            payload_ref,
+            ts: chrono::Utc::now().to_rfc3339(),
+            subject: None,
+            payload_b64: None,
+            truncated: false,
+            error_detail: None,
+            worker_id: None,
+            attempts: None,
+            tenant_id: None,
+        }
+    }
+
+    /// Keeps the bytes that failed so the entry can be re-published as-is.
+    ///
+    /// At most `max_bytes` are stored; a longer payload keeps its prefix and is marked `truncated`.
+    pub fn with_original(mut self, subject: &str, payload: &[u8], max_bytes: usize) -> Self {
+        use base64::Engine as _;
+        let kept = &payload[..payload.len().min(max_bytes)];
+        self.subject = Some(subject.to_string());
+        self.payload_b64 = Some(base64::engine::general_purpose::STANDARD.encode(kept));
+        self.truncated = kept.len() < payload.len();
+        self
+    }
+
+    pub fn with_error(mut self, detail: impl Into<String>) -> Self {
+        self.error_detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_worker(mut self, worker_id: &str) -> Self {
+        self.worker_id = Some(worker_id.to_string());
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant_id: Option<&str>) -> Self {
+        self.tenant_id = tenant_id.map(str::to_string);
+        self
+    }
+
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    pub fn is_replayable(&self) -> bool {
+        self.subject.is_some() && self.payload_b64.is_some() && !self.truncated
+    }
+
+    pub fn original_payload(&self) -> Option<Vec<u8>> {
+        use base64::Engine as _;
+        base64::engine::general_purpose::STANDARD.decode(self.payload_b64.as_ref()?).ok()
+    }
+}
+
+/// Envelope versions this build understands; `ENVELOPE_ACCEPT_VERSIONS` defaults to these.
+pub const SUPPORTED_ENVELOPE_VERSIONS: &[&str] = &["v1"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvelopeError {
+    /// Not an envelope (it may still be a bare assignment), or one that doesn't decode.
+    Malformed(String),
+    /// An envelope whose version isn't accepted; empty when it has none.
+    UnsupportedVersion(String),
+}
+
+/// Decodes an envelope, refusing versions outside `accepted` before looking at `data`, so a
+/// future format is never half-parsed as this one.
+///
+/// Anything with `kind` and `data` counts as an envelope, even without a `version`.
+pub fn decode_envelope(bytes: &[u8], accepted: &[String]) -> Result<EventEnvelopeV1, EnvelopeError> {
+    // Skims the payload without building `data`, which can be megabytes
+    #[derive(Deserialize)]
+    struct Header {
+        version: Option<String>,
+        kind: Option<IgnoredAny>,
+        data: Option<IgnoredAny>,
+    }
+    let header: Header = serde_json::from_slice(bytes).map_err(|e| EnvelopeError::Malformed(e.to_string()))?;
+    if header.kind.is_none() || header.data.is_none() {
+        return Err(EnvelopeError::Malformed("missing kind or data".to_string()));
+    }
+    let version = header.version.unwrap_or_default();
+    if !accepted.contains(&version) {
+        return Err(EnvelopeError::UnsupportedVersion(version));
+    }
+    serde_json::from_slice(bytes).map_err(|e| EnvelopeError::Malformed(e.to_string()))
+}
+
+/// A message from an assign subject, before its kind is looked at.
+#[derive(Debug)]
+pub enum IncomingAssignment {
+    Envelope(EventEnvelopeV1),
+    /// A bare `ExecAssignment`, the pre-envelope format.
+    Bare(Box<ExecAssignment>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignmentDecodeError {
+    UnsupportedVersion(String),
+    /// Neither an envelope nor, where allowed, a bare assignment.
+    Malformed(String),
+    /// A well-formed bare assignment where `allow_bare` is off.
+    EnvelopeRequired,
+}
+
+/// Decodes what arrived on an assign subject, falling back to a bare assignment only when
+/// `allow_bare`. Without the fallback a payload that isn't an envelope reports the envelope
+/// error rather than why it isn't an assignment either.
+pub fn decode_assignment(bytes: &[u8], accepted: &[String], allow_bare: bool) -> Result<IncomingAssignment, AssignmentDecodeError> {
+    let envelope_error = match decode_envelope(bytes, accepted) {
+        Ok(envelope) => return Ok(IncomingAssignment::Envelope(envelope)),
+        Err(EnvelopeError::UnsupportedVersion(version)) => return Err(AssignmentDecodeError::UnsupportedVersion(version)),
+        Err(EnvelopeError::Malformed(e)) => e,
+    };
+    match serde_json::from_slice::<ExecAssignment>(bytes) {
+        Ok(assignment) if allow_bare => Ok(IncomingAssignment::Bare(Box::new(assignment))),
+        Ok(_) => Err(AssignmentDecodeError::EnvelopeRequired),
+        Err(e) if allow_bare => Err(AssignmentDecodeError::Malformed(e.to_string())),
+        Err(_) => Err(AssignmentDecodeError::Malformed(envelope_error)),
+    }
+}
+
+/// Serializes an envelope (or any other message) for the wire.
+///
+/// Every publish goes through here so an unencodable value is an error to log, never a panic.
+pub fn encode_envelope<T: Serialize + ?Sized>(message: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(message).map_err(|e| format!("failed to encode envelope: {}", e))
 }
 
 impl EventEnvelopeV1 {
-    #[allow(dead_code)]
     pub fn wrap_assignment(a: &ExecAssignment) -> Self {
         Self {
             version: "v1".to_string(),
             kind: EnvelopeKind::ExecAssign,
             data: serde_json::to_value(a).unwrap_or(Value::Null),
+            signature: None,
         }
     }
     pub fn wrap_result(r: &ExecResult) -> Self {
@@ -41,6 +275,7 @@ impl EventEnvelopeV1 {
             version: "v1".to_string(),
             kind: EnvelopeKind::ExecResult,
             data: serde_json::to_value(r).unwrap_or(Value::Null),
+            signature: None,
         }
     }
     pub fn wrap_heartbeat(h: &WorkerHeartbeat) -> Self {
@@ -48,8 +283,40 @@ impl EventEnvelopeV1 {
             version: "v1".to_string(),
             kind: EnvelopeKind::Heartbeat,
             data: serde_json::to_value(h).unwrap_or(Value::Null),
+            signature: None,
+        }
+    }
+    pub fn wrap_batch(b: &ExecAssignBatch) -> Self {
+        Self {
+            version: "v1".to_string(),
+            kind: EnvelopeKind::ExecAssignBatch,
+            data: serde_json::to_value(b).unwrap_or(Value::Null),
+            signature: None,
+        }
+    }
+    pub fn wrap_receipt(r: &ExecReceipt) -> Self {
+        Self {
+            version: "v1".to_string(),
+            kind: EnvelopeKind::ExecReceipt,
+            data: serde_json::to_value(r).unwrap_or(Value::Null),
+            signature: None,
+        }
+    }
+    pub fn wrap_batch_summary(s: &BatchSummary) -> Self {
+        Self {
+            version: "v1".to_string(),
+            kind: EnvelopeKind::ExecBatchSummary,
+            data: serde_json::to_value(s).unwrap_or(Value::Null),
+            signature: None,
         }
     }
+    /// Signs the envelope when a signer is configured; a no-op otherwise.
+    pub fn signed(mut self, signer: Option<&EnvelopeSigner>) -> Self {
+        if let Some(signer) = signer {
+            self.signature = Some(signer.sign(&self.data));
+        }
+        self
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -68,6 +335,109 @@ pub struct ExecAssignment {
     pub flow_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub step_id: Option<String>,
+    /// RFC3339 time the controller published the assignment; older controllers leave it out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<String>,
+    /// Assignments sharing a key run one at a time on a worker, e.g. migrations of one database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_key: Option<String>,
+    /// Stable across the controller's retries of one operation, unlike `assignment_id`; a repeat
+    /// within `IDEMPOTENCY_CACHE_TTL_SECONDS` is answered with the earlier result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+impl ExecAssignment {
+    /// Milliseconds from `published_at` to `started_at`, negative when the controller's clock is
+    /// ahead of ours. None without a parseable `published_at`.
+    pub fn queue_latency_ms(&self, started_at: chrono::DateTime<chrono::Utc>) -> Option<i64> {
+        let published = chrono::DateTime::parse_from_rfc3339(self.published_at.as_deref()?).ok()?;
+        Some((started_at - published.with_timezone(&chrono::Utc)).num_milliseconds())
+    }
+
+    /// A copy with a null payload, for answering the assignment after it has moved into its
+    /// handler. The payload is lent out rather than cloned.
+    pub fn without_payload(&mut self) -> ExecAssignment {
+        let payload = std::mem::take(&mut self.job.payload);
+        let copy = self.clone();
+        self.job.payload = payload;
+        copy
+    }
+}
+
+/// Several assignments delivered in one message to amortize per-message overhead.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecAssignBatch {
+    pub batch_id: String,
+    pub assignments: Vec<ExecAssignment>,
+    /// Publish a `BatchSummary` once every entry has finished.
+    #[serde(default)]
+    pub summary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedBatch {
+    pub batch_id: String,
+    pub summary: bool,
+    pub assignments: Vec<ExecAssignment>,
+    /// Entries that failed to decode, as (index, error).
+    pub errors: Vec<(usize, String)>,
+}
+
+#[derive(Deserialize)]
+struct RawBatch {
+    batch_id: String,
+    assignments: Vec<Value>,
+    #[serde(default)]
+    summary: bool,
+}
+
+/// Decodes a batch entry by entry so one bad assignment doesn't sink the rest.
+pub fn decode_batch(data: Value, max_size: usize) -> Result<DecodedBatch, String> {
+    let raw: RawBatch = serde_json::from_value(data).map_err(|e| e.to_string())?;
+    if raw.assignments.len() > max_size {
+        return Err(format!("batch of {} exceeds max size {}", raw.assignments.len(), max_size));
+    }
+    let mut assignments = Vec::with_capacity(raw.assignments.len());
+    let mut errors = Vec::new();
+    for (idx, entry) in raw.assignments.into_iter().enumerate() {
+        match serde_json::from_value::<ExecAssignment>(entry) {
+            Ok(a) => assignments.push(a),
+            Err(e) => errors.push((idx, e.to_string())),
+        }
+    }
+    Ok(DecodedBatch { batch_id: raw.batch_id, summary: raw.summary, assignments, errors })
+}
+
+/// Evidence that the payload hashing to `input_hash` produced the output hashing to
+/// `output_hash`, without either payload. Hashes are `receipts::content_hash`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecReceipt {
+    pub assignment_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub job_type: String,
+    pub status: ExecStatus,
+    pub input_hash: String,
+    /// Absent when the job produced no output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    pub worker_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Entries that never executed (decode/validation failures, duplicates).
+    pub rejected: usize,
+    pub ts: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -88,6 +458,18 @@ pub enum ExecStatus {
     Cancelled,
 }
 
+impl ExecStatus {
+    /// Wire name, also used as the `status` metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecStatus::Success => "success",
+            ExecStatus::Error => "error",
+            ExecStatus::Timeout => "timeout",
+            ExecStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ExecResult {
     pub version: String,
@@ -103,22 +485,139 @@ pub struct ExecResult {
     
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
+    /// The assignment arrived without a `trace_id`; the worker generated this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_generated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tenant_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub run_id: Option<String>,
+    /// Echoed from the assignment so the controller can route the result back to its step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_code: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
+    /// The failing handler judged that resubmitting the same job could plausibly succeed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retryable: bool,
+    /// What the handler produced besides its output, e.g. references to written blobs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<Value>,
+    /// Time from the assignment's `published_at` to the worker starting it, zero under clock skew.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_latency_ms: Option<u64>,
+    /// Time spent waiting for another assignment with the same `concurrency_key` to finish.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serialization_wait_ms: Option<u64>,
+    /// RFC3339 times the worker started and finished the assignment; equal for one turned away
+    /// before running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ExecResult {
+    /// An empty result carrying `assignment`'s identifying and routing fields. Every path that
+    /// answers an assignment starts here, so none can drop one of them; callers fill in the rest.
+    pub fn from_assignment(assignment: &ExecAssignment, provider_id: &str, status: ExecStatus) -> Self {
+        Self {
+            version: "1.0".to_string(),
+            assignment_id: assignment.assignment_id.clone(),
+            request_id: assignment.request_id.clone(),
+            status,
+            provider_id: provider_id.to_string(),
+            job_type: assignment.job.r#type.clone(),
+            output: None,
+            latency_ms: 0,
+            cost: 0.0,
+            trace_id: assignment.trace_id.clone(),
+            trace_generated: None,
+            tenant_id: Some(assignment.tenant_id.clone()),
+            run_id: assignment.run_id.clone(),
+            flow_id: assignment.flow_id.clone(),
+            step_id: assignment.step_id.clone(),
+            error_code: None,
+            error_message: None,
+            retryable: false,
+            artifacts: Vec::new(),
+            queue_latency_ms: None,
+            serialization_wait_ms: None,
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    /// A result for an assignment that failed, timed out or was turned away without its handler
+    /// answering: `latency_ms` is the time actually spent and the timestamps span it, ending now.
+    pub fn for_failure(assignment: &ExecAssignment, worker_id: &str, status: ExecStatus, error_code: &str, error_message: &str, elapsed: Duration) -> Self {
+        let finished_at = chrono::Utc::now();
+        let started_at = finished_at - chrono::Duration::from_std(elapsed).unwrap_or_default();
+        Self {
+            latency_ms: elapsed.as_millis() as u64,
+            error_code: Some(error_code.to_string()),
+            error_message: Some(error_message.to_string()),
+            started_at: Some(started_at.to_rfc3339()),
+            finished_at: Some(finished_at.to_rfc3339()),
+            ..Self::from_assignment(assignment, worker_id, status)
+        }
+    }
+}
+
+/// Heartbeats list at most this many running assignments.
+pub const HEARTBEAT_MAX_TASKS: usize = 50;
+
+/// One running assignment as reported in a heartbeat.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HeartbeatTask {
+    pub assignment_id: String,
+    pub job_type: String,
+    pub running_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct WorkerHeartbeat {
     pub worker_id: String,
     pub timestamp: String,
     pub status: String, // e.g., "idle", "busy"
     pub load: f64,      // 0.0 to 1.0
+
+    /// Job types this worker will execute.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub max_concurrency: usize,
+    #[serde(default)]
+    pub in_flight: usize,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub git_sha: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+    /// Longest-running first; empty when `HEARTBEAT_INCLUDE_INFLIGHT=false`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_flight_tasks: Vec<HeartbeatTask>,
+    /// More assignments are running than `in_flight_tasks` lists.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub in_flight_truncated: bool,
+    #[serde(default)]
+    pub completed_total: u64,
+    /// Failed and timed-out tasks.
+    #[serde(default)]
+    pub failed_total: u64,
+    #[serde(default)]
+    pub uptime_s: u64,
+    /// When the last assignment finished; absent until one has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity_at: Option<String>,
+    /// Envelope versions accepted on the assign subjects, for controller rollouts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envelope_versions: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -145,6 +644,104 @@ pub fn map_status_to_task_state(s: &ExecStatus) -> TaskState {
         ExecStatus::Cancelled => TaskState::Cancelled,
     }
 }
+
+pub const SUPPORTED_ASSIGNMENT_VERSIONS: &[&str] = &["1.0"];
+
+/// Longest `assignment_id`, `request_id` or `tenant_id` accepted, in characters.
+pub const MAX_IDENTIFIER_LEN: usize = 256;
+
+/// Checks decoded assignments before they reach the executor.
+///
+/// Structural checks (well-formed identifiers, supported version, job type) always run;
+/// payload checks run only for job types that have a JSON Schema registered.
+#[derive(Debug, Clone, Default)]
+pub struct AssignmentValidator {
+    schemas: HashMap<String, jsonschema::Validator>,
+}
+
+impl AssignmentValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `<job_type>.json` schema files from `dir`.
+    pub fn from_schema_dir(dir: &str) -> Result<Self, String> {
+        let mut validator = Self::new();
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read schema dir {}: {}", dir, e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let job_type = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(s) => s.to_string(),
+                None => continue,
+            };
+            let raw = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read schema {}: {}", path.display(), e))?;
+            let schema: Value = serde_json::from_str(&raw)
+                .map_err(|e| format!("Invalid JSON in schema {}: {}", path.display(), e))?;
+            validator = validator.with_schema(&job_type, &schema)
+                .map_err(|e| format!("{} ({})", e, path.display()))?;
+        }
+        Ok(validator)
+    }
+
+    pub fn with_schema(mut self, job_type: &str, schema: &Value) -> Result<Self, String> {
+        let compiled = jsonschema::validator_for(schema)
+            .map_err(|e| format!("Invalid schema for job type {}: {}", job_type, e))?;
+        self.schemas.insert(job_type.to_string(), compiled);
+        Ok(self)
+    }
+
+    /// Returns every violation found, not just the first.
+    pub fn validate(&self, a: &ExecAssignment) -> Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+
+        if !SUPPORTED_ASSIGNMENT_VERSIONS.contains(&a.version.as_str()) {
+            violations.push(format!("unsupported version: {}", a.version));
+        }
+        let identifiers = [
+            ("assignment_id", &a.assignment_id),
+            ("request_id", &a.request_id),
+            ("tenant_id", &a.tenant_id),
+        ];
+        let keys = [("concurrency_key", &a.concurrency_key), ("idempotency_key", &a.idempotency_key)];
+        let keys = keys.into_iter().filter_map(|(field, key)| key.as_ref().map(|key| (field, key)));
+        for (field, value) in identifiers.into_iter().chain(keys) {
+            // The value itself is left out: it may be huge or break log lines
+            if value.trim().is_empty() {
+                violations.push(format!("{} must not be empty", field));
+            } else if value.chars().count() > MAX_IDENTIFIER_LEN {
+                violations.push(format!("{} must be at most {} characters", field, MAX_IDENTIFIER_LEN));
+            } else if value.chars().any(char::is_control) {
+                violations.push(format!("{} must not contain control characters", field));
+            }
+        }
+        if a.job.r#type.trim().is_empty() {
+            violations.push("job.type must not be empty".to_string());
+        }
+
+        if let Some(schema) = self.schemas.get(&a.job.r#type) {
+            for err in schema.iter_errors(&a.job.payload) {
+                let path = err.instance_path().to_string();
+                if path.is_empty() {
+                    violations.push(format!("job.payload: {}", err));
+                } else {
+                    violations.push(format!("job.payload{}: {}", path, err));
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,6 +762,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
         let env = EventEnvelopeV1::wrap_assignment(&assignment);
         assert!(matches!(env.kind, EnvelopeKind::ExecAssign));
@@ -185,10 +785,19 @@ mod tests {
             latency_ms: 100,
             cost: 0.0,
             trace_id: None,
+            trace_generated: None,
             tenant_id: None,
             run_id: None,
+            flow_id: None,
+            step_id: None,
             error_code: None,
             error_message: None,
+            retryable: false,
+            artifacts: Vec::new(),
+            queue_latency_ms: None,
+            serialization_wait_ms: None,
+            started_at: None,
+            finished_at: None,
         };
         let env = EventEnvelopeV1::wrap_result(&result);
         assert!(matches!(env.kind, EnvelopeKind::ExecResult));
@@ -211,6 +820,9 @@ mod tests {
             run_id: None,
             flow_id: None,
             step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
         };
 
         let json = serde_json::to_string(&assignment).unwrap();
@@ -233,10 +845,19 @@ mod tests {
             latency_ms: 100,
             cost: 0.0,
             trace_id: None,
+            trace_generated: None,
             tenant_id: None,
             run_id: None,
+            flow_id: None,
+            step_id: None,
             error_code: None,
             error_message: None,
+            retryable: false,
+            artifacts: Vec::new(),
+            queue_latency_ms: None,
+            serialization_wait_ms: None,
+            started_at: None,
+            finished_at: None,
         };
 
         let json = serde_json::to_string(&result).unwrap();
@@ -245,4 +866,386 @@ mod tests {
         let parsed: ExecResult = serde_json::from_str(&json).unwrap();
         matches!(parsed.status, ExecStatus::Success);
     }
+
+    fn sample_assignment() -> ExecAssignment {
+        ExecAssignment {
+            version: "1.0".to_string(),
+            assignment_id: "assign-1".to_string(),
+            request_id: "req-1".to_string(),
+            tenant_id: "tenant-1".to_string(),
+            job: Job {
+                r#type: "http".to_string(),
+                payload: json!({"url": "http://example.com"}),
+            },
+            trace_id: None,
+            run_id: None,
+            flow_id: None,
+            step_id: None,
+            published_at: None,
+            concurrency_key: None,
+            idempotency_key: None,
+        }
+    }
+
+    #[test]
+    fn test_validator_accepts_valid_assignment() {
+        let validator = AssignmentValidator::new();
+        assert!(validator.validate(&sample_assignment()).is_ok());
+    }
+
+    #[test]
+    fn test_validator_collects_structural_violations() {
+        let mut a = sample_assignment();
+        a.version = "9.9".to_string();
+        a.assignment_id = "".to_string();
+        a.tenant_id = "   ".to_string();
+        a.job.r#type = "".to_string();
+
+        let violations = AssignmentValidator::new().validate(&a).unwrap_err();
+        assert_eq!(violations.len(), 4);
+        assert!(violations.iter().any(|v| v.contains("unsupported version")));
+        assert!(violations.iter().any(|v| v.contains("assignment_id")));
+        assert!(violations.iter().any(|v| v.contains("tenant_id")));
+        assert!(violations.iter().any(|v| v.contains("job.type")));
+    }
+
+    #[test]
+    fn test_validator_bounds_identifiers() {
+        let mut a = sample_assignment();
+        a.assignment_id = "a".repeat(MAX_IDENTIFIER_LEN);
+        a.request_id = "é".repeat(MAX_IDENTIFIER_LEN);
+        assert!(AssignmentValidator::new().validate(&a).is_ok(), "the limit counts characters");
+
+        a.assignment_id.push('a');
+        a.tenant_id = "tenant\u{7}".to_string();
+        let violations = AssignmentValidator::new().validate(&a).unwrap_err();
+        assert_eq!(violations, vec![
+            "assignment_id must be at most 256 characters".to_string(),
+            "tenant_id must not contain control characters".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_validator_applies_payload_schema() {
+        let schema = json!({
+            "type": "object",
+            "required": ["url"],
+            "properties": {"url": {"type": "string"}}
+        });
+        let validator = AssignmentValidator::new().with_schema("http", &schema).unwrap();
+        assert!(validator.validate(&sample_assignment()).is_ok());
+
+        let mut a = sample_assignment();
+        a.job.payload = json!({"url": 42});
+        let violations = validator.validate(&a).unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("job.payload/url"));
+
+        // Job types without a schema are not payload-checked
+        a.job.r#type = "echo".to_string();
+        assert!(validator.validate(&a).is_ok());
+    }
+
+    #[test]
+    fn test_validator_loads_schema_dir() {
+        let dir = std::env::temp_dir().join(format!("worker-schemas-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("http.json"), r#"{"type": "object", "required": ["method"]}"#).unwrap();
+        std::fs::write(dir.join("README.txt"), "ignored").unwrap();
+
+        let validator = AssignmentValidator::from_schema_dir(dir.to_str().unwrap()).unwrap();
+        let violations = validator.validate(&sample_assignment()).unwrap_err();
+        assert_eq!(violations.len(), 1);
+
+        std::fs::write(dir.join("bad.json"), "{not json").unwrap();
+        assert!(AssignmentValidator::from_schema_dir(dir.to_str().unwrap()).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_heartbeat_wire_shape() {
+        let mut labels = HashMap::new();
+        labels.insert("region".to_string(), "eu".to_string());
+        let hb = WorkerHeartbeat {
+            worker_id: "worker-1".to_string(),
+            timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+            status: "busy".to_string(),
+            load: 0.5,
+            capabilities: vec!["echo".to_string(), "http".to_string()],
+            max_concurrency: 8,
+            in_flight: 4,
+            version: "0.1.0".to_string(),
+            git_sha: "0123abcd".to_string(),
+            labels,
+            in_flight_tasks: vec![HeartbeatTask { assignment_id: "a1".to_string(), job_type: "sql".to_string(), running_ms: 1500 }],
+            in_flight_truncated: true,
+            completed_total: 10,
+            failed_total: 2,
+            uptime_s: 3600,
+            last_activity_at: Some("2025-01-01T00:00:00+00:00".to_string()),
+            envelope_versions: vec!["v1".to_string()],
+        };
+        let v = serde_json::to_value(&hb).unwrap();
+        assert_eq!(v, json!({
+            "worker_id": "worker-1",
+            "timestamp": "2025-01-01T00:00:00+00:00",
+            "status": "busy",
+            "load": 0.5,
+            "capabilities": ["echo", "http"],
+            "max_concurrency": 8,
+            "in_flight": 4,
+            "version": "0.1.0",
+            "git_sha": "0123abcd",
+            "labels": {"region": "eu"},
+            "in_flight_tasks": [{"assignment_id": "a1", "job_type": "sql", "running_ms": 1500}],
+            "in_flight_truncated": true,
+            "completed_total": 10,
+            "failed_total": 2,
+            "uptime_s": 3600,
+            "last_activity_at": "2025-01-01T00:00:00+00:00",
+            "envelope_versions": ["v1"]
+        }));
+        let parsed: WorkerHeartbeat = serde_json::from_value(v).unwrap();
+        assert_eq!(parsed.capabilities, hb.capabilities);
+        assert_eq!(parsed.labels, hb.labels);
+        assert_eq!(parsed.in_flight_tasks, hb.in_flight_tasks);
+        assert!(parsed.in_flight_truncated);
+    }
+
+    #[test]
+    fn test_heartbeat_backward_compatible() {
+        // Heartbeats from older workers carry only the original fields
+        let old = json!({"worker_id": "w", "timestamp": "t", "status": "idle", "load": 0.0});
+        let parsed: WorkerHeartbeat = serde_json::from_value(old).unwrap();
+        assert!(parsed.capabilities.is_empty());
+        assert_eq!(parsed.max_concurrency, 0);
+        assert!(parsed.labels.is_empty());
+        assert!(parsed.in_flight_tasks.is_empty());
+        assert_eq!((parsed.completed_total, parsed.failed_total, parsed.uptime_s), (0, 0, 0));
+
+        // Empty optional fields are omitted on the wire
+        let v = serde_json::to_value(&parsed).unwrap();
+        assert!(v.get("capabilities").is_none());
+        assert!(v.get("version").is_none());
+        assert!(v.get("labels").is_none());
+        assert!(v.get("in_flight_tasks").is_none());
+        assert!(v.get("in_flight_truncated").is_none());
+    }
+
+    #[test]
+    fn test_decode_batch_partial_failure() {
+        let good = serde_json::to_value(sample_assignment()).unwrap();
+        let data = json!({
+            "batch_id": "b1",
+            "assignments": [good.clone(), {"assignment_id": "broken"}, good],
+        });
+        let batch = decode_batch(data, 10).unwrap();
+        assert_eq!(batch.batch_id, "b1");
+        assert!(!batch.summary);
+        assert_eq!(batch.assignments.len(), 2);
+        assert_eq!(batch.errors.len(), 1);
+        assert_eq!(batch.errors[0].0, 1);
+    }
+
+    #[test]
+    fn test_decode_batch_limits() {
+        let good = serde_json::to_value(sample_assignment()).unwrap();
+        let data = json!({"batch_id": "b1", "assignments": [good.clone(), good], "summary": true});
+        assert!(decode_batch(data.clone(), 1).is_err());
+        assert!(decode_batch(data, 2).unwrap().summary);
+        assert!(decode_batch(json!({"assignments": []}), 10).is_err());
+    }
+
+    #[test]
+    fn test_envelope_wrap_batch() {
+        let batch = ExecAssignBatch {
+            batch_id: "b1".to_string(),
+            assignments: vec![sample_assignment()],
+            summary: true,
+        };
+        let env = EventEnvelopeV1::wrap_batch(&batch);
+        let v = serde_json::to_value(&env).unwrap();
+        assert_eq!(v["kind"], "exec_assign_batch");
+        let decoded = decode_batch(env.data, 10).unwrap();
+        assert_eq!(decoded.assignments[0].assignment_id, "assign-1");
+    }
+
+    #[test]
+    fn test_deadletter_reads_legacy_shape() {
+        let legacy = r#"{"reason":"PARSE_ERROR","payload_ref":{"subject":"caf.exec.assign.v1","len":3},"ts":"2024-01-01T00:00:00Z"}"#;
+        let dlq: DeadLetter = serde_json::from_str(legacy).unwrap();
+        assert_eq!(dlq.reason, DeadLetterReason::ParseError);
+        assert!(dlq.payload_b64.is_none() && dlq.worker_id.is_none() && dlq.attempts.is_none());
+        assert!(!dlq.truncated);
+        assert!(!dlq.is_replayable());
+        // Nothing new is written back for an entry without the extra context
+        assert_eq!(serde_json::to_value(&dlq).unwrap().as_object().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_deadletter_round_trips_full_context() {
+        let dlq = DeadLetter::new(DeadLetterReason::PublishError, json!({"assignment_id": "a1"}))
+            .with_original("caf.exec.result.v1", b"{\"kind\":\"exec_result\"}", 1024)
+            .with_error("connection closed")
+            .with_worker("worker-1")
+            .with_attempts(6);
+        let line = serde_json::to_string(&dlq).unwrap();
+        assert!(!line.contains("truncated"));
+        let back: DeadLetter = serde_json::from_str(&line).unwrap();
+        assert_eq!(back.subject.as_deref(), Some("caf.exec.result.v1"));
+        assert_eq!(back.original_payload().unwrap(), b"{\"kind\":\"exec_result\"}");
+        assert_eq!(back.error_detail.as_deref(), Some("connection closed"));
+        assert_eq!(back.worker_id.as_deref(), Some("worker-1"));
+        assert_eq!(back.attempts, Some(6));
+        assert!(back.is_replayable());
+    }
+
+    #[test]
+    fn test_deadletter_truncates_large_payload() {
+        let dlq = DeadLetter::new(DeadLetterReason::ParseError, json!({})).with_original("caf.exec.assign.v1", &[b'x'; 100], 10);
+        assert!(dlq.truncated);
+        assert_eq!(dlq.original_payload().unwrap().len(), 10);
+        assert!(!dlq.is_replayable());
+        let back: DeadLetter = serde_json::from_str(&serde_json::to_string(&dlq).unwrap()).unwrap();
+        assert!(back.truncated);
+    }
+
+    #[test]
+    fn test_deadletter_reason_wire_strings() {
+        let cases = [
+            (DeadLetterReason::DecompressError, "DECOMPRESS_ERROR"),
+            (DeadLetterReason::SignatureInvalid, "SIGNATURE_INVALID"),
+            (DeadLetterReason::DecodeError, "DECODE_ERROR"),
+            (DeadLetterReason::ParseError, "PARSE_ERROR"),
+            (DeadLetterReason::ValidationError, "VALIDATION_ERROR"),
+            (DeadLetterReason::PublishError, "PUBLISH_ERROR"),
+            (DeadLetterReason::PayloadTooLarge, "PAYLOAD_TOO_LARGE"),
+            (DeadLetterReason::UnexpectedKind, "UNEXPECTED_KIND"),
+            (DeadLetterReason::UnsupportedVersion, "UNSUPPORTED_VERSION"),
+            (DeadLetterReason::EnvelopeRequired, "ENVELOPE_REQUIRED"),
+            (DeadLetterReason::Other("LEGACY_REASON".to_string()), "LEGACY_REASON"),
+        ];
+        for (reason, wire) in cases {
+            assert_eq!(serde_json::to_value(&reason).unwrap(), json!(wire));
+            assert_eq!(serde_json::from_value::<DeadLetterReason>(json!(wire)).unwrap(), reason);
+            assert_eq!(reason.as_str(), wire);
+            assert_eq!(reason.to_string(), wire);
+        }
+    }
+
+    /// Serializes to an error, like a map keyed by something other than strings.
+    struct Unencodable;
+
+    impl Serialize for Unencodable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("not representable as JSON"))
+        }
+    }
+
+    #[test]
+    fn test_encode_envelope_reports_unencodable_values() {
+        let env = EventEnvelopeV1::wrap_assignment(&sample_assignment());
+        let bytes = encode_envelope(&env).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&bytes).unwrap()["kind"], "exec_assign");
+
+        let err = encode_envelope(&Unencodable).unwrap_err();
+        assert!(err.contains("not representable as JSON"), "{}", err);
+        let keyed: HashMap<(u8, u8), u8> = [((1, 2), 3)].into_iter().collect();
+        assert!(encode_envelope(&keyed).unwrap_err().starts_with("failed to encode envelope"));
+    }
+
+    #[test]
+    fn test_queue_latency_from_published_at() {
+        let started = chrono::DateTime::parse_from_rfc3339("2026-01-01T00:00:02.500Z").unwrap().with_timezone(&chrono::Utc);
+        let mut assignment = sample_assignment();
+        // Older controllers send no published_at
+        assert_eq!(assignment.queue_latency_ms(started), None);
+        let old: ExecAssignment = serde_json::from_value(serde_json::to_value(&assignment).unwrap()).unwrap();
+        assert!(old.published_at.is_none());
+
+        assignment.published_at = Some("2026-01-01T00:00:00Z".to_string());
+        assert_eq!(assignment.queue_latency_ms(started), Some(2500));
+        assignment.published_at = Some("2026-01-01T01:00:03+01:00".to_string());
+        assert_eq!(assignment.queue_latency_ms(started), Some(-500), "controller clock ahead");
+        assignment.published_at = Some("yesterday".to_string());
+        assert_eq!(assignment.queue_latency_ms(started), None);
+    }
+
+    #[test]
+    fn test_envelope_kind_wire_names() {
+        for kind in [EnvelopeKind::ExecAssign, EnvelopeKind::ExecAssignBatch, EnvelopeKind::ExecBatchSummary, EnvelopeKind::ExecResult, EnvelopeKind::Heartbeat, EnvelopeKind::DeadLetter, EnvelopeKind::ExecReceipt] {
+            assert_eq!(serde_json::to_value(&kind).unwrap(), json!(kind.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_decode_envelope_checks_the_version() {
+        let accepted = vec!["v1".to_string()];
+        let v1 = json!({"version": "v1", "kind": "exec_assign", "data": {"assignment_id": "a1"}});
+        let env = decode_envelope(&serde_json::to_vec(&v1).unwrap(), &accepted).unwrap();
+        assert!(matches!(env.kind, EnvelopeKind::ExecAssign));
+
+        let v2 = json!({"version": "v2", "kind": "exec_assign", "data": {"shape": "different"}});
+        assert_eq!(decode_envelope(&serde_json::to_vec(&v2).unwrap(), &accepted).unwrap_err(), EnvelopeError::UnsupportedVersion("v2".to_string()));
+        let widened = vec!["v1".to_string(), "v2".to_string()];
+        assert!(decode_envelope(&serde_json::to_vec(&v2).unwrap(), &widened).is_ok());
+
+        let missing = json!({"kind": "exec_assign", "data": {}});
+        assert_eq!(decode_envelope(&serde_json::to_vec(&missing).unwrap(), &accepted).unwrap_err(), EnvelopeError::UnsupportedVersion(String::new()));
+
+        // Bare assignments and garbage aren't envelopes at all
+        let bare = json!({"assignment_id": "a1", "version": "1.0"});
+        assert!(matches!(decode_envelope(&serde_json::to_vec(&bare).unwrap(), &accepted), Err(EnvelopeError::Malformed(_))));
+        assert!(matches!(decode_envelope(b"not json", &accepted), Err(EnvelopeError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_decode_assignment_with_and_without_bare_fallback() {
+        let accepted = vec!["v1".to_string()];
+        let bare = serde_json::to_vec(&json!({
+            "version": "1.0", "assignment_id": "a1", "request_id": "r1", "tenant_id": "t1",
+            "job": {"type": "echo", "payload": {}}
+        })).unwrap();
+        let envelope = serde_json::to_vec(&json!({"version": "v1", "kind": "exec_assign", "data": {"assignment_id": "a1"}})).unwrap();
+        let truncated = &envelope[..envelope.len() - 5];
+        let not_an_assignment = br#"{"assignment_id": "a1"}"#;
+
+        for allow_bare in [true, false] {
+            assert!(matches!(decode_assignment(&envelope, &accepted, allow_bare), Ok(IncomingAssignment::Envelope(_))));
+            assert!(matches!(decode_assignment(truncated, &accepted, allow_bare), Err(AssignmentDecodeError::Malformed(_))));
+        }
+        match decode_assignment(&bare, &accepted, true) {
+            Ok(IncomingAssignment::Bare(a)) => assert_eq!(a.assignment_id, "a1"),
+            other => panic!("expected a bare assignment, got {:?}", other),
+        }
+        assert_eq!(decode_assignment(&bare, &accepted, false).unwrap_err(), AssignmentDecodeError::EnvelopeRequired);
+
+        // Invalid bare payloads report the assignment error only when bare ones are accepted
+        let Err(AssignmentDecodeError::Malformed(lenient)) = decode_assignment(not_an_assignment, &accepted, true) else { panic!() };
+        assert!(lenient.contains("missing field"), "{}", lenient);
+        let Err(AssignmentDecodeError::Malformed(strict)) = decode_assignment(not_an_assignment, &accepted, false) else { panic!() };
+        assert_eq!(strict, "missing kind or data");
+    }
+
+    #[test]
+    fn test_results_from_assignments_round_trip_flow_and_step() {
+        let mut assignment = sample_assignment();
+        assignment.run_id = Some("run-1".to_string());
+        assignment.flow_id = Some("flow-1".to_string());
+        assignment.step_id = Some("step-2".to_string());
+        let result = ExecResult::from_assignment(&assignment, "worker-1", ExecStatus::Success);
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!((json["flow_id"].as_str(), json["step_id"].as_str(), json["run_id"].as_str()), (Some("flow-1"), Some("step-2"), Some("run-1")));
+        let parsed: ExecResult = serde_json::from_value(json).unwrap();
+        assert_eq!((parsed.flow_id.as_deref(), parsed.step_id.as_deref()), (Some("flow-1"), Some("step-2")));
+
+        let bare = serde_json::to_value(ExecResult::from_assignment(&sample_assignment(), "worker-1", ExecStatus::Error)).unwrap();
+        assert!(bare.get("flow_id").is_none() && bare.get("step_id").is_none());
+        let old: ExecResult = serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": "a", "request_id": "r", "status": "success",
+            "provider_id": "w", "job_type": "echo", "latency_ms": 1, "cost": 0.0
+        })).unwrap();
+        assert!(old.flow_id.is_none() && old.step_id.is_none());
+    }
 }
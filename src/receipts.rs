@@ -0,0 +1,112 @@
+use crate::config::Config;
+use crate::observability::metrics::Metrics;
+use crate::observability::Logger;
+use crate::pipeline::{self, ResultPublisher};
+use crate::protocol::{EventEnvelopeV1, ExecReceipt, ExecResult};
+use crate::rotation::{append_line, RotationPolicy};
+use crate::signing::{canonical_json, EnvelopeSigner};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+/// Hex sha256 of `value`'s canonical JSON, so the same document hashes alike on every worker
+/// whatever order its keys arrived in.
+pub fn content_hash(value: &Value) -> String {
+    Sha256::digest(canonical_json(value).as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The receipt for `result` of a job whose payload hashed to `input_hash`.
+pub fn receipt(input_hash: String, result: &ExecResult) -> ExecReceipt {
+    ExecReceipt {
+        assignment_id: result.assignment_id.clone(),
+        tenant_id: result.tenant_id.clone(),
+        job_type: result.job_type.clone(),
+        status: result.status.clone(),
+        input_hash,
+        output_hash: result.output.as_ref().map(content_hash),
+        started_at: result.started_at.clone(),
+        finished_at: result.finished_at.clone(),
+        worker_id: result.provider_id.clone(),
+    }
+}
+
+/// Sends receipts to the `RECEIPTS_SINK`s. Failures are counted and logged, never returned:
+/// a receipt that cannot be written must not change what happened to the job.
+pub struct Receipts {
+    config: Arc<Config>,
+    publisher: Arc<dyn ResultPublisher>,
+    signer: Option<EnvelopeSigner>,
+    metrics: Arc<Metrics>,
+    logger: Logger,
+    /// Keeps concurrent appends and rotations of `RECEIPTS_FILE` apart.
+    file_lock: Arc<Mutex<()>>,
+}
+
+impl Receipts {
+    pub fn new(config: Arc<Config>, publisher: Arc<dyn ResultPublisher>, signer: Option<EnvelopeSigner>, metrics: Arc<Metrics>, logger: Logger) -> Self {
+        Self { config, publisher, signer, metrics, logger, file_lock: Arc::new(Mutex::new(())) }
+    }
+
+    pub async fn emit(&self, receipt: &ExecReceipt) {
+        let envelope = EventEnvelopeV1::wrap_receipt(receipt).signed(self.signer.as_ref());
+        let sink = self.config.receipts_sink;
+        if sink.publishes() {
+            let published = pipeline::publish_encoded(self.publisher.as_ref(), &self.config, &self.config.caf_receipt_subject, &envelope).await;
+            self.count("nats", &receipt.assignment_id, published);
+        }
+        if sink.writes_file() {
+            let written = match serde_json::to_string(&envelope) {
+                Ok(line) => {
+                    let (path, lock) = (self.config.receipts_file.clone(), self.file_lock.clone());
+                    let policy = RotationPolicy {
+                        max_bytes: self.config.receipts_file_max_bytes,
+                        max_rotations: self.config.receipts_file_max_rotations,
+                        total_max_bytes: u64::MAX,
+                        max_age_days: None,
+                        compress: false,
+                    };
+                    tokio::task::spawn_blocking(move || {
+                        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                        append_line(&path, &line, &policy).map_err(|e| e.to_string())
+                    }).await.unwrap_or_else(|e| Err(e.to_string()))
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            self.count("file", &receipt.assignment_id, written);
+        }
+    }
+
+    fn count(&self, sink: &str, assignment_id: &str, outcome: Result<(), String>) {
+        match outcome {
+            Ok(()) => self.metrics.receipts_written_total.with_label_values(&[sink]).inc(),
+            Err(e) => {
+                self.metrics.receipt_failures_total.with_label_values(&[sink]).inc();
+                self.logger.warn("Failed to emit execution receipt", Some(&json!({
+                    "sink": sink,
+                    "assignment_id": assignment_id,
+                    "error": e
+                })));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash_ignores_key_order() {
+        let a = json!({"b": [{"y": null, "x": true}], "a": 1});
+        let b: Value = serde_json::from_str(r#"{"a":1,"b":[{"x":true,"y":null}]}"#).unwrap();
+        assert_eq!(content_hash(&a), content_hash(&b));
+        assert_ne!(content_hash(&a), content_hash(&json!({"a": 1, "b": [{"x": false, "y": null}]})));
+        assert_ne!(content_hash(&json!([1, 2])), content_hash(&json!([2, 1])), "array order is content");
+    }
+
+    #[test]
+    fn test_content_hash_is_sha256_of_canonical_json() {
+        // Pinned so a change to canonicalization shows up here rather than as receipts that no longer match
+        assert_eq!(content_hash(&json!({"b": [{"y": null, "x": true}], "a": 1})), "2dac4741eb80b6c09664b7ac7c832229f796e67998caf77a28be7463ba8b4a91");
+    }
+}
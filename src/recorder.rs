@@ -0,0 +1,206 @@
+use crate::executor::Executor;
+use crate::handlers::diff;
+use crate::observability::pii::PiiMasker;
+use crate::protocol::{EventEnvelopeV1, ExecAssignment, ExecResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Stands in for a payload or output over `RECORD_MAX_PAYLOAD_BYTES`, holding its size. An
+/// assignment recorded this way can be read but not replayed.
+pub const OMITTED_KEY: &str = "omitted_bytes";
+
+/// Result fields that change on every run, left out when a replay is compared.
+pub const VOLATILE_RESULT_FIELDS: &[&str] = &[
+    "provider_id",
+    "latency_ms",
+    "cost",
+    "started_at",
+    "finished_at",
+    "queue_latency_ms",
+    "serialization_wait_ms",
+    "trace_generated",
+];
+
+/// One assignment and the result the worker published for it, as `worker replay` reads it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub recorded_at: String,
+    pub assignment: EventEnvelopeV1,
+    pub result: EventEnvelopeV1,
+}
+
+/// What `worker replay` prints: the recorded and replayed statuses and a `json_diff` of the
+/// results without `VOLATILE_RESULT_FIELDS`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReport {
+    pub assignment_id: String,
+    pub recorded_status: Value,
+    pub replayed_status: Value,
+    pub comparison: Value,
+}
+
+impl ReplayReport {
+    pub fn equal(&self) -> bool {
+        self.comparison["equal"] == true
+    }
+}
+
+/// Writes `RECORD_DIR/<date>/<assignment_id>.json` for each finished assignment, payload and
+/// output masked like logs. A redelivered assignment overwrites its earlier recording.
+pub struct Recorder {
+    dir: PathBuf,
+    max_payload_bytes: usize,
+    masker: PiiMasker,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, max_payload_bytes: usize, masker: PiiMasker) -> Self {
+        Self { dir, max_payload_bytes, masker }
+    }
+
+    /// The recording for `assignment` and its `result`, scrubbed and size-capped.
+    pub fn recording(&self, assignment: &ExecAssignment, result: &ExecResult) -> Recording {
+        let mut assignment = assignment.clone();
+        assignment.job.payload = self.scrub(&assignment.job.payload);
+        let mut result = result.clone();
+        result.output = result.output.as_ref().map(|output| self.scrub(output));
+        Recording {
+            recorded_at: chrono::Utc::now().to_rfc3339(),
+            assignment: EventEnvelopeV1::wrap_assignment(&assignment),
+            result: EventEnvelopeV1::wrap_result(&result),
+        }
+    }
+
+    /// Writes the recording through a temporary file, so a reader never sees half of one.
+    pub fn record(&self, assignment: &ExecAssignment, result: &ExecResult) -> std::io::Result<PathBuf> {
+        let recording = self.recording(assignment, result);
+        let dir = self.dir.join(chrono::Utc::now().format("%Y-%m-%d").to_string());
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", file_stem(&assignment.assignment_id)));
+        let tmp = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        serde_json::to_writer_pretty(&mut file, &recording)?;
+        file.write_all(b"\n")?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    /// Runs the recorded assignment through `executor` and compares the results. Outputs are
+    /// scrubbed the same way first, so masked fields match.
+    pub async fn replay(&self, executor: &Executor, recording: &Recording) -> Result<ReplayReport, String> {
+        let assignment: ExecAssignment = serde_json::from_value(recording.assignment.data.clone())
+            .map_err(|e| format!("the recorded assignment does not decode: {}", e))?;
+        if assignment.job.payload.get(OMITTED_KEY).is_some() {
+            return Err(format!("the payload of {} was too large to record and cannot be replayed", assignment.assignment_id));
+        }
+        let assignment_id = assignment.assignment_id.clone();
+        let mut replayed = executor.execute(assignment).await;
+        replayed.output = replayed.output.as_ref().map(|output| self.scrub(output));
+        let mut recorded = recording.result.data.clone();
+        let mut replayed = serde_json::to_value(&replayed).map_err(|e| e.to_string())?;
+        for result in [&mut recorded, &mut replayed] {
+            if let Some(fields) = result.as_object_mut() {
+                VOLATILE_RESULT_FIELDS.iter().for_each(|field| { fields.remove(*field); });
+            }
+        }
+        Ok(ReplayReport {
+            assignment_id,
+            recorded_status: recorded["status"].clone(),
+            replayed_status: replayed["status"].clone(),
+            comparison: diff::diff_values(&recorded, &replayed, &[]),
+        })
+    }
+
+    fn scrub(&self, value: &Value) -> Value {
+        let bytes = serde_json::to_vec(value).map(|v| v.len()).unwrap_or(usize::MAX);
+        if bytes > self.max_payload_bytes {
+            return json!({ OMITTED_KEY: bytes });
+        }
+        self.masker.mask_value(value)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Recording, String> {
+    let raw = std::fs::read(path).map_err(|e| format!("cannot read recording {}: {}", path.display(), e))?;
+    serde_json::from_slice(&raw).map_err(|e| format!("{} is not a recording: {}", path.display(), e))
+}
+
+/// Assignment ids may hold characters a file name cannot.
+fn file_stem(assignment_id: &str) -> String {
+    assignment_id.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assignment(id: &str, job: Value) -> ExecAssignment {
+        serde_json::from_value(json!({
+            "version": "1.0", "assignment_id": id, "request_id": "r1", "tenant_id": "t1", "job": job,
+            "trace_id": "0af7651916cd43dd8448eb211c80319c", "flow_id": "f1", "step_id": "s1"
+        })).unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_recorded_echo_replays_without_differences() {
+        let dir = temp_dir();
+        let executor = Executor::new("w1".to_string(), dir.to_string_lossy().to_string());
+        let recorder = Recorder::new(dir.clone(), 64 * 1024, PiiMasker::default());
+        let echo = assignment("a/1", json!({"type": "echo", "payload": {"note": "mail ops@example.com", "token": "s3cret", "n": 1}}));
+        let result = executor.execute(echo.clone()).await;
+
+        let path = recorder.record(&echo, &result).unwrap();
+        assert_eq!(path.file_name().unwrap(), "a_1.json");
+        assert_eq!(path.parent().unwrap().parent().unwrap(), dir);
+        let recording = load(&path).unwrap();
+        let payload = &recording.assignment.data["job"]["payload"];
+        assert_eq!((payload["token"].as_str(), payload["n"].as_i64()), (Some("***"), Some(1)));
+        assert!(!payload["note"].as_str().unwrap().contains("ops@example.com"));
+        assert_eq!(recording.result.data["flow_id"], "f1");
+
+        let report = recorder.replay(&executor, &recording).await.unwrap();
+        assert!(report.equal(), "{}", report.comparison);
+        assert_eq!((report.recorded_status.as_str(), report.replayed_status.as_str()), (Some("success"), Some("success")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_changed_results() {
+        let dir = temp_dir();
+        let executor = Executor::new("w1".to_string(), dir.to_string_lossy().to_string());
+        let recorder = Recorder::new(dir.clone(), 64 * 1024, PiiMasker::default());
+        let echo = assignment("a2", json!({"type": "echo", "payload": {"n": 1}}));
+        let mut result = executor.execute(echo.clone()).await;
+        result.output = Some(json!({"n": 2}));
+
+        let report = recorder.replay(&executor, &recorder.recording(&echo, &result)).await.unwrap();
+        assert!(!report.equal());
+        assert_eq!(report.comparison["differences"], json!([{"path": "output.n", "kind": "value", "left": 2, "right": 1}]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_payloads_are_omitted_and_refuse_replay() {
+        let dir = temp_dir();
+        let executor = Executor::new("w1".to_string(), dir.to_string_lossy().to_string());
+        let recorder = Recorder::new(dir.clone(), 64, PiiMasker::default());
+        let echo = assignment("a3", json!({"type": "echo", "payload": {"blob": "x".repeat(100)}}));
+        let result = executor.execute(echo.clone()).await;
+
+        let recording = recorder.recording(&echo, &result);
+        let size = serde_json::to_vec(&echo.job.payload).unwrap().len();
+        assert_eq!(recording.assignment.data["job"]["payload"], json!({OMITTED_KEY: size}));
+        assert!(recording.result.data["output"][OMITTED_KEY].as_u64() > Some(64));
+        assert!(recorder.replay(&executor, &recording).await.unwrap_err().contains("too large"));
+        assert!(load(&dir.join("missing.json")).unwrap_err().contains("cannot read"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::observability::pii::{PiiMasker, REDACTED, TRUNCATED};
+use serde_json::Value;
+
+/// Response headers scrubbed from outputs unless `RESULT_REDACT_HEADERS` says otherwise.
+pub const DEFAULT_REDACT_HEADERS: &[&str] = &["set-cookie", "authorization", "proxy-authorization"];
+
+/// Payload key a job sets to `false` to keep its output as produced; only honoured when
+/// the worker runs with `RESULT_REDACTION_OPTIONAL=true`.
+pub const REDACT_KEY: &str = "redact";
+
+/// String bytes `RESULT_MASK_PII` runs the patterns over per output. Strings past the
+/// budget are replaced whole rather than left unmasked.
+pub const PII_SCAN_MAX_BYTES: usize = 1024 * 1024;
+
+/// Scrubbing applied to every output before the result leaves the worker.
+#[derive(Debug, Clone)]
+pub struct ResultRedaction {
+    /// Lowercased names, matched case-insensitively against the output's `headers` object.
+    headers: Vec<String>,
+    /// Set when `RESULT_MASK_PII` is on.
+    masker: Option<PiiMasker>,
+    /// Honour a job's `"redact": false`.
+    optional: bool,
+}
+
+impl Default for ResultRedaction {
+    fn default() -> Self {
+        Self::new(DEFAULT_REDACT_HEADERS.iter().map(|h| h.to_string()).collect(), false, false)
+    }
+}
+
+impl ResultRedaction {
+    pub fn new(headers: Vec<String>, mask_pii: bool, optional: bool) -> Self {
+        Self {
+            headers: headers.into_iter().map(|h| h.to_ascii_lowercase()).collect(),
+            masker: mask_pii.then(|| PiiMasker::new(Vec::new())),
+            optional,
+        }
+    }
+
+    /// Whether `payload` leaves its output alone, which it may only here if optional.
+    pub fn skips(&self, payload: &Value) -> bool {
+        self.optional && payload.get(REDACT_KEY) == Some(&Value::Bool(false))
+    }
+
+    /// Masks the listed headers under a top-level `headers` object (as the `http` handler
+    /// returns them), then runs the string fields through the PII patterns if enabled.
+    pub fn apply(&self, output: &mut Value) {
+        if let Some(Value::Object(headers)) = output.get_mut("headers") {
+            for (name, value) in headers.iter_mut() {
+                if self.headers.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                    *value = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+        if let Some(masker) = &self.masker {
+            let mut budget = PII_SCAN_MAX_BYTES;
+            mask_strings(masker, output, &mut budget);
+        }
+    }
+}
+
+fn mask_strings(masker: &PiiMasker, value: &mut Value, budget: &mut usize) {
+    match value {
+        Value::String(s) if s.len() > *budget => *s = TRUNCATED.to_string(),
+        Value::String(s) => {
+            *budget -= s.len();
+            *s = masker.mask_str(s);
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| mask_strings(masker, item, budget)),
+        Value::Object(map) => map.values_mut().for_each(|item| mask_strings(masker, item, budget)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_listed_headers_are_masked_case_insensitively() {
+        let mut output = json!({
+            "status": 200,
+            "headers": {"Set-Cookie": ["a=1", "b=2"], "content-type": "text/plain", "authorization": "Bearer x"},
+            "body": "Set-Cookie: a=1"
+        });
+        ResultRedaction::default().apply(&mut output);
+        assert_eq!(output["headers"], json!({"Set-Cookie": "***", "content-type": "text/plain", "authorization": "***"}));
+        // Bodies are only touched by PII masking
+        assert_eq!(output["body"], "Set-Cookie: a=1");
+    }
+
+    #[test]
+    fn test_pii_masking_walks_strings_within_budget() {
+        let redaction = ResultRedaction::new(Vec::new(), true, false);
+        let mut output = json!({"rows": [{"email": "a@b.com", "n": 1}], "note": "ask x@y.org"});
+        redaction.apply(&mut output);
+        assert_eq!(output, json!({"rows": [{"email": "***@***.***", "n": 1}], "note": "ask ***@***.***"}));
+
+        let mut output = json!(["x".repeat(PII_SCAN_MAX_BYTES), "a@b.com"]);
+        redaction.apply(&mut output);
+        assert_eq!(output[1], TRUNCATED);
+    }
+
+    #[test]
+    fn test_opt_out_needs_the_worker_setting() {
+        let payload = json!({"url": "http://x", "redact": false});
+        assert!(!ResultRedaction::default().skips(&payload));
+        assert!(ResultRedaction::new(Vec::new(), false, true).skips(&payload));
+        assert!(!ResultRedaction::new(Vec::new(), false, true).skips(&json!({"redact": true})));
+    }
+}
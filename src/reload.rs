@@ -0,0 +1,168 @@
+use crate::config::{Config, DynamicConfig, DYNAMIC_FIELDS};
+use crate::observability::Logger;
+use arc_swap::ArcSwap;
+use serde_json::{json, Map, Value};
+use std::sync::{Arc, Mutex};
+
+type Listener = Box<dyn Fn(&DynamicConfig) + Send + Sync>;
+
+/// Re-reads the configuration on SIGHUP or `POST /admin/reload` and publishes the
+/// reloadable part.
+///
+/// Readers take `dynamic()` snapshots; components that need to act on a change (the
+/// concurrency limit, the logger, the executor's timeouts) register with `on_change`.
+pub struct ConfigReloader {
+    current: Mutex<Config>,
+    dynamic: ArcSwap<DynamicConfig>,
+    listeners: Mutex<Vec<Listener>>,
+    logger: Logger,
+}
+
+impl ConfigReloader {
+    pub fn new(config: Config, logger: Logger) -> Self {
+        Self {
+            dynamic: ArcSwap::from_pointee(config.dynamic()),
+            current: Mutex::new(config),
+            listeners: Mutex::new(Vec::new()),
+            logger,
+        }
+    }
+
+    pub fn dynamic(&self) -> Arc<DynamicConfig> {
+        self.dynamic.load_full()
+    }
+
+    pub fn on_change<F: Fn(&DynamicConfig) + Send + Sync + 'static>(&self, listener: F) {
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(listener));
+    }
+
+    /// Loads the environment and config file again and applies the result, logging the outcome.
+    pub fn reload(&self, trigger: &str) -> Result<Map<String, Value>, Vec<String>> {
+        let result = Config::from_env_all_errors().and_then(|config| self.apply(config));
+        match &result {
+            Ok(changes) => self.logger.info("Configuration reloaded", Some(&json!({"trigger": trigger, "changes": changes}))),
+            Err(errors) => self.logger.error("Configuration reload rejected", Some(&json!({"trigger": trigger, "errors": errors}))),
+        }
+        result
+    }
+
+    /// Swaps in `next` if it only differs in reloadable fields. Returns `{field: {from, to}}`.
+    pub fn apply(&self, mut next: Config) -> Result<Map<String, Value>, Vec<String>> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        if next.worker_id_generated && current.worker_id_generated {
+            next.worker_id = current.worker_id.clone();
+        }
+        let old = serde_json::to_value(&*current).unwrap_or(Value::Null);
+        let new = serde_json::to_value(&next).unwrap_or(Value::Null);
+        let changed = changed_fields(&old, &new);
+        let immutable: Vec<String> = changed.iter()
+            .filter(|field| !DYNAMIC_FIELDS.contains(&field.as_str()))
+            .map(|field| format!("{} cannot change without a restart", field))
+            .collect();
+        if !immutable.is_empty() {
+            return Err(immutable);
+        }
+
+        // Only reloadable fields are left, none of which are secrets
+        let changes: Map<String, Value> = changed.iter()
+            .map(|field| (field.clone(), json!({"from": old[field], "to": new[field]})))
+            .collect();
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let dynamic = Arc::new(next.dynamic());
+        self.dynamic.store(dynamic.clone());
+        for listener in self.listeners.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            listener(&dynamic);
+        }
+        *current = next;
+        Ok(changes)
+    }
+}
+
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = old.keys().chain(new.keys())
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::LogLevel;
+    use serial_test::serial;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn reloader() -> ConfigReloader {
+        std::env::set_var("WORKER_ID", "reload-test");
+        let config = Config::from_env().unwrap();
+        std::env::remove_var("WORKER_ID");
+        ConfigReloader::new(config, Logger::new("reload-test".to_string()))
+    }
+
+    #[test]
+    #[serial]
+    fn test_reload_swaps_dynamic_fields() {
+        let reloader = reloader();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let seen_by_listener = seen.clone();
+        reloader.on_change(move |dynamic| seen_by_listener.store(dynamic.max_concurrency, Ordering::SeqCst));
+
+        let mut next = reloader.current.lock().unwrap().clone();
+        next.max_concurrency = 32;
+        next.log_level = LogLevel::Debug;
+        let changes = reloader.apply(next).unwrap();
+
+        assert_eq!(changes.keys().collect::<Vec<_>>(), vec!["log_level", "max_concurrency"]);
+        assert_eq!(changes["max_concurrency"], json!({"from": 8, "to": 32}));
+        assert_eq!(reloader.dynamic().max_concurrency, 32);
+        assert_eq!(reloader.dynamic().log_level, LogLevel::Debug);
+        assert_eq!(seen.load(Ordering::SeqCst), 32);
+    }
+
+    #[test]
+    #[serial]
+    fn test_reload_rejects_immutable_and_invalid_changes() {
+        let reloader = reloader();
+        let mut next = reloader.current.lock().unwrap().clone();
+        next.max_concurrency = 2;
+        next.nats_url = "nats://elsewhere:4222".to_string();
+        let errors = reloader.apply(next).unwrap_err();
+        assert_eq!(errors, vec!["nats_url cannot change without a restart".to_string()]);
+        // Nothing is applied when any field is rejected
+        assert_eq!(reloader.dynamic().max_concurrency, 8);
+
+        std::env::set_var("WORKER_ID", "reload-test");
+        std::env::set_var("WORKER_MAX_CONCURRENCY", "0");
+        let result = reloader.reload("test");
+        std::env::remove_var("WORKER_MAX_CONCURRENCY");
+        std::env::remove_var("WORKER_ID");
+        assert!(result.unwrap_err()[0].contains("WORKER_MAX_CONCURRENCY"));
+        assert_eq!(reloader.dynamic().max_concurrency, 8);
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let old = json!({"a": 1, "b": {"x": 1}, "c": null});
+        let new = json!({"a": 1, "b": {"x": 2}, "d": true});
+        assert_eq!(changed_fields(&old, &new), vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_reload_changes_the_tenant_allowlist() {
+        let reloader = reloader();
+        let mut next = reloader.current.lock().unwrap().clone();
+        next.tenant_allowlist = vec!["acme".to_string()];
+        let changes = reloader.apply(next).unwrap();
+        assert_eq!(changes["tenant_allowlist"], json!({"from": [], "to": ["acme"]}));
+        assert_eq!(reloader.dynamic().tenant_allowlist, vec!["acme"]);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::observability::{metrics::Metrics, Logger};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Error code for assignments turned away while a resource threshold is crossed.
+pub const WORKER_RESOURCE_PRESSURE: &str = "WORKER_RESOURCE_PRESSURE";
+
+/// cgroup v1 reports "no limit" as a page-rounded `i64::MAX`; anything this large is unlimited.
+const CGROUP_UNLIMITED: u64 = 1 << 60;
+
+/// One reading; `None` where the platform doesn't say.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ResourceSample {
+    pub disk_free_bytes: Option<u64>,
+    pub rss_bytes: Option<u64>,
+    /// The cgroup memory limit, when the process runs under one.
+    pub memory_limit_bytes: Option<u64>,
+}
+
+pub trait ResourceSampler: Send + Sync {
+    fn sample(&self) -> ResourceSample;
+}
+
+/// Free space on the filesystem holding `dir`, RSS from `/proc/self/status` and the
+/// cgroup v2 (or v1) memory limit.
+pub struct SystemSampler {
+    dir: PathBuf,
+}
+
+impl SystemSampler {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl ResourceSampler for SystemSampler {
+    fn sample(&self) -> ResourceSample {
+        ResourceSample { disk_free_bytes: disk_free(&self.dir), rss_bytes: rss(), memory_limit_bytes: cgroup_memory_limit() }
+    }
+}
+
+#[cfg(unix)]
+// The statvfs field widths differ between platforms
+#[allow(clippy::unnecessary_cast)]
+fn disk_free(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is NUL-terminated and `stat` is a plain struct the call fills in
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn disk_free(_dir: &Path) -> Option<u64> {
+    None
+}
+
+fn rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn cgroup_memory_limit() -> Option<u64> {
+    ["/sys/fs/cgroup/memory.max", "/sys/fs/cgroup/memory/memory.limit_in_bytes"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .filter(|limit| *limit < CGROUP_UNLIMITED)
+}
+
+/// Samples disk and memory every few seconds and flips into pressure when
+/// `MIN_FREE_DISK_BYTES` or `MAX_RSS_BYTES` is crossed. While under pressure readiness is
+/// off and new assignments are answered with `WORKER_RESOURCE_PRESSURE`; running ones finish.
+pub struct ResourceMonitor {
+    sampler: Box<dyn ResourceSampler>,
+    min_free_disk_bytes: Option<u64>,
+    max_rss_bytes: Option<u64>,
+    pressure: AtomicBool,
+    last: Mutex<ResourceSample>,
+}
+
+impl ResourceMonitor {
+    pub fn new(sampler: Box<dyn ResourceSampler>, min_free_disk_bytes: Option<u64>, max_rss_bytes: Option<u64>) -> Self {
+        Self { sampler, min_free_disk_bytes, max_rss_bytes, pressure: AtomicBool::new(false), last: Mutex::new(ResourceSample::default()) }
+    }
+
+    pub fn under_pressure(&self) -> bool {
+        self.pressure.load(Ordering::SeqCst)
+    }
+
+    /// Takes a sample, updates the gauges and the pressure flag; returns the flag.
+    pub fn check(&self, metrics: &Metrics, logger: &Logger) -> bool {
+        let sample = self.sampler.sample();
+        if let Some(free) = sample.disk_free_bytes {
+            metrics.disk_free_bytes.set(free as i64);
+        }
+        if let Some(rss) = sample.rss_bytes {
+            metrics.process_rss_bytes.set(rss as i64);
+        }
+        let reasons = self.reasons(&sample);
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = sample;
+        let pressure = !reasons.is_empty();
+        if self.pressure.swap(pressure, Ordering::SeqCst) != pressure {
+            let context = json!({"reasons": reasons, "sample": sample});
+            if pressure {
+                logger.warn("Resource pressure, not accepting new assignments", Some(&context));
+            } else {
+                logger.info("Resource pressure cleared, accepting assignments again", Some(&context));
+            }
+        }
+        pressure
+    }
+
+    fn reasons(&self, sample: &ResourceSample) -> Vec<&'static str> {
+        let mut reasons = Vec::new();
+        if matches!((self.min_free_disk_bytes, sample.disk_free_bytes), (Some(min), Some(free)) if free < min) {
+            reasons.push("disk");
+        }
+        if matches!((self.max_rss_bytes, sample.rss_bytes), (Some(max), Some(rss)) if rss > max) {
+            reasons.push("memory");
+        }
+        reasons
+    }
+
+    /// The last sample and what it crossed, for `/_state`.
+    pub fn state(&self) -> Value {
+        let sample = *self.last.lock().unwrap_or_else(|e| e.into_inner());
+        json!({
+            "pressure": self.under_pressure(),
+            "reasons": self.reasons(&sample),
+            "disk_free_bytes": sample.disk_free_bytes,
+            "rss_bytes": sample.rss_bytes,
+            "memory_limit_bytes": sample.memory_limit_bytes,
+            "min_free_disk_bytes": self.min_free_disk_bytes,
+            "max_rss_bytes": self.max_rss_bytes,
+        })
+    }
+
+    /// Checks every `interval` until the task is dropped.
+    pub async fn run(self: Arc<Self>, interval: Duration, metrics: Arc<Metrics>, logger: Logger) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.check(&metrics, &logger);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays `samples`, repeating the last one.
+    struct FakeSampler(Mutex<Vec<ResourceSample>>);
+
+    impl ResourceSampler for FakeSampler {
+        fn sample(&self) -> ResourceSample {
+            let mut samples = self.0.lock().unwrap();
+            if samples.len() > 1 { samples.remove(0) } else { samples[0] }
+        }
+    }
+
+    fn sample(disk: u64, rss: u64) -> ResourceSample {
+        ResourceSample { disk_free_bytes: Some(disk), rss_bytes: Some(rss), memory_limit_bytes: None }
+    }
+
+    #[test]
+    fn test_thresholds_flip_pressure_and_recover() {
+        let metrics = Metrics::new();
+        let logger = Logger::new("resources-test".to_string());
+        let samples = vec![sample(500, 10), sample(50, 10), sample(500, 900), sample(500, 10)];
+        let monitor = ResourceMonitor::new(Box::new(FakeSampler(Mutex::new(samples))), Some(100), Some(800));
+
+        assert!(!monitor.check(&metrics, &logger));
+        assert_eq!(metrics.disk_free_bytes.get(), 500);
+        assert!(monitor.check(&metrics, &logger));
+        assert_eq!(monitor.state()["reasons"], json!(["disk"]));
+        assert!(monitor.check(&metrics, &logger));
+        assert_eq!(monitor.state()["reasons"], json!(["memory"]));
+        assert_eq!(metrics.process_rss_bytes.get(), 900);
+        assert!(!monitor.check(&metrics, &logger));
+        assert!(!monitor.under_pressure());
+    }
+
+    #[test]
+    fn test_no_thresholds_never_pressure() {
+        let monitor = ResourceMonitor::new(Box::new(FakeSampler(Mutex::new(vec![sample(0, u64::MAX)]))), None, None);
+        assert!(!monitor.check(&Metrics::new(), &Logger::new("resources-test".to_string())));
+    }
+
+    #[test]
+    fn test_system_sampler_reads_this_machine() {
+        let sample = SystemSampler::new(std::env::temp_dir()).sample();
+        if cfg!(target_os = "linux") {
+            assert!(sample.disk_free_bytes.is_some());
+            assert!(sample.rss_bytes.unwrap() > 0);
+        }
+    }
+}
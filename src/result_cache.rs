@@ -0,0 +1,111 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What a finished assignment left behind for redeliveries of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedResult {
+    /// The encoded result envelope and the subject it went to.
+    Envelope { subject: String, payload: Vec<u8> },
+    /// Finished, but its envelope was over the per-entry cap so only the fact is kept.
+    TooLarge,
+}
+
+struct Entry {
+    result: CachedResult,
+    stored: Instant,
+}
+
+/// Result envelopes of recently finished assignments, so a duplicate can be answered with the
+/// result the controller missed instead of being dropped.
+///
+/// Holds at most `capacity` entries of at most `max_entry_bytes` each, least recently used
+/// evicted first; entries older than `ttl` count as gone. A capacity of 0 keeps nothing.
+pub struct ResultCache {
+    capacity: usize,
+    ttl: Duration,
+    max_entry_bytes: usize,
+    inner: Mutex<(HashMap<String, Entry>, VecDeque<String>)>,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize, ttl: Duration, max_entry_bytes: usize) -> Self {
+        Self { capacity, ttl, max_entry_bytes, inner: Mutex::new((HashMap::new(), VecDeque::new())) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn insert(&self, assignment_id: &str, subject: &str, payload: Vec<u8>) {
+        if !self.is_enabled() {
+            return;
+        }
+        let result = if payload.len() > self.max_entry_bytes {
+            CachedResult::TooLarge
+        } else {
+            CachedResult::Envelope { subject: subject.to_string(), payload }
+        };
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        if entries.insert(assignment_id.to_string(), Entry { result, stored: Instant::now() }).is_some() {
+            order.retain(|id| id != assignment_id);
+        }
+        order.push_back(assignment_id.to_string());
+        while order.len() > self.capacity {
+            if let Some(old) = order.pop_front() {
+                entries.remove(&old);
+            }
+        }
+    }
+
+    /// The cached result, marking it recently used; expired entries are dropped on the way.
+    pub fn get(&self, assignment_id: &str) -> Option<CachedResult> {
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (entries, order) = &mut *guard;
+        let expired = entries.get(assignment_id)?.stored.elapsed() > self.ttl;
+        order.retain(|id| id != assignment_id);
+        if expired {
+            entries.remove(assignment_id);
+            return None;
+        }
+        order.push_back(assignment_id.to_string());
+        entries.get(assignment_id).map(|e| e.result.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(subject: &str, payload: &[u8]) -> CachedResult {
+        CachedResult::Envelope { subject: subject.to_string(), payload: payload.to_vec() }
+    }
+
+    #[test]
+    fn test_bounded_least_recently_used() {
+        let cache = ResultCache::new(2, Duration::from_secs(60), 16);
+        cache.insert("a1", "results", b"one".to_vec());
+        cache.insert("a2", "results", b"two".to_vec());
+        assert_eq!(cache.get("a1"), Some(envelope("results", b"one")));
+        cache.insert("a3", "results", b"three".to_vec());
+        assert_eq!(cache.get("a2"), None, "a1 was used more recently");
+        assert_eq!(cache.get("a1"), Some(envelope("results", b"one")));
+
+        cache.insert("a4", "results", vec![b'x'; 17]);
+        assert_eq!(cache.get("a4"), Some(CachedResult::TooLarge));
+
+        let disabled = ResultCache::new(0, Duration::from_secs(60), 16);
+        disabled.insert("a1", "results", b"one".to_vec());
+        assert_eq!(disabled.get("a1"), None);
+    }
+
+    #[test]
+    fn test_expired_entries_are_gone() {
+        let cache = ResultCache::new(4, Duration::from_millis(20), 16);
+        cache.insert("a1", "results", b"one".to_vec());
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(cache.get("a1"), None);
+        assert!(cache.inner.lock().unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::chaos::Chaos;
+use crate::compression;
+use crate::config::Config;
+use crate::dlq::DlqWriter;
+use crate::outbox::Outbox;
+use crate::observability::{Logger, metrics::{Metrics, TaskTimings}};
+use crate::pipeline::{self, BatchTracker, PublishFailure, ResultPublisher};
+use crate::protocol::{self, DeadLetter, DeadLetterReason, EventEnvelopeV1, ExecResult};
+use crate::retry;
+use crate::signing::EnvelopeSigner;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
+
+/// A finished assignment's result, handed from its task to the publisher task.
+pub struct QueuedResult {
+    pub subject: String,
+    pub envelope: EventEnvelopeV1,
+    pub result: ExecResult,
+    /// Carries the assignment's log context.
+    pub logger: Logger,
+    /// Observed once the publish has completed, so totals still run to publish-complete.
+    pub timings: TaskTimings,
+    pub batch: Option<Arc<BatchTracker>>,
+    pub span: tracing::Span,
+    /// Cleared once the result is published, or dead-lettered as unpublishable.
+    pub outbox: Option<Arc<Outbox>>,
+}
+
+enum Command {
+    Publish(Box<QueuedResult>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Everything the publisher task needs to send a result or dead-letter it.
+struct Sender {
+    config: Arc<Config>,
+    publisher: Arc<dyn ResultPublisher>,
+    dlq_writer: Arc<DlqWriter>,
+    metrics: Arc<Metrics>,
+    signer: Option<EnvelopeSigner>,
+    chaos: Option<Arc<Chaos>>,
+}
+
+/// Bounded queue in front of a single task that publishes results.
+///
+/// Assignment tasks enqueue their result and release their permit right away, so a NATS outage
+/// costs one task sitting in retry backoff instead of every running assignment. Results are
+/// published in the order they were queued, which is no longer the order assignments arrived
+/// in; they already finished out of order, so nothing relied on it.
+#[derive(Clone)]
+pub struct ResultQueue {
+    tx: mpsc::Sender<Command>,
+    sender: Arc<Sender>,
+}
+
+impl ResultQueue {
+    /// Spawns the publisher task with room for `config.result_queue_capacity` results.
+    pub fn start(config: Arc<Config>, publisher: Arc<dyn ResultPublisher>, dlq_writer: Arc<DlqWriter>, metrics: Arc<Metrics>, signer: Option<EnvelopeSigner>) -> Self {
+        let (tx, mut rx) = mpsc::channel(config.result_queue_capacity);
+        let chaos = Chaos::from_settings(config.chaos.as_ref());
+        let sender = Arc::new(Sender { config, publisher, dlq_writer, metrics, signer, chaos });
+        let task_sender = sender.clone();
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                match command {
+                    Command::Publish(item) => {
+                        task_sender.metrics.result_queue_depth.dec();
+                        task_sender.send(*item).await;
+                    }
+                    Command::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self { tx, sender }
+    }
+
+    /// Waits for room while the queue is full. Publishes inline if the publisher task is gone.
+    pub async fn enqueue(&self, item: QueuedResult) {
+        self.sender.metrics.result_queue_depth.inc();
+        if let Err(mpsc::error::SendError(Command::Publish(item))) = self.tx.send(Command::Publish(Box::new(item))).await {
+            self.sender.metrics.result_queue_depth.dec();
+            self.sender.send(*item).await;
+        }
+    }
+
+    /// Waits up to `deadline` for every result queued before the call to be published or
+    /// dead-lettered. Returns false if the deadline passed first.
+    pub async fn flush(&self, deadline: Duration) -> bool {
+        let (done_tx, done) = oneshot::channel();
+        let flushed = async { self.tx.send(Command::Flush(done_tx)).await.is_ok() && done.await.is_ok() };
+        tokio::time::timeout(deadline, flushed).await.unwrap_or(false)
+    }
+}
+
+impl Sender {
+    /// Publishes with retries, dead-letters the envelope on give-up, then closes out the batch entry.
+    async fn send(&self, item: QueuedResult) {
+        let QueuedResult { subject, envelope, result, logger, mut timings, batch, span, outbox } = item;
+        let clear_outbox = || {
+            if let Some(Err(e)) = outbox.as_ref().map(|outbox| outbox.published(&result.assignment_id)) {
+                logger.error("Failed to clear outbox entry", Some(&json!({"error": e.to_string()})));
+            }
+        };
+        let config = &self.config;
+        let metrics = &self.metrics;
+        async {
+            if self.chaos.as_ref().is_some_and(|chaos| chaos.drop_result(metrics)) {
+                logger.warn("Result dropped by CHAOS_DROP_RESULT_RATE", Some(&json!({"error_code": crate::chaos::CHAOS_ERROR_CODE})));
+                return;
+            }
+            match protocol::encode_envelope(&envelope) {
+                Ok(encoded) => {
+                    let (headers, payload) = compression::encode_for_publish(&encoded, config.envelope_compress_threshold_bytes);
+                    if let Some(max) = self.publisher.max_payload().filter(|max| payload.len() > *max) {
+                        // The server would refuse it on every attempt, so skip the retries
+                        metrics.result_payload_too_large_total.inc();
+                        logger.error("Result exceeds the server max_payload, sending to DLQ", Some(&json!({
+                            "bytes": payload.len(),
+                            "max_payload": max
+                        })));
+                        let dlq = DeadLetter::new(DeadLetterReason::PayloadTooLarge, json!({
+                            "assignment_id": result.assignment_id,
+                            "trace_id": result.trace_id,
+                            "len": payload.len(),
+                            "max_payload": max
+                        }))
+                            .with_original(&subject, &encoded, config.dlq_max_payload_bytes)
+                            .with_worker(&config.worker_id)
+                            .with_tenant(result.tenant_id.as_deref())
+                            .with_attempts(0);
+                        pipeline::publish_deadletter(&dlq, &self.dlq_writer, config, self.publisher.as_ref(), metrics).await;
+                        clear_outbox();
+                        return;
+                    }
+                    let mut attempts = 1_u32;
+                    let publish_started = std::time::Instant::now();
+                    let published = retry::retry_with_backoff(&config.result_publish_retry(), |e: &PublishFailure, retry, delay| {
+                        let we = &e.classified;
+                        if we.is_transient() {
+                            attempts += 1;
+                            logger.error("Publish transient error, retrying", Some(&json!({
+                                "attempt": retry,
+                                "error": e.error,
+                                "we_msg": we.message(),
+                                "kind": "transient",
+                                "backoff_ms": delay.as_millis() as u64
+                            })));
+                        }
+                        we.is_transient()
+                    }, |_| self.publisher.publish(&subject, headers.as_ref(), &payload)).await;
+                    match published {
+                        Ok(_) => {
+                            clear_outbox();
+                            logger.info("Result published", Some(&json!({
+                                "status": format!("{:?}", result.status),
+                                "latency_ms": result.latency_ms
+                            })));
+                        }
+                        Err(e) => {
+                            let we = &e.classified;
+                            metrics.result_publish_failures_total.inc();
+                            logger.error("Publish failed, sending to DLQ", Some(&json!({
+                                "error": e.error,
+                                "we_msg": we.message(),
+                                "we_code": we.code(),
+                                "kind": if we.is_transient() { "transient" } else { "permanent" }
+                            })));
+                            // The unsent result envelope rides along so /dlq/replay can retry it
+                            let dlq = DeadLetter::new(DeadLetterReason::PublishError, json!({"assignment_id": result.assignment_id, "trace_id": result.trace_id}))
+                                .with_original(&subject, &encoded, config.dlq_max_payload_bytes)
+                                .with_error(e.error.clone())
+                                .with_worker(&config.worker_id)
+                                .with_tenant(result.tenant_id.as_deref())
+                                .with_attempts(attempts);
+                            pipeline::publish_deadletter(&dlq, &self.dlq_writer, config, self.publisher.as_ref(), metrics).await;
+                        }
+                    }
+                    timings.set_publish(publish_started.elapsed());
+                    metrics.result_publish_duration_seconds.observe(timings.publish().as_secs_f64());
+                }
+                Err(e) => {
+                    logger.error("Failed to serialize result, sending to DLQ", Some(&json!({
+                        "error": e
+                    })));
+                    // Nothing to replay, but the controller still learns the assignment finished
+                    let dlq = DeadLetter::new(DeadLetterReason::PublishError, json!({"assignment_id": result.assignment_id, "trace_id": result.trace_id}))
+                        .with_error(e)
+                        .with_worker(&config.worker_id)
+                        .with_tenant(result.tenant_id.as_deref());
+                    pipeline::publish_deadletter(&dlq, &self.dlq_writer, config, self.publisher.as_ref(), metrics).await;
+                }
+            }
+        }.instrument(tracing::info_span!(parent: &span, "publish")).await;
+        metrics.observe_timings(&timings);
+        if let Some(tracker) = &batch {
+            pipeline::finish_batch_entry(tracker, Some(&result.status), self.publisher.as_ref(), &subject, self.signer.as_ref()).await;
+        }
+    }
+}
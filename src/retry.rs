@@ -0,0 +1,144 @@
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+/// Exponential backoff: retry `n` (1-based) waits up to `base * factor^(n-1)`, capped at `max`.
+///
+/// With full jitter the actual wait is uniform in `[0, ceiling]`, so callers that failed
+/// together don't retry together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub factor: u32,
+    pub max: Duration,
+    pub jitter: bool,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, factor: 2, max, jitter: true }
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Upper bound of the wait before retry `retry`.
+    pub fn ceiling(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1);
+        let scale = (self.factor as u64).checked_pow(exponent).unwrap_or(u64::MAX);
+        let millis = (self.base.as_millis() as u64).saturating_mul(scale);
+        Duration::from_millis(millis).min(self.max)
+    }
+
+    pub fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self.ceiling(retry);
+        if !self.jitter || ceiling.is_zero() {
+            return ceiling;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    /// `None` retries until `is_retryable` says stop.
+    pub max_retries: Option<u32>,
+}
+
+impl RetryPolicy {
+    pub fn new(backoff: Backoff, max_retries: Option<u32>) -> Self {
+        Self { backoff, max_retries }
+    }
+}
+
+/// Runs `op` until it succeeds, the retry budget is spent, or `is_retryable` declines.
+///
+/// `op` receives the attempt number (0 for the first call). `is_retryable` sees each
+/// failure together with the upcoming retry number and the delay about to be slept, so it
+/// can log, or refuse when the delay would overrun a deadline. The last error is returned.
+pub async fn retry_with_backoff<T, E, Op, Fut, R>(policy: &RetryPolicy, mut is_retryable: R, mut op: Op) -> Result<T, E>
+where
+    Op: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    R: FnMut(&E, u32, Duration) -> bool,
+{
+    let mut attempt = 0_u32;
+    loop {
+        let err = match op(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let retry = attempt.saturating_add(1);
+        if policy.max_retries.is_some_and(|max| retry > max) {
+            return Err(err);
+        }
+        let delay = policy.backoff.delay(retry);
+        if !is_retryable(&err, retry, delay) {
+            return Err(err);
+        }
+        tokio::time::sleep(delay).await;
+        attempt = retry;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ceiling_grows_then_caps() {
+        let backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30)).with_jitter(false);
+        assert_eq!(backoff.delay(1), Duration::from_millis(500));
+        assert_eq!(backoff.delay(2), Duration::from_millis(1000));
+        assert_eq!(backoff.delay(4), Duration::from_millis(4000));
+        assert_eq!(backoff.delay(7), Duration::from_secs(30));
+        // Huge retry counts saturate instead of overflowing
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_ceiling() {
+        for (base_ms, max_ms) in [(1, 10), (100, 5_000), (500, 30_000), (0, 100)] {
+            let backoff = Backoff::new(Duration::from_millis(base_ms), Duration::from_millis(max_ms));
+            for retry in 1..=40 {
+                let ceiling = backoff.ceiling(retry);
+                assert!(ceiling <= backoff.max);
+                for _ in 0..50 {
+                    assert!(backoff.delay(retry) <= ceiling);
+                }
+            }
+        }
+        // Full jitter actually spreads retries out
+        let backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(10));
+        let distinct: std::collections::HashSet<_> = (0..50).map(|_| backoff.delay(1)).collect();
+        assert!(distinct.len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_respects_budget_and_predicate() {
+        let policy = RetryPolicy::new(Backoff::new(Duration::from_millis(1), Duration::from_millis(2)), Some(3));
+        let mut calls = Vec::new();
+        let result: Result<(), u32> = retry_with_backoff(&policy, |_, _, _| true, |attempt| {
+            calls.push(attempt);
+            async move { Err(attempt) }
+        }).await;
+        assert_eq!(result, Err(3));
+        assert_eq!(calls, vec![0, 1, 2, 3]);
+
+        let result = retry_with_backoff(&policy, |_, _, _| true, |attempt| async move {
+            if attempt < 2 { Err("flaky") } else { Ok(attempt) }
+        }).await;
+        assert_eq!(result, Ok(2));
+
+        let mut seen = Vec::new();
+        let result: Result<(), &str> = retry_with_backoff(&policy, |e, retry, delay| {
+            seen.push((*e, retry, delay <= Duration::from_millis(1)));
+            false
+        }, |_| async { Err("permanent") }).await;
+        assert_eq!(result, Err("permanent"));
+        assert_eq!(seen, vec![("permanent", 1, true)]);
+    }
+}
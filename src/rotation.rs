@@ -0,0 +1,309 @@
+use std::fs::{File, OpenOptions, rename, metadata, remove_file, read_dir, create_dir_all};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use chrono::{NaiveDateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Suffix of a rotation that has been gzipped.
+pub const GZ_SUFFIX: &str = ".gz";
+
+const ROTATION_TS_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Size/count/age limits for an append-only file rotated to `<path>.<timestamp>`.
+#[derive(Debug, Clone, Copy)]
+pub struct RotationPolicy {
+    pub max_bytes: u64,
+    pub max_rotations: u32,
+    pub total_max_bytes: u64,
+    pub max_age_days: Option<u32>,
+    /// Gzip each rotation to `<path>.<timestamp>.gz`.
+    pub compress: bool,
+}
+
+pub fn rotate_if_needed(path: &str, policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    if let Ok(meta) = metadata(path) {
+        if meta.len() >= policy.max_bytes {
+            let ts = Utc::now().format(ROTATION_TS_FORMAT).to_string();
+            let mut rotated = format!("{}.{}", path, ts);
+            // Several rotations within one second must not overwrite each other
+            let mut n = 1;
+            while Path::new(&rotated).exists() || Path::new(&format!("{}{}", rotated, GZ_SUFFIX)).exists() {
+                rotated = format!("{}.{}-{:03}", path, ts, n);
+                n += 1;
+            }
+            rename(path, &rotated)?;
+            if policy.compress {
+                gzip_file(&rotated)?;
+            }
+
+            enforce_limits(path, policy)?;
+        }
+    }
+    Ok(())
+}
+
+/// Streams `path` into `path.gz`, removing the original only once the archive is complete.
+fn gzip_file(path: &str) -> Result<(), std::io::Error> {
+    let gz_path = format!("{}{}", path, GZ_SUFFIX);
+    let mut input = BufReader::new(File::open(path)?);
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&gz_path)?), Compression::default());
+    let written = std::io::copy(&mut input, &mut encoder).and_then(|_| encoder.finish()?.flush());
+    if let Err(e) = written {
+        let _ = remove_file(&gz_path);
+        return Err(e);
+    }
+    remove_file(path)
+}
+
+/// Whether `file` is named like a rotation of `path`: `<path>.<timestamp>[-NNN][.gz]`. Other files
+/// that share the prefix, such as per-tenant DLQ files, are not.
+pub fn is_rotation_of(path: &str, file: &str) -> bool {
+    let (Some(base), Some(name)) = (Path::new(path).file_name(), Path::new(file).file_name()) else {
+        return false;
+    };
+    let (base, name) = (base.to_string_lossy(), name.to_string_lossy());
+    name.strip_prefix(base.as_ref())
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|suffix| suffix.get(..15))
+        .is_some_and(|ts| NaiveDateTime::parse_from_str(ts, ROTATION_TS_FORMAT).is_ok())
+}
+
+/// Rotations of `path`, plain or gzipped, oldest first.
+pub fn rotated_files(path: &str) -> Result<Vec<String>, std::io::Error> {
+    let base = Path::new(path);
+    let dir = base.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Some(base_name) = base.file_name().map(|n| n.to_string_lossy().to_string()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}.", base_name);
+    let mut rotated: Vec<String> = match read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| e.file_name().to_str().map(str::to_string))
+            .filter(|name| name.starts_with(&prefix))
+            .map(|name| dir.join(name).to_string_lossy().to_string())
+            .filter(|file| is_rotation_of(path, file))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    // Timestamp suffixes sort chronologically once `.gz` is out of the way
+    rotated.sort_by(|a, b| a.trim_end_matches(GZ_SUFFIX).cmp(b.trim_end_matches(GZ_SUFFIX)));
+    Ok(rotated)
+}
+
+/// When a rotation was made, read from its name since gzipping resets the mtime.
+fn rotated_at(path: &str, rotated: &str) -> Option<chrono::DateTime<Utc>> {
+    let suffix = rotated.strip_prefix(path)?.strip_prefix('.')?;
+    let ts = suffix.get(..15)?;
+    NaiveDateTime::parse_from_str(ts, ROTATION_TS_FORMAT).ok().map(|dt| dt.and_utc())
+}
+
+/// Bytes held by the live file plus its rotations, and the rotation count.
+pub fn usage(path: &str) -> Result<(u64, usize), std::io::Error> {
+    let rotated = rotated_files(path)?;
+    let live = metadata(path).map(|m| m.len()).unwrap_or(0);
+    let archived: u64 = rotated.iter().filter_map(|f| metadata(f).ok()).map(|m| m.len()).sum();
+    Ok((live + archived, rotated.len()))
+}
+
+fn enforce_limits(path: &str, policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    let mut rotated_files: Vec<(String, u64)> = rotated_files(path)?
+        .into_iter()
+        .filter_map(|f| metadata(&f).ok().map(|md| (f, md.len())))
+        .collect();
+    if let Some(days) = policy.max_age_days {
+        let now = Utc::now();
+        rotated_files.retain(|(name, _)| {
+            // Names without a parsable timestamp fall back to the mtime
+            let made = rotated_at(path, name).or_else(|| metadata(name).ok()?.modified().ok().map(Into::into));
+            if let Some(made) = made {
+                if now.signed_duration_since(made).num_days() > days as i64 {
+                    let _ = remove_file(name);
+                    return false;
+                }
+            }
+            true
+        });
+    }
+    while rotated_files.len() > policy.max_rotations as usize {
+        if let Some((oldest, _)) = rotated_files.first().cloned() {
+            let _ = remove_file(&oldest);
+            rotated_files.remove(0);
+        } else {
+            break;
+        }
+    }
+    let mut total: u64 = rotated_files.iter().map(|(_, sz)| *sz).sum();
+    while total > policy.total_max_bytes {
+        if let Some((oldest, sz)) = rotated_files.first().cloned() {
+            let _ = remove_file(&oldest);
+            rotated_files.remove(0);
+            total = total.saturating_sub(sz);
+        } else {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Applies `max_age_days` and `total_max_bytes` to several file families as one: rotations
+/// past the age limit go from every family, then the oldest rotations anywhere until the
+/// live files and rotations together fit in `total_max_bytes`.
+///
+/// `rotate_if_needed` only looks at the family that rotated, so a family that stops growing
+/// would otherwise keep its rotations forever.
+pub fn enforce_shared_limits(paths: &[String], policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    let now = Utc::now();
+    let mut live = 0;
+    let mut rotations: Vec<(Option<chrono::DateTime<Utc>>, String, u64)> = Vec::new();
+    for path in paths {
+        live += metadata(path).map(|m| m.len()).unwrap_or(0);
+        for file in rotated_files(path)? {
+            let Ok(md) = metadata(&file) else { continue };
+            let made = rotated_at(path, &file).or_else(|| md.modified().ok().map(Into::into));
+            if policy.max_age_days.is_some_and(|days| made.is_some_and(|made| now.signed_duration_since(made).num_days() > days as i64)) {
+                let _ = remove_file(&file);
+                continue;
+            }
+            rotations.push((made, file, md.len()));
+        }
+    }
+    rotations.sort();
+    let mut total = live + rotations.iter().map(|(_, _, size)| size).sum::<u64>();
+    for (_, file, size) in rotations {
+        if total <= policy.total_max_bytes {
+            break;
+        }
+        let _ = remove_file(&file);
+        total = total.saturating_sub(size);
+    }
+    Ok(())
+}
+
+/// Appends `line` plus a newline, rotating first so a line never straddles two files.
+pub fn append_line(path: &str, line: &str, policy: &RotationPolicy) -> Result<(), std::io::Error> {
+    if let Some(parent) = Path::new(path).parent() {
+        let _ = create_dir_all(parent);
+    }
+    rotate_if_needed(path, policy)?;
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut buf = Vec::with_capacity(line.len() + 1);
+    buf.extend_from_slice(line.as_bytes());
+    buf.push(b'\n');
+    f.write_all(&buf)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rotation-{}", uuid::Uuid::new_v4()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_rotations() {
+        let dir = temp_dir();
+        let path = dir.join("out.jsonl").to_string_lossy().to_string();
+        let policy = RotationPolicy { max_bytes: 64, max_rotations: 2, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+
+        for i in 0..40 {
+            append_line(&path, &format!(r#"{{"i":{},"pad":"xxxxxxxxxxxxxxxx"}}"#, i), &policy).unwrap();
+        }
+
+        let rotated = read_dir(&dir).unwrap().flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with("out.jsonl."))
+            .count();
+        assert_eq!(rotated, 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotations_are_gzipped_and_counted() {
+        use std::io::Read;
+        let dir = temp_dir();
+        let path = dir.join("out.jsonl").to_string_lossy().to_string();
+        let policy = RotationPolicy { max_bytes: 256, max_rotations: 100, total_max_bytes: u64::MAX, max_age_days: None, compress: true };
+
+        let line = format!(r#"{{"pad":"{}"}}"#, "x".repeat(100));
+        for _ in 0..10 {
+            append_line(&path, &line, &policy).unwrap();
+        }
+        let rotated = rotated_files(&path).unwrap();
+        assert!(!rotated.is_empty());
+        let mut lines = 0;
+        for file in &rotated {
+            assert!(file.ends_with(GZ_SUFFIX), "{} was not compressed", file);
+            let mut contents = String::new();
+            flate2::read::GzDecoder::new(File::open(file).unwrap()).read_to_string(&mut contents).unwrap();
+            lines += contents.lines().count();
+        }
+        lines += std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 10);
+
+        let (bytes, count) = usage(&path).unwrap();
+        assert_eq!(count, rotated.len());
+        let on_disk: u64 = read_dir(&dir).unwrap().flatten().map(|e| e.metadata().unwrap().len()).sum();
+        assert_eq!(bytes, on_disk);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_age_limit_reads_timestamp_from_name() {
+        let dir = temp_dir();
+        let path = dir.join("out.jsonl").to_string_lossy().to_string();
+        // Fresh mtimes, but the names say one of them is years old
+        std::fs::write(format!("{}.20200101-000000.gz", path), b"old").unwrap();
+        let recent = format!("{}.{}", path, Utc::now().format(ROTATION_TS_FORMAT));
+        std::fs::write(&recent, b"new").unwrap();
+        let policy = RotationPolicy { max_bytes: u64::MAX, max_rotations: 100, total_max_bytes: u64::MAX, max_age_days: Some(30), compress: false };
+
+        enforce_limits(&path, &policy).unwrap();
+        assert_eq!(rotated_files(&path).unwrap(), vec![recent]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_files_sharing_the_prefix_are_not_rotations() {
+        let dir = temp_dir();
+        let path = dir.join("out").to_string_lossy().to_string();
+        let rotation = format!("{}.20200101-000000-001.gz", path);
+        std::fs::write(&rotation, b"old").unwrap();
+        std::fs::write(format!("{}.acme", path), b"tenant").unwrap();
+        std::fs::write(format!("{}.acme.20200101-000000", path), b"tenant rotation").unwrap();
+
+        assert_eq!(rotated_files(&path).unwrap(), vec![rotation]);
+        assert!(is_rotation_of("out", "./out.20200101-000000"));
+        assert!(!is_rotation_of("out.jsonl", "out.jsonl.jsonl"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_shared_limits_span_file_families() {
+        let dir = temp_dir();
+        let (a, b) = (dir.join("a.jsonl").to_string_lossy().to_string(), dir.join("b.jsonl").to_string_lossy().to_string());
+        std::fs::write(&a, "live\n").unwrap();
+        std::fs::write(format!("{}.20200101-000000", a), "x".repeat(100)).unwrap();
+        std::fs::write(format!("{}.20990101-000000", a), "x".repeat(100)).unwrap();
+        std::fs::write(format!("{}.20980101-000000", b), "x".repeat(100)).unwrap();
+        std::fs::write(format!("{}.20990102-000000", b), "x".repeat(100)).unwrap();
+
+        // Age applies to the idle family too
+        let policy = RotationPolicy { max_bytes: u64::MAX, max_rotations: 100, total_max_bytes: u64::MAX, max_age_days: Some(30), compress: false };
+        enforce_shared_limits(&[a.clone(), b.clone()], &policy).unwrap();
+        assert_eq!(rotated_files(&a).unwrap().len(), 1);
+        assert_eq!(rotated_files(&b).unwrap().len(), 2);
+
+        // The budget is shared: the oldest rotation goes first, whichever family it belongs to
+        let policy = RotationPolicy { total_max_bytes: 250, ..policy };
+        enforce_shared_limits(&[a.clone(), b.clone()], &policy).unwrap();
+        assert_eq!(rotated_files(&a).unwrap(), vec![format!("{}.20990101-000000", a)]);
+        assert_eq!(rotated_files(&b).unwrap(), vec![format!("{}.20990102-000000", b)]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
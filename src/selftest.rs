@@ -0,0 +1,217 @@
+use crate::executor::Executor;
+use crate::protocol::{ExecAssignment, ExecStatus, Job};
+use base64::{Engine as _, engine::general_purpose};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// How long one smoke job may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of one smoke job; `target` tells apart the `sql` checks of different databases.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelftestCheck {
+    pub handler: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub ok: bool,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelftestReport {
+    pub passed: bool,
+    pub checks: Vec<SelftestCheck>,
+    /// Advertised job types without a canned job (e.g. `http`, registered handlers).
+    pub skipped: Vec<String>,
+}
+
+/// Runs a canned job through every handler `executor` advertises that has one, plus
+/// `SELECT 1` against each of `sql_urls` when `sql` is enabled.
+pub async fn run(executor: &Executor, sql_urls: &[String]) -> SelftestReport {
+    let mut checks = Vec::new();
+    let mut skipped = Vec::new();
+    for job_type in executor.capabilities() {
+        match job_type.as_str() {
+            "echo" => checks.push(check(executor, "echo", None, json!({"selftest": true}), |out| out["selftest"] == true).await),
+            "sleep" => checks.push(check(executor, "sleep", None, json!({"ms": 1}), |out| out["slept_ms"].is_u64()).await),
+            "jmespath" => {
+                let payload = json!({"expression": "a.b", "data": {"a": {"b": 2}}});
+                checks.push(check(executor, "jmespath", None, payload, |out| *out == json!(2)).await);
+            }
+            "javascript" => checks.push(check(executor, "javascript", None, json!({"code": "1+1"}), |out| *out == json!(2)).await),
+            "cache" => checks.push(check(executor, "cache", None, json!({"op": "get", "key": "selftest"}), |out| out["found"].is_boolean()).await),
+            "json_diff" => {
+                let payload = json!({"left": {"a": [1, 2]}, "right": {"a": [2, 1]}, "array_mode": "set"});
+                checks.push(check(executor, "json_diff", None, payload, |out| out["equal"] == true).await);
+            }
+            "datetime" => {
+                let payload = json!({"op": "parse", "input": "1970-01-01T01:00:00", "tz": "Europe/Berlin"});
+                checks.push(check(executor, "datetime", None, payload, |out| out["epoch_ms"] == 0).await);
+            }
+            "generate" => {
+                let payload = json!({"kind": "uuid_v4", "seed": 1});
+                checks.push(check(executor, "generate", None, payload, |out| out.as_str().is_some_and(|id| id.len() == 36)).await);
+            }
+            "fs_blob_put" | "fs_blob_get" => checks.push(check_fs(executor, &job_type).await),
+            "sql" if !sql_urls.is_empty() => {
+                for (index, url) in sql_urls.iter().enumerate() {
+                    let payload = json!({"connection_string": url, "query": "SELECT 1 AS ok"});
+                    checks.push(check(executor, "sql", Some(format!("STARTUP_SELFTEST_SQL_URLS[{}]", index)), payload, |_| true).await);
+                }
+            }
+            _ => skipped.push(job_type),
+        }
+    }
+    SelftestReport { passed: checks.iter().all(|c| c.ok), checks, skipped }
+}
+
+async fn check(executor: &Executor, job_type: &str, target: Option<String>, payload: Value, expected: impl Fn(&Value) -> bool) -> SelftestCheck {
+    let started = Instant::now();
+    let assignment = ExecAssignment {
+        version: "1.0".to_string(),
+        assignment_id: format!("selftest-{}-{}", job_type, uuid::Uuid::new_v4()),
+        request_id: "selftest".to_string(),
+        tenant_id: "selftest".to_string(),
+        job: Job { r#type: job_type.to_string(), payload },
+        trace_id: None,
+        run_id: None,
+        flow_id: None,
+        step_id: None,
+        published_at: None,
+        concurrency_key: None,
+        idempotency_key: None,
+    };
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, executor.execute(assignment)).await {
+        Err(_) => Some(format!("no result within {}s", CHECK_TIMEOUT.as_secs())),
+        Ok(result) if !matches!(result.status, ExecStatus::Success) => Some(format!(
+            "{}: {}",
+            result.error_code.unwrap_or_default(),
+            result.error_message.unwrap_or_default()
+        )),
+        Ok(result) if !expected(result.output.as_ref().unwrap_or(&Value::Null)) => {
+            Some(format!("unexpected output: {}", result.output.unwrap_or_default()))
+        }
+        Ok(_) => None,
+    };
+    SelftestCheck { handler: job_type.to_string(), target, ok: error.is_none(), duration_ms: started.elapsed().as_millis() as u64, error }
+}
+
+/// Writes a 1-byte probe (through `fs_blob_put`, or directly to test `fs_blob_get`), reads it
+/// back where `fs_blob_get` is being tested, then deletes it.
+async fn check_fs(executor: &Executor, job_type: &str) -> SelftestCheck {
+    let probe = format!(".selftest-{}", uuid::Uuid::new_v4());
+    let full_path = Path::new(executor.fs_base_dir()).join(&probe);
+    let mut result = if job_type == "fs_blob_put" {
+        check(executor, job_type, None, json!({"path": probe, "content": "x"}), |out| out["size"] == 1).await
+    } else {
+        let started = Instant::now();
+        match std::fs::write(&full_path, b"x") {
+            Ok(()) => {
+                let encoded = general_purpose::STANDARD.encode(b"x");
+                check(executor, job_type, None, json!({"path": probe}), |out| out["bytes"] == encoded.as_str()).await
+            }
+            Err(e) => SelftestCheck {
+                handler: job_type.to_string(),
+                target: None,
+                ok: false,
+                duration_ms: started.elapsed().as_millis() as u64,
+                error: Some(format!("cannot write probe: {}", e)),
+            },
+        }
+    };
+    result.target = Some(executor.fs_base_dir().to_string());
+    if let Err(e) = std::fs::remove_file(&full_path) {
+        if result.ok && e.kind() != std::io::ErrorKind::NotFound {
+            result.ok = false;
+            result.error = Some(format!("cannot delete probe: {}", e));
+        }
+    }
+    result
+}
+
+/// The self-test as `/ready` and `/_state` see it.
+pub struct SelftestState {
+    enabled: bool,
+    strict: bool,
+    report: OnceLock<SelftestReport>,
+}
+
+impl SelftestState {
+    pub fn new(enabled: bool, strict: bool) -> Self {
+        Self { enabled, strict, report: OnceLock::new() }
+    }
+
+    pub fn set(&self, report: SelftestReport) {
+        let _ = self.report.set(report);
+    }
+
+    pub fn report(&self) -> Option<&SelftestReport> {
+        self.report.get()
+    }
+
+    /// Why readiness is held back, if it is: the self-test hasn't run yet, or failed while strict.
+    pub fn blocks_readiness(&self) -> Option<&'static str> {
+        if !self.enabled {
+            return None;
+        }
+        match self.report.get() {
+            None => Some("SELFTEST_PENDING"),
+            Some(report) if !report.passed && self.strict => Some("SELFTEST_FAILED"),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executor(dir: &Path) -> Executor {
+        Executor::new("selftest".to_string(), dir.to_string_lossy().to_string()).with_js_context_pool(1)
+    }
+
+    #[tokio::test]
+    async fn test_default_handlers_pass() {
+        let dir = std::env::temp_dir().join(format!("selftest-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let report = run(&executor(&dir), &[]).await;
+        assert!(report.passed, "{:?}", report.checks);
+        let handlers: Vec<_> = report.checks.iter().map(|c| c.handler.as_str()).collect();
+        assert_eq!(handlers, vec!["echo", "sleep", "jmespath", "javascript", "fs_blob_get", "fs_blob_put", "cache", "json_diff", "datetime", "generate"]);
+        assert_eq!(report.skipped, vec!["http", "sql", "graphql", "human_approval"]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0, "probes are cleaned up");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_reported_per_handler() {
+        let dir = std::env::temp_dir().join(format!("selftest-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&dir, b"a file, not a directory").unwrap();
+        let executor = executor(&dir).with_job_type_lists(&["echo".to_string(), "fs_blob_put".to_string(), "sql".to_string()], &[]);
+        let report = run(&executor, &["not a connection string".to_string()]).await;
+        assert!(!report.passed);
+        let outcomes: Vec<_> = report.checks.iter().map(|c| (c.handler.as_str(), c.ok)).collect();
+        assert_eq!(outcomes, vec![("echo", true), ("sql", false), ("fs_blob_put", false)]);
+        assert_eq!(report.checks[1].target.as_deref(), Some("STARTUP_SELFTEST_SQL_URLS[0]"));
+        assert!(report.checks[2].error.is_some());
+        let _ = std::fs::remove_file(&dir);
+    }
+
+    #[test]
+    fn test_state_holds_back_readiness() {
+        assert_eq!(SelftestState::new(false, true).blocks_readiness(), None);
+        let failed = SelftestReport { passed: false, checks: Vec::new(), skipped: Vec::new() };
+        let strict = SelftestState::new(true, true);
+        assert_eq!(strict.blocks_readiness(), Some("SELFTEST_PENDING"));
+        strict.set(failed.clone());
+        assert_eq!(strict.blocks_readiness(), Some("SELFTEST_FAILED"));
+        let lenient = SelftestState::new(true, false);
+        lenient.set(failed);
+        assert_eq!(lenient.blocks_readiness(), None);
+    }
+}
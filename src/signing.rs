@@ -0,0 +1,140 @@
+use base64::{Engine as _, engine::general_purpose};
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Serializes `value` with object keys sorted recursively so every worker and the
+/// controller produce identical bytes for the same logical document.
+pub fn canonical_json(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, k) in keys.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String((*k).clone()).to_string());
+                out.push(':');
+                write_canonical(&map[*k], out);
+            }
+            out.push('}');
+        }
+        Value::Array(arr) => {
+            out.push('[');
+            for (i, v) in arr.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(v, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Missing,
+    Invalid,
+}
+
+/// HMAC-SHA256 signer for envelope `data`.
+///
+/// Signs with the first key and verifies against all of them, so keys can be rotated by
+/// prepending the new key and dropping the old one once every publisher has switched.
+#[derive(Clone)]
+pub struct EnvelopeSigner {
+    keys: Vec<Vec<u8>>,
+}
+
+impl EnvelopeSigner {
+    pub fn new(keys: Vec<String>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Self { keys: keys.into_iter().map(String::into_bytes).collect() })
+    }
+
+    pub fn sign(&self, data: &Value) -> String {
+        general_purpose::STANDARD.encode(mac_for(&self.keys[0], data).finalize().into_bytes())
+    }
+
+    pub fn verify(&self, data: &Value, signature: Option<&str>) -> Result<(), SignatureError> {
+        let signature = signature.ok_or(SignatureError::Missing)?;
+        let raw = general_purpose::STANDARD.decode(signature).map_err(|_| SignatureError::Invalid)?;
+        for key in &self.keys {
+            if mac_for(key, data).verify_slice(&raw).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(SignatureError::Invalid)
+    }
+}
+
+fn mac_for(key: &[u8], data: &Value) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(canonical_json(data).as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical_json_sorts_keys() {
+        let a = json!({"b": 1, "a": {"d": [1, {"z": true, "y": null}], "c": "x"}});
+        assert_eq!(canonical_json(&a), r#"{"a":{"c":"x","d":[1,{"y":null,"z":true}]},"b":1}"#);
+    }
+
+    #[test]
+    fn test_sign_and_verify_valid() {
+        let signer = EnvelopeSigner::new(vec!["secret".to_string()]).unwrap();
+        let data = json!({"assignment_id": "a1", "job": {"type": "echo"}});
+        let sig = signer.sign(&data);
+        assert_eq!(signer.verify(&data, Some(&sig)), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_missing_signature() {
+        let signer = EnvelopeSigner::new(vec!["secret".to_string()]).unwrap();
+        assert_eq!(signer.verify(&json!({}), None), Err(SignatureError::Missing));
+    }
+
+    #[test]
+    fn test_verify_tampered_data() {
+        let signer = EnvelopeSigner::new(vec!["secret".to_string()]).unwrap();
+        let sig = signer.sign(&json!({"tenant_id": "t1"}));
+        assert_eq!(signer.verify(&json!({"tenant_id": "t2"}), Some(&sig)), Err(SignatureError::Invalid));
+        assert_eq!(signer.verify(&json!({"tenant_id": "t1"}), Some("not-base64!")), Err(SignatureError::Invalid));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = EnvelopeSigner::new(vec!["old".to_string()]).unwrap();
+        let rotated = EnvelopeSigner::new(vec!["new".to_string(), "old".to_string()]).unwrap();
+        let data = json!({"x": 1});
+
+        // Still accepts signatures made with the previous key
+        assert_eq!(rotated.verify(&data, Some(&old.sign(&data))), Ok(()));
+        // Signs with the first key only
+        assert_eq!(old.verify(&data, Some(&rotated.sign(&data))), Err(SignatureError::Invalid));
+    }
+
+    #[test]
+    fn test_no_keys_disables_signing() {
+        assert!(EnvelopeSigner::new(vec![]).is_none());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::Config;
+use serde::Serialize;
+use std::path::Path;
+
+/// Outcome of one startup check, reported in the first log line and under `/config`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StartupCheck {
+    pub name: &'static str,
+    pub target: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Checks that would otherwise only fail on the first job or dead letter that needs them.
+pub fn run_checks(config: &Config) -> Vec<StartupCheck> {
+    vec![
+        check_writable_dir("fs_base_dir", Path::new(&config.fs_base_dir)),
+        check_writable_dir("dlq_dir", dlq_dir(&config.dlq_path)),
+    ]
+}
+
+pub fn all_passed(checks: &[StartupCheck]) -> bool {
+    checks.iter().all(|c| c.ok)
+}
+
+fn dlq_dir(dlq_path: &str) -> &Path {
+    match Path::new(dlq_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Creates `dir` if needed, then writes and deletes a probe file in it.
+pub fn check_writable_dir(name: &'static str, dir: &Path) -> StartupCheck {
+    let result = std::fs::create_dir_all(dir)
+        .map_err(|e| format!("cannot create directory: {}", e))
+        .and_then(|_| {
+            let probe = dir.join(format!(".worker-probe-{}", uuid::Uuid::new_v4()));
+            std::fs::write(&probe, b"probe").map_err(|e| format!("directory is not writable: {}", e))?;
+            std::fs::remove_file(&probe).map_err(|e| format!("cannot delete probe file: {}", e))
+        });
+    StartupCheck {
+        name,
+        target: dir.to_string_lossy().to_string(),
+        ok: result.is_ok(),
+        error: result.err(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("startup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_writable_dir_is_created_and_left_clean() {
+        let dir = temp_dir();
+        let target = dir.join("nested").join("storage");
+        let check = check_writable_dir("fs_base_dir", &target);
+        assert!(check.ok, "{:?}", check.error);
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unwritable_dir_fails() {
+        let dir = temp_dir();
+        let readonly = dir.join("readonly");
+        std::fs::create_dir_all(&readonly).unwrap();
+        std::fs::set_permissions(&readonly, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Root ignores permission bits, so only assert when they actually apply
+        let enforced = std::fs::write(readonly.join("canary"), b"").is_err();
+        let check = check_writable_dir("dlq_dir", &readonly);
+        if enforced {
+            assert!(!check.ok);
+            assert!(check.error.unwrap().contains("not writable"));
+        }
+
+        // A regular file in the way fails for every user
+        let blocker = dir.join("blocker");
+        std::fs::write(&blocker, b"").unwrap();
+        let check = check_writable_dir("fs_base_dir", &blocker.join("storage"));
+        assert!(!check.ok);
+        assert!(check.error.unwrap().contains("cannot create directory"));
+
+        std::fs::set_permissions(&readonly, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_dlq_dir_of_bare_file_name() {
+        assert_eq!(dlq_dir("dlq.jsonl"), Path::new("."));
+        assert_eq!(dlq_dir("/var/lib/worker/dlq.jsonl"), Path::new("/var/lib/worker"));
+    }
+}
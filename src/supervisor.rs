@@ -0,0 +1,119 @@
+use crate::health::Liveness;
+use crate::observability::metrics::Metrics;
+use crate::observability::Logger;
+use futures::FutureExt;
+use serde_json::json;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+/// Default for `TASK_SUPERVISOR_MAX_RESTARTS`.
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+
+/// Restarts long-running tasks (the heartbeat, the processing loop) that panic, so a bug in one
+/// does not leave the worker half alive. Once a task has used up `max_restarts`, `/livez`
+/// fails and the orchestrator replaces the process.
+#[derive(Clone)]
+pub struct Supervisor {
+    max_restarts: u32,
+    liveness: Arc<Liveness>,
+    metrics: Arc<Metrics>,
+    logger: Logger,
+}
+
+impl Supervisor {
+    pub fn new(max_restarts: u32, liveness: Arc<Liveness>, metrics: Arc<Metrics>, logger: Logger) -> Self {
+        Self { max_restarts, liveness, metrics, logger }
+    }
+
+    /// Runs `start()` until it returns without panicking. Each run gets a fresh future, so
+    /// `start` must rebuild whatever state the task needs.
+    pub async fn supervise<F, Fut>(&self, task: &'static str, mut start: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut restarts = 0;
+        loop {
+            let Err(panic) = AssertUnwindSafe(start()).catch_unwind().await else {
+                return;
+            };
+            let error = panic_message(panic.as_ref());
+            if restarts >= self.max_restarts {
+                self.liveness.task_failed(task);
+                self.logger.error("Supervised task keeps panicking, failing /livez", Some(&json!({
+                    "task": task,
+                    "error": error,
+                    "restarts": restarts,
+                })));
+                return;
+            }
+            restarts += 1;
+            self.metrics.task_supervisor_restarts_total.with_label_values(&[task]).inc();
+            self.logger.error("Supervised task panicked, restarting it", Some(&json!({
+                "task": task,
+                "error": error,
+                "restart": restarts,
+                "max_restarts": self.max_restarts,
+            })));
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "non-string panic payload".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    fn supervisor(max_restarts: u32) -> (Supervisor, Arc<Liveness>, Arc<Metrics>) {
+        let liveness = Arc::new(Liveness::new(Duration::from_secs(30)));
+        let metrics = Arc::new(Metrics::new());
+        (Supervisor::new(max_restarts, liveness.clone(), metrics.clone(), Logger::new("w1".to_string())), liveness, metrics)
+    }
+
+    /// A task that ticks `iterations` times per run, panicking at the end of the first `panics` runs.
+    async fn flaky(runs: Arc<AtomicU32>, ticks: Arc<AtomicU32>, iterations: u32, panics: u32) {
+        let run = runs.fetch_add(1, Ordering::SeqCst);
+        for _ in 0..iterations {
+            ticks.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+        }
+        if run < panics {
+            panic!("tick {} went wrong", ticks.load(Ordering::SeqCst));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_is_restarted() {
+        let (supervisor, liveness, metrics) = supervisor(5);
+        let (runs, ticks) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        supervisor.supervise("heartbeat", || flaky(runs.clone(), ticks.clone(), 3, 2)).await;
+
+        assert_eq!((runs.load(Ordering::SeqCst), ticks.load(Ordering::SeqCst)), (3, 9));
+        assert_eq!(metrics.task_supervisor_restarts_total.with_label_values(&["heartbeat"]).get(), 2);
+        assert!(liveness.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_liveness_fails_once_restarts_run_out() {
+        let (supervisor, liveness, metrics) = supervisor(2);
+        let runs = Arc::new(AtomicU32::new(0));
+        supervisor.supervise("processing", || flaky(runs.clone(), Arc::new(AtomicU32::new(0)), 1, u32::MAX)).await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(metrics.task_supervisor_restarts_total.with_label_values(&["processing"]).get(), 2);
+        assert_eq!(liveness.failures(), ["supervised_task_failed"]);
+        assert_eq!(liveness.failed_tasks(), ["processing"]);
+        assert_eq!(panic_message(&"static"), "static");
+    }
+}
@@ -0,0 +1,808 @@
+use crate::build_info;
+use crate::concurrency::{ConcurrencyLimit, KeyedLocks, KEY_LOCKS_MAX_IDLE};
+use crate::config::{Config, DrainPolicy};
+use crate::dlq::{self, DlqWriter};
+use crate::executor::Executor;
+use crate::handlers::JobHandler;
+use crate::heartbeat;
+use crate::health;
+use crate::history::TaskHistory;
+use crate::idempotency::IdempotencyCache;
+use crate::receipts::Receipts;
+use crate::recorder::Recorder;
+use crate::outbox::{self, Outbox};
+use crate::resources::{ResourceMonitor, SystemSampler};
+use crate::result_cache::ResultCache;
+use crate::inflight;
+use crate::observability::{Logger, metrics::Metrics, sink::{FileSink, LogSink, StdoutSink}};
+use crate::observability::{pii::{PiiMasker, PiiPatterns}, push::MetricsPusher, telemetry};
+use crate::pipeline;
+use crate::protocol::{self, EventEnvelopeV1, AssignmentValidator};
+use crate::reload;
+use crate::result_queue;
+use crate::retry;
+use crate::rotation::RotationPolicy;
+use crate::signing::EnvelopeSigner;
+use crate::selftest::{self, SelftestState};
+use crate::startup;
+use crate::supervisor::Supervisor;
+use futures::StreamExt;
+use serde_json::json;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::collections::HashMap;
+use std::time::Duration;
+use arc_swap::ArcSwap;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+/// What `Worker::run` fails with.
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// The whole worker: NATS connection, assignment processing, heartbeats and the health server.
+///
+/// `main` is a thin wrapper around this, so an application can embed the worker with its own
+/// config source and job types, and tests can run the full pipeline in-process.
+pub struct Worker {
+    config: Config,
+    logger: Logger,
+    metrics: Arc<Metrics>,
+    handlers: Vec<(String, Arc<dyn JobHandler>)>,
+    health_server: bool,
+}
+
+/// Built by `Worker::builder()`; only the config is required.
+pub struct WorkerBuilder {
+    config: Option<Config>,
+    metrics: Option<Arc<Metrics>>,
+    handlers: Vec<(String, Arc<dyn JobHandler>)>,
+    health_server: bool,
+}
+
+impl WorkerBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Shares a registry with the caller; defaults to one built with the config's duration buckets.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Adds a job type, or replaces a built-in one of the same name. Registered types are
+    /// advertised in heartbeats unless the allow/deny lists turn them away.
+    pub fn register_handler<H: JobHandler + 'static>(mut self, job_type: impl Into<String>, handler: H) -> Self {
+        self.handlers.push((job_type.into(), Arc::new(handler)));
+        self
+    }
+
+    /// Whether `run` serves HEALTH_BIND; on by default.
+    pub fn health_server(mut self, enabled: bool) -> Self {
+        self.health_server = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Worker, String> {
+        let config = self.config.ok_or("Worker::builder() needs a config")?;
+        let mut sinks: Vec<Arc<dyn LogSink>> = vec![Arc::new(StdoutSink)];
+        if let Some(path) = &config.log_file_path {
+            let policy = RotationPolicy {
+                max_bytes: config.log_file_max_bytes,
+                max_rotations: config.log_file_max_rotations,
+                total_max_bytes: config.log_file_max_bytes.saturating_mul(config.log_file_max_rotations as u64),
+                max_age_days: None,
+                compress: false,
+            };
+            sinks.push(Arc::new(FileSink::new(path.clone(), policy)));
+        }
+        let known: Vec<&str> = crate::executor::JOB_TYPES.iter().copied().chain(self.handlers.iter().map(|(t, _)| t.as_str())).collect();
+        let unknown = config.unknown_job_types(&known);
+        if !unknown.is_empty() {
+            return Err(unknown.join("; "));
+        }
+        // Config::from_env validates these, a config built by hand may not have been
+        let patterns = PiiPatterns::new(config.pii_mask_ips, &config.pii_custom_patterns)?;
+        let logger = Logger::with_sinks(config.worker_id.clone(), config.log_level, sinks)
+            .with_masker(PiiMasker::new(config.log_redact_keys.clone()).with_patterns(patterns))
+            .with_max_value_bytes(config.log_max_value_bytes)
+            .with_max_line_bytes(config.log_max_line_bytes);
+        let metrics = self.metrics.unwrap_or_else(|| {
+            Arc::new(Metrics::with_buckets(config.task_duration_buckets.clone()).with_tenant_allowlist(&config.metrics_tenant_allowlist))
+        });
+        Ok(Worker { config, logger, metrics, handlers: self.handlers, health_server: self.health_server })
+    }
+}
+
+/// Background loops that live as long as one `run`, aborted however it returns.
+struct Background(Vec<tokio::task::JoinHandle<()>>);
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+impl Worker {
+    pub fn builder() -> WorkerBuilder {
+        WorkerBuilder { config: None, metrics: None, handlers: Vec::new(), health_server: true }
+    }
+
+    /// Runs until `shutdown` is cancelled, then drains in-flight assignments and queued results
+    /// (up to DRAIN_TIMEOUT_SECONDS each) and sends the final heartbeat before returning.
+    ///
+    /// A health server that fails shuts the worker down the same way, and `run` returns its error.
+    pub async fn run(self, shutdown: CancellationToken) -> Result<(), Error> {
+        let started = std::time::Instant::now();
+        let Worker { config, logger, metrics, handlers, health_server } = self;
+        let mut background = Background(Vec::new());
+        // Cancelled by the caller or by a failed health server, without touching the caller's token
+        let shutdown = shutdown.child_token();
+        let health_failure = Arc::new(std::sync::OnceLock::new());
+
+        // Held for the life of the run; dropping it flushes buffered spans
+        let _telemetry = match telemetry::init(config.otel_exporter_otlp_endpoint.as_deref(), &config.worker_id) {
+            Ok(guard) => guard,
+            Err(e) => {
+                logger.error("Tracing export disabled", Some(&json!({"error": e})));
+                None
+            }
+        };
+
+        let startup_checks = startup::run_checks(&config);
+        let startup_ok = startup::all_passed(&startup_checks);
+        logger.info("Worker starting up", Some(&json!({
+            "nats_url": config.nats_url,
+            "health_bind": config.health_bind,
+            "build": build_info::current(),
+            "startup_checks": startup_checks
+        })));
+        if !startup_ok {
+            let failed: Vec<_> = startup_checks.iter().filter(|c| !c.ok).collect();
+            if config.strict_startup {
+                logger.error("Startup checks failed, refusing to start", Some(&json!({"failed": failed})));
+                return Err("startup checks failed".into());
+            }
+            logger.error("Startup checks failed, running NOT READY (STRICT_STARTUP=false)", Some(&json!({"failed": failed})));
+        }
+        for warning in &config.load_warnings {
+            logger.warn(warning, Some(&json!({"config_file": config.config_file})));
+        }
+        if let Some(chaos) = &config.chaos {
+            logger.warn("Fault injection is enabled (CHAOS_ENABLED)", Some(&json!({"chaos": chaos})));
+        }
+
+        // Tunables a reload may change without a restart
+        let reloader = Arc::new(reload::ConfigReloader::new(config.clone(), logger.clone()));
+        let concurrency = Arc::new(ConcurrencyLimit::new(config.max_concurrency));
+        let tenant_allowlist = Arc::new(ArcSwap::from_pointee(config.tenant_allowlist.clone()));
+        {
+            let logger = logger.clone();
+            let concurrency = concurrency.clone();
+            let tenant_allowlist = tenant_allowlist.clone();
+            reloader.on_change(move |dynamic| {
+                logger.set_level(dynamic.log_level);
+                concurrency.set_limit(dynamic.max_concurrency);
+                tenant_allowlist.store(Arc::new(dynamic.tenant_allowlist.clone()));
+            });
+        }
+
+        // 1. Start Health Server, unless the embedding application serves its own
+        let health_bind = config.health_bind.clone();
+        let health_logger = logger.clone();
+        let readiness = Arc::new(AtomicBool::new(false));
+        let dlq_policy = RotationPolicy {
+            max_bytes: config.dlq_max_bytes,
+            max_rotations: config.dlq_max_rotations,
+            total_max_bytes: config.dlq_total_max_bytes,
+            max_age_days: config.dlq_max_age_days,
+            compress: true,
+        };
+        let dlq_writer = Arc::new(DlqWriter::new(config.dlq_path.clone(), dlq_policy, metrics.clone(), logger.clone()).with_partition_by_tenant(config.dlq_partition_by_tenant));
+        let draining = Arc::new(AtomicBool::new(false));
+        let readiness_for_health = readiness.clone();
+        let metrics_for_health = metrics.clone();
+        let draining_for_health = draining.clone();
+        let liveness = Arc::new(health::Liveness::new(Duration::from_secs(config.liveness_stall_seconds)));
+        let liveness_for_health = liveness.clone();
+        let control = Arc::new(health::WorkerControl::new(draining.clone(), config.admin_token.clone()));
+        let control_for_health = control.clone();
+        let inflight = Arc::new(inflight::InflightTracker::new());
+        let inflight_for_health = inflight.clone();
+        let history = Arc::new(TaskHistory::new(config.task_history_size));
+        let history_for_health = history.clone();
+        let selftest = Arc::new(SelftestState::new(config.startup_selftest, config.startup_selftest_strict));
+        let selftest_for_health = selftest.clone();
+        let resources = Arc::new(ResourceMonitor::new(
+            Box::new(SystemSampler::new(&config.fs_base_dir)),
+            config.min_free_disk_bytes,
+            config.max_rss_bytes,
+        ));
+        background.0.push(tokio::spawn(resources.clone().run(
+            Duration::from_millis(config.resource_sample_interval_ms),
+            metrics.clone(),
+            logger.clone(),
+        )));
+        let resources_for_health = resources.clone();
+        // Pushing runs alongside /metrics, and before NATS is up so a stuck connect still reports
+        if let Some(url) = &config.metrics_push_url {
+            let interval = Duration::from_millis(config.metrics_push_interval_ms);
+            let pusher = MetricsPusher::new(url.clone(), config.metrics_push_mode, config.worker_id.clone(), interval.min(Duration::from_secs(10)));
+            background.0.push(tokio::spawn(pusher.run(interval, metrics.clone(), logger.clone())));
+        }
+        let config_for_health = config.config_endpoint_enabled.then(|| {
+            let mut value = config.redacted_json();
+            value["startup_checks"] = json!(startup_checks);
+            Arc::new(value)
+        });
+        let bearer_token_for_health = config.health_bearer_token.clone();
+        let dlq_path_for_health = config.dlq_path.clone();
+        let nats_handle = Arc::new(std::sync::OnceLock::new());
+        let nats_for_health = nats_handle.clone();
+        let concurrency_for_health = concurrency.clone();
+        let reloader_for_health = reloader.clone();
+        // A configured certificate that can't be loaded must stop startup, never fall back to plaintext
+        let health_tls = match (&config.health_tls_cert_file, &config.health_tls_key_file) {
+            (Some(cert), Some(key)) => match health::load_tls(cert, key) {
+                Ok(tls) => Some(tls),
+                Err(e) => {
+                    logger.error("Failed to load health server TLS", Some(&json!({"error": e})));
+                    return Err(e.into());
+                }
+            },
+            _ => None,
+        };
+
+        if health_server {
+            let (health_failure, stop) = (health_failure.clone(), shutdown.clone());
+            background.0.push(tokio::spawn(async move {
+                let logger = health_logger;
+                logger.info(&format!("Health server listening on {}", health_bind), None);
+        
+                let state = health::HealthState { readiness: readiness_for_health, build: build_info::current(), metrics: metrics_for_health, draining: draining_for_health.clone(), concurrency: concurrency_for_health, liveness: liveness_for_health, control: control_for_health, inflight: inflight_for_health, history: history_for_health, config: config_for_health, bearer_token: bearer_token_for_health, dlq_path: dlq_path_for_health, nats: nats_for_health, startup_ok, selftest: selftest_for_health, reloader: Some(reloader_for_health), resources: Some(resources_for_health) };
+                if let Err(e) = health::start_server(health_bind, state, health_tls).await {
+                    logger.error(&format!("Health server crashed: {}", e), None);
+                    let _ = health_failure.set(e.to_string());
+                    stop.cancel();
+                }
+            }));
+        }
+
+        // 2. Connect to NATS with exponential backoff
+        logger.info(&format!("Connecting to NATS at {}", config.nats_url), None);
+        let connection_monitor = Arc::new(health::ConnectionMonitor::new(readiness.clone(), metrics.clone(), liveness.clone(), logger.clone()).with_client(nats_handle.clone()));
+        let connect_retry = config.nats_connect_retry();
+        let connecting = retry::retry_with_backoff(&connect_retry, |e: &async_nats::ConnectError, retry, delay| {
+            readiness.store(false, Ordering::SeqCst);
+            metrics.nats_connected.set(0);
+            logger.error("Failed to connect to NATS, will retry", Some(&json!({
+                "error": e.to_string(),
+                "attempt": retry,
+                "backoff_ms": delay.as_millis() as u64
+            })));
+            true
+        }, |_| {
+            metrics.nats_connect_attempts.inc();
+            let monitor = connection_monitor.clone();
+            let options = async_nats::ConnectOptions::new().event_callback(move |event| {
+                monitor.on_event(&event);
+                async {}
+            });
+            async_nats::connect_with_options(config.nats_servers(), options)
+        });
+        let connected = tokio::select! {
+            connected = connecting => connected,
+            _ = shutdown.cancelled() => {
+                logger.info("Worker shutdown before NATS connected", None);
+                return run_outcome(&health_failure);
+            }
+        };
+        // The connect policy has no retry limit, so an error here means the predicate gave up
+        let nc = connected?;
+        logger.info("Connected to NATS", Some(&json!({"server": health::active_server(&nc)})));
+        let server_max_payload = nc.server_info().max_payload;
+        logger.info("Message size limits", Some(&json!({
+            "max_payload": server_max_payload,
+            "envelope_compress_threshold_bytes": config.envelope_compress_threshold_bytes,
+            "result_max_output_bytes": config.result_max_output_bytes,
+            "dlq_max_payload_bytes": config.dlq_max_payload_bytes
+        })));
+        for warning in config.max_payload_warnings(server_max_payload) {
+            logger.warn(&warning, Some(&json!({"max_payload": server_max_payload})));
+        }
+        metrics.nats_connected.set(1);
+        liveness.set_nats(health::NatsLink::Connected);
+        let _ = nats_handle.set(nc.clone());
+        let publisher = Arc::new(pipeline::NatsPublisher(nc.clone()));
+
+        // Results a previous run finished but never published go out before new work comes in
+        let outbox = match config.outbox_enabled.then(|| Outbox::open(config.outbox_dir())) {
+            None => None,
+            Some(Ok(outbox)) => {
+                let outbox = Arc::new(outbox);
+                outbox::recover(&outbox, publisher.as_ref(), &config, &metrics, &logger).await;
+                Some(outbox)
+            }
+            Some(Err(e)) => {
+                logger.error("Failed to open the outbox", Some(&json!({"error": e.to_string(), "dir": config.outbox_dir()})));
+                return Err(e.into());
+            }
+        };
+
+        // 3. Subscribe to Assignments
+        let assign_subjects = config.caf_assign_subjects.clone();
+        let subscription = match subscribe_all(&nc, &assign_subjects).await {
+            Ok(sub) => sub,
+            Err(e) => {
+                logger.error(&format!("Failed to subscribe to {}: {}", assign_subjects.join(","), e), None);
+                return Err(e.into());
+            }
+        };
+        logger.info(&format!("Subscribed to {}", assign_subjects.join(",")), None);
+        readiness.store(true, Ordering::SeqCst);
+        metrics.subs_active.set(assign_subjects.len() as i64);
+
+        // Dead letters written while NATS was unreachable never reached the DLQ subject
+        if config.dlq_recovery_interval_seconds > 0 {
+            let publisher = publisher.clone();
+            let config = Arc::new(config.clone());
+            let writer = dlq_writer.clone();
+            let metrics = metrics.clone();
+            let liveness = liveness.clone();
+            let logger = logger.clone();
+            let reloader = reloader.clone();
+            background.0.push(tokio::spawn(async move {
+                let period = Duration::from_secs(config.dlq_recovery_interval_seconds);
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    if liveness.nats() != health::NatsLink::Connected {
+                        continue;
+                    }
+                    let rate = reloader.dynamic().dlq_recovery_rate_per_second;
+                    let pace = Duration::from_secs(1) / rate;
+                    // One pass never runs into the next
+                    let max = (config.dlq_recovery_interval_seconds * rate as u64) as usize;
+                    let outcome = dlq::recover_pending(&config.dlq_path, &writer, max, pace, |dlq| {
+                        let publisher = publisher.clone();
+                        let config = config.clone();
+                        async move { pipeline::send_deadletter_envelope(&dlq, &config, publisher.as_ref()).await }
+                    }).await;
+                    match outcome {
+                        Ok(outcome) => {
+                            metrics.dlq_replayed_total.inc_by(outcome.published as u64);
+                            metrics.dlq_pending.set(outcome.pending as i64);
+                            if outcome.published > 0 {
+                                logger.info("Recovered file-only dead letters", Some(&json!({"published": outcome.published, "pending": outcome.pending})));
+                            }
+                        }
+                        Err(e) => logger.error("DLQ recovery failed", Some(&json!({"path": config.dlq_path, "error": e.to_string()}))),
+                    }
+                }
+            }));
+        }
+
+        // 4. Prepare Heartbeat (spawned after concurrency setup)
+        let heartbeat_subject = config.caf_heartbeat_subject.clone();
+        let heartbeat_interval = config.caf_heartbeat_interval_ms;
+        let heartbeat_worker_id = config.worker_id.clone();
+        let heartbeat_logger = logger.clone();
+
+        // 5. Process Assignments
+        let assign_logger = logger.clone();
+        let executor = Executor::from_config(&config).with_observability(assign_logger.clone(), metrics.clone());
+        let executor = handlers.into_iter().fold(executor, |executor, (job_type, handler)| executor.with_handler(job_type, handler));
+        // Heartbeats advertise only the job types the allow/deny lists let through
+        let executor_caps: Vec<String> = executor.capabilities();
+        if config.startup_selftest {
+            let report = selftest::run(&executor, &config.startup_selftest_sql_urls).await;
+            let summary = json!({"checks": report.checks, "skipped": report.skipped});
+            if report.passed {
+                logger.info("Startup self-test passed", Some(&summary));
+            } else if config.startup_selftest_strict {
+                logger.error("Startup self-test failed, running NOT READY (STARTUP_SELFTEST_STRICT=true)", Some(&summary));
+            } else {
+                logger.warn("Startup self-test failed (STARTUP_SELFTEST_STRICT=false)", Some(&summary));
+            }
+            selftest.set(report);
+        }
+        {
+            let executor = executor.clone();
+            reloader.on_change(move |dynamic| {
+                executor.set_timeouts(Duration::from_millis(dynamic.default_job_timeout_ms), &dynamic.job_timeouts);
+            });
+        }
+        #[cfg(unix)]
+        {
+            let reloader = reloader.clone();
+            let logger = logger.clone();
+            background.0.push(tokio::spawn(async move {
+                let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(signal) => signal,
+                    Err(e) => {
+                        logger.error("SIGHUP reload disabled", Some(&json!({"error": e.to_string()})));
+                        return;
+                    }
+                };
+                while hangup.recv().await.is_some() {
+                    let _ = reloader.reload("SIGHUP");
+                }
+            }));
+        }
+        let signer = EnvelopeSigner::new(config.envelope_hmac_keys.clone());
+        let validator = match &config.assignment_schema_dir {
+            Some(dir) => AssignmentValidator::from_schema_dir(dir)?,
+            None => AssignmentValidator::new(),
+        };
+        let metrics_for_loop = metrics.clone();
+        let shutdown_flag = draining.clone();
+        let nc_for_loop = nc.clone();
+        let hb_subject_for_loop = heartbeat_subject.clone();
+        let (shutdown_tx, _) = broadcast::channel::<()>(1);
+        // Outlives a panicked processing loop, so its restart still reaps the tasks it started
+        // and hears a shutdown sent in between
+        let loop_state = Arc::new(tokio::sync::Mutex::new((tokio::task::JoinSet::new(), shutdown_tx.subscribe())));
+
+        let supervisor = Supervisor::new(config.task_supervisor_max_restarts, liveness.clone(), metrics.clone(), logger.clone());
+
+        // Spawn Heartbeat Loop with dynamic load/status
+        {
+            let heartbeat_concurrency = concurrency.clone();
+            let heartbeat_signer = signer.clone();
+            let capabilities = executor_caps.clone();
+            let labels = config.worker_labels.clone();
+            let envelope_versions = config.envelope_accept_versions.clone();
+            let heartbeat_metrics = metrics.clone();
+            let heartbeat_liveness = liveness.clone();
+            let heartbeat_control = control.clone();
+            let heartbeat_inflight = inflight.clone();
+            let heartbeat_resources = resources.clone();
+            let heartbeat_tenants = tenant_allowlist.clone();
+            let include_inflight = config.heartbeat_include_inflight;
+            let heartbeat_publisher = publisher.clone();
+            let heartbeat_readiness = readiness.clone();
+            let failures_before_unready = config.heartbeat_failures_before_unready;
+            let supervisor = supervisor.clone();
+            background.0.push(tokio::spawn(async move { supervisor.supervise("heartbeat", || {
+                // A restarted loop counts publish failures from zero again
+                let mut monitor = heartbeat::HeartbeatMonitor::new(
+                    Duration::from_millis(heartbeat_interval),
+                    failures_before_unready,
+                    heartbeat_readiness.clone(),
+                    heartbeat_liveness.clone(),
+                    heartbeat_metrics.clone(),
+                    heartbeat_logger.clone(),
+                );
+                let (heartbeat_concurrency, heartbeat_control, heartbeat_resources) = (heartbeat_concurrency.clone(), heartbeat_control.clone(), heartbeat_resources.clone());
+                let (heartbeat_worker_id, capabilities, labels, envelope_versions) = (heartbeat_worker_id.clone(), capabilities.clone(), labels.clone(), envelope_versions.clone());
+                let (heartbeat_metrics, heartbeat_liveness, heartbeat_inflight, heartbeat_tenants) = (heartbeat_metrics.clone(), heartbeat_liveness.clone(), heartbeat_inflight.clone(), heartbeat_tenants.clone());
+                let (heartbeat_signer, heartbeat_publisher, hb_subject_for_loop) = (heartbeat_signer.clone(), heartbeat_publisher.clone(), hb_subject_for_loop.clone());
+                async move { loop {
+                    heartbeat_liveness.touch();
+                    let max_permits = heartbeat_concurrency.limit();
+                    let in_use = heartbeat_concurrency.in_use();
+                    let load = if max_permits == 0 { 0.0 } else { (in_use as f64) / (max_permits as f64) };
+                    let status = match heartbeat_control.state() {
+                        health::RunState::Running if heartbeat_resources.under_pressure() => "degraded".to_string(),
+                        health::RunState::Running if in_use > 0 => "busy".to_string(),
+                        health::RunState::Running => "idle".to_string(),
+                        paused_or_draining => paused_or_draining.as_str().to_string(),
+                    };
+                    let mut hb = protocol::WorkerHeartbeat {
+                        worker_id: heartbeat_worker_id.clone(),
+                        timestamp: chrono::Utc::now().to_rfc3339(),
+                        status,
+                        load,
+                        capabilities: capabilities.clone(),
+                        max_concurrency: max_permits,
+                        in_flight: in_use,
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        git_sha: build_info::GIT_SHA.to_string(),
+                        labels: advertised_labels(&labels, &heartbeat_tenants.load()),
+                        envelope_versions: envelope_versions.clone(),
+                        ..Default::default()
+                    };
+                    heartbeat_activity(&mut hb, &heartbeat_metrics, &heartbeat_inflight, include_inflight, started);
+                    let env = EventEnvelopeV1::wrap_heartbeat(&hb).signed(heartbeat_signer.as_ref());
+                    if let Ok(payload) = protocol::encode_envelope(&env) {
+                        monitor.send(heartbeat_publisher.as_ref(), &hb_subject_for_loop, &payload).await;
+                    }
+                    tokio::time::sleep(monitor.next_delay()).await;
+                } }
+            }).await }));
+        }
+
+        let shared_config = Arc::new(config.clone());
+        let results = result_queue::ResultQueue::start(shared_config.clone(), publisher.clone(), dlq_writer.clone(), metrics.clone(), signer.clone());
+        let deps = pipeline::PipelineDeps {
+            config: shared_config.clone(),
+            executor: executor.clone(),
+            publisher: publisher.clone(),
+            dedup: Arc::new(std::sync::Mutex::new(pipeline::Dedup::new(4096))),
+            metrics: metrics.clone(),
+            logger: assign_logger.clone(),
+            signer: signer.clone(),
+            validator: Arc::new(validator),
+            dlq_writer: dlq_writer.clone(),
+            results: results.clone(),
+            concurrency: concurrency.clone(),
+            key_locks: Arc::new(KeyedLocks::new(KEY_LOCKS_MAX_IDLE)),
+            outbox,
+            resources: Some(resources.clone()),
+            inflight: inflight.clone(),
+            history,
+            result_cache: Arc::new(ResultCache::new(config.result_cache_size, Duration::from_secs(config.result_cache_ttl_seconds), config.result_cache_max_entry_bytes)),
+            idempotency: Arc::new(IdempotencyCache::new(config.idempotency_cache_size, Duration::from_secs(config.idempotency_cache_ttl_seconds), config.idempotency_cache_failures)),
+            recorder: config.record_dir().map(|dir| Arc::new(Recorder::new(dir, config.record_max_payload_bytes, assign_logger.masker().clone()))),
+            receipts: config.receipts_enabled.then(|| Arc::new(Receipts::new(shared_config.clone(), publisher.clone(), signer.clone(), metrics.clone(), assign_logger.clone()))),
+            result_subject: config.caf_result_subject.clone(),
+            draining: draining.clone(),
+            tenant_allowlist: tenant_allowlist.clone(),
+            backpressure_log: Arc::new(pipeline::LogThrottle::new(pipeline::BACKPRESSURE_LOG_INTERVAL)),
+        };
+        let config_loop = config.clone();
+        let liveness_for_loop = liveness.clone();
+        let control_for_loop = control.clone();
+        let mut initial_subscription = Some(subscription);
+        let processing = tokio::spawn(async move { supervisor.supervise("processing", || {
+            let config = config_loop.clone();
+            let (deps, loop_state, mut control_rx) = (deps.clone(), loop_state.clone(), control_for_loop.subscribe());
+            let (nc_for_loop, assign_subjects, shutdown_flag) = (nc_for_loop.clone(), assign_subjects.clone(), shutdown_flag.clone());
+            let (metrics_for_loop, assign_logger, liveness_for_loop) = (metrics_for_loop.clone(), assign_logger.clone(), liveness_for_loop.clone());
+            // A restarted loop starts unsubscribed; the stream-ended path below resubscribes
+            let (mut subscription, mut consuming) = match initial_subscription.take() {
+                Some(subscription) => (subscription, true),
+                None => (Subscriptions::new(), *control_rx.borrow_and_update() == health::RunState::Running),
+            };
+            async move {
+            // Lets an idle loop prove it is still being polled
+            let mut idle_tick = tokio::time::interval(Duration::from_secs(1));
+            // Every assignment task, so panics are seen and shutdown can wait on them
+            let mut state = loop_state.lock_owned().await;
+            let (tasks, shutdown_rx_loop) = &mut *state;
+            let drain_deadline = Duration::from_secs(config.drain_timeout_seconds);
+            let drain_grace = Duration::from_millis(config.drain_grace_ms);
+            // Set on drain; until then deliveries keep being answered by DRAIN_POLICY instead of
+            // waiting, unanswered, in a subscription the load balancer still routes to
+            let mut unsubscribe_at: Option<tokio::time::Instant> = None;
+            loop {
+                liveness_for_loop.touch();
+                let msg = tokio::select! {
+                    _ = shutdown_rx_loop.recv() => {
+                        if consuming {
+                            unsubscribe_all(&mut subscription).await;
+                            pipeline::turn_away_buffered(&deps, tasks, &mut subscription, drain_deadline).await;
+                        }
+                        break;
+                    }
+                    Ok(()) = control_rx.changed() => {
+                        let run_state = *control_rx.borrow_and_update();
+                        if run_state == health::RunState::Running && !consuming {
+                            match subscribe_all(&nc_for_loop, &assign_subjects).await {
+                                Ok(sub) => {
+                                    subscription = sub;
+                                    consuming = true;
+                                    metrics_for_loop.subs_active.set(assign_subjects.len() as i64);
+                                    assign_logger.info("Consumption resumed", Some(&json!({"subjects": assign_subjects})));
+                                }
+                                Err(e) => {
+                                    assign_logger.error("Failed to resubscribe on resume", Some(&json!({"error": e.to_string()})));
+                                }
+                            }
+                        } else if run_state == health::RunState::Draining && consuming && !drain_grace.is_zero() {
+                            unsubscribe_at.get_or_insert_with(|| tokio::time::Instant::now() + drain_grace);
+                        } else if run_state != health::RunState::Running && consuming {
+                            // In-flight tasks keep running; only new deliveries stop
+                            unsubscribe_all(&mut subscription).await;
+                            if run_state == health::RunState::Draining {
+                                pipeline::turn_away_buffered(&deps, tasks, &mut subscription, drain_deadline).await;
+                            }
+                            consuming = false;
+                            metrics_for_loop.subs_active.set(0);
+                            assign_logger.info("Consumption stopped", Some(&json!({"state": run_state.as_str()})));
+                        }
+                        continue;
+                    }
+                    _ = tokio::time::sleep_until(unsubscribe_at.unwrap_or_else(tokio::time::Instant::now)), if consuming && unsubscribe_at.is_some() => {
+                        unsubscribe_all(&mut subscription).await;
+                        pipeline::turn_away_buffered(&deps, tasks, &mut subscription, drain_deadline).await;
+                        consuming = false;
+                        metrics_for_loop.subs_active.set(0);
+                        assign_logger.info("Consumption stopped", Some(&json!({"state": health::RunState::Draining.as_str()})));
+                        continue;
+                    }
+                    Some(joined) = tasks.join_next(), if !tasks.is_empty() => {
+                        pipeline::reap(joined, &deps);
+                        continue;
+                    }
+                    _ = idle_tick.tick() => continue,
+                    next_msg = subscription.next(), if consuming => next_msg,
+                };
+
+                if let Some(msg) = msg {
+                    pipeline::process_message(&deps, tasks, msg.payload, msg.headers.as_ref(), &msg.subject).await;
+                    // One subject's stream ending alone still needs every subject restored
+                    if subscription.len() == assign_subjects.len() {
+                        continue;
+                    }
+                } // End of if let Some(msg)
+            
+                // Check shutdown before resubscribe logic (if stream ended)
+                if shutdown_flag.load(Ordering::SeqCst) {
+                     break;
+                }
+
+                // Stream ended (None from next()), try to resubscribe
+                unsubscribe_all(&mut subscription).await;
+                metrics_for_loop.subs_active.set(0);
+                sleep(Duration::from_secs(1)).await;
+            
+                // Check shutdown again after sleep
+                if shutdown_flag.load(Ordering::SeqCst) {
+                    break;
+                }
+                match subscribe_all(&nc_for_loop, &assign_subjects).await {
+                    Ok(sub) => {
+                        subscription = sub;
+                        metrics_for_loop.subs_active.set(assign_subjects.len() as i64);
+                        assign_logger.info("Resubscribed after stream end", Some(&json!({"subjects": assign_subjects})));
+                    }
+                    Err(e) => {
+                        assign_logger.error("Failed to resubscribe", Some(&json!({"error": e.to_string()})));
+                    }
+                }
+            }
+            pipeline::drain(tasks, &deps, drain_deadline).await;
+            }
+        }).await });
+        let (processing_done_tx, processing_done) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            // A loop the supervisor gave up on has already failed /livez
+            let _ = processing.await;
+            let _ = processing_done_tx.send(());
+        });
+
+        // Run until the caller asks to stop
+        shutdown.cancelled().await;
+        // Phase 1: not ready and nothing new starts, but still subscribed for DRAIN_GRACE_MS so
+        // deliveries already on their way are answered rather than stranded
+        let drain_started = std::time::Instant::now();
+        let (turned_away_before, aborted_before) = (metrics.drain_rejected_total.get(), metrics.drain_aborted_total.get());
+        let max_concurrency = concurrency.limit();
+        let in_use = concurrency.in_use();
+        readiness.store(false, Ordering::SeqCst);
+        control.drain();
+        logger.info("Drain phase 1: not ready, turning away new assignments", Some(&json!({
+            "drain_grace_ms": config.drain_grace_ms,
+            "in_flight": in_use,
+            "drain_policy": config.drain_policy
+        })));
+        let load = if max_concurrency == 0 { 0.0 } else { (in_use as f64) / (max_concurrency as f64) };
+        let mut draining_hb = protocol::WorkerHeartbeat {
+            worker_id: config.worker_id.clone(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            status: "draining".to_string(),
+            load,
+            capabilities: executor_caps.clone(),
+            max_concurrency,
+            in_flight: in_use,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: build_info::GIT_SHA.to_string(),
+            labels: advertised_labels(&config.worker_labels, &tenant_allowlist.load()),
+            envelope_versions: config.envelope_accept_versions.clone(),
+            ..Default::default()
+        };
+        heartbeat_activity(&mut draining_hb, &metrics, &inflight, config.heartbeat_include_inflight, started);
+        let env_d = EventEnvelopeV1::wrap_heartbeat(&draining_hb).signed(signer.as_ref());
+        if let Ok(payload) = protocol::encode_envelope(&env_d) {
+            let _ = nc.publish(heartbeat_subject.clone(), payload.into()).await;
+        }
+        sleep(Duration::from_millis(config.drain_grace_ms)).await;
+
+        // Phase 2: unsubscribe, answer what the client still buffered, then wait for running
+        // tasks (up to DRAIN_TIMEOUT_SECONDS) before the loop finishes
+        let phase_2_started = std::time::Instant::now();
+        logger.info("Drain phase 2: unsubscribing and waiting for running tasks", Some(&json!({
+            "in_flight": concurrency.in_use(),
+            "drain_timeout_ms": config.drain_timeout_seconds * 1000
+        })));
+        let _ = shutdown_tx.send(());
+        metrics.subs_active.set(0);
+        let _ = processing_done.await;
+        // Results still queued go out (or to the DLQ) before the final heartbeat
+        if !results.flush(Duration::from_secs(config.drain_timeout_seconds)).await {
+            logger.error("Result queue not flushed before the drain deadline", Some(&json!({
+                "queued": metrics.result_queue_depth.get()
+            })));
+        }
+        // Every task has finished, so no more dead letters can be queued
+        let flushing = dlq_writer.clone();
+        let _ = tokio::task::spawn_blocking(move || flushing.flush()).await;
+        let mut final_hb = protocol::WorkerHeartbeat {
+            worker_id: config.worker_id.clone(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            status: "stopped".to_string(),
+            load: 0.0,
+            capabilities: executor_caps,
+            max_concurrency,
+            in_flight: 0,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: build_info::GIT_SHA.to_string(),
+            labels: advertised_labels(&config.worker_labels, &tenant_allowlist.load()),
+            envelope_versions: config.envelope_accept_versions.clone(),
+            ..Default::default()
+        };
+        heartbeat_activity(&mut final_hb, &metrics, &inflight, config.heartbeat_include_inflight, started);
+        let env = EventEnvelopeV1::wrap_heartbeat(&final_hb).signed(signer.as_ref());
+        if let Ok(payload) = protocol::encode_envelope(&env) {
+            let _ = nc.publish(heartbeat_subject.clone(), payload.into()).await;
+        }
+        let turned_away = metrics.drain_rejected_total.get() - turned_away_before;
+        let requeued = if config.drain_policy == DrainPolicy::Requeue { turned_away } else { 0 };
+        logger.info("Drain finished", Some(&json!({
+            "phase_1_ms": (phase_2_started - drain_started).as_millis() as u64,
+            "phase_2_ms": phase_2_started.elapsed().as_millis() as u64,
+            "in_flight_at_drain": in_use,
+            "received_during_drain": turned_away,
+            "requeued": requeued,
+            "cancelled": turned_away - requeued,
+            "aborted": metrics.drain_aborted_total.get() - aborted_before
+        })));
+        logger.info("Worker shutdown", None);
+
+        run_outcome(&health_failure)
+    }
+}
+
+/// What `run` returns once it has shut down: the health server's error if that is why.
+fn run_outcome(health_failure: &std::sync::OnceLock<String>) -> Result<(), Error> {
+    match health_failure.get() {
+        Some(e) => Err(format!("health server failed: {}", e).into()),
+        None => Ok(()),
+    }
+}
+
+type Subscriptions = futures::stream::SelectAll<async_nats::Subscriber>;
+
+/// Subscribes to every assign subject as one stream, which ends only once all of them have.
+async fn subscribe_all(nc: &async_nats::Client, subjects: &[String]) -> Result<Subscriptions, async_nats::SubscribeError> {
+    let mut subscribers = Vec::with_capacity(subjects.len());
+    for subject in subjects {
+        subscribers.push(nc.subscribe(subject.clone()).await?);
+    }
+    Ok(futures::stream::select_all(subscribers))
+}
+
+async fn unsubscribe_all(subscription: &mut Subscriptions) {
+    for subscriber in subscription.iter_mut() {
+        let _ = subscriber.unsubscribe().await;
+    }
+}
+
+/// Heartbeat label carrying `TENANT_ALLOWLIST`, comma-separated.
+const TENANTS_LABEL: &str = "tenants";
+
+/// `WORKER_LABELS` plus a `tenants` label listing `TENANT_ALLOWLIST`, so the scheduler can
+/// route other tenants elsewhere.
+fn advertised_labels(labels: &HashMap<String, String>, tenant_allowlist: &[String]) -> HashMap<String, String> {
+    let mut labels = labels.clone();
+    if !tenant_allowlist.is_empty() {
+        labels.insert(TENANTS_LABEL.to_string(), tenant_allowlist.join(","));
+    }
+    labels
+}
+
+/// Running tasks and lifetime counters, added to every heartbeat.
+fn heartbeat_activity(hb: &mut protocol::WorkerHeartbeat, metrics: &Metrics, inflight: &inflight::InflightTracker, include_inflight: bool, started: std::time::Instant) {
+    if include_inflight {
+        (hb.in_flight_tasks, hb.in_flight_truncated) = inflight.heartbeat_tasks(protocol::HEARTBEAT_MAX_TASKS);
+    }
+    hb.completed_total = metrics.task_completed.get();
+    hb.failed_total = metrics.task_failed.get() + metrics.task_timeout.get();
+    hb.uptime_s = started.elapsed().as_secs();
+    hb.last_activity_at = metrics.last_activity_at();
+    metrics.worker_uptime_seconds.set(started.elapsed().as_secs_f64());
+}
@@ -0,0 +1,56 @@
+use futures::StreamExt;
+use serde_json::json;
+use std::time::Duration;
+
+const ASSIGN_SUBJECT: &str = "caf.exec.assign.v1";
+const RESULT_SUBJECT: &str = "caf.exec.result.v1";
+
+async fn admin(client: &reqwest::Client, base: &str, action: &str, token: &str) -> u16 {
+    client
+        .post(format!("{}/admin/{}", base, action))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("admin request")
+        .status()
+        .as_u16()
+}
+
+fn assignment(id: &str) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "version": "1.0",
+        "assignment_id": id,
+        "request_id": format!("req-{}", id),
+        "tenant_id": "t1",
+        "job": {"type": "echo", "payload": {"id": id}}
+    })).unwrap()
+}
+
+#[tokio::test]
+#[ignore]
+async fn pause_stops_consumption_until_resume() {
+    // Requires a running worker started with ADMIN_TOKEN and the default subjects
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let base = std::env::var("HEALTH_URL").unwrap_or_else(|_| "http://127.0.0.1:9091".to_string());
+    let token = std::env::var("ADMIN_TOKEN").expect("ADMIN_TOKEN");
+    let http = reqwest::Client::new();
+    let nc = async_nats::connect(&url).await.expect("connect nats");
+    let mut results = nc.subscribe(RESULT_SUBJECT).await.expect("subscribe results");
+
+    assert_eq!(admin(&http, &base, "pause", "wrong-token").await, 401);
+    assert_eq!(admin(&http, &base, "pause", &token).await, 200);
+    let state = http.get(format!("{}/_state", base)).send().await.unwrap().text().await.unwrap();
+    assert!(state.contains(r#""state":"paused""#));
+
+    nc.publish(ASSIGN_SUBJECT, assignment("paused-1").into()).await.unwrap();
+    nc.flush().await.unwrap();
+    let nothing = tokio::time::timeout(Duration::from_secs(2), results.next()).await;
+    assert!(nothing.is_err(), "paused worker consumed an assignment");
+
+    assert_eq!(admin(&http, &base, "resume", &token).await, 200);
+    // Give the worker a moment to resubscribe before publishing again
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    nc.publish(ASSIGN_SUBJECT, assignment("resumed-1").into()).await.unwrap();
+    let msg = tokio::time::timeout(Duration::from_secs(5), results.next()).await.expect("result after resume");
+    assert!(String::from_utf8_lossy(&msg.unwrap().payload).contains("resumed-1"));
+}
@@ -0,0 +1,88 @@
+use futures::StreamExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use worker::config::Config;
+use worker::observability::metrics::Metrics;
+use worker::Worker;
+
+/// Subjects of its own, so a worker already running against the same NATS never sees them.
+fn config(nats_url: String) -> Config {
+    std::env::set_var("WORKER_ID", "drain-test");
+    let mut config = Config::from_env().expect("config");
+    std::env::remove_var("WORKER_ID");
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    config.nats_url = nats_url;
+    config.caf_assign_subject = format!("test.{}.assign", run);
+    config.caf_assign_subjects = vec![config.caf_assign_subject.clone()];
+    config.caf_result_subject = format!("test.{}.result", run);
+    config.caf_heartbeat_subject = format!("test.{}.heartbeat", run);
+    config.caf_dlq_subject = format!("test.{}.dlq", run);
+    config.dlq_path = std::env::temp_dir().join(format!("dlq-{}.jsonl", run)).to_string_lossy().to_string();
+    config
+}
+
+#[tokio::test]
+#[ignore]
+async fn assignments_accepted_just_before_drain_all_get_results() {
+    // Requires a local NATS server
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let mut config = config(url.clone());
+    config.drain_grace_ms = 300;
+    let (assign_subject, result_subject) = (config.caf_assign_subject.clone(), config.caf_result_subject.clone());
+    let metrics = Arc::new(Metrics::new());
+    let worker = Worker::builder().config(config).metrics(metrics.clone()).health_server(false).build().expect("build worker");
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(worker.run(shutdown.clone()));
+
+    let nc = async_nats::connect(&url).await.expect("connect nats");
+    let mut results = nc.subscribe(result_subject).await.expect("subscribe results");
+    nc.flush().await.unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while metrics.subs_active.get() == 0 {
+        assert!(tokio::time::Instant::now() < deadline, "worker never subscribed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Half are in the worker's hands when the drain starts, the rest arrive during its grace period
+    let ids: Vec<String> = (0..40).map(|i| format!("drain-{}", i)).collect();
+    for (i, id) in ids.iter().enumerate() {
+        let assignment = json!({
+            "version": "1.0",
+            "assignment_id": id,
+            "request_id": format!("req-{}", id),
+            "tenant_id": "t1",
+            "job": {"type": "sleep", "payload": {"ms": 200}}
+        });
+        nc.publish(assign_subject.clone(), serde_json::to_vec(&assignment).unwrap().into()).await.unwrap();
+        if i == 19 {
+            nc.flush().await.unwrap();
+            shutdown.cancel();
+        }
+    }
+    nc.flush().await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(30), running).await.expect("worker stops").unwrap().expect("clean shutdown");
+    let mut seen: HashMap<String, (String, Option<String>)> = HashMap::new();
+    while let Ok(Some(msg)) = tokio::time::timeout(Duration::from_secs(2), results.next()).await {
+        let env: serde_json::Value = serde_json::from_slice(&msg.payload).expect("result envelope");
+        let data = &env["data"];
+        let previous = seen.insert(
+            data["assignment_id"].as_str().unwrap().to_string(),
+            (data["status"].as_str().unwrap().to_string(), data["error_code"].as_str().map(str::to_string)),
+        );
+        assert!(previous.is_none(), "two results for {}", data["assignment_id"]);
+    }
+
+    let missing: Vec<&String> = ids.iter().filter(|id| !seen.contains_key(*id)).collect();
+    assert!(missing.is_empty(), "no result for {:?}", missing);
+    let cancelled = seen.values().filter(|(status, code)| status == "cancelled" && code.as_deref() == Some("WORKER_DRAINING")).count();
+    let succeeded = seen.values().filter(|(status, _)| status == "success").count();
+    assert_eq!(succeeded + cancelled, ids.len(), "{:?}", seen);
+    assert!(succeeded > 0, "assignments started before the drain finish");
+    assert_eq!(metrics.drain_rejected_total.get(), cancelled as u64);
+    assert_eq!(metrics.drain_aborted_total.get(), 0);
+}
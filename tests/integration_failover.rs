@@ -0,0 +1,38 @@
+use std::process::Command;
+use std::time::Duration;
+use tokio::time::sleep;
+use futures::StreamExt;
+
+#[tokio::test]
+#[ignore]
+async fn fails_over_to_the_next_server() {
+    // Two clustered NATS nodes (CI starts them with docker compose as services `nats` and
+    // `nats-2`, each with `--name` set to its service name)
+    std::env::set_var("NATS_URL", std::env::var("NATS_URLS").unwrap_or_else(|_| "nats://127.0.0.1:4222,nats://127.0.0.1:4223".to_string()));
+    let config = worker::config::Config::from_env().expect("config");
+    let subject = "test.failover.v1";
+
+    let nc = async_nats::connect(config.nats_servers()).await.expect("connect nats");
+    let mut sub = nc.subscribe(subject).await.expect("subscribe");
+    nc.publish(subject.to_string(), "before".into()).await.expect("publish before");
+    let msg = tokio::time::timeout(Duration::from_secs(3), sub.next()).await.expect("wait msg");
+    assert_eq!(String::from_utf8_lossy(&msg.unwrap().payload), "before");
+
+    // Stop whichever node we are on; the same client should move to the other one
+    let active = nc.server_info().server_name;
+    Command::new("docker")
+        .args(["compose", "stop", &active])
+        .status()
+        .expect("stop active nats");
+    sleep(Duration::from_secs(5)).await;
+    assert_ne!(nc.server_info().server_name, active);
+
+    nc.publish(subject.to_string(), "after".into()).await.expect("publish after");
+    let msg = tokio::time::timeout(Duration::from_secs(5), sub.next()).await.expect("wait msg after failover");
+    assert_eq!(String::from_utf8_lossy(&msg.unwrap().payload), "after");
+
+    Command::new("docker")
+        .args(["compose", "start", &active])
+        .status()
+        .expect("restart stopped nats");
+}
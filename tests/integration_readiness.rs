@@ -0,0 +1,41 @@
+use std::process::Command;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+async fn wait_for_status(url: &str, expected: u16, within: Duration) -> bool {
+    let client = reqwest::Client::new();
+    let deadline = Instant::now() + within;
+    while Instant::now() < deadline {
+        if let Ok(res) = client.get(url).send().await {
+            if res.status().as_u16() == expected {
+                return true;
+            }
+        }
+        sleep(Duration::from_millis(250)).await;
+    }
+    false
+}
+
+#[tokio::test]
+#[ignore]
+async fn readiness_follows_nats_outage() {
+    // Requires a running worker (HEALTH_URL) connected to the compose NATS service
+    let base = std::env::var("HEALTH_URL").unwrap_or_else(|_| "http://127.0.0.1:9091".to_string());
+    let readyz = format!("{}/readyz", base);
+    assert!(wait_for_status(&readyz, 200, Duration::from_secs(10)).await, "worker not ready before outage");
+
+    Command::new("docker")
+        .args(["compose", "stop", "nats"])
+        .status()
+        .expect("stop nats");
+    assert!(wait_for_status(&readyz, 503, Duration::from_secs(15)).await, "readiness did not drop while NATS was down");
+
+    Command::new("docker")
+        .args(["compose", "start", "nats"])
+        .status()
+        .expect("start nats");
+    assert!(wait_for_status(&readyz, 200, Duration::from_secs(30)).await, "readiness did not recover after NATS restart");
+
+    let metrics = reqwest::get(format!("{}/metrics", base)).await.unwrap().text().await.unwrap();
+    assert!(metrics.lines().any(|l| l.starts_with("nats_reconnects_total") && !l.ends_with(" 0")));
+}
@@ -0,0 +1,53 @@
+use futures::StreamExt;
+use serde_json::json;
+use std::collections::HashSet;
+use std::time::Duration;
+
+const RESULT_SUBJECT: &str = "caf.exec.result.v1";
+
+fn assignment(id: &str, job_type: &str) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "version": "1.0",
+        "assignment_id": id,
+        "request_id": format!("req-{}", id),
+        "tenant_id": "t1",
+        "job": {"type": job_type, "payload": {"id": id}}
+    })).unwrap()
+}
+
+async fn next_result(results: &mut async_nats::Subscriber) -> serde_json::Value {
+    let msg = tokio::time::timeout(Duration::from_secs(5), results.next())
+        .await
+        .expect("result within 5s")
+        .expect("result stream open");
+    let env: serde_json::Value = serde_json::from_slice(&msg.payload).expect("result envelope");
+    env["data"].clone()
+}
+
+#[tokio::test]
+#[ignore]
+async fn two_subjects_feed_one_worker() {
+    // Requires a running worker started with
+    // CAF_ASSIGN_SUBJECTS=caf.exec.assign.v1.sql,caf.exec.assign.v1.* and WORKER_JOB_TYPE_DENYLIST=sleep
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let nc = async_nats::connect(&url).await.expect("connect nats");
+    let mut results = nc.subscribe(RESULT_SUBJECT).await.expect("subscribe results");
+
+    nc.publish("caf.exec.assign.v1.sql", assignment("subj-1", "echo").into()).await.unwrap();
+    nc.publish("caf.exec.assign.v1.http", assignment("subj-2", "echo").into()).await.unwrap();
+    nc.flush().await.unwrap();
+    let mut seen = HashSet::new();
+    for _ in 0..2 {
+        let result = next_result(&mut results).await;
+        assert_eq!(result["status"], "success");
+        seen.insert(result["assignment_id"].as_str().unwrap().to_string());
+    }
+    assert_eq!(seen, HashSet::from(["subj-1".to_string(), "subj-2".to_string()]));
+
+    // A denied job type comes straight back without running
+    nc.publish("caf.exec.assign.v1.http", assignment("subj-3", "sleep").into()).await.unwrap();
+    let result = next_result(&mut results).await;
+    assert_eq!(result["assignment_id"], "subj-3");
+    assert_eq!(result["status"], "cancelled");
+    assert_eq!(result["error_code"], "UNSUPPORTED_JOB_TYPE");
+}
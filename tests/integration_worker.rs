@@ -0,0 +1,150 @@
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use worker::config::Config;
+use worker::handlers::{ExecContext, HandlerOutcome, JobHandler};
+use worker::loadgen::{self, LoadgenOptions};
+use worker::observability::metrics::Metrics;
+use worker::protocol::Job;
+use worker::Worker;
+
+struct Reverse;
+
+impl JobHandler for Reverse {
+    fn handle<'a>(&'a self, _ctx: &'a ExecContext, job: &'a Job) -> BoxFuture<'a, HandlerOutcome> {
+        Box::pin(async move {
+            let text = job.payload["text"].as_str().unwrap_or_default();
+            HandlerOutcome::success(json!({"text": text.chars().rev().collect::<String>()}))
+        })
+    }
+}
+
+/// Subjects of its own, so a worker already running against the same NATS never sees them.
+fn config(nats_url: String) -> Config {
+    std::env::set_var("WORKER_ID", "embedded-test");
+    let mut config = Config::from_env().expect("config");
+    std::env::remove_var("WORKER_ID");
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    config.nats_url = nats_url;
+    config.caf_assign_subject = format!("test.{}.assign", run);
+    config.caf_assign_subjects = vec![config.caf_assign_subject.clone()];
+    config.caf_result_subject = format!("test.{}.result", run);
+    config.caf_heartbeat_subject = format!("test.{}.heartbeat", run);
+    config.caf_dlq_subject = format!("test.{}.dlq", run);
+    config.dlq_path = std::env::temp_dir().join(format!("dlq-{}.jsonl", run)).to_string_lossy().to_string();
+    config
+}
+
+async fn wait_subscribed(metrics: &Metrics) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while metrics.subs_active.get() == 0 {
+        assert!(tokio::time::Instant::now() < deadline, "worker never subscribed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // The worker's SUB goes out on its own connection; give the server a moment to register it
+    tokio::time::sleep(Duration::from_millis(200)).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn embedded_worker_runs_a_registered_handler() {
+    // Requires a local NATS server; no worker process is needed
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let config = config(url.clone());
+    let (assign_subject, result_subject) = (config.caf_assign_subject.clone(), config.caf_result_subject.clone());
+    let metrics = Arc::new(Metrics::new());
+    let worker = Worker::builder()
+        .config(config)
+        .register_handler("reverse", Reverse)
+        .metrics(metrics.clone())
+        .health_server(false)
+        .build()
+        .expect("build worker");
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(worker.run(shutdown.clone()));
+
+    let nc = async_nats::connect(&url).await.expect("connect nats");
+    let mut results = nc.subscribe(result_subject).await.expect("subscribe results");
+    nc.flush().await.unwrap();
+    wait_subscribed(&metrics).await;
+
+    let assignment = json!({
+        "version": "1.0",
+        "assignment_id": "embedded-1",
+        "request_id": "req-embedded-1",
+        "tenant_id": "t1",
+        "job": {"type": "reverse", "payload": {"text": "worker"}}
+    });
+    nc.publish(assign_subject, serde_json::to_vec(&assignment).unwrap().into()).await.unwrap();
+    let msg = tokio::time::timeout(Duration::from_secs(5), results.next())
+        .await
+        .expect("result within 5s")
+        .expect("result stream open");
+    let env: serde_json::Value = serde_json::from_slice(&msg.payload).expect("result envelope");
+    assert_eq!(env["kind"], "exec_result");
+    assert_eq!(env["data"]["assignment_id"], "embedded-1");
+    assert_eq!(env["data"]["status"], "success");
+    assert_eq!(env["data"]["job_type"], "reverse");
+    assert_eq!(env["data"]["provider_id"], "embedded-test");
+    assert_eq!(env["data"]["output"], json!({"text": "rekrow"}));
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(10), running)
+        .await
+        .expect("worker stops after shutdown")
+        .unwrap()
+        .expect("clean shutdown");
+    assert_eq!(metrics.task_completed.get(), 1);
+}
+
+#[tokio::test]
+#[ignore]
+async fn loadgen_against_an_embedded_worker() {
+    // Requires a local NATS server
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let config = config(url.clone());
+    let metrics = Arc::new(Metrics::new());
+    let worker = Worker::builder().config(config.clone()).metrics(metrics.clone()).health_server(false).build().expect("build worker");
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(worker.run(shutdown.clone()));
+    wait_subscribed(&metrics).await;
+
+    let nc = async_nats::connect(&url).await.expect("connect nats");
+    let options = LoadgenOptions {
+        rate: 200,
+        count: Some(20),
+        duration: None,
+        concurrency: 8,
+        job_type: "jmespath".to_string(),
+        payload: loadgen::default_payload("jmespath").unwrap(),
+        result_wait: Duration::from_secs(10),
+    };
+    let report = loadgen::run(&nc, &config, &options).await.expect("loadgen run");
+    assert_eq!((report.sent, report.received, report.succeeded, report.lost), (20, 20, 20, 0));
+    assert_eq!(report.exit_code(), 0);
+    let latency = report.latency_ms.expect("latency percentiles");
+    assert!(latency.p50 <= latency.p99 && latency.p99 <= latency.max);
+    assert!(report.queue_latency_ms.is_some(), "the worker reports queue latency from published_at");
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(10), running).await.expect("worker stops").unwrap().expect("clean shutdown");
+    assert_eq!(metrics.task_completed.get(), 20);
+}
+
+#[tokio::test]
+async fn health_server_failure_ends_run_with_an_error() {
+    // Nothing listens on port 1, so the worker is still connecting when its health server fails
+    let mut config = config("nats://127.0.0.1:1".to_string());
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    config.health_bind = taken.local_addr().unwrap().to_string();
+    let worker = Worker::builder().config(config).build().expect("build worker");
+    let shutdown = CancellationToken::new();
+
+    let outcome = tokio::time::timeout(Duration::from_secs(10), worker.run(shutdown.clone())).await.expect("run returns");
+    let error = outcome.expect_err("a health server that cannot bind fails the run").to_string();
+    assert!(error.starts_with("health server failed"), "{}", error);
+    assert!(!shutdown.is_cancelled(), "the caller's token is left alone");
+}
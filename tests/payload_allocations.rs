@@ -0,0 +1,177 @@
+//! Bytes allocated, and peak bytes live, while large payloads are handled.
+//!
+//! Its own test binary, since it installs a counting global allocator. The counters are
+//! process-wide, so the tests take `SERIAL` instead of running in parallel.
+
+use arc_swap::ArcSwap;
+use async_nats::HeaderMap;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use worker::concurrency::{ConcurrencyLimit, KeyedLocks, KEY_LOCKS_MAX_IDLE};
+use worker::config::Config;
+use worker::dlq::DlqWriter;
+use worker::executor::Executor;
+use worker::history::TaskHistory;
+use worker::idempotency::IdempotencyCache;
+use worker::result_cache::ResultCache;
+use worker::inflight::InflightTracker;
+use worker::observability::{metrics::Metrics, Logger};
+use worker::pipeline::{self, Dedup, LogThrottle, PipelineDeps, PublishFailure, ResultPublisher};
+use worker::protocol::{AssignmentValidator, ExecAssignment};
+use worker::result_queue::ResultQueue;
+use worker::rotation::RotationPolicy;
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn grow(bytes: usize) {
+    let live = LIVE.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Peak live bytes since the call, above what was live at the call.
+fn track_peak<T>(run: impl FnOnce() -> T) -> (T, usize) {
+    let baseline = LIVE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let out = run();
+    (out, PEAK.load(Ordering::Relaxed).saturating_sub(baseline))
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        grow(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        grow(new_size);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+struct Discard;
+
+impl ResultPublisher for Discard {
+    fn publish<'a>(&'a self, _subject: &'a str, _headers: Option<&'a HeaderMap>, _payload: &'a [u8]) -> BoxFuture<'a, Result<(), PublishFailure>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+fn deps(dir: &std::path::Path) -> PipelineDeps {
+    std::env::set_var("WORKER_ID", "alloc-test");
+    let config = Arc::new(Config::from_env().unwrap());
+    std::env::remove_var("WORKER_ID");
+    let logger = Logger::new(config.worker_id.clone());
+    let metrics = Arc::new(Metrics::new());
+    let policy = RotationPolicy { max_bytes: u64::MAX, max_rotations: 1, total_max_bytes: u64::MAX, max_age_days: None, compress: false };
+    let dlq_writer = Arc::new(DlqWriter::new(dir.join("dlq.jsonl").to_string_lossy().to_string(), policy, metrics.clone(), logger.clone()));
+    let publisher: Arc<dyn ResultPublisher> = Arc::new(Discard);
+    PipelineDeps {
+        executor: Executor::new(config.worker_id.clone(), dir.to_string_lossy().to_string()),
+        results: ResultQueue::start(config.clone(), publisher.clone(), dlq_writer.clone(), metrics.clone(), None),
+        publisher,
+        dedup: Arc::new(Mutex::new(Dedup::new(16))),
+        dlq_writer,
+        metrics,
+        logger,
+        signer: None,
+        validator: Arc::new(AssignmentValidator::new()),
+        concurrency: Arc::new(ConcurrencyLimit::new(4)),
+        key_locks: Arc::new(KeyedLocks::new(KEY_LOCKS_MAX_IDLE)),
+        outbox: None,
+        resources: None,
+        inflight: Arc::new(InflightTracker::new()),
+        history: Arc::new(TaskHistory::new(config.task_history_size)),
+        result_cache: Arc::new(ResultCache::new(config.result_cache_size, Duration::from_secs(config.result_cache_ttl_seconds), config.result_cache_max_entry_bytes)),
+        idempotency: Arc::new(IdempotencyCache::new(config.idempotency_cache_size, Duration::from_secs(config.idempotency_cache_ttl_seconds), config.idempotency_cache_failures)),
+        recorder: None,
+        receipts: None,
+        result_subject: config.caf_result_subject.clone(),
+        draining: Arc::new(AtomicBool::new(false)),
+        tenant_allowlist: Arc::new(ArcSwap::from_pointee(config.tenant_allowlist.clone())),
+        backpressure_log: Arc::new(LogThrottle::new(pipeline::BACKPRESSURE_LOG_INTERVAL)),
+        config,
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+#[test]
+fn large_payload_is_not_copied_per_task() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    runtime().block_on(async {
+        // 1 MB, just under the default ASSIGNMENT_MAX_BYTES
+        const PAYLOAD_BYTES: usize = 1_000_000;
+        let dir = std::env::temp_dir().join(format!("alloc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let deps = deps(&dir);
+        // The sleep handler ignores the blob, so anything beyond parsing it once is a copy
+        let message = serde_json::to_vec(&json!({
+            "version": "1.0",
+            "assignment_id": "big-1",
+            "request_id": "r1",
+            "tenant_id": "t1",
+            "job": {"type": "sleep", "payload": {"ms": 1, "blob": "x".repeat(PAYLOAD_BYTES)}}
+        })).unwrap();
+
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let mut tasks = JoinSet::new();
+        pipeline::process_message(&deps, &mut tasks, Bytes::from(message), None, &deps.config.caf_assign_subject).await;
+        assert_eq!(pipeline::drain(&mut tasks, &deps, Duration::from_secs(5)).await, 0);
+        assert!(deps.results.flush(Duration::from_secs(5)).await);
+        let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+
+        assert_eq!(deps.metrics.task_completed.get(), 1);
+        assert!(allocated >= PAYLOAD_BYTES, "parsing allocates the payload once: {}", allocated);
+        assert!(allocated < PAYLOAD_BYTES * 3 / 2, "payload copied after parsing: {} bytes allocated", allocated);
+        let _ = std::fs::remove_dir_all(&dir);
+    });
+}
+
+#[test]
+fn blob_get_holds_only_the_encoding_at_its_peak() {
+    const FILE_BYTES: usize = 4 * 1024 * 1024;
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = std::env::temp_dir().join(format!("alloc-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("blob.bin"), vec![7u8; FILE_BYTES]).unwrap();
+    let executor = Executor::new("alloc-test".to_string(), dir.to_string_lossy().to_string());
+    let assignment: ExecAssignment = serde_json::from_value(json!({
+        "version": "1.0",
+        "assignment_id": "blob-1",
+        "request_id": "r1",
+        "tenant_id": "t1",
+        "job": {"type": "fs_blob_get", "payload": {"path": "blob.bin"}}
+    })).unwrap();
+    let runtime = runtime();
+
+    let (result, peak) = track_peak(|| runtime.block_on(executor.execute(assignment)));
+    let encoded_len = result.output.as_ref().unwrap()["bytes"].as_str().unwrap().len();
+    assert_eq!(encoded_len, FILE_BYTES.div_ceil(3) * 4);
+    // The encoding plus a chunk buffer; reading the whole file first would add FILE_BYTES
+    assert!(peak < encoded_len + FILE_BYTES / 4, "peak {} bytes live for {} encoded", peak, encoded_len);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -13,6 +13,9 @@ fn envelope_roundtrip_assignment() {
         run_id: None,
         flow_id: None,
         step_id: None,
+        published_at: None,
+        concurrency_key: None,
+        idempotency_key: None,
     };
     let env = EventEnvelopeV1::wrap_assignment(&a);
     assert!(matches!(env.kind, EnvelopeKind::ExecAssign));